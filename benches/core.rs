@@ -0,0 +1,144 @@
+//! Timings of the hot paths, run with `cargo bench`. Pass a name filter to
+//! run only some, e.g. `cargo bench -- email`.
+//!
+//! Each benchmark is warmed up, then timed over enough iterations to take
+//! about half a second, and reported in nanoseconds per iteration.
+
+use rust_ddd_playground::event_store::StoredEvent;
+use rust_ddd_playground::events::{
+    DomainEvent, EmailVerified, EventId, NameChanged, UserRegistered,
+};
+use rust_ddd_playground::history::fold_user;
+use rust_ddd_playground::parallel::{available_parallelism, map_ordered};
+use rust_ddd_playground::{create_user, format_fullname, Age, Email, UserId};
+use std::hint::black_box;
+use std::time::{Duration, Instant, SystemTime};
+
+const TARGET: Duration = Duration::from_millis(500);
+
+fn bench(filter: Option<&str>, name: &str, mut f: impl FnMut()) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    let started = Instant::now();
+    let mut warmup = 0u32;
+    while started.elapsed() < TARGET / 10 {
+        f();
+        warmup += 1;
+    }
+    let iterations = (warmup * 10).max(1);
+    let started = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let per_iteration = started.elapsed().as_nanos() / u128::from(iterations);
+    println!("{:<40} {:>12} ns/iter", name, per_iteration);
+}
+
+fn emails(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| match i % 4 {
+            0 => format!("user{}.at.example.com", i),
+            _ => format!("user{}@example.com", i),
+        })
+        .collect()
+}
+
+fn stream(changes: u64) -> Vec<StoredEvent> {
+    let stored = |id: u64, event: DomainEvent| StoredEvent {
+        id: EventId(id),
+        recorded_at: SystemTime::UNIX_EPOCH,
+        event,
+    };
+    let mut events = vec![stored(
+        1,
+        DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+            name: "Luca".to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+            age: 22,
+        }),
+    )];
+    for id in 2..changes + 2 {
+        let event = match id % 2 {
+            0 => DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(1),
+                name: format!("Luca{}", id),
+                middle_name: Some("Maria".to_string()),
+                surname: "Rossi".to_string(),
+            }),
+            _ => DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(1),
+                email: "foo@ok.com".to_string(),
+            }),
+        };
+        events.push(stored(id, event));
+    }
+    events
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; anything else is a name filter.
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+
+    let valid = "luca.rossi@example.com".to_string();
+    let invalid = "luca.rossi.example.com".to_string();
+    bench(filter, "email/valid", || {
+        black_box(Email::try_from(black_box(valid.clone())).is_ok());
+    });
+    bench(filter, "email/invalid", || {
+        black_box(Email::try_from(black_box(invalid.clone())).is_ok());
+    });
+    let mix = emails(100);
+    bench(filter, "email/mix_of_100", || {
+        for email in &mix {
+            black_box(Email::try_from(email.clone()).is_ok());
+        }
+    });
+
+    bench(filter, "create_user", || {
+        black_box(
+            create_user(
+                "foo@ok.com".to_string(),
+                22,
+                "Luca".to_string(),
+                "Rossi".to_string(),
+                Some("Maria".to_string()),
+            )
+            .is_ok(),
+        );
+    });
+
+    bench(filter, "format_fullname", || {
+        black_box(format_fullname(
+            black_box("Luca"),
+            black_box(Some("Maria")),
+            black_box("Rossi"),
+        ));
+    });
+
+    for changes in [10, 1_000] {
+        let events = stream(changes);
+        bench(filter, &format!("fold_user/{}_events", changes), || {
+            black_box(fold_user(&events));
+        });
+    }
+
+    let rows = emails(10_000);
+    let validate =
+        |email: &String| Email::try_from(email.clone()).is_ok() && Age::try_from(22).is_ok();
+    bench(filter, "validate_batch/10000_sequential", || {
+        black_box(map_ordered(rows.iter().collect(), 1, validate));
+    });
+    let threads = available_parallelism();
+    bench(
+        filter,
+        &format!("validate_batch/10000_on_{}_threads", threads),
+        || {
+            black_box(map_ordered(rows.iter().collect(), threads, validate));
+        },
+    );
+}
@@ -0,0 +1,53 @@
+/*
+ * C interface to the rust_ddd_playground validation and user-creation
+ * rules. Kept in step by hand with src/ffi.rs.
+ *
+ * Functions return a DddStatus. On failure, ddd_last_error_code() and
+ * ddd_last_error_message() describe the calling thread's last error until
+ * its next failing call.
+ */
+
+#ifndef RUST_DDD_PLAYGROUND_H
+#define RUST_DDD_PLAYGROUND_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum DddStatus {
+    DDD_STATUS_OK = 0,
+    /* A domain rule was broken; the last error says which. */
+    DDD_STATUS_DOMAIN_ERROR = 1,
+    /* A required pointer was null or a string was not UTF-8. */
+    DDD_STATUS_INVALID_ARGUMENT = 2,
+} DddStatus;
+
+/* Opaque user, created by ddd_create_user and freed by ddd_user_free. */
+typedef struct DddUser DddUser;
+
+/* Error code such as "invalid_email", or NULL if nothing failed yet. */
+const char *ddd_last_error_code(void);
+const char *ddd_last_error_message(void);
+
+DddStatus ddd_check_email(const char *email);
+DddStatus ddd_check_age(int32_t age);
+
+/* middle_name may be NULL. On success *user holds the new user. */
+DddStatus ddd_create_user(const char *email, int32_t age, const char *name,
+                          const char *surname, const char *middle_name,
+                          DddUser **user);
+
+uint64_t ddd_user_id(const DddUser *user);
+/* Free the result with ddd_string_free. */
+char *ddd_user_full_name(const DddUser *user);
+
+void ddd_user_free(DddUser *user);
+void ddd_string_free(char *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUST_DDD_PLAYGROUND_H */
@@ -0,0 +1,20 @@
+CREATE TABLE users (
+    id BIGINT PRIMARY KEY,
+    tenant_id TEXT NOT NULL DEFAULT 'default',
+    name TEXT NOT NULL,
+    middle_name TEXT,
+    surname TEXT NOT NULL,
+    age INTEGER NOT NULL,
+    email TEXT NOT NULL,
+    email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    pronouns TEXT,
+    avatar TEXT,
+    custom_attributes JSONB NOT NULL DEFAULT '{}',
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    merged_into BIGINT REFERENCES users (id),
+    registered_at TIMESTAMPTZ NOT NULL,
+    version BIGINT NOT NULL DEFAULT 0
+);
+
+-- One live user per address.
+CREATE UNIQUE INDEX users_email ON users (lower(trim(email))) WHERE merged_into IS NULL;
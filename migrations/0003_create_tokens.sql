@@ -0,0 +1,10 @@
+CREATE TABLE tokens (
+    -- SHA-256 of the token; the token itself is only ever sent to the user.
+    token_hash TEXT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id),
+    purpose TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX tokens_user ON tokens (user_id);
@@ -0,0 +1,3 @@
+-- One live user per address within each tenant.
+DROP INDEX users_email;
+CREATE UNIQUE INDEX users_email ON users (tenant_id, lower(trim(email))) WHERE merged_into IS NULL;
@@ -0,0 +1,167 @@
+//! One actor per user: commands for a user are queued in its mailbox and run
+//! one at a time on the actor's thread, so they never race each other for
+//! the aggregate, while commands for different users run in parallel.
+
+use crate::bus::Command;
+use crate::commands::{handle_change_name, handle_create_user, handle_verify_email};
+use crate::config::AgeLimits;
+use crate::events::DomainEvent;
+use crate::merge::merge_users;
+use crate::repository::UserRepository;
+use crate::UserId;
+use anyhow::{Error, Result};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+struct Envelope {
+    command: Command,
+    reply: Sender<Result<DomainEvent>>,
+}
+
+struct Actor {
+    mailbox: Sender<Envelope>,
+    thread: JoinHandle<()>,
+}
+
+fn run(
+    repository: &impl UserRepository,
+    age_limits: &AgeLimits,
+    command: Command,
+) -> Result<DomainEvent> {
+    Ok(match command {
+        Command::CreateUser(command) => handle_create_user(repository, age_limits, command)?.into(),
+        Command::VerifyEmail(user_id) => handle_verify_email(repository, user_id)?.into(),
+        Command::ChangeName(command) => handle_change_name(repository, command)?.into(),
+        Command::MergeUsers {
+            primary_id,
+            duplicate_id,
+        } => merge_users(repository, primary_id, duplicate_id)?.into(),
+    })
+}
+
+/// Spawns actors on a user's first command and keeps them until
+/// [`UserActors::shutdown`].
+pub struct UserActors<R: UserRepository + Send + Sync + 'static> {
+    repository: Arc<R>,
+    age_limits: Arc<AgeLimits>,
+    actors: Mutex<HashMap<UserId, Actor>>,
+}
+
+impl<R: UserRepository + Send + Sync + 'static> UserActors<R> {
+    pub fn new(repository: Arc<R>) -> Self {
+        Self {
+            repository,
+            age_limits: Arc::new(AgeLimits::default()),
+            actors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limits `CreateUser` checks ages against, the defaults until set.
+    pub fn with_age_limits(mut self, age_limits: AgeLimits) -> Self {
+        self.age_limits = Arc::new(age_limits);
+        self
+    }
+
+    /// Runs `command` on the actor of the user it acts on and waits for its
+    /// event. `CreateUser` has no user yet and runs on the calling thread; a
+    /// merge runs on the primary user's actor.
+    pub fn send(&self, command: Command) -> Result<DomainEvent> {
+        let Some(user_id) = command.user_id() else {
+            return run(self.repository.as_ref(), &self.age_limits, command);
+        };
+        let (reply, response) = channel();
+        self.mailbox(user_id)
+            .send(Envelope { command, reply })
+            .map_err(|_| Error::msg("User actor stopped"))?;
+        response
+            .recv()
+            .map_err(|_| Error::msg("User actor stopped"))?
+    }
+
+    /// Users with a running actor.
+    pub fn len(&self) -> usize {
+        self.actors.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lets every actor finish the commands already in its mailbox, then
+    /// stops it.
+    pub fn shutdown(self) {
+        for (_, actor) in self.actors.into_inner().unwrap() {
+            drop(actor.mailbox);
+            let _ = actor.thread.join();
+        }
+    }
+
+    fn mailbox(&self, user_id: UserId) -> Sender<Envelope> {
+        let mut actors = self.actors.lock().unwrap();
+        let actor = actors.entry(user_id).or_insert_with(|| {
+            let (mailbox, envelopes) = channel::<Envelope>();
+            let repository = Arc::clone(&self.repository);
+            let age_limits = Arc::clone(&self.age_limits);
+            let thread = thread::spawn(move || {
+                for envelope in envelopes {
+                    let result = run(repository.as_ref(), &age_limits, envelope.command);
+                    // The sender may have given up waiting; the command ran
+                    // regardless.
+                    let _ = envelope.reply.send(result);
+                }
+            });
+            Actor { mailbox, thread }
+        });
+        actor.mailbox.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::{ChangeName, CreateUser};
+    use crate::repository::InMemoryUserRepository;
+
+    #[test]
+    fn ok_concurrent_commands_for_one_user_never_conflict() {
+        let repository = Arc::new(InMemoryUserRepository::new());
+        let actors = Arc::new(UserActors::new(Arc::clone(&repository)));
+        let event = actors
+            .send(Command::CreateUser(CreateUser {
+                email: "foo@ok.com".to_string(),
+                age: 22,
+                name: "Luca".to_string(),
+                surname: "Rossi".to_string(),
+                middle_name: None,
+            }))
+            .unwrap();
+        let user_id = event.user_id();
+
+        let senders: Vec<_> = (0..4)
+            .map(|sender| {
+                let actors = Arc::clone(&actors);
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        actors
+                            .send(Command::ChangeName(ChangeName {
+                                user_id,
+                                name: format!("Luca{}", sender),
+                                middle_name: None,
+                                surname: "Rossi".to_string(),
+                            }))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        assert_eq!(repository.find(user_id).unwrap().unwrap().version, 41);
+        assert_eq!(actors.len(), 1);
+        Arc::into_inner(actors).unwrap().shutdown();
+    }
+}
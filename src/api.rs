@@ -0,0 +1,804 @@
+//! Routes of the HTTP API, and its request and response bodies with one
+//! module per version. Each version converts to and from the same domain
+//! commands and users, so the domain can change without breaking clients of
+//! an older version.
+
+use crate::bus::{Command, CommandBus, CommandMetadata, ExecutionMode};
+use crate::clock::Clock;
+use crate::commands::CreateUser;
+use crate::date::Date;
+use crate::events::{DomainEvent, EventPublisher, UserRegistered};
+use crate::http::{HttpRequest, HttpResponse, Router};
+use crate::idempotency::IdempotencyKey;
+use crate::json::{parse_json, JsonValue};
+use crate::pii::{mask_email, mask_name};
+use crate::repository::{Cursor, TenantScopedRepository, UserRepository};
+use crate::{TenantId, User};
+use anyhow::{Error, Result};
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+
+/// Header naming the tenant a request acts for, the default tenant if absent.
+pub const TENANT_HEADER: &str = "X-Tenant-Id";
+
+/// Header carrying the [`IdempotencyKey`] of a `POST`, so a client can retry
+/// it without creating a second user.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+/// A request body that is malformed or misses a field, as opposed to a
+/// well-formed request the domain rejects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidRequest {
+    pub field: String,
+    pub reason: String,
+}
+
+impl Display for InvalidRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Field {} {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidRequest {}
+
+fn invalid(field: &str, reason: &str) -> InvalidRequest {
+    InvalidRequest {
+        field: field.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn string(body: &JsonValue, field: &str) -> Result<String, InvalidRequest> {
+    match body.get(field) {
+        Some(JsonValue::String(value)) => Ok(value.clone()),
+        Some(_) => Err(invalid(field, "must be a string")),
+        None => Err(invalid(field, "is required")),
+    }
+}
+
+fn optional_string(body: &JsonValue, field: &str) -> Result<Option<String>, InvalidRequest> {
+    match body.get(field) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(_) => string(body, field).map(Some),
+    }
+}
+
+fn json_string(value: &str) -> JsonValue {
+    JsonValue::String(value.to_string())
+}
+
+fn optional_json_string(value: Option<&str>) -> JsonValue {
+    value.map_or(JsonValue::Null, json_string)
+}
+
+fn email(user: &User) -> (&str, bool) {
+    let verified = user.is_email_verified();
+    (user.email.address().as_str(), verified)
+}
+
+pub mod v1 {
+    use super::*;
+
+    #[derive(Clone, PartialEq)]
+    pub struct CreateUserRequest {
+        pub email: String,
+        pub age: i32,
+        pub name: String,
+        pub surname: String,
+        pub middle_name: Option<String>,
+    }
+
+    impl Debug for CreateUserRequest {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("CreateUserRequest")
+                .field("email", &mask_email(&self.email))
+                .field("age", &self.age)
+                .field("name", &mask_name(&self.name))
+                .field("surname", &mask_name(&self.surname))
+                .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+                .finish()
+        }
+    }
+
+    impl CreateUserRequest {
+        pub fn from_json(body: &JsonValue) -> Result<Self> {
+            let age = match body.get("age") {
+                Some(JsonValue::Number(age)) => {
+                    i32::try_from(*age).map_err(|_| invalid("age", "is out of range"))?
+                }
+                Some(_) => return Err(invalid("age", "must be a number").into()),
+                None => return Err(invalid("age", "is required").into()),
+            };
+            Ok(Self {
+                email: string(body, "email")?,
+                age,
+                name: string(body, "name")?,
+                surname: string(body, "surname")?,
+                middle_name: optional_string(body, "middle_name")?,
+            })
+        }
+
+        pub fn into_command(self) -> CreateUser {
+            CreateUser {
+                email: self.email,
+                age: self.age,
+                name: self.name,
+                surname: self.surname,
+                middle_name: self.middle_name,
+            }
+        }
+    }
+
+    #[derive(Clone, PartialEq)]
+    pub struct UserResponse {
+        pub id: u64,
+        pub email: String,
+        pub email_verified: bool,
+        pub age: i32,
+        pub name: String,
+        pub surname: String,
+        pub middle_name: Option<String>,
+    }
+
+    impl Debug for UserResponse {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("UserResponse")
+                .field("id", &self.id)
+                .field("email", &mask_email(&self.email))
+                .field("email_verified", &self.email_verified)
+                .field("age", &self.age)
+                .field("name", &mask_name(&self.name))
+                .field("surname", &mask_name(&self.surname))
+                .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+                .finish()
+        }
+    }
+
+    impl UserResponse {
+        pub fn from_user(user: &User) -> Self {
+            let (email, email_verified) = email(user);
+            Self {
+                id: user.id.0,
+                email: email.to_string(),
+                email_verified,
+                age: user.age.0,
+                name: user.name.clone(),
+                surname: user.surname.clone(),
+                middle_name: user.middle_name.clone(),
+            }
+        }
+
+        /// The user as it was registered, before any later change.
+        pub fn from_registered(event: &UserRegistered) -> Self {
+            Self {
+                id: event.user_id.0,
+                email: event.email.clone(),
+                email_verified: false,
+                age: event.age,
+                name: event.name.clone(),
+                surname: event.surname.clone(),
+                middle_name: event.middle_name.clone(),
+            }
+        }
+
+        pub fn to_json(&self) -> JsonValue {
+            JsonValue::Object(vec![
+                ("id".to_string(), JsonValue::Number(self.id as i64)),
+                ("email".to_string(), json_string(&self.email)),
+                (
+                    "email_verified".to_string(),
+                    JsonValue::Bool(self.email_verified),
+                ),
+                ("age".to_string(), JsonValue::Number(self.age.into())),
+                ("name".to_string(), json_string(&self.name)),
+                ("surname".to_string(), json_string(&self.surname)),
+                (
+                    "middle_name".to_string(),
+                    optional_json_string(self.middle_name.as_deref()),
+                ),
+            ])
+        }
+    }
+}
+
+/// Takes a date of birth instead of an age, which goes stale, and names
+/// people by given and family name.
+pub mod v2 {
+    use super::*;
+    use crate::date::check_date;
+
+    #[derive(Clone, PartialEq)]
+    pub struct CreateUserRequest {
+        pub email: String,
+        pub date_of_birth: Date,
+        pub given_name: String,
+        pub middle_name: Option<String>,
+        pub family_name: String,
+    }
+
+    impl Debug for CreateUserRequest {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("CreateUserRequest")
+                .field("email", &mask_email(&self.email))
+                .field("given_name", &mask_name(&self.given_name))
+                .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+                .field("family_name", &mask_name(&self.family_name))
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl CreateUserRequest {
+        pub fn from_json(body: &JsonValue) -> Result<Self> {
+            let date_of_birth = check_date(string(body, "date_of_birth")?)
+                .map_err(|_| invalid("date_of_birth", "must be a YYYY-MM-DD date"))?;
+            Ok(Self {
+                email: string(body, "email")?,
+                date_of_birth,
+                given_name: string(body, "given_name")?,
+                middle_name: optional_string(body, "middle_name")?,
+                family_name: string(body, "family_name")?,
+            })
+        }
+
+        /// The domain still records an age, computed as of `today`.
+        pub fn into_command(self, today: Date) -> CreateUser {
+            CreateUser {
+                email: self.email,
+                age: self.date_of_birth.years_until(today),
+                name: self.given_name,
+                surname: self.family_name,
+                middle_name: self.middle_name,
+            }
+        }
+    }
+
+    #[derive(Clone, PartialEq)]
+    pub struct UserResponse {
+        pub id: String,
+        pub email: String,
+        pub email_verified: bool,
+        pub age: i32,
+        pub given_name: String,
+        pub middle_name: Option<String>,
+        pub family_name: String,
+    }
+
+    impl Debug for UserResponse {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("UserResponse")
+                .field("id", &self.id)
+                .field("email", &mask_email(&self.email))
+                .field("email_verified", &self.email_verified)
+                .field("age", &self.age)
+                .field("given_name", &mask_name(&self.given_name))
+                .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+                .field("family_name", &mask_name(&self.family_name))
+                .finish()
+        }
+    }
+
+    impl UserResponse {
+        /// Ids are strings from v2 on, so clients never round them as floats.
+        pub fn from_user(user: &User) -> Self {
+            let (email, email_verified) = email(user);
+            Self {
+                id: user.id.0.to_string(),
+                email: email.to_string(),
+                email_verified,
+                age: user.age.0,
+                given_name: user.name.clone(),
+                middle_name: user.middle_name.clone(),
+                family_name: user.surname.clone(),
+            }
+        }
+
+        /// The user as it was registered, before any later change.
+        pub fn from_registered(event: &UserRegistered) -> Self {
+            Self {
+                id: event.user_id.0.to_string(),
+                email: event.email.clone(),
+                email_verified: false,
+                age: event.age,
+                given_name: event.name.clone(),
+                middle_name: event.middle_name.clone(),
+                family_name: event.surname.clone(),
+            }
+        }
+
+        pub fn to_json(&self) -> JsonValue {
+            JsonValue::Object(vec![
+                ("id".to_string(), json_string(&self.id)),
+                ("email".to_string(), json_string(&self.email)),
+                (
+                    "email_verified".to_string(),
+                    JsonValue::Bool(self.email_verified),
+                ),
+                ("age".to_string(), JsonValue::Number(self.age.into())),
+                ("given_name".to_string(), json_string(&self.given_name)),
+                (
+                    "middle_name".to_string(),
+                    optional_json_string(self.middle_name.as_deref()),
+                ),
+                ("family_name".to_string(), json_string(&self.family_name)),
+            ])
+        }
+    }
+}
+
+fn tenant(request: &HttpRequest) -> TenantId {
+    request
+        .header(TENANT_HEADER)
+        .map_or_else(TenantId::default, |tenant| TenantId(tenant.to_string()))
+}
+
+/// Answers `GET ?cursor=...&limit=...` with a page of users rendered by
+/// `render` and the cursor of the next page.
+fn list_users(
+    users: &dyn UserRepository,
+    request: &HttpRequest,
+    render: fn(&User) -> JsonValue,
+) -> Result<HttpResponse> {
+    let cursor = request
+        .query("cursor")
+        .map(|cursor| Cursor::parse(cursor).map_err(|_| invalid("cursor", "is invalid")))
+        .transpose()?;
+    let limit = match request.query("limit") {
+        Some(limit) => limit
+            .parse()
+            .ok()
+            .filter(|limit| (1..=MAX_PAGE_SIZE).contains(limit))
+            .ok_or_else(|| invalid("limit", "must be between 1 and 100"))?,
+        None => DEFAULT_PAGE_SIZE,
+    };
+    let page = users.list_users(cursor.as_ref(), limit)?;
+    let body = JsonValue::Object(vec![
+        (
+            "users".to_string(),
+            JsonValue::Array(page.users.iter().map(render).collect()),
+        ),
+        (
+            "next_cursor".to_string(),
+            optional_json_string(page.next_cursor.map(|cursor| cursor.to_string()).as_deref()),
+        ),
+        ("has_more".to_string(), JsonValue::Bool(page.has_more)),
+    ]);
+    Ok(HttpResponse {
+        status: 200,
+        content_type: "application/json".to_string(),
+        body: body.to_string(),
+    })
+}
+
+fn json_body(request: &HttpRequest) -> Result<JsonValue> {
+    parse_json(&request.body).map_err(|_| invalid("body", "must be JSON").into())
+}
+
+/// Metadata of a command sent by `request`: its client address, tenant and
+/// idempotency key.
+fn command_metadata(request: &HttpRequest) -> CommandMetadata {
+    CommandMetadata {
+        source_ip: request.peer,
+        tenant_id: Some(tenant(request)),
+        idempotency_key: request
+            .header(IDEMPOTENCY_KEY_HEADER)
+            .map(|key| IdempotencyKey(key.to_string())),
+        ..CommandMetadata::default()
+    }
+}
+
+/// Dispatches `command` and answers `201 Created` with the registered user
+/// rendered by `render`. The answer is built from the registration event, so
+/// a retry under the same idempotency key, which gets that event back
+/// without creating or publishing anything, gets the same answer.
+fn register_user<R: UserRepository, P: EventPublisher, C: Clock>(
+    bus: &CommandBus<R, P, C>,
+    request: &HttpRequest,
+    command: CreateUser,
+    render: fn(&UserRegistered) -> JsonValue,
+) -> Result<HttpResponse> {
+    let events = bus.dispatch_with_metadata(
+        Command::CreateUser(command),
+        &command_metadata(request),
+        ExecutionMode::Commit,
+    )?;
+    let Some(DomainEvent::UserRegistered(registered)) = events.first() else {
+        return Err(Error::msg("CreateUser did not register a user"));
+    };
+    Ok(HttpResponse {
+        status: 201,
+        content_type: "application/json".to_string(),
+        body: render(registered).to_string(),
+    })
+}
+
+/// Adds `/v1/users` and `/v2/users`: `GET` lists the users of the request's
+/// [`TENANT_HEADER`] a page at a time, `POST` creates one in that tenant
+/// through `bus`, which publishes its registration. A `POST` sent again with
+/// the same [`IDEMPOTENCY_KEY_HEADER`] gets the first answer. `clock` dates
+/// the age of users created through v2, which send a date of birth.
+pub fn user_routes<R, P, K, C>(router: Router, bus: Arc<CommandBus<R, P, K>>, clock: C) -> Router
+where
+    R: UserRepository + Send + Sync + 'static,
+    P: EventPublisher + Send + Sync + 'static,
+    K: Clock + Send + Sync + 'static,
+    C: Clock + Send + Sync + 'static,
+{
+    let v1_create = Arc::clone(&bus);
+    let v2_users = Arc::clone(&bus);
+    let v2_create = Arc::clone(&bus);
+    router
+        .try_route("GET", "/v1/users", move |request| {
+            let users = TenantScopedRepository::new(bus.repository(), tenant(request));
+            list_users(&users, request, |user| {
+                v1::UserResponse::from_user(user).to_json()
+            })
+        })
+        .try_route("POST", "/v1/users", move |request| {
+            let command = v1::CreateUserRequest::from_json(&json_body(request)?)?.into_command();
+            register_user(&v1_create, request, command, |registered| {
+                v1::UserResponse::from_registered(registered).to_json()
+            })
+        })
+        .try_route("GET", "/v2/users", move |request| {
+            let users = TenantScopedRepository::new(v2_users.repository(), tenant(request));
+            list_users(&users, request, |user| {
+                v2::UserResponse::from_user(user).to_json()
+            })
+        })
+        .try_route("POST", "/v2/users", move |request| {
+            let today = Date::from_system_time(clock.now());
+            let command =
+                v2::CreateUserRequest::from_json(&json_body(request)?)?.into_command(today);
+            register_user(&v2_create, request, command, |registered| {
+                v2::UserResponse::from_registered(registered).to_json()
+            })
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::create_user;
+    use crate::events::InMemoryEventPublisher;
+    use crate::metrics::PrometheusMetrics;
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::{assert_debug_masks, assert_json_snapshot, UserFixture};
+    use crate::UserId;
+
+    #[test]
+    fn ok_v1_and_v2_requests_build_same_command() {
+        let v1 = parse_json(
+            r#"{"email":"foo@ok.com","age":22,"name":"Luca","surname":"Rossi","middle_name":null}"#,
+        )
+        .unwrap();
+        let v2 = parse_json(
+            r#"{"email":"foo@ok.com","date_of_birth":"2002-10-16","given_name":"Luca","family_name":"Rossi"}"#,
+        )
+        .unwrap();
+        let today = Date {
+            year: 2025,
+            month: 10,
+            day: 15,
+        };
+
+        let v1 = v1::CreateUserRequest::from_json(&v1)
+            .unwrap()
+            .into_command();
+        let v2 = v2::CreateUserRequest::from_json(&v2)
+            .unwrap()
+            .into_command(today);
+
+        assert_eq!(v1.age, 22);
+        assert_eq!(format!("{:?}", v1), format!("{:?}", v2));
+    }
+
+    #[test]
+    fn err_v2_request_with_age_instead_of_date_of_birth() {
+        let body = parse_json(
+            r#"{"email":"foo@ok.com","age":22,"given_name":"Luca","family_name":"Rossi"}"#,
+        )
+        .unwrap();
+
+        let result = v2::CreateUserRequest::from_json(&body);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Field date_of_birth is required");
+    }
+
+    #[test]
+    fn ok_list_users_follows_next_cursor() {
+        let users = Arc::new(InMemoryUserRepository::new());
+        for email in ["a@ok.com", "b@ok.com"] {
+            let user = create_user(
+                email.to_string(),
+                22,
+                "Luca".to_string(),
+                "Rossi".to_string(),
+                None,
+            )
+            .unwrap();
+            users.save(user).unwrap();
+        }
+        let router = routes(users);
+        let get = |path: &str| {
+            let response = router.handle(&HttpRequest {
+                method: "GET".to_string(),
+                path: path.to_string(),
+                headers: Vec::new(),
+                body: String::new(),
+                peer: None,
+            });
+            parse_json(&response.body).unwrap()
+        };
+
+        let first = get("/v1/users?limit=1");
+        let cursor = first
+            .get("next_cursor")
+            .and_then(JsonValue::as_str)
+            .unwrap();
+        let second = get(&format!("/v2/users?limit=1&cursor={}", cursor));
+        let invalid = get("/v1/users?limit=0");
+
+        assert_eq!(first.get("has_more"), Some(&JsonValue::Bool(true)));
+        assert_eq!(second.get("has_more"), Some(&JsonValue::Bool(false)));
+        assert_eq!(second.get("next_cursor"), Some(&JsonValue::Null));
+        assert_eq!(
+            invalid.get("detail").and_then(JsonValue::as_str),
+            Some("Field limit must be between 1 and 100")
+        );
+    }
+
+    fn routes(users: Arc<InMemoryUserRepository>) -> Router {
+        let bus = CommandBus::new(users, InMemoryEventPublisher::new());
+        user_routes(Router::new(), Arc::new(bus), SystemClock)
+    }
+
+    fn post(router: &Router, path: &str, tenant: &str, body: &str) -> HttpResponse {
+        router.handle(&HttpRequest {
+            method: "POST".to_string(),
+            path: path.to_string(),
+            headers: vec![(TENANT_HEADER.to_string(), tenant.to_string())],
+            body: body.to_string(),
+            peer: None,
+        })
+    }
+
+    #[test]
+    fn ok_create_users_through_v1_and_v2() {
+        let bus = Arc::new(CommandBus::new(
+            InMemoryUserRepository::new(),
+            InMemoryEventPublisher::new(),
+        ));
+        let router = user_routes(Router::new(), Arc::clone(&bus), SystemClock);
+
+        let v1 = post(
+            &router,
+            "/v1/users",
+            "acme",
+            r#"{"email":"luca@acme.com","age":22,"name":"Luca","surname":"Rossi"}"#,
+        );
+        let v2 = post(
+            &router,
+            "/v2/users",
+            "acme",
+            r#"{"email":"maria@acme.com","date_of_birth":"2000-01-01","given_name":"Maria","family_name":"Rossi"}"#,
+        );
+
+        assert_eq!(v1.status, 201);
+        assert_eq!(v2.status, 201);
+        let v1 = parse_json(&v1.body).unwrap();
+        let v2 = parse_json(&v2.body).unwrap();
+        assert_eq!(v1.get("age"), Some(&JsonValue::Number(22)));
+        let today = Date::from_system_time(SystemClock.now());
+        let born = Date {
+            year: 2000,
+            month: 1,
+            day: 1,
+        };
+        assert_eq!(
+            v2.get("age"),
+            Some(&JsonValue::Number(born.years_until(today).into()))
+        );
+        let Some(JsonValue::String(id)) = v2.get("id") else {
+            panic!("expected a string id, got {:?}", v2.get("id"));
+        };
+        let created = bus
+            .repository()
+            .find(UserId(id.parse().unwrap()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(created.tenant_id, TenantId("acme".to_string()));
+        assert_eq!(created.name, "Maria");
+        let registered: Vec<&str> = bus
+            .publisher()
+            .published()
+            .iter()
+            .map(DomainEvent::name)
+            .collect();
+        assert_eq!(registered, vec!["UserRegistered", "UserRegistered"]);
+    }
+
+    #[test]
+    fn ok_create_user_retried_with_idempotency_key() {
+        let bus = Arc::new(CommandBus::new(
+            InMemoryUserRepository::new(),
+            InMemoryEventPublisher::new(),
+        ));
+        let router = user_routes(Router::new(), Arc::clone(&bus), SystemClock);
+        let post = |body: &str| {
+            router.handle(&HttpRequest {
+                method: "POST".to_string(),
+                path: "/v1/users".to_string(),
+                headers: vec![
+                    (TENANT_HEADER.to_string(), "acme".to_string()),
+                    (IDEMPOTENCY_KEY_HEADER.to_string(), "request-1".to_string()),
+                ],
+                body: body.to_string(),
+                peer: None,
+            })
+        };
+        let body = r#"{"email":"luca@acme.com","age":22,"name":"Luca","surname":"Rossi"}"#;
+
+        let first = post(body);
+        let retry = post(body);
+        let reused =
+            post(r#"{"email":"maria@acme.com","age":22,"name":"Maria","surname":"Rossi"}"#);
+
+        assert_eq!(first.status, 201);
+        assert_eq!(retry.status, 201);
+        assert_eq!(retry.body, first.body);
+        assert_eq!(reused.status, 422);
+        assert_eq!(
+            parse_json(&reused.body)
+                .unwrap()
+                .get("code")
+                .and_then(JsonValue::as_str),
+            Some("idempotency_key_reused")
+        );
+        assert_eq!(bus.publisher().published().len(), 1);
+    }
+
+    #[test]
+    fn ok_created_and_rejected_users_counted_in_bus_metrics() {
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+            .with_metrics(metrics.clone());
+        let router = user_routes(Router::new(), Arc::new(bus), SystemClock);
+
+        post(
+            &router,
+            "/v1/users",
+            "acme",
+            r#"{"email":"luca@acme.com","age":22,"name":"Luca","surname":"Rossi"}"#,
+        );
+        post(
+            &router,
+            "/v1/users",
+            "acme",
+            r#"{"email":"kid@acme.com","age":5,"name":"Luca","surname":"Rossi"}"#,
+        );
+
+        assert_eq!(metrics.counter("users_created_total", &[]), 1);
+        assert_eq!(
+            metrics.counter("validation_errors_total", &[("code", "underage")]),
+            1
+        );
+    }
+
+    #[test]
+    fn err_create_user_rejected_as_problem() {
+        let users = Arc::new(InMemoryUserRepository::new());
+        let router = routes(users);
+        let body = r#"{"email":"luca@acme.com","age":22,"name":"Luca","surname":"Rossi"}"#;
+
+        assert_eq!(post(&router, "/v1/users", "acme", body).status, 201);
+        let duplicate = post(&router, "/v1/users", "acme", body);
+        let too_young = post(
+            &router,
+            "/v1/users",
+            "acme",
+            r#"{"email":"kid@acme.com","age":5,"name":"Luca","surname":"Rossi"}"#,
+        );
+        let malformed = post(&router, "/v2/users", "acme", "{");
+
+        assert_eq!(duplicate.status, 409);
+        assert_eq!(too_young.status, 422);
+        assert_eq!(malformed.status, 400);
+        assert_eq!(
+            parse_json(&malformed.body)
+                .unwrap()
+                .get("detail")
+                .and_then(JsonValue::as_str),
+            Some("Field body must be JSON")
+        );
+    }
+
+    #[test]
+    fn ok_list_users_scoped_by_tenant_header() {
+        let users = Arc::new(InMemoryUserRepository::new());
+        let mut acme = UserFixture::new().with_email("luca@acme.com").build();
+        acme.tenant_id = TenantId("acme".to_string());
+        let acme_id = acme.id;
+        users.save(acme).unwrap();
+        users
+            .save(UserFixture::new().with_email("luca@ok.com").build())
+            .unwrap();
+        let router = routes(users);
+        let ids = |headers: Vec<(String, String)>| {
+            let response = router.handle(&HttpRequest {
+                method: "GET".to_string(),
+                path: "/v2/users".to_string(),
+                headers,
+                body: String::new(),
+                peer: None,
+            });
+            let body = parse_json(&response.body).unwrap();
+            let Some(JsonValue::Array(users)) = body.get("users") else {
+                panic!("expected users, got {}", response.body);
+            };
+            users
+                .iter()
+                .map(|user| {
+                    user.get("id")
+                        .and_then(JsonValue::as_str)
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let acme = ids(vec![(TENANT_HEADER.to_string(), "acme".to_string())]);
+        let default = ids(Vec::new());
+
+        assert_eq!(acme, vec![acme_id.0.to_string()]);
+        assert_eq!(default.len(), 1);
+        assert_ne!(default, acme);
+    }
+
+    #[test]
+    fn ok_user_responses_match_snapshots() {
+        let mut user = UserFixture::verified()
+            .with_name("Luca", Some("Maria"), "Rossi")
+            .build();
+        user.id = UserId(7);
+
+        assert_json_snapshot(
+            "user_response_v1",
+            &v1::UserResponse::from_user(&user).to_json(),
+        );
+        assert_json_snapshot(
+            "user_response_v2",
+            &v2::UserResponse::from_user(&user).to_json(),
+        );
+    }
+
+    #[test]
+    fn ok_dtos_debug_masks_pii() {
+        let user = UserFixture::new()
+            .with_email("luca.rossi@ok.com")
+            .with_name("Luca", Some("Maria"), "Rossi")
+            .build();
+        let v1_request = v1::CreateUserRequest {
+            email: "luca.rossi@ok.com".to_string(),
+            age: 22,
+            name: "Luca".to_string(),
+            surname: "Rossi".to_string(),
+            middle_name: Some("Maria".to_string()),
+        };
+        let v2_request = v2::CreateUserRequest::from_json(&parse_json(
+            r#"{"email":"luca.rossi@ok.com","date_of_birth":"2001-04-12","given_name":"Luca","middle_name":"Maria","family_name":"Rossi"}"#,
+        ).unwrap())
+        .unwrap();
+
+        let pii = ["luca.rossi", "Luca", "Maria", "Rossi"];
+        assert_debug_masks(&v1_request, &pii);
+        assert_debug_masks(&v1::UserResponse::from_user(&user), &pii);
+        assert_debug_masks(&v2_request, &[&pii[..], &["2001"]].concat());
+        assert_debug_masks(&v2::UserResponse::from_user(&user), &pii);
+    }
+}
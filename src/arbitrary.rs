@@ -0,0 +1,165 @@
+//! Random inputs for property tests: a seeded generator, valid and invalid
+//! values for each value object, and [`check`] to run a property over many
+//! of them.
+
+use std::fmt::Debug;
+use std::ops::{Range, RangeInclusive};
+
+const WORD: [char; 10] = ['a', 'k', 'z', 'A', 'Q', '0', '7', '_', 'é', 'ß'];
+const LETTERS: [char; 10] = ['a', 'e', 'l', 'r', 'u', 'L', 'R', 'à', 'ö', 'ñ'];
+
+/// Xorshift generator; the same seed always yields the same values.
+pub struct Gen {
+    state: u64,
+}
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero.
+        Self {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    pub fn range(&mut self, range: RangeInclusive<i32>) -> i32 {
+        let span = (*range.end() as i64 - *range.start() as i64 + 1) as u64;
+        (*range.start() as i64 + (self.next_u64() % span) as i64) as i32
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    pub fn string(&mut self, alphabet: &[char], len: Range<usize>) -> String {
+        let len = len.start + self.below(len.end - len.start);
+        (0..len).map(|_| *self.pick(alphabet)).collect()
+    }
+}
+
+pub trait Arbitrary: Sized {
+    fn arbitrary(rng: &mut Gen) -> Self;
+}
+
+/// Runs `property` on `cases` generated values and panics with the first
+/// one it does not hold for, along with the seed that rebuilds it.
+pub fn check<T: Arbitrary + Debug>(cases: u64, property: impl Fn(&T) -> bool) {
+    for seed in 0..cases {
+        let value = T::arbitrary(&mut Gen::new(seed));
+        assert!(
+            property(&value),
+            "property failed for {:?} (seed {})",
+            value,
+            seed
+        );
+    }
+}
+
+/// Any string, built from characters parsers tend to trip over.
+#[derive(Debug)]
+pub struct AnyString(pub String);
+
+impl Arbitrary for AnyString {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        const ALPHABET: [char; 14] = [
+            'a',
+            'Z',
+            '0',
+            '_',
+            '.',
+            '@',
+            '-',
+            '+',
+            ' ',
+            '\n',
+            '\0',
+            'é',
+            'ß',
+            '\u{1F600}',
+        ];
+        Self(rng.string(&ALPHABET, 0..24))
+    }
+}
+
+/// Address `Email::try_from` accepts.
+#[derive(Debug)]
+pub struct ValidEmail(pub String);
+
+impl Arbitrary for ValidEmail {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        let mut local = rng.string(&WORD, 1..10);
+        if rng.below(2) == 0 {
+            local = format!("{}.{}", local, rng.string(&WORD, 1..6));
+        }
+        Self(format!(
+            "{}@{}.{}",
+            local,
+            rng.string(&WORD, 1..8),
+            rng.string(&LETTERS, 2..5)
+        ))
+    }
+}
+
+/// Address `Email::try_from` rejects: a valid one broken in one place.
+#[derive(Debug)]
+pub struct InvalidEmail(pub String);
+
+impl Arbitrary for InvalidEmail {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        let ValidEmail(email) = ValidEmail::arbitrary(rng);
+        Self(match rng.below(4) {
+            0 => email.replace('@', "."),
+            1 => email.replacen('@', "@@", 1),
+            2 => format!("{}.", email),
+            _ => format!(" {}", email),
+        })
+    }
+}
+
+/// Any age, mostly around the accepted range and sometimes far outside it.
+#[derive(Debug)]
+pub struct AnyAge(pub i32);
+
+impl Arbitrary for AnyAge {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        Self(match rng.below(4) {
+            0 => rng.next_u64() as i32,
+            _ => rng.range(-20..=150),
+        })
+    }
+}
+
+/// Name part without spaces, e.g. a first name or surname.
+#[derive(Debug)]
+pub struct NamePart(pub String);
+
+impl Arbitrary for NamePart {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        Self(rng.string(&LETTERS, 1..12))
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        match rng.below(2) {
+            0 => None,
+            _ => Some(T::arbitrary(rng)),
+        }
+    }
+}
+
+impl<A: Arbitrary, B: Arbitrary, C: Arbitrary> Arbitrary for (A, B, C) {
+    fn arbitrary(rng: &mut Gen) -> Self {
+        (A::arbitrary(rng), B::arbitrary(rng), C::arbitrary(rng))
+    }
+}
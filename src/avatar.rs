@@ -0,0 +1,175 @@
+use crate::error::DomainError;
+use crate::hash::{sha256, to_hex};
+use crate::{Email, User};
+use anyhow::Result;
+
+const MAX_AVATAR_SIZE: u64 = 2 * 1024 * 1024;
+const ALLOWED_CONTENT_TYPES: [&str; 3] = ["image/png", "image/jpeg", "image/webp"];
+
+/// Metadata the storage backend reports about an uploaded file.
+#[derive(Debug)]
+pub struct StoredImage {
+    pub content_type: String,
+    pub size: u64,
+}
+
+/// Port to whatever holds the uploaded avatar files (disk, object storage, ...).
+pub trait AvatarStorage {
+    /// Fails with [`DomainError::AvatarNotFound`] if no file has `key`.
+    fn describe(&self, key: &str) -> Result<StoredImage>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UploadedAvatar(pub String);
+
+#[derive(Debug, PartialEq)]
+pub enum Avatar {
+    Uploaded(UploadedAvatar),
+    Gravatar(String),
+}
+
+pub fn gravatar_url(email: &Email) -> String {
+    format!(
+        "https://www.gravatar.com/avatar/{}?d=identicon",
+        to_hex(&sha256(email.normalized().as_bytes()))
+    )
+}
+
+pub fn set_avatar(user: &mut User, storage: &impl AvatarStorage, key: String) -> Result<()> {
+    let image = storage.describe(&key)?;
+    if !ALLOWED_CONTENT_TYPES.contains(&image.content_type.as_str()) {
+        return Err(DomainError::UnsupportedAvatarType.into());
+    }
+    if image.size > MAX_AVATAR_SIZE {
+        return Err(DomainError::AvatarTooLarge.into());
+    }
+
+    user.avatar = Some(UploadedAvatar(key));
+    Ok(())
+}
+
+pub fn clear_avatar(user: &mut User) {
+    user.avatar = None;
+}
+
+pub fn get_avatar(user: &User) -> Avatar {
+    match &user.avatar {
+        Some(uploaded) => Avatar::Uploaded(uploaded.clone()),
+        None => Avatar::Gravatar(gravatar_url(user.email.address())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::UserFixture;
+    use std::collections::HashMap;
+
+    struct FakeStorage(HashMap<String, StoredImage>);
+
+    impl AvatarStorage for FakeStorage {
+        fn describe(&self, key: &str) -> Result<StoredImage> {
+            self.0
+                .get(key)
+                .map(|image| StoredImage {
+                    content_type: image.content_type.clone(),
+                    size: image.size,
+                })
+                .ok_or_else(|| DomainError::AvatarNotFound.into())
+        }
+    }
+
+    fn storage() -> FakeStorage {
+        let mut images = HashMap::new();
+        images.insert(
+            "luca.png".to_string(),
+            StoredImage {
+                content_type: "image/png".to_string(),
+                size: 1024,
+            },
+        );
+        images.insert(
+            "luca.gif".to_string(),
+            StoredImage {
+                content_type: "image/gif".to_string(),
+                size: 1024,
+            },
+        );
+        FakeStorage(images)
+    }
+
+    fn user() -> User {
+        UserFixture::new().with_email("Foo@ok.com").build()
+    }
+
+    #[test]
+    fn ok_set_and_clear_avatar() {
+        let mut user = user();
+
+        let result = set_avatar(&mut user, &storage(), "luca.png".to_string());
+        assert!(result.is_ok());
+        assert_eq!(
+            get_avatar(&user),
+            Avatar::Uploaded(UploadedAvatar("luca.png".to_string()))
+        );
+
+        clear_avatar(&mut user);
+        assert_eq!(
+            get_avatar(&user),
+            Avatar::Gravatar(gravatar_url(&Email("foo@ok.com".to_string())))
+        );
+    }
+
+    #[test]
+    fn err_avatar_content_type() {
+        let mut user = user();
+
+        let result = set_avatar(&mut user, &storage(), "luca.gif".to_string());
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Avatar must be a PNG, JPEG or WebP image"
+        );
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::UnsupportedAvatarType)
+        );
+        assert!(user.avatar.is_none());
+    }
+
+    #[test]
+    fn err_avatar_too_large() {
+        let mut user = user();
+        let mut storage = storage();
+        storage.0.insert(
+            "luca.webp".to_string(),
+            StoredImage {
+                content_type: "image/webp".to_string(),
+                size: MAX_AVATAR_SIZE + 1,
+            },
+        );
+
+        let result = set_avatar(&mut user, &storage, "luca.webp".to_string());
+
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<DomainError>(),
+            Some(&DomainError::AvatarTooLarge)
+        );
+        assert!(user.avatar.is_none());
+    }
+
+    #[test]
+    fn err_avatar_not_found() {
+        let mut user = user();
+
+        let result = set_avatar(&mut user, &storage(), "missing.png".to_string());
+
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<DomainError>(),
+            Some(&DomainError::AvatarNotFound)
+        );
+        assert!(user.avatar.is_none());
+    }
+}
@@ -0,0 +1,737 @@
+//! Avro encoding of domain events in the Confluent wire format: a zero magic
+//! byte, the big-endian id of the writer's schema in the registry, then the
+//! Avro binary record. Readers decode with whatever schema the writer used,
+//! so producers and consumers can be upgraded independently.
+
+use crate::events::{
+    DomainEvent, EmailVerified, MergedProfile, NameChanged, UserRegistered, UsersMerged,
+    VerificationThrottled,
+};
+use crate::json::{parse_json, JsonValue};
+use crate::UserId;
+use anyhow::{Error, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const MAGIC_BYTE: u8 = 0;
+const NAMESPACE: &str = "users.events";
+
+/// Port to a schema registry. Schemas are Avro schemas as JSON text.
+pub trait SchemaRegistry {
+    /// Registers `schema` under `subject` and returns its global id.
+    /// Registering a schema the registry already knows returns the same id.
+    fn register(&self, subject: &str, schema: &str) -> Result<u32>;
+
+    fn fetch(&self, id: u32) -> Result<String>;
+}
+
+fn not_found(id: u32) -> Error {
+    Error::msg(format!("Schema {} not found", id))
+}
+
+#[derive(Default)]
+pub struct InMemorySchemaRegistry {
+    /// Subject and schema, the id being the index plus one.
+    schemas: Mutex<Vec<(String, String)>>,
+}
+
+impl InMemorySchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SchemaRegistry for InMemorySchemaRegistry {
+    fn register(&self, subject: &str, schema: &str) -> Result<u32> {
+        let mut schemas = self.schemas.lock().unwrap();
+        let index = match schemas.iter().position(|(_, known)| known == schema) {
+            Some(index) => index,
+            None => {
+                schemas.push((subject.to_string(), schema.to_string()));
+                schemas.len() - 1
+            }
+        };
+        Ok(index as u32 + 1)
+    }
+
+    fn fetch(&self, id: u32) -> Result<String> {
+        let schemas = self.schemas.lock().unwrap();
+        id.checked_sub(1)
+            .and_then(|index| schemas.get(index as usize))
+            .map(|(_, schema)| schema.clone())
+            .ok_or_else(|| not_found(id))
+    }
+}
+
+/// Port to an HTTP client for the registry. Returns the status code and body.
+pub trait RegistryTransport {
+    fn get(&self, url: &str) -> Result<(u16, String)>;
+    fn post(&self, url: &str, content_type: &str, body: &str) -> Result<(u16, String)>;
+}
+
+/// Client of the Confluent Schema Registry REST API.
+pub struct HttpSchemaRegistry<T: RegistryTransport> {
+    transport: T,
+    address: String,
+}
+
+impl<T: RegistryTransport> HttpSchemaRegistry<T> {
+    pub fn new(transport: T, address: &str) -> Self {
+        Self {
+            transport,
+            address: address.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+fn unexpected_status(status: u16) -> Error {
+    Error::msg(format!("Schema registry responded with {}", status))
+}
+
+impl<T: RegistryTransport> SchemaRegistry for HttpSchemaRegistry<T> {
+    fn register(&self, subject: &str, schema: &str) -> Result<u32> {
+        let url = format!("{}/subjects/{}/versions", self.address, subject);
+        let body = JsonValue::Object(vec![(
+            "schema".to_string(),
+            JsonValue::String(schema.to_string()),
+        )]);
+        let (status, body) = self.transport.post(
+            &url,
+            "application/vnd.schemaregistry.v1+json",
+            &body.to_string(),
+        )?;
+        match status {
+            200 => {}
+            409 => {
+                return Err(Error::msg(format!(
+                    "Schema incompatible with subject {}",
+                    subject
+                )))
+            }
+            status => return Err(unexpected_status(status)),
+        }
+        parse_json(&body)?
+            .get("id")
+            .and_then(JsonValue::as_i64)
+            .and_then(|id| u32::try_from(id).ok())
+            .ok_or_else(|| Error::msg("Schema registry returned no id"))
+    }
+
+    fn fetch(&self, id: u32) -> Result<String> {
+        let (status, body) = self
+            .transport
+            .get(&format!("{}/schemas/ids/{}", self.address, id))?;
+        match status {
+            200 => {}
+            404 => return Err(not_found(id)),
+            status => return Err(unexpected_status(status)),
+        }
+        parse_json(&body)?
+            .get("schema")
+            .and_then(JsonValue::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| not_found(id))
+    }
+}
+
+/// Remembers registered and fetched schemas. Ids never change meaning, so
+/// entries are kept for good.
+pub struct CachingSchemaRegistry<R: SchemaRegistry> {
+    inner: R,
+    ids: Mutex<HashMap<(String, String), u32>>,
+    schemas: Mutex<HashMap<u32, String>>,
+}
+
+impl<R: SchemaRegistry> CachingSchemaRegistry<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            ids: Mutex::new(HashMap::new()),
+            schemas: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<R: SchemaRegistry> SchemaRegistry for CachingSchemaRegistry<R> {
+    fn register(&self, subject: &str, schema: &str) -> Result<u32> {
+        let key = (subject.to_string(), schema.to_string());
+        if let Some(id) = self.ids.lock().unwrap().get(&key) {
+            return Ok(*id);
+        }
+        let id = self.inner.register(subject, schema)?;
+        self.ids.lock().unwrap().insert(key, id);
+        self.schemas.lock().unwrap().insert(id, schema.to_string());
+        Ok(id)
+    }
+
+    fn fetch(&self, id: u32) -> Result<String> {
+        if let Some(schema) = self.schemas.lock().unwrap().get(&id) {
+            return Ok(schema.clone());
+        }
+        let schema = self.inner.fetch(id)?;
+        self.schemas.lock().unwrap().insert(id, schema.clone());
+        Ok(schema)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    Int,
+    Long,
+    String,
+    /// `["null", "string"]`, defaulting to null.
+    OptionalString,
+    /// Defaulting to false.
+    Boolean,
+    /// Array of strings, defaulting to empty.
+    Strings,
+}
+
+fn record_fields(event_name: &str) -> &'static [(&'static str, Type)] {
+    match event_name {
+        "UserRegistered" => &[
+            ("user_id", Type::Long),
+            ("email", Type::String),
+            ("name", Type::String),
+            ("middle_name", Type::OptionalString),
+            ("surname", Type::String),
+            ("age", Type::Int),
+        ],
+        "EmailVerified" => &[("user_id", Type::Long), ("email", Type::String)],
+        "NameChanged" => &[
+            ("user_id", Type::Long),
+            ("name", Type::String),
+            ("middle_name", Type::OptionalString),
+            ("surname", Type::String),
+        ],
+        "VerificationThrottled" => &[("user_id", Type::Long), ("attempts", Type::Int)],
+        // The merged profile is flattened; a null `email` means it is unknown.
+        "UsersMerged" => &[
+            ("primary_id", Type::Long),
+            ("duplicate_id", Type::Long),
+            ("email", Type::OptionalString),
+            ("email_verified", Type::Boolean),
+            ("middle_name", Type::OptionalString),
+            ("pronouns", Type::OptionalString),
+            ("avatar", Type::OptionalString),
+            ("tags", Type::Strings),
+        ],
+        _ => &[],
+    }
+}
+
+/// Avro record schema of the events named `event_name`, as currently written.
+pub fn event_schema(event_name: &str) -> JsonValue {
+    let string = |value: &str| JsonValue::String(value.to_string());
+    let fields = record_fields(event_name)
+        .iter()
+        .map(|(name, field_type)| {
+            let mut field = vec![("name".to_string(), string(name))];
+            match field_type {
+                Type::Int => field.push(("type".to_string(), string("int"))),
+                Type::Long => field.push(("type".to_string(), string("long"))),
+                Type::String => field.push(("type".to_string(), string("string"))),
+                Type::OptionalString => {
+                    field.push((
+                        "type".to_string(),
+                        JsonValue::Array(vec![string("null"), string("string")]),
+                    ));
+                    field.push(("default".to_string(), JsonValue::Null));
+                }
+                Type::Boolean => {
+                    field.push(("type".to_string(), string("boolean")));
+                    field.push(("default".to_string(), JsonValue::Bool(false)));
+                }
+                Type::Strings => {
+                    field.push((
+                        "type".to_string(),
+                        JsonValue::Object(vec![
+                            ("type".to_string(), string("array")),
+                            ("items".to_string(), string("string")),
+                        ]),
+                    ));
+                    field.push(("default".to_string(), JsonValue::Array(Vec::new())));
+                }
+            }
+            JsonValue::Object(field)
+        })
+        .collect();
+    JsonValue::Object(vec![
+        ("type".to_string(), string("record")),
+        ("name".to_string(), string(event_name)),
+        ("namespace".to_string(), string(NAMESPACE)),
+        ("fields".to_string(), JsonValue::Array(fields)),
+    ])
+}
+
+fn write_long(bytes: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        bytes.push(zigzag as u8 | 0x80);
+        zigzag >>= 7;
+    }
+    bytes.push(zigzag as u8);
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    write_long(bytes, value.len() as i64);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn write_optional_string(bytes: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        None => write_long(bytes, 0),
+        Some(value) => {
+            write_long(bytes, 1);
+            write_string(bytes, value);
+        }
+    }
+}
+
+fn write_bool(bytes: &mut Vec<u8>, value: bool) {
+    bytes.push(value.into());
+}
+
+/// One block holding every value, then the empty block ending the array.
+fn write_strings(bytes: &mut Vec<u8>, values: &[String]) {
+    if !values.is_empty() {
+        write_long(bytes, values.len() as i64);
+        for value in values {
+            write_string(bytes, value);
+        }
+    }
+    write_long(bytes, 0);
+}
+
+fn encode_record(event: &DomainEvent) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match event {
+        DomainEvent::UserRegistered(event) => {
+            write_long(&mut bytes, event.user_id.0 as i64);
+            write_string(&mut bytes, &event.email);
+            write_string(&mut bytes, &event.name);
+            write_optional_string(&mut bytes, event.middle_name.as_deref());
+            write_string(&mut bytes, &event.surname);
+            write_long(&mut bytes, event.age.into());
+        }
+        DomainEvent::EmailVerified(event) => {
+            write_long(&mut bytes, event.user_id.0 as i64);
+            write_string(&mut bytes, &event.email);
+        }
+        DomainEvent::NameChanged(event) => {
+            write_long(&mut bytes, event.user_id.0 as i64);
+            write_string(&mut bytes, &event.name);
+            write_optional_string(&mut bytes, event.middle_name.as_deref());
+            write_string(&mut bytes, &event.surname);
+        }
+        DomainEvent::VerificationThrottled(event) => {
+            write_long(&mut bytes, event.user_id.0 as i64);
+            write_long(&mut bytes, event.attempts.into());
+        }
+        DomainEvent::UsersMerged(event) => {
+            write_long(&mut bytes, event.primary_id.0 as i64);
+            write_long(&mut bytes, event.duplicate_id.0 as i64);
+            let merged = event.merged.as_ref();
+            write_optional_string(&mut bytes, merged.map(|merged| merged.email.as_str()));
+            write_bool(
+                &mut bytes,
+                merged.is_some_and(|merged| merged.email_verified),
+            );
+            for value in [
+                merged.and_then(|merged| merged.middle_name.as_deref()),
+                merged.and_then(|merged| merged.pronouns.as_deref()),
+                merged.and_then(|merged| merged.avatar.as_deref()),
+            ] {
+                write_optional_string(&mut bytes, value);
+            }
+            write_strings(&mut bytes, merged.map_or(&[], |merged| &merged.tags));
+        }
+    }
+    bytes
+}
+
+fn invalid() -> Error {
+    Error::msg("Invalid Avro message")
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn long(&mut self) -> Result<i64> {
+        let mut zigzag = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.bytes.split_first().ok_or_else(invalid)?;
+            self.bytes = rest;
+            zigzag |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+            }
+        }
+        Err(invalid())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = usize::try_from(self.long()?).map_err(|_| invalid())?;
+        if length > self.bytes.len() {
+            return Err(invalid());
+        }
+        let (value, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        String::from_utf8(value.to_vec()).map_err(|_| invalid())
+    }
+
+    /// Reads a value of the writer's `field_type`. Only the types event
+    /// schemas use are supported.
+    fn value(&mut self, field_type: &JsonValue) -> Result<Datum> {
+        match field_type {
+            JsonValue::String(name) if name == "int" || name == "long" => {
+                Ok(Datum::Long(self.long()?))
+            }
+            JsonValue::String(name) if name == "string" => Ok(Datum::String(self.string()?)),
+            JsonValue::String(name) if name == "boolean" => {
+                let (&byte, rest) = self.bytes.split_first().ok_or_else(invalid)?;
+                self.bytes = rest;
+                Ok(Datum::Bool(byte != 0))
+            }
+            JsonValue::Object(_)
+                if field_type.get("type").and_then(JsonValue::as_str) == Some("array") =>
+            {
+                let items = field_type.get("items").ok_or_else(invalid)?;
+                let mut values = Vec::new();
+                loop {
+                    let mut count = self.long()?;
+                    if count == 0 {
+                        return Ok(Datum::Array(values));
+                    }
+                    // A negative count is followed by the block's size in bytes.
+                    if count < 0 {
+                        count = -count;
+                        self.long()?;
+                    }
+                    for _ in 0..count {
+                        values.push(self.value(items)?);
+                    }
+                }
+            }
+            JsonValue::Array(branches) => {
+                let index = usize::try_from(self.long()?).map_err(|_| invalid())?;
+                match branches.get(index) {
+                    Some(JsonValue::String(name)) if name == "null" => Ok(Datum::Null),
+                    Some(branch) => self.value(branch),
+                    None => Err(invalid()),
+                }
+            }
+            other => Err(Error::msg(format!("Unsupported Avro type {}", other))),
+        }
+    }
+}
+
+enum Datum {
+    Null,
+    Long(i64),
+    String(String),
+    Bool(bool),
+    Array(Vec<Datum>),
+}
+
+/// Record decoded with the writer's schema, read by field name so fields
+/// the reader does not know are ignored and nullable ones may be missing.
+struct Record {
+    name: String,
+    values: HashMap<String, Datum>,
+}
+
+impl Record {
+    fn missing(&self, field: &str) -> Error {
+        Error::msg(format!("Avro record {} misses field {}", self.name, field))
+    }
+
+    fn long(&self, field: &str) -> Result<i64> {
+        match self.values.get(field) {
+            Some(Datum::Long(value)) => Ok(*value),
+            _ => Err(self.missing(field)),
+        }
+    }
+
+    fn id(&self, field: &str) -> Result<UserId> {
+        Ok(UserId(self.long(field)? as u64))
+    }
+
+    fn int<N: TryFrom<i64>>(&self, field: &str) -> Result<N> {
+        N::try_from(self.long(field)?).map_err(|_| invalid())
+    }
+
+    fn string(&self, field: &str) -> Result<String> {
+        match self.values.get(field) {
+            Some(Datum::String(value)) => Ok(value.clone()),
+            _ => Err(self.missing(field)),
+        }
+    }
+
+    fn optional_string(&self, field: &str) -> Result<Option<String>> {
+        match self.values.get(field) {
+            None | Some(Datum::Null) => Ok(None),
+            Some(_) => self.string(field).map(Some),
+        }
+    }
+
+    /// False when the writer's schema predates the field.
+    fn bool(&self, field: &str) -> Result<bool> {
+        match self.values.get(field) {
+            None => Ok(false),
+            Some(Datum::Bool(value)) => Ok(*value),
+            Some(_) => Err(self.missing(field)),
+        }
+    }
+
+    /// Empty when the writer's schema predates the field.
+    fn strings(&self, field: &str) -> Result<Vec<String>> {
+        match self.values.get(field) {
+            None => Ok(Vec::new()),
+            Some(Datum::Array(values)) => values
+                .iter()
+                .map(|value| match value {
+                    Datum::String(value) => Ok(value.clone()),
+                    _ => Err(self.missing(field)),
+                })
+                .collect(),
+            Some(_) => Err(self.missing(field)),
+        }
+    }
+
+    fn into_event(self) -> Result<DomainEvent> {
+        Ok(match self.name.as_str() {
+            "UserRegistered" => DomainEvent::UserRegistered(UserRegistered {
+                user_id: self.id("user_id")?,
+                email: self.string("email")?,
+                name: self.string("name")?,
+                middle_name: self.optional_string("middle_name")?,
+                surname: self.string("surname")?,
+                age: self.int("age")?,
+            }),
+            "EmailVerified" => DomainEvent::EmailVerified(EmailVerified {
+                user_id: self.id("user_id")?,
+                email: self.string("email")?,
+            }),
+            "NameChanged" => DomainEvent::NameChanged(NameChanged {
+                user_id: self.id("user_id")?,
+                name: self.string("name")?,
+                middle_name: self.optional_string("middle_name")?,
+                surname: self.string("surname")?,
+            }),
+            "VerificationThrottled" => DomainEvent::VerificationThrottled(VerificationThrottled {
+                user_id: self.id("user_id")?,
+                attempts: self.int("attempts")?,
+            }),
+            "UsersMerged" => DomainEvent::UsersMerged(UsersMerged {
+                primary_id: self.id("primary_id")?,
+                duplicate_id: self.id("duplicate_id")?,
+                merged: match self.optional_string("email")? {
+                    Some(email) => Some(MergedProfile {
+                        email,
+                        email_verified: self.bool("email_verified")?,
+                        middle_name: self.optional_string("middle_name")?,
+                        pronouns: self.optional_string("pronouns")?,
+                        avatar: self.optional_string("avatar")?,
+                        tags: self.strings("tags")?,
+                    }),
+                    None => None,
+                },
+            }),
+            name => return Err(Error::msg(format!("Unknown event type {}", name))),
+        })
+    }
+}
+
+fn decode_record(schema: &JsonValue, bytes: &[u8]) -> Result<Record> {
+    let invalid_schema = || Error::msg("Invalid Avro schema");
+    let name = schema
+        .get("name")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(invalid_schema)?;
+    let Some(JsonValue::Array(fields)) = schema.get("fields") else {
+        return Err(invalid_schema());
+    };
+    let mut reader = Reader { bytes };
+    let mut values = HashMap::new();
+    for field in fields {
+        let field_name = field
+            .get("name")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(invalid_schema)?;
+        let field_type = field.get("type").ok_or_else(invalid_schema)?;
+        values.insert(field_name.to_string(), reader.value(field_type)?);
+    }
+    Ok(Record {
+        name: name.to_string(),
+        values,
+    })
+}
+
+/// Serializes events for one topic, registering each event's schema under
+/// `<topic>-<EventName>` (Confluent's TopicRecordNameStrategy), so every
+/// event type of the topic evolves on its own.
+pub struct AvroSerializer<R: SchemaRegistry> {
+    registry: R,
+    topic: String,
+}
+
+impl<R: SchemaRegistry> AvroSerializer<R> {
+    pub fn new(registry: R, topic: &str) -> Self {
+        Self {
+            registry,
+            topic: topic.to_string(),
+        }
+    }
+
+    pub fn serialize(&self, event: &DomainEvent) -> Result<Vec<u8>> {
+        let subject = format!("{}-{}", self.topic, event.name());
+        let id = self
+            .registry
+            .register(&subject, &event_schema(event.name()).to_string())?;
+        let mut bytes = vec![MAGIC_BYTE];
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend(encode_record(event));
+        Ok(bytes)
+    }
+
+    /// Decodes a message written with any schema registered for the event,
+    /// older or newer than the one this build writes.
+    pub fn deserialize(&self, bytes: &[u8]) -> Result<DomainEvent> {
+        let [MAGIC_BYTE, a, b, c, d, record @ ..] = bytes else {
+            return Err(invalid());
+        };
+        let schema = parse_json(&self.registry.fetch(u32::from_be_bytes([*a, *b, *c, *d]))?)?;
+        decode_record(&schema, record)?.into_event()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registered() -> DomainEvent {
+        DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(300),
+            email: "foo@ok.com".to_string(),
+            name: "Luca".to_string(),
+            middle_name: Some("Maria".to_string()),
+            surname: "Rossi".to_string(),
+            age: 22,
+        })
+    }
+
+    #[test]
+    fn ok_round_trip_with_registered_schema() {
+        let serializer = AvroSerializer::new(InMemorySchemaRegistry::new(), "user-events");
+
+        let bytes = serializer.serialize(&registered()).unwrap();
+
+        assert_eq!(bytes[..5], [0, 0, 0, 0, 1]);
+        assert_eq!(serializer.deserialize(&bytes).unwrap(), registered());
+        assert_eq!(
+            serializer.serialize(&registered()).unwrap()[..5],
+            [0, 0, 0, 0, 1]
+        );
+    }
+
+    /// The producer's schema has no middle name and a field this build does
+    /// not know; the event still decodes.
+    #[test]
+    fn ok_decode_other_schema_version() {
+        let registry = InMemorySchemaRegistry::new();
+        let schema = r#"{"type":"record","name":"NameChanged","fields":[{"name":"user_id","type":"long"},{"name":"name","type":"string"},{"name":"surname","type":"string"},{"name":"locale","type":["null","string"]}]}"#;
+        let id = registry
+            .register("user-events-NameChanged", schema)
+            .unwrap();
+        let mut bytes = vec![0, 0, 0, 0, id as u8];
+        write_long(&mut bytes, 7);
+        write_string(&mut bytes, "Luca");
+        write_string(&mut bytes, "Rossi");
+        write_optional_string(&mut bytes, Some("it-IT"));
+
+        let event = AvroSerializer::new(registry, "user-events").deserialize(&bytes);
+
+        assert_eq!(
+            event.unwrap(),
+            DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(7),
+                name: "Luca".to_string(),
+                middle_name: None,
+                surname: "Rossi".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn ok_cached_schema_fetched_once() {
+        struct CountingRegistry {
+            inner: InMemorySchemaRegistry,
+            fetches: Mutex<u32>,
+        }
+
+        impl SchemaRegistry for &CountingRegistry {
+            fn register(&self, subject: &str, schema: &str) -> Result<u32> {
+                self.inner.register(subject, schema)
+            }
+
+            fn fetch(&self, id: u32) -> Result<String> {
+                *self.fetches.lock().unwrap() += 1;
+                self.inner.fetch(id)
+            }
+        }
+
+        let counting = CountingRegistry {
+            inner: InMemorySchemaRegistry::new(),
+            fetches: Mutex::new(0),
+        };
+        let id = counting
+            .inner
+            .register("user-events-UserRegistered", "{}")
+            .unwrap();
+        let registry = CachingSchemaRegistry::new(&counting);
+
+        registry.fetch(id).unwrap();
+        registry.fetch(id).unwrap();
+
+        assert_eq!(*counting.fetches.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn err_incompatible_schema_rejected_by_registry() {
+        struct RejectingTransport;
+
+        impl RegistryTransport for RejectingTransport {
+            fn get(&self, _url: &str) -> Result<(u16, String)> {
+                Ok((404, String::new()))
+            }
+
+            fn post(&self, url: &str, content_type: &str, body: &str) -> Result<(u16, String)> {
+                assert_eq!(
+                    url,
+                    "http://registry:8081/subjects/user-events-EmailVerified/versions"
+                );
+                assert_eq!(content_type, "application/vnd.schemaregistry.v1+json");
+                assert!(body.starts_with(r#"{"schema":"{\"type\":\"record\""#));
+                Ok((409, String::new()))
+            }
+        }
+
+        let registry = HttpSchemaRegistry::new(RejectingTransport, "http://registry:8081/");
+        let result = AvroSerializer::new(registry, "user-events").serialize(
+            &DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(1),
+                email: "foo@ok.com".to_string(),
+            }),
+        );
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Schema incompatible with subject user-events-EmailVerified"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::events::{DomainEvent, EventId, EventPublisher};
+use crate::health::HealthCheck;
+use crate::json::parse_json;
+use crate::protobuf::{decode_event, encode_event};
+use anyhow::{Error, Result};
+
+/// Encoding of message payloads. Consumers tell them apart by
+/// [`BrokerMessage::content_type`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    /// `EventEnvelope` from `proto/events.proto`.
+    Protobuf,
+}
+
+impl WireFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireFormat::Json => "application/json",
+            WireFormat::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Result<Self> {
+        match content_type {
+            "application/json" => Ok(WireFormat::Json),
+            "application/x-protobuf" => Ok(WireFormat::Protobuf),
+            other => Err(Error::msg(format!("Unsupported content type {}", other))),
+        }
+    }
+
+    fn encode(self, event: &DomainEvent) -> Vec<u8> {
+        match self {
+            WireFormat::Json => event.to_json().to_string().into_bytes(),
+            WireFormat::Protobuf => encode_event(event),
+        }
+    }
+
+    pub fn decode(self, payload: &[u8]) -> Result<DomainEvent> {
+        match self {
+            WireFormat::Json => {
+                let text = std::str::from_utf8(payload)
+                    .map_err(|_| Error::msg("Invalid event payload"))?;
+                DomainEvent::from_json(&parse_json(text)?)
+            }
+            WireFormat::Protobuf => decode_event(payload),
+        }
+    }
+}
+
+/// Message as handed to a broker client: the key decides the partition, so
+/// every event of one user lands on the same partition and stays ordered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrokerMessage {
+    /// Id of the outbox entry the message was relayed from, the same on every
+    /// redelivery, for consumers to deduplicate on. `None` when the event was
+    /// published without going through the outbox.
+    pub message_id: Option<EventId>,
+    pub topic: String,
+    pub key: String,
+    pub content_type: String,
+    pub payload: Vec<u8>,
+}
+
+/// Port implemented by broker clients (Kafka, NATS, AMQP, ...). An error means
+/// the broker did not acknowledge the message.
+pub trait MessageProducer {
+    fn send(&self, message: &BrokerMessage) -> Result<()>;
+
+    /// Fails when the client has lost its connection to the broker. Clients
+    /// that connect per message have nothing to report.
+    fn check_connection(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Publishes domain events as JSON, or protobuf when configured, keyed by
+/// user id. Used as the outbox relay's publisher, a failed delivery leaves
+/// the event pending.
+pub struct BrokerPublisher<P: MessageProducer> {
+    producer: P,
+    topic: String,
+    format: WireFormat,
+}
+
+impl<P: MessageProducer> BrokerPublisher<P> {
+    pub fn new(producer: P, topic: &str) -> Self {
+        Self {
+            producer,
+            topic: topic.to_string(),
+            format: WireFormat::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl<P: MessageProducer> BrokerPublisher<P> {
+    fn send(&self, message_id: Option<EventId>, event: &DomainEvent) -> Result<()> {
+        self.producer.send(&BrokerMessage {
+            message_id,
+            topic: self.topic.clone(),
+            key: event.user_id().0.to_string(),
+            content_type: self.format.content_type().to_string(),
+            payload: self.format.encode(event),
+        })
+    }
+}
+
+impl<P: MessageProducer> EventPublisher for BrokerPublisher<P> {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        self.send(None, event)
+    }
+
+    fn publish_with_id(&self, id: EventId, event: &DomainEvent) -> Result<()> {
+        self.send(Some(id), event)
+    }
+}
+
+impl<P: MessageProducer + Send + Sync> HealthCheck for BrokerPublisher<P> {
+    fn name(&self) -> &str {
+        "broker"
+    }
+
+    fn check(&self) -> Result<()> {
+        self.producer.check_connection()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::EmailVerified;
+    use crate::outbox::{InMemoryOutbox, Outbox, OutboxRelay};
+    use crate::UserId;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeProducer {
+        sent: Mutex<Vec<BrokerMessage>>,
+        reject_key: Option<String>,
+    }
+
+    impl MessageProducer for &FakeProducer {
+        fn send(&self, message: &BrokerMessage) -> Result<()> {
+            if self.reject_key.as_ref() == Some(&message.key) {
+                return Err(Error::msg("Delivery failed"));
+            }
+            self.sent.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    fn email_verified(user_id: u64) -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(user_id),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_publish_keyed_json_message() {
+        let producer = FakeProducer::default();
+        let publisher = BrokerPublisher::new(&producer, "user-events");
+
+        publisher.publish(&email_verified(42)).unwrap();
+
+        assert_eq!(
+            producer.sent.lock().unwrap()[0],
+            BrokerMessage {
+                message_id: None,
+                topic: "user-events".to_string(),
+                key: "42".to_string(),
+                content_type: "application/json".to_string(),
+                payload: br#"{"type":"EmailVerified","user_id":42,"email":"foo@ok.com"}"#.to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn ok_publish_protobuf_message() {
+        let producer = FakeProducer::default();
+        let publisher =
+            BrokerPublisher::new(&producer, "user-events").with_format(WireFormat::Protobuf);
+
+        publisher.publish(&email_verified(42)).unwrap();
+
+        let message = producer.sent.lock().unwrap()[0].clone();
+        assert_eq!(message.content_type, "application/x-protobuf");
+        assert_eq!(decode_event(&message.payload).unwrap(), email_verified(42));
+    }
+
+    #[test]
+    fn err_failed_delivery_stays_in_outbox() {
+        let producer = FakeProducer {
+            reject_key: Some("2".to_string()),
+            ..FakeProducer::default()
+        };
+        let publisher = BrokerPublisher::new(&producer, "user-events");
+        let outbox = InMemoryOutbox::new();
+        for user_id in [1, 2, 3] {
+            outbox.append(email_verified(user_id)).unwrap();
+        }
+
+        let result = OutboxRelay::new(&outbox, &publisher).relay();
+
+        assert!(result.is_err());
+        assert_eq!(producer.sent.lock().unwrap().len(), 1);
+        assert_eq!(outbox.pending(10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn ok_relayed_message_carries_outbox_id() {
+        let producer = FakeProducer::default();
+        let publisher = BrokerPublisher::new(&producer, "user-events");
+        let outbox = InMemoryOutbox::new();
+        let id = outbox.append(email_verified(42)).unwrap();
+
+        OutboxRelay::new(&outbox, &publisher).relay().unwrap();
+
+        let message = producer.sent.lock().unwrap()[0].clone();
+        assert_eq!(message.message_id, Some(id));
+        assert_eq!(
+            WireFormat::from_content_type(&message.content_type)
+                .unwrap()
+                .decode(&message.payload)
+                .unwrap(),
+            email_verified(42)
+        );
+    }
+}
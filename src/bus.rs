@@ -0,0 +1,753 @@
+use crate::clock::{Clock, SystemClock};
+use crate::commands::{
+    handle_change_name, handle_create_user, handle_verify_email, ChangeName, CreateUser,
+};
+use crate::config::AgeLimits;
+use crate::error::DomainError;
+use crate::events::{DomainEvent, EventPublisher, VerificationThrottled};
+use crate::feature_flags::{FeatureFlags, SELF_SERVICE_NAME_CHANGE};
+use crate::idempotency::{
+    run_idempotent, Fingerprint, IdempotencyKey, IdempotencyStore, InMemoryIdempotencyStore,
+};
+use crate::logging::{current_correlation_id, with_correlation_id, CorrelationId};
+use crate::merge::merge_users;
+use crate::metrics::Metrics;
+use crate::repository::{TenantScopedRepository, TracedRepository, UserRepository};
+use crate::throttle::{SignupThrottle, SignupThrottled, SlidingWindowLimiter};
+use crate::trace::{instrument, with_remote_parent, TraceContext};
+use crate::unit_of_work::{InMemoryUnitOfWork, UnitOfWork};
+use crate::{TenantId, UserId};
+use anyhow::{Error, Result};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    CreateUser(CreateUser),
+    VerifyEmail(UserId),
+    ChangeName(ChangeName),
+    MergeUsers {
+        primary_id: UserId,
+        duplicate_id: UserId,
+    },
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::CreateUser(_) => "CreateUser",
+            Command::VerifyEmail(_) => "VerifyEmail",
+            Command::ChangeName(_) => "ChangeName",
+            Command::MergeUsers { .. } => "MergeUsers",
+        }
+    }
+
+    /// The existing user the command acts on, if any.
+    pub fn user_id(&self) -> Option<UserId> {
+        match self {
+            Command::CreateUser(_) => None,
+            Command::VerifyEmail(user_id) => Some(*user_id),
+            Command::ChangeName(command) => Some(command.user_id),
+            Command::MergeUsers { primary_id, .. } => Some(*primary_id),
+        }
+    }
+
+    /// Fingerprint of the command and every field, so an idempotency key
+    /// reused for another command is told apart from a retry.
+    pub fn fingerprint(&self) -> Fingerprint {
+        match self {
+            Command::CreateUser(command) => command.fingerprint(),
+            Command::VerifyEmail(user_id) => Fingerprint::of([self.name(), &user_id.0.to_string()]),
+            Command::ChangeName(command) => Fingerprint::of([
+                self.name(),
+                &command.user_id.0.to_string(),
+                &command.name,
+                &command.surname,
+                command.middle_name.as_deref().unwrap_or(""),
+                if command.middle_name.is_some() {
+                    "1"
+                } else {
+                    "0"
+                },
+            ]),
+            Command::MergeUsers {
+                primary_id,
+                duplicate_id,
+            } => Fingerprint::of([
+                self.name(),
+                &primary_id.0.to_string(),
+                &duplicate_id.0.to_string(),
+            ]),
+        }
+    }
+}
+
+/// Facts about where a command came from, supplied by the adapter that
+/// received it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandMetadata {
+    pub source_ip: Option<IpAddr>,
+    /// When set, the command only sees and writes users of this tenant.
+    pub tenant_id: Option<TenantId>,
+    /// Trace the request was part of, e.g. from its `traceparent` header.
+    /// The command span joins it.
+    pub trace_context: Option<TraceContext>,
+    /// Id of the request the command belongs to, found on every log line and
+    /// event it produces. One is generated when neither the metadata nor the
+    /// calling request has one.
+    pub correlation_id: Option<CorrelationId>,
+    /// Client token identifying the command across retries, e.g. from an
+    /// `Idempotency-Key` header. A committed command sent again with the
+    /// same key, in the same tenant, returns its original events instead of
+    /// running again.
+    pub idempotency_key: Option<IdempotencyKey>,
+}
+
+impl CommandMetadata {
+    /// [`CommandMetadata::idempotency_key`] qualified by the tenant, so two
+    /// tenants using the same key do not see each other's outcome.
+    fn scoped_idempotency_key(&self) -> Option<IdempotencyKey> {
+        let key = self.idempotency_key.as_ref()?;
+        let tenant_id = self.tenant_id.clone().unwrap_or_default();
+        Some(IdempotencyKey(format!(
+            "{}:{}:{}",
+            tenant_id.0.len(),
+            tenant_id.0,
+            key.0
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutionMode {
+    Commit,
+    /// Run every validation and policy check and report the events that would
+    /// be emitted, but persist nothing.
+    DryRun,
+}
+
+pub struct CommandBus<R: UserRepository, P: EventPublisher, C: Clock = SystemClock> {
+    repository: R,
+    publisher: P,
+    verification_limiter: Option<SlidingWindowLimiter<UserId, C>>,
+    signup_throttle: Option<Box<dyn SignupThrottle + Send + Sync>>,
+    feature_flags: Option<Box<dyn FeatureFlags + Send + Sync>>,
+    metrics: Option<Arc<dyn Metrics>>,
+    age_limits: AgeLimits,
+    /// Outcome of every committed command sent with an idempotency key.
+    idempotency: Box<dyn IdempotencyStore<Vec<DomainEvent>> + Send + Sync>,
+}
+
+fn execute<C: Clock>(
+    unit_of_work: &mut impl UnitOfWork,
+    command: Command,
+    age_limits: &AgeLimits,
+    verification_limiter: Option<&SlidingWindowLimiter<UserId, C>>,
+    mode: ExecutionMode,
+) -> Result<Vec<DomainEvent>> {
+    let users = unit_of_work.users();
+    let event: DomainEvent = match command {
+        Command::CreateUser(command) => handle_create_user(users, age_limits, command)?.into(),
+        Command::VerifyEmail(user_id) => {
+            // A dry run reports throttling but does not use up an attempt.
+            let throttled = verification_limiter.filter(|limiter| match mode {
+                ExecutionMode::Commit => !limiter.try_acquire(user_id),
+                ExecutionMode::DryRun => !limiter.would_allow(&user_id),
+            });
+            match throttled {
+                Some(limiter) => VerificationThrottled {
+                    user_id,
+                    attempts: limiter.attempts(&user_id),
+                }
+                .into(),
+                None => handle_verify_email(users, user_id)?.into(),
+            }
+        }
+        Command::ChangeName(command) => handle_change_name(users, command)?.into(),
+        Command::MergeUsers {
+            primary_id,
+            duplicate_id,
+        } => merge_users(users, primary_id, duplicate_id)?.into(),
+    };
+    unit_of_work.record(event.clone());
+    Ok(vec![event])
+}
+
+fn record_metrics(metrics: &dyn Metrics, command: &str, result: &Result<Vec<DomainEvent>>) {
+    match (command, result) {
+        ("CreateUser", Ok(_)) => metrics.increment("users_created_total", &[]),
+        ("VerifyEmail", Ok(events)) => {
+            metrics.increment("verifications_attempted_total", &[]);
+            match events.first() {
+                Some(DomainEvent::EmailVerified(_)) => {
+                    metrics.increment("verifications_succeeded_total", &[])
+                }
+                _ => metrics.increment("verifications_failed_total", &[]),
+            }
+        }
+        ("VerifyEmail", Err(_)) => {
+            metrics.increment("verifications_attempted_total", &[]);
+            metrics.increment("verifications_failed_total", &[]);
+        }
+        _ => {}
+    }
+    if let Some(error) = result
+        .as_ref()
+        .err()
+        .and_then(|error| error.downcast_ref::<DomainError>())
+    {
+        metrics.increment("validation_errors_total", &[("code", error.code())]);
+    }
+}
+
+impl<R: UserRepository, P: EventPublisher> CommandBus<R, P> {
+    pub fn new(repository: R, publisher: P) -> Self {
+        Self {
+            repository,
+            publisher,
+            verification_limiter: None,
+            signup_throttle: None,
+            feature_flags: None,
+            metrics: None,
+            age_limits: AgeLimits::default(),
+            idempotency: Box::new(InMemoryIdempotencyStore::new()),
+        }
+    }
+
+    /// Limits `VerifyEmail` commands per user. Commands over the limit are
+    /// refused and recorded as [`VerificationThrottled`] instead.
+    pub fn with_verification_limiter<C: Clock>(
+        self,
+        limiter: SlidingWindowLimiter<UserId, C>,
+    ) -> CommandBus<R, P, C> {
+        CommandBus {
+            repository: self.repository,
+            publisher: self.publisher,
+            verification_limiter: Some(limiter),
+            signup_throttle: self.signup_throttle,
+            feature_flags: self.feature_flags,
+            metrics: self.metrics,
+            age_limits: self.age_limits,
+            idempotency: self.idempotency,
+        }
+    }
+}
+
+impl<R: UserRepository, P: EventPublisher, C: Clock> CommandBus<R, P, C> {
+    /// Bounds `CreateUser` commands per source address. Commands without a
+    /// source address are not throttled.
+    pub fn with_signup_throttle(
+        mut self,
+        throttle: impl SignupThrottle + Send + Sync + 'static,
+    ) -> Self {
+        self.signup_throttle = Some(Box::new(throttle));
+        self
+    }
+
+    /// Limits `CreateUser` checks ages against, the defaults until set.
+    pub fn with_age_limits(mut self, age_limits: AgeLimits) -> Self {
+        self.age_limits = age_limits;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Gates flagged commands, such as `ChangeName` behind
+    /// [`SELF_SERVICE_NAME_CHANGE`]. Without flags nothing is gated.
+    pub fn with_feature_flags(mut self, flags: impl FeatureFlags + Send + Sync + 'static) -> Self {
+        self.feature_flags = Some(Box::new(flags));
+        self
+    }
+
+    /// Where outcomes of keyed commands are kept, in memory until set.
+    pub fn with_idempotency_store(
+        mut self,
+        store: impl IdempotencyStore<Vec<DomainEvent>> + Send + Sync + 'static,
+    ) -> Self {
+        self.idempotency = Box::new(store);
+        self
+    }
+
+    pub fn repository(&self) -> &R {
+        &self.repository
+    }
+
+    pub fn publisher(&self) -> &P {
+        &self.publisher
+    }
+
+    /// Runs `command` in its own unit of work. Nothing is saved or published
+    /// if the command fails, or at all in [`ExecutionMode::DryRun`].
+    pub fn dispatch(&self, command: Command, mode: ExecutionMode) -> Result<Vec<DomainEvent>> {
+        self.dispatch_with_metadata(command, &CommandMetadata::default(), mode)
+    }
+
+    /// Like [`CommandBus::dispatch`], in a `command` span carrying the command
+    /// name, mode, tenant and user id. A committed command with an
+    /// idempotency key runs through [`run_idempotent`]; a dry run never
+    /// uses or claims the key.
+    pub fn dispatch_with_metadata(
+        &self,
+        command: Command,
+        metadata: &CommandMetadata,
+        mode: ExecutionMode,
+    ) -> Result<Vec<DomainEvent>> {
+        let mut fields = vec![
+            ("command", command.name().to_string()),
+            ("mode", format!("{:?}", mode)),
+        ];
+        if let Some(tenant_id) = &metadata.tenant_id {
+            fields.push(("tenant_id", tenant_id.0.clone()));
+        }
+        let user_id = command.user_id();
+        if let Some(user_id) = user_id {
+            fields.push(("user_id", user_id.0.to_string()));
+        }
+        let name = command.name();
+        let fingerprint = command.fingerprint();
+        let idempotency_key = match mode {
+            ExecutionMode::Commit => metadata.scoped_idempotency_key(),
+            ExecutionMode::DryRun => None,
+        };
+        let started = Instant::now();
+        let run = || {
+            instrument("command", fields, |span| {
+                let events = run_idempotent(
+                    &*self.idempotency,
+                    idempotency_key.as_ref(),
+                    fingerprint,
+                    || self.check_and_run(command, metadata, mode),
+                )?;
+                if let (None, Some(event)) = (user_id, events.first()) {
+                    span.record("user_id", event.user_id().0);
+                }
+                Ok(events)
+            })
+        };
+        let correlation_id = metadata
+            .correlation_id
+            .clone()
+            .or_else(current_correlation_id)
+            .unwrap_or_else(CorrelationId::generate);
+        let result = with_correlation_id(correlation_id, || match metadata.trace_context {
+            Some(parent) => with_remote_parent(parent, run),
+            None => run(),
+        });
+        if let Some(metrics) = &self.metrics {
+            record_metrics(metrics.as_ref(), name, &result);
+            metrics.observe(
+                "command_duration_seconds",
+                &[("command", name)],
+                started.elapsed().as_secs_f64(),
+            );
+        }
+        result
+    }
+
+    fn check_and_run(
+        &self,
+        command: Command,
+        metadata: &CommandMetadata,
+        mode: ExecutionMode,
+    ) -> Result<Vec<DomainEvent>> {
+        if let (Command::CreateUser(_), Some(throttle), Some(source_ip)) =
+            (&command, &self.signup_throttle, metadata.source_ip)
+        {
+            let allowed = match mode {
+                ExecutionMode::Commit => throttle.try_acquire(source_ip)?,
+                ExecutionMode::DryRun => throttle.would_allow(source_ip)?,
+            };
+            if !allowed {
+                return Err(SignupThrottled.into());
+            }
+        }
+        if let (Command::ChangeName(change), Some(flags)) = (&command, &self.feature_flags) {
+            let tenant_id = metadata.tenant_id.clone().unwrap_or_default();
+            if !flags.is_enabled(SELF_SERVICE_NAME_CHANGE, &tenant_id, Some(change.user_id))? {
+                return Err(Error::msg(format!(
+                    "Feature {} is disabled",
+                    SELF_SERVICE_NAME_CHANGE
+                )));
+            }
+        }
+        match &metadata.tenant_id {
+            Some(tenant_id) => self.run(
+                &TenantScopedRepository::new(&self.repository, tenant_id.clone()),
+                command,
+                mode,
+            ),
+            None => self.run(&self.repository, command, mode),
+        }
+    }
+
+    fn run(
+        &self,
+        repository: &impl UserRepository,
+        command: Command,
+        mode: ExecutionMode,
+    ) -> Result<Vec<DomainEvent>> {
+        let repository = TracedRepository::new(repository);
+        let mut unit_of_work = InMemoryUnitOfWork::begin(&repository, &self.publisher);
+        let events = execute(
+            &mut unit_of_work,
+            command,
+            &self.age_limits,
+            self.verification_limiter.as_ref(),
+            mode,
+        )?;
+        match mode {
+            ExecutionMode::Commit => unit_of_work.commit()?,
+            ExecutionMode::DryRun => unit_of_work.rollback(),
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{EmailVerified, InMemoryEventPublisher, UserRegistered};
+    use crate::feature_flags::{FlagRule, InMemoryFeatureFlags};
+    use crate::idempotency::IdempotencyError;
+    use crate::metrics::PrometheusMetrics;
+    use crate::repository::InMemoryUserRepository;
+    use crate::trace::{with_subscriber, Outcome, RecordingSubscriber};
+    use crate::UserEmail;
+    use std::time::Duration;
+
+    fn create_user_command(email: &str) -> Command {
+        Command::CreateUser(CreateUser {
+            email: email.to_string(),
+            age: 22,
+            name: "Luca".to_string(),
+            surname: "Rossi".to_string(),
+            middle_name: None,
+        })
+    }
+
+    #[test]
+    fn ok_dispatch_dry_run_persists_nothing() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+        let events = bus
+            .dispatch(create_user_command("foo@ok.com"), ExecutionMode::Commit)
+            .unwrap();
+        let DomainEvent::UserRegistered(UserRegistered { user_id, .. }) = events[0] else {
+            panic!("expected UserRegistered, got {:?}", events);
+        };
+
+        let events = bus
+            .dispatch(Command::VerifyEmail(user_id), ExecutionMode::DryRun)
+            .unwrap();
+
+        assert_eq!(
+            events,
+            vec![DomainEvent::EmailVerified(EmailVerified {
+                user_id,
+                email: "foo@ok.com".to_string()
+            })]
+        );
+        let user = bus.repository().find(user_id).unwrap().unwrap();
+        assert!(matches!(user.email, UserEmail::UnverifiedEmail(_)));
+        assert_eq!(bus.publisher().published().len(), 1);
+    }
+
+    #[test]
+    fn ok_merge_into_older_user_takes_verified_email() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+        let primary = bus
+            .dispatch(create_user_command("luca@old.com"), ExecutionMode::Commit)
+            .unwrap()[0]
+            .user_id();
+        let duplicate = bus
+            .dispatch(create_user_command("luca@ok.com"), ExecutionMode::Commit)
+            .unwrap()[0]
+            .user_id();
+        bus.dispatch(Command::VerifyEmail(duplicate), ExecutionMode::Commit)
+            .unwrap();
+        assert!(primary < duplicate);
+
+        bus.dispatch(
+            Command::MergeUsers {
+                primary_id: primary,
+                duplicate_id: duplicate,
+            },
+            ExecutionMode::Commit,
+        )
+        .unwrap();
+
+        let primary = bus.repository().find(primary).unwrap().unwrap();
+        assert_eq!(primary.email.address().as_str(), "luca@ok.com");
+        assert!(primary.is_email_verified());
+    }
+
+    #[test]
+    fn ok_verification_attempts_are_throttled() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+            .with_verification_limiter(SlidingWindowLimiter::new(
+                2,
+                Duration::from_secs(600),
+                SystemClock,
+            ));
+        let unknown = UserId(999);
+
+        for _ in 0..2 {
+            let result = bus.dispatch(Command::VerifyEmail(unknown), ExecutionMode::Commit);
+            assert_eq!(result.unwrap_err().to_string(), "User not found");
+        }
+        let events = bus
+            .dispatch(Command::VerifyEmail(unknown), ExecutionMode::Commit)
+            .unwrap();
+
+        assert_eq!(
+            events,
+            vec![DomainEvent::VerificationThrottled(VerificationThrottled {
+                user_id: unknown,
+                attempts: 2
+            })]
+        );
+        assert_eq!(bus.publisher().published(), events);
+    }
+
+    #[test]
+    fn err_signups_throttled_per_source_ip() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+            .with_signup_throttle(SlidingWindowLimiter::new(
+                1,
+                Duration::from_secs(3600),
+                SystemClock,
+            ));
+        let metadata = CommandMetadata {
+            source_ip: Some(IpAddr::from([203, 0, 113, 7])),
+            ..CommandMetadata::default()
+        };
+        bus.dispatch_with_metadata(
+            create_user_command("foo@ok.com"),
+            &metadata,
+            ExecutionMode::Commit,
+        )
+        .unwrap();
+
+        let result = bus.dispatch_with_metadata(
+            create_user_command("bar@ok.com"),
+            &metadata,
+            ExecutionMode::Commit,
+        );
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Too many signups from this address");
+        bus.dispatch(create_user_command("baz@ok.com"), ExecutionMode::Commit)
+            .unwrap();
+    }
+
+    #[test]
+    fn ok_keyed_command_replayed_without_running_again() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+        let keyed = |tenant: &str| CommandMetadata {
+            tenant_id: Some(TenantId(tenant.to_string())),
+            idempotency_key: Some(IdempotencyKey("request-1".to_string())),
+            ..CommandMetadata::default()
+        };
+        let dispatch = |metadata: &CommandMetadata| {
+            bus.dispatch_with_metadata(
+                create_user_command("foo@ok.com"),
+                metadata,
+                ExecutionMode::Commit,
+            )
+        };
+
+        let first = dispatch(&keyed("acme")).unwrap();
+        let replay = dispatch(&keyed("acme")).unwrap();
+        let other_tenant = dispatch(&keyed("globex")).unwrap();
+
+        assert_eq!(first, replay);
+        assert_ne!(first[0].user_id(), other_tenant[0].user_id());
+        assert_eq!(bus.publisher().published().len(), 2);
+    }
+
+    #[test]
+    fn err_idempotency_key_reused_for_another_command() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+        let metadata = CommandMetadata {
+            idempotency_key: Some(IdempotencyKey("request-1".to_string())),
+            ..CommandMetadata::default()
+        };
+        bus.dispatch_with_metadata(
+            create_user_command("foo@ok.com"),
+            &metadata,
+            ExecutionMode::Commit,
+        )
+        .unwrap();
+
+        let error = bus
+            .dispatch_with_metadata(
+                create_user_command("bar@ok.com"),
+                &metadata,
+                ExecutionMode::Commit,
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<IdempotencyError>(),
+            Some(&IdempotencyError::KeyReused)
+        );
+        assert_eq!(bus.publisher().published().len(), 1);
+    }
+
+    #[test]
+    fn err_tenant_cannot_reach_other_tenants_users() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+        let acme = CommandMetadata {
+            tenant_id: Some(TenantId("acme".to_string())),
+            ..CommandMetadata::default()
+        };
+        let globex = CommandMetadata {
+            tenant_id: Some(TenantId("globex".to_string())),
+            ..CommandMetadata::default()
+        };
+        let events = bus
+            .dispatch_with_metadata(
+                create_user_command("foo@ok.com"),
+                &acme,
+                ExecutionMode::Commit,
+            )
+            .unwrap();
+        let user_id = events[0].user_id();
+
+        let result = bus.dispatch_with_metadata(
+            Command::VerifyEmail(user_id),
+            &globex,
+            ExecutionMode::Commit,
+        );
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "User not found");
+        let user = bus.repository().find(user_id).unwrap().unwrap();
+        assert_eq!(user.tenant_id, TenantId("acme".to_string()));
+    }
+
+    #[test]
+    fn err_name_change_disabled_by_feature_flag() {
+        let flags = InMemoryFeatureFlags::new();
+        flags.set(
+            SELF_SERVICE_NAME_CHANGE,
+            FlagRule {
+                users: [UserId(1)].into(),
+                ..FlagRule::default()
+            },
+        );
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+            .with_feature_flags(flags);
+
+        let result = bus.dispatch(
+            Command::ChangeName(ChangeName {
+                user_id: UserId(2),
+                name: "Anna".to_string(),
+                middle_name: None,
+                surname: "Bianchi".to_string(),
+            }),
+            ExecutionMode::Commit,
+        );
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Feature self_service_name_change is disabled"
+        );
+    }
+
+    #[test]
+    fn ok_dispatch_is_traced() {
+        let recorder = Arc::new(RecordingSubscriber::new());
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+
+        let events = with_subscriber(recorder.clone(), || {
+            bus.dispatch(create_user_command("foo@ok.com"), ExecutionMode::Commit)
+        })
+        .unwrap();
+
+        let commands = recorder.named("command");
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].field("command"), Some("CreateUser"));
+        let user_id = events[0].user_id().0.to_string();
+        assert_eq!(commands[0].field("user_id"), Some(user_id.as_str()));
+        assert_eq!(commands[0].outcome, Outcome::Ok);
+        let saves = recorder.named("repository.save_all");
+        assert_eq!(saves[0].parent, Some(commands[0].id));
+        assert_eq!(saves[0].field("count"), Some("1"));
+    }
+
+    #[test]
+    fn ok_dispatch_records_metrics() {
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+            .with_metrics(metrics.clone());
+
+        bus.dispatch(create_user_command("foo@ok.com"), ExecutionMode::Commit)
+            .unwrap();
+        let _ = bus.dispatch(create_user_command("foo.at.com"), ExecutionMode::Commit);
+        let _ = bus.dispatch(Command::VerifyEmail(UserId(0)), ExecutionMode::Commit);
+
+        assert_eq!(metrics.counter("users_created_total", &[]), 1);
+        assert_eq!(
+            metrics.counter("validation_errors_total", &[("code", "invalid_email")]),
+            1
+        );
+        assert_eq!(metrics.counter("verifications_failed_total", &[]), 1);
+        assert!(metrics
+            .render()
+            .contains("command_duration_seconds_count{command=\"CreateUser\"} 2"));
+    }
+
+    #[test]
+    fn err_dispatch_dry_run_reports_validation_errors() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+
+        let result = bus.dispatch(create_user_command("foo.at.com"), ExecutionMode::DryRun);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid email");
+    }
+
+    #[test]
+    fn err_dispatch_dry_run_fails_where_commit_fails() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new());
+        bus.dispatch(create_user_command("foo@ok.com"), ExecutionMode::Commit)
+            .unwrap();
+
+        for mode in [ExecutionMode::DryRun, ExecutionMode::Commit] {
+            let error = bus
+                .dispatch(create_user_command("FOO@ok.com"), mode)
+                .unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<DomainError>(),
+                Some(&DomainError::EmailAlreadyRegistered),
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn err_create_user_checks_configured_age_limits() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+            .with_age_limits(AgeLimits { min: 25, max: 120 });
+
+        let error = bus
+            .dispatch(create_user_command("foo@ok.com"), ExecutionMode::Commit)
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::Underage { min_age: 25 })
+        );
+        assert!(bus.publisher().published().is_empty());
+    }
+}
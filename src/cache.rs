@@ -0,0 +1,507 @@
+//! Cache-aside layer keeping serialized users in Redis in front of any
+//! repository.
+
+use crate::avatar::UploadedAvatar;
+use crate::custom_attributes::{AttributeValue, CustomAttributes};
+use crate::date::check_date;
+use crate::events::{DomainEvent, EventHandler};
+use crate::hash::{sha256, to_hex};
+use crate::json::{parse_json, JsonValue};
+use crate::metrics::Metrics;
+use crate::pronouns::check_pronouns;
+use crate::query::UserQuery;
+use crate::repository::{Cursor, UserPage, UserRepository};
+use crate::tags::{Tag, TagFilter};
+use crate::{Age, Email, TenantId, UnverifiedEmail, User, UserEmail, UserId, VerifiedEmail};
+use anyhow::{Error, Result};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The slice of a Redis client the cache needs.
+pub trait RedisKeyValue {
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    /// `SET key value PX ttl`.
+    fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()>;
+    fn del(&self, key: &str) -> Result<()>;
+}
+
+fn user_key(id: UserId) -> String {
+    format!("user:id:{}", id)
+}
+
+/// Hashed, so cache keys do not spell out addresses. Emails are unique per
+/// tenant, so the tenant is part of the key.
+fn email_key(tenant_id: &TenantId, email: &Email) -> String {
+    format!(
+        "user:email:{}:{}",
+        tenant_id.0,
+        to_hex(&sha256(email.normalized().as_bytes()))
+    )
+}
+
+fn string(value: &str) -> JsonValue {
+    JsonValue::String(value.to_string())
+}
+
+fn attribute_json(value: &AttributeValue) -> JsonValue {
+    let (kind, value) = match value {
+        AttributeValue::String(value) => ("string", string(value)),
+        // Kept as text, as `JsonValue` numbers are integers.
+        AttributeValue::Number(value) => ("number", string(&value.to_string())),
+        AttributeValue::Bool(value) => ("bool", JsonValue::Bool(*value)),
+        AttributeValue::Date(value) => ("date", string(&value.to_string())),
+    };
+    JsonValue::Object(vec![
+        ("kind".to_string(), string(kind)),
+        ("value".to_string(), value),
+    ])
+}
+
+fn user_json(user: &User) -> JsonValue {
+    let registered_at = user
+        .registered_at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let optional = |value: Option<String>| value.map_or(JsonValue::Null, |value| string(&value));
+    JsonValue::Object(vec![
+        ("id".to_string(), JsonValue::Number(user.id.0 as i64)),
+        ("tenant_id".to_string(), string(&user.tenant_id.0)),
+        ("name".to_string(), string(&user.name)),
+        (
+            "middle_name".to_string(),
+            optional(user.middle_name.clone()),
+        ),
+        ("surname".to_string(), string(&user.surname)),
+        ("age".to_string(), JsonValue::Number(user.age.0.into())),
+        ("email".to_string(), string(&user.email.address().0)),
+        (
+            "email_verified".to_string(),
+            JsonValue::Bool(user.is_email_verified()),
+        ),
+        (
+            "pronouns".to_string(),
+            optional(user.pronouns.as_ref().map(ToString::to_string)),
+        ),
+        (
+            "avatar".to_string(),
+            optional(user.avatar.as_ref().map(|avatar| avatar.0.clone())),
+        ),
+        (
+            "custom_attributes".to_string(),
+            JsonValue::Object(
+                user.custom_attributes
+                    .0
+                    .iter()
+                    .map(|(name, value)| (name.clone(), attribute_json(value)))
+                    .collect(),
+            ),
+        ),
+        (
+            "tags".to_string(),
+            JsonValue::Array(user.tags.iter().map(|tag| string(&tag.0)).collect()),
+        ),
+        (
+            "merged_into".to_string(),
+            user.merged_into
+                .map_or(JsonValue::Null, |id| JsonValue::Number(id.0 as i64)),
+        ),
+        (
+            "registered_at".to_string(),
+            JsonValue::Number(registered_at as i64),
+        ),
+        (
+            "version".to_string(),
+            JsonValue::Number(user.version as i64),
+        ),
+    ])
+}
+
+fn invalid() -> Error {
+    Error::msg("Invalid cached user")
+}
+
+fn text(value: &JsonValue, field: &str) -> Result<String> {
+    value
+        .get(field)
+        .and_then(JsonValue::as_str)
+        .map(str::to_string)
+        .ok_or_else(invalid)
+}
+
+fn optional_text(value: &JsonValue, field: &str) -> Result<Option<String>> {
+    match value.get(field) {
+        Some(JsonValue::Null) => Ok(None),
+        _ => text(value, field).map(Some),
+    }
+}
+
+fn number(value: &JsonValue, field: &str) -> Result<i64> {
+    value
+        .get(field)
+        .and_then(JsonValue::as_i64)
+        .ok_or_else(invalid)
+}
+
+fn attribute_value(value: &JsonValue) -> Result<AttributeValue> {
+    let kind = text(value, "kind")?;
+    match (kind.as_str(), value.get("value")) {
+        ("bool", Some(JsonValue::Bool(value))) => Ok(AttributeValue::Bool(*value)),
+        ("string", _) => text(value, "value").map(AttributeValue::String),
+        ("number", _) => Ok(AttributeValue::Number(
+            text(value, "value")?.parse().map_err(|_| invalid())?,
+        )),
+        ("date", _) => Ok(AttributeValue::Date(check_date(text(value, "value")?)?)),
+        _ => Err(invalid()),
+    }
+}
+
+fn user_from_json(value: &JsonValue) -> Result<User> {
+    let email = Email(text(value, "email")?);
+    let email = match value.get("email_verified") {
+        Some(JsonValue::Bool(true)) => UserEmail::VerifiedEmail(VerifiedEmail(email)),
+        Some(JsonValue::Bool(false)) => UserEmail::UnverifiedEmail(UnverifiedEmail(email)),
+        _ => return Err(invalid()),
+    };
+    let custom_attributes = match value.get("custom_attributes") {
+        Some(JsonValue::Object(fields)) => fields
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), attribute_value(value)?)))
+            .collect::<Result<_>>()?,
+        _ => return Err(invalid()),
+    };
+    let tags = match value.get("tags") {
+        Some(JsonValue::Array(tags)) => tags
+            .iter()
+            .map(|tag| tag.as_str().map(|tag| Tag(tag.to_string())))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?,
+        _ => return Err(invalid()),
+    };
+    let merged_into = match value.get("merged_into") {
+        Some(JsonValue::Null) => None,
+        _ => Some(UserId(number(value, "merged_into")? as u64)),
+    };
+    Ok(User {
+        id: UserId(number(value, "id")? as u64),
+        tenant_id: TenantId(text(value, "tenant_id")?),
+        name: text(value, "name")?,
+        middle_name: optional_text(value, "middle_name")?,
+        surname: text(value, "surname")?,
+        age: Age(number(value, "age")? as i32),
+        email,
+        pronouns: optional_text(value, "pronouns")?
+            .map(check_pronouns)
+            .transpose()?,
+        avatar: optional_text(value, "avatar")?.map(UploadedAvatar),
+        custom_attributes: CustomAttributes(custom_attributes),
+        tags,
+        merged_into,
+        registered_at: SystemTime::UNIX_EPOCH
+            + Duration::from_millis(number(value, "registered_at")? as u64),
+        version: number(value, "version")? as u64,
+    })
+}
+
+/// Answers lookups by id and by email from Redis, falling back to `base` on
+/// a miss and caching what it finds for `ttl`. Saves go to `base` and evict
+/// the user, so the next read sees the change.
+pub struct CachedRepository<'a, R: UserRepository, K: RedisKeyValue> {
+    base: &'a R,
+    redis: K,
+    ttl: Duration,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl<'a, R: UserRepository, K: RedisKeyValue> CachedRepository<'a, R, K> {
+    pub fn new(base: &'a R, redis: K, ttl: Duration) -> Self {
+        Self {
+            base,
+            redis,
+            ttl,
+            metrics: None,
+        }
+    }
+
+    /// Counts `cache_hits_total` and `cache_misses_total`, labelled with the
+    /// lookup (`id` or `email`).
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn record(&self, lookup: &str, hit: bool) {
+        if let Some(metrics) = &self.metrics {
+            let name = if hit {
+                "cache_hits_total"
+            } else {
+                "cache_misses_total"
+            };
+            metrics.increment(name, &[("lookup", lookup)]);
+        }
+    }
+
+    fn cached(&self, id: UserId) -> Result<Option<User>> {
+        let Some(value) = self.redis.get(&user_key(id))? else {
+            return Ok(None);
+        };
+        // An entry written by an older layout is a miss, replaced below.
+        Ok(parse_json(&value)
+            .and_then(|value| user_from_json(&value))
+            .ok())
+    }
+
+    fn store(&self, user: &User) -> Result<()> {
+        self.redis
+            .set(&user_key(user.id), &user_json(user).to_string(), self.ttl)?;
+        if user.merged_into.is_none() {
+            let id = user.id.0.to_string();
+            self.redis.set(
+                &email_key(&user.tenant_id, user.email.address()),
+                &id,
+                self.ttl,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: UserRepository, K: RedisKeyValue> UserRepository for CachedRepository<'_, R, K> {
+    fn save(&self, user: User) -> Result<()> {
+        let id = user.id;
+        self.base.save(user)?;
+        // The email key is left to expire: a lookup through it checks the
+        // address of the user it points to.
+        self.redis.del(&user_key(id))
+    }
+
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        let ids: Vec<UserId> = users.iter().map(|user| user.id).collect();
+        self.base.save_all_with(users, alongside)?;
+        for id in ids {
+            self.redis.del(&user_key(id))?;
+        }
+        Ok(())
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        if let Some(user) = self.cached(id)? {
+            self.record("id", true);
+            return Ok(Some(user));
+        }
+        self.record("id", false);
+        let user = self.base.find(id)?;
+        if let Some(user) = &user {
+            self.store(user)?;
+        }
+        Ok(user)
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        let pointer = self.redis.get(&email_key(tenant_id, email))?;
+        let cached = match pointer.and_then(|id| id.parse().ok()) {
+            Some(id) => self.cached(UserId(id))?,
+            None => None,
+        };
+        if let Some(user) = cached.filter(|user| {
+            user.merged_into.is_none()
+                && &user.tenant_id == tenant_id
+                && user.email.address().normalized() == email.normalized()
+        }) {
+            self.record("email", true);
+            return Ok(Some(user));
+        }
+        self.record("email", false);
+        let user = self.base.find_by_email(tenant_id, email)?;
+        if let Some(user) = &user {
+            self.store(user)?;
+        }
+        Ok(user)
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        self.base.find_by_tags(filter)
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        self.base.for_each(visit)
+    }
+
+    fn query(&self, query: &UserQuery) -> Result<Vec<User>> {
+        self.base.query(query)
+    }
+
+    fn list_users(&self, cursor: Option<&Cursor>, limit: usize) -> Result<UserPage> {
+        self.base.list_users(cursor, limit)
+    }
+}
+
+/// Evicts the users an event changed, so caches in every instance drop
+/// them as soon as the event is handled instead of when their TTL runs out.
+pub struct CacheInvalidator<K: RedisKeyValue> {
+    redis: K,
+}
+
+impl<K: RedisKeyValue> CacheInvalidator<K> {
+    pub fn new(redis: K) -> Self {
+        Self { redis }
+    }
+}
+
+impl<K: RedisKeyValue> EventHandler for CacheInvalidator<K> {
+    fn name(&self) -> &str {
+        "cache_invalidation"
+    }
+
+    fn handle(&self, event: &DomainEvent) -> Result<()> {
+        match event {
+            DomainEvent::UserRegistered(_) | DomainEvent::VerificationThrottled(_) => Ok(()),
+            // The email entry only points at the user, and is checked
+            // against the user it points at, so dropping the user is enough.
+            DomainEvent::EmailVerified(event) => self.redis.del(&user_key(event.user_id)),
+            DomainEvent::NameChanged(event) => self.redis.del(&user_key(event.user_id)),
+            // The duplicate's data lives on in the primary; neither cached
+            // copy may be served.
+            DomainEvent::UsersMerged(event) => {
+                self.redis.del(&user_key(event.duplicate_id))?;
+                self.redis.del(&user_key(event.primary_id))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::NameChanged;
+    use crate::metrics::PrometheusMetrics;
+    use crate::pronouns::Pronouns;
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::UserFixture;
+    use crate::{create_user, verify_email};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Redis without expiry; the tests never wait for a TTL.
+    #[derive(Default)]
+    struct FakeRedis(Mutex<HashMap<String, String>>);
+
+    impl RedisKeyValue for &FakeRedis {
+        fn get(&self, key: &str) -> Result<Option<String>> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, value: &str, _ttl: Duration) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn del(&self, key: &str) -> Result<()> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ok_user_round_trips_through_cache_format() {
+        let mut user = UserFixture::new()
+            .with_name("Luca", Some("Maria"), "Rossi")
+            .build();
+        if let UserEmail::UnverifiedEmail(email) = &user.email {
+            user.email = UserEmail::VerifiedEmail(verify_email(email).unwrap());
+        }
+        user.pronouns = Some(Pronouns::TheyThem);
+        user.tags.insert(Tag("beta".to_string()));
+        user.custom_attributes.0.insert(
+            "started".to_string(),
+            AttributeValue::Date(check_date("2021-09-01".to_string()).unwrap()),
+        );
+        user.custom_attributes
+            .0
+            .insert("score".to_string(), AttributeValue::Number(4.5));
+
+        let decoded = user_from_json(&parse_json(&user_json(&user).to_string()).unwrap()).unwrap();
+
+        assert_eq!(user_json(&decoded), user_json(&user));
+    }
+
+    #[test]
+    fn ok_hits_after_miss_and_evicts_on_save() {
+        let redis = FakeRedis::default();
+        let base = InMemoryUserRepository::new();
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let repository = CachedRepository::new(&base, &redis, Duration::from_secs(60))
+            .with_metrics(metrics.clone());
+        let user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        let id = user.id;
+        repository.save(user).unwrap();
+
+        repository.find(id).unwrap().unwrap();
+        let mut user = repository
+            .find_by_email(&TenantId::default(), &Email("FOO@ok.com".to_string()))
+            .unwrap()
+            .unwrap();
+        user.name = "Marco".to_string();
+        repository.save(user).unwrap();
+        let found = repository.find(id).unwrap().unwrap();
+
+        assert_eq!(found.name, "Marco");
+        assert_eq!(
+            metrics.counter("cache_misses_total", &[("lookup", "id")]),
+            2
+        );
+        assert_eq!(
+            metrics.counter("cache_hits_total", &[("lookup", "email")]),
+            1
+        );
+    }
+
+    #[test]
+    fn ok_event_evicts_user_saved_elsewhere() {
+        let redis = FakeRedis::default();
+        let base = InMemoryUserRepository::new();
+        let repository = CachedRepository::new(&base, &redis, Duration::from_secs(60));
+        let mut user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        let id = user.id;
+        base.save(user.clone()).unwrap();
+        repository.find(id).unwrap();
+
+        user.version = 1;
+        user.name = "Marco".to_string();
+        base.save(user).unwrap();
+        let stale = repository.find(id).unwrap().unwrap();
+        CacheInvalidator::new(&redis)
+            .handle(&DomainEvent::NameChanged(NameChanged {
+                user_id: id,
+                name: "Marco".to_string(),
+                middle_name: None,
+                surname: "Rossi".to_string(),
+            }))
+            .unwrap();
+        let fresh = repository.find(id).unwrap().unwrap();
+
+        assert_eq!(stale.name, "Luca");
+        assert_eq!(fresh.name, "Marco");
+    }
+}
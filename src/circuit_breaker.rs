@@ -0,0 +1,243 @@
+use crate::clock::Clock;
+use crate::health::HealthCheck;
+use crate::notifications::{EmailMessage, EmailSender};
+use crate::verification::EmailVerifier;
+use crate::{UnverifiedEmail, VerifiedEmail};
+use anyhow::Result;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Returned without calling the service while its circuit is open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceUnavailable {
+    pub service: String,
+}
+
+impl Display for ServiceUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Service {} is unavailable", self.service)
+    }
+}
+
+impl std::error::Error for ServiceUnavailable {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CircuitState {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        until: SystemTime,
+    },
+    /// One probe call is in flight; other calls are rejected until it ends.
+    HalfOpen,
+}
+
+/// Opens after `failure_threshold` consecutive failures, rejects calls for
+/// `open_for`, then lets a single probe through: success closes the circuit,
+/// failure opens it again.
+pub struct CircuitBreaker<C: Clock> {
+    service: String,
+    clock: C,
+    failure_threshold: u32,
+    open_for: Duration,
+    state: Mutex<CircuitState>,
+}
+
+impl<C: Clock> CircuitBreaker<C> {
+    pub fn new(service: &str, clock: C) -> Self {
+        Self {
+            service: service.to_string(),
+            clock,
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+            state: Mutex::new(CircuitState::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    pub fn with_thresholds(mut self, failure_threshold: u32, open_for: Duration) -> Self {
+        self.failure_threshold = failure_threshold;
+        self.open_for = open_for;
+        self
+    }
+
+    pub fn state(&self) -> CircuitState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn call<T>(&self, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+        let probe = {
+            let mut state = self.state.lock().unwrap();
+            match *state {
+                CircuitState::Closed { .. } => false,
+                CircuitState::Open { until } if self.clock.now() >= until => {
+                    *state = CircuitState::HalfOpen;
+                    true
+                }
+                CircuitState::Open { .. } | CircuitState::HalfOpen => {
+                    return Err(ServiceUnavailable {
+                        service: self.service.clone(),
+                    }
+                    .into())
+                }
+            }
+        };
+
+        let result = operation();
+        let mut state = self.state.lock().unwrap();
+        *state = match (&result, *state) {
+            (Ok(_), _) => CircuitState::Closed {
+                consecutive_failures: 0,
+            },
+            (
+                Err(_),
+                CircuitState::Closed {
+                    consecutive_failures,
+                },
+            ) if !probe && consecutive_failures + 1 < self.failure_threshold => {
+                CircuitState::Closed {
+                    consecutive_failures: consecutive_failures + 1,
+                }
+            }
+            (Err(_), _) => CircuitState::Open {
+                until: self.clock.now() + self.open_for,
+            },
+        };
+        result
+    }
+}
+
+/// Reports the service as down while its circuit is open, so traffic is
+/// routed elsewhere instead of failing fast here.
+impl<C: Clock + Send + Sync> HealthCheck for CircuitBreaker<C> {
+    fn name(&self) -> &str {
+        &self.service
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.state() {
+            CircuitState::Open { until } if self.clock.now() < until => Err(ServiceUnavailable {
+                service: self.service.clone(),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct CircuitBreakingEmailSender<E: EmailSender, C: Clock> {
+    inner: E,
+    breaker: CircuitBreaker<C>,
+}
+
+impl<E: EmailSender, C: Clock> CircuitBreakingEmailSender<E, C> {
+    pub fn new(inner: E, breaker: CircuitBreaker<C>) -> Self {
+        Self { inner, breaker }
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker<C> {
+        &self.breaker
+    }
+}
+
+impl<E: EmailSender, C: Clock> EmailSender for CircuitBreakingEmailSender<E, C> {
+    fn send(&self, message: &EmailMessage) -> Result<()> {
+        self.breaker.call(|| self.inner.send(message))
+    }
+}
+
+pub struct CircuitBreakingEmailVerifier<V: EmailVerifier, C: Clock> {
+    inner: V,
+    breaker: CircuitBreaker<C>,
+}
+
+impl<V: EmailVerifier, C: Clock> CircuitBreakingEmailVerifier<V, C> {
+    pub fn new(inner: V, breaker: CircuitBreaker<C>) -> Self {
+        Self { inner, breaker }
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker<C> {
+        &self.breaker
+    }
+}
+
+impl<V: EmailVerifier, C: Clock> EmailVerifier for CircuitBreakingEmailVerifier<V, C> {
+    fn verify(&self, email: &UnverifiedEmail) -> Result<VerifiedEmail> {
+        self.breaker.call(|| self.inner.verify(email))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use anyhow::Error;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    #[derive(Default)]
+    struct FlakySender {
+        down: AtomicBool,
+        calls: AtomicU32,
+    }
+
+    impl EmailSender for &FlakySender {
+        fn send(&self, _message: &EmailMessage) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                Err(Error::msg("Connection timed out"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            to: "foo@ok.com".to_string(),
+            subject: "Welcome!".to_string(),
+            body: "Your account is ready.".to_string(),
+        }
+    }
+
+    #[test]
+    fn ok_open_then_recover_through_probe() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let flaky = FlakySender::default();
+        flaky.down.store(true, Ordering::SeqCst);
+        let sender = CircuitBreakingEmailSender::new(
+            &flaky,
+            CircuitBreaker::new("smtp", &clock).with_thresholds(2, Duration::from_secs(30)),
+        );
+
+        assert!(sender.send(&message()).is_err());
+        assert!(sender.send(&message()).is_err());
+        let error = sender.send(&message()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ServiceUnavailable>(),
+            Some(&ServiceUnavailable {
+                service: "smtp".to_string()
+            })
+        );
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 2);
+
+        clock.advance(Duration::from_secs(30));
+        assert!(sender.send(&message()).is_err());
+        assert!(matches!(
+            sender.breaker().state(),
+            CircuitState::Open { .. }
+        ));
+
+        clock.advance(Duration::from_secs(30));
+        flaky.down.store(false, Ordering::SeqCst);
+        sender.send(&message()).unwrap();
+        assert_eq!(
+            sender.breaker().state(),
+            CircuitState::Closed {
+                consecutive_failures: 0
+            }
+        );
+    }
+}
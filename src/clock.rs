@@ -0,0 +1,49 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Source of the current time, so time-dependent rules can be tested.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that stands still until moved, so expiry, reminders and the like
+/// can be tested by jumping over time instead of sleeping through it.
+/// Share it by reference: `&TestClock` is the [`Clock`].
+#[derive(Debug)]
+pub struct TestClock(Mutex<SystemTime>);
+
+impl TestClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self(Mutex::new(now))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+/// Starts at the Unix epoch.
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for &TestClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,370 @@
+use crate::config::AgeLimits;
+use crate::error::DomainError;
+use crate::events::{EmailVerified, EventPublisher, NameChanged, UserRegistered};
+use crate::idempotency::Fingerprint;
+use crate::parallel::{available_parallelism, map_ordered};
+use crate::pii::{mask_email, mask_name};
+use crate::repository::UserRepository;
+use crate::trace::instrument;
+use crate::unit_of_work::{InMemoryUnitOfWork, UnitOfWork};
+use crate::{create_user_within, grant_user, User, UserEmail, UserId, VerifiedEmail};
+use anyhow::{Error, Result};
+use std::fmt::Debug;
+
+#[derive(Clone)]
+pub struct CreateUser {
+    pub email: String,
+    pub age: i32,
+    pub name: String,
+    pub surname: String,
+    pub middle_name: Option<String>,
+}
+
+impl Debug for CreateUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CreateUser")
+            .field("email", &mask_email(&self.email))
+            .field("age", &self.age)
+            .field("name", &mask_name(&self.name))
+            .field("surname", &mask_name(&self.surname))
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct ChangeName {
+    pub user_id: UserId,
+    pub name: String,
+    pub middle_name: Option<String>,
+    pub surname: String,
+}
+
+impl Debug for ChangeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChangeName")
+            .field("user_id", &self.user_id)
+            .field("name", &mask_name(&self.name))
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .field("surname", &mask_name(&self.surname))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchMode {
+    /// Persist nothing unless every input is valid.
+    AllOrNothing,
+    /// Persist every valid input and report the invalid ones.
+    BestEffort,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateUsersBatch {
+    pub users: Vec<CreateUser>,
+    pub mode: BatchMode,
+}
+
+impl CreateUser {
+    /// Fingerprint of every field, so a reused idempotency key with any
+    /// field changed is told apart from a retry.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let age = self.age.to_string();
+        Fingerprint::of([
+            self.email.as_str(),
+            &age,
+            &self.name,
+            &self.surname,
+            self.middle_name.as_deref().unwrap_or(""),
+            if self.middle_name.is_some() { "1" } else { "0" },
+        ])
+    }
+
+    fn validate(self, age_limits: &AgeLimits) -> Result<User, DomainError> {
+        create_user_within(
+            self.email,
+            self.age,
+            self.name,
+            self.surname,
+            self.middle_name,
+            age_limits,
+        )
+    }
+}
+
+pub fn handle_create_user(
+    repository: &impl UserRepository,
+    age_limits: &AgeLimits,
+    command: CreateUser,
+) -> Result<UserRegistered> {
+    let user = command.validate(age_limits)?;
+    let event = UserRegistered::from_user(&user);
+    repository.save(user)?;
+    Ok(event)
+}
+
+pub fn handle_verify_email(
+    repository: &impl UserRepository,
+    user_id: UserId,
+) -> Result<EmailVerified> {
+    let fields = vec![("user_id", user_id.0.to_string())];
+    instrument("verify_email", fields, |_| {
+        let mut user = repository
+            .find(user_id)?
+            .ok_or_else(|| Error::msg("User not found"))?;
+        grant_user(&mut user)?;
+
+        let UserEmail::VerifiedEmail(VerifiedEmail(email)) = &user.email else {
+            unreachable!("grant_user leaves the email verified on success");
+        };
+        let event = EmailVerified {
+            user_id,
+            email: email.0.clone(),
+        };
+        repository.save(user)?;
+        Ok(event)
+    })
+}
+
+pub fn handle_change_name(
+    repository: &impl UserRepository,
+    command: ChangeName,
+) -> Result<NameChanged> {
+    let mut user = repository
+        .find(command.user_id)?
+        .ok_or_else(|| Error::msg("User not found"))?;
+    user.name = command.name;
+    user.middle_name = command.middle_name;
+    user.surname = command.surname;
+
+    let event = NameChanged {
+        user_id: user.id,
+        name: user.name.clone(),
+        middle_name: user.middle_name.clone(),
+        surname: user.surname.clone(),
+    };
+    repository.save(user)?;
+    Ok(event)
+}
+
+/// Batches at least this large are validated on every available thread.
+const PARALLEL_BATCH_SIZE: usize = 256;
+
+/// Returns one result per input, in input order. Every saved user is
+/// staged in one unit of work, committed once along with its
+/// [`UserRegistered`] events, so a failed commit saves nothing. With
+/// [`BatchMode::AllOrNothing`] a single failed input means nothing is saved,
+/// and the inputs that did not fail report [`DomainError::BatchAborted`].
+pub fn handle_create_users_batch(
+    repository: &impl UserRepository,
+    publisher: &impl EventPublisher,
+    age_limits: &AgeLimits,
+    command: CreateUsersBatch,
+) -> Result<Vec<Result<UserId, DomainError>>> {
+    let parallelism = if command.users.len() >= PARALLEL_BATCH_SIZE {
+        available_parallelism()
+    } else {
+        1
+    };
+    let validated = map_ordered(command.users, parallelism, |user| user.validate(age_limits));
+
+    let mut unit_of_work = InMemoryUnitOfWork::begin(repository, publisher);
+    let mut results = Vec::with_capacity(validated.len());
+    for user in validated {
+        let result = match user {
+            Ok(user) => {
+                let user_id = user.id;
+                let event = UserRegistered::from_user(&user);
+                match unit_of_work.users().save(user) {
+                    Ok(()) => {
+                        unit_of_work.record(event.into());
+                        Ok(user_id)
+                    }
+                    Err(error) => Err(error.downcast::<DomainError>()?),
+                }
+            }
+            Err(error) => Err(error),
+        };
+        results.push(result);
+    }
+
+    if command.mode == BatchMode::AllOrNothing && results.iter().any(Result::is_err) {
+        unit_of_work.rollback();
+        return Ok(results
+            .into_iter()
+            .map(|result| result.and(Err(DomainError::BatchAborted)))
+            .collect());
+    }
+    unit_of_work.commit()?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::InMemoryEventPublisher;
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::assert_debug_masks;
+
+    fn command(email: &str, age: i32) -> CreateUser {
+        CreateUser {
+            email: email.to_string(),
+            age,
+            name: "Luca".to_string(),
+            surname: "Rossi".to_string(),
+            middle_name: None,
+        }
+    }
+
+    fn batch(mode: BatchMode) -> CreateUsersBatch {
+        CreateUsersBatch {
+            users: vec![
+                command("foo@ok.com", 22),
+                command("foo.at.com", 22),
+                command("bar@ok.com", 130),
+            ],
+            mode,
+        }
+    }
+
+    #[test]
+    fn ok_create_users_batch_best_effort() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = InMemoryEventPublisher::new();
+
+        let results = handle_create_users_batch(
+            &repository,
+            &publisher,
+            &AgeLimits::default(),
+            batch(BatchMode::BestEffort),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[1], Err(DomainError::InvalidEmail));
+        assert_eq!(results[2], Err(DomainError::AgeTooHigh));
+        let id = results[0].as_ref().unwrap();
+        assert!(repository.find(*id).unwrap().is_some());
+        assert_eq!(publisher.published().len(), 1);
+    }
+
+    #[test]
+    fn ok_create_users_batch_reports_duplicate_email_on_its_row() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = InMemoryEventPublisher::new();
+        let command = CreateUsersBatch {
+            users: vec![
+                command("foo@ok.com", 22),
+                command("FOO@ok.com", 22),
+                command("bar@ok.com", 22),
+            ],
+            mode: BatchMode::BestEffort,
+        };
+
+        let results =
+            handle_create_users_batch(&repository, &publisher, &AgeLimits::default(), command)
+                .unwrap();
+
+        assert_eq!(results[1], Err(DomainError::EmailAlreadyRegistered));
+        for result in [&results[0], &results[2]] {
+            assert!(repository
+                .find(*result.as_ref().unwrap())
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn ok_large_batch_results_in_input_order() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = InMemoryEventPublisher::new();
+        let users = (0..PARALLEL_BATCH_SIZE + 44)
+            .map(|i| match i % 3 {
+                0 => command(&format!("user{}.at.com", i), 22),
+                _ => command(&format!("user{}@ok.com", i), 22),
+            })
+            .collect();
+        let command = CreateUsersBatch {
+            users,
+            mode: BatchMode::BestEffort,
+        };
+
+        let results =
+            handle_create_users_batch(&repository, &publisher, &AgeLimits::default(), command)
+                .unwrap();
+
+        let invalid: Vec<usize> = results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| result.is_err())
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(invalid, (0..300).step_by(3).collect::<Vec<_>>());
+        let id = results[1].as_ref().unwrap();
+        assert_eq!(
+            repository
+                .find(*id)
+                .unwrap()
+                .unwrap()
+                .email
+                .address()
+                .as_str(),
+            "user1@ok.com"
+        );
+    }
+
+    #[test]
+    fn err_create_users_batch_all_or_nothing() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = InMemoryEventPublisher::new();
+
+        let results = handle_create_users_batch(
+            &repository,
+            &publisher,
+            &AgeLimits::default(),
+            batch(BatchMode::AllOrNothing),
+        )
+        .unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                Err(DomainError::BatchAborted),
+                Err(DomainError::InvalidEmail),
+                Err(DomainError::AgeTooHigh)
+            ]
+        );
+        let mut saved = 0;
+        repository
+            .for_each(&mut |_| {
+                saved += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(saved, 0);
+        assert!(publisher.published().is_empty());
+    }
+
+    #[test]
+    fn ok_commands_debug_masks_pii() {
+        let mut create = command("luca.rossi@ok.com", 22);
+        create.middle_name = Some("Maria".to_string());
+        let change_name = ChangeName {
+            user_id: UserId(7),
+            name: "Luca".to_string(),
+            middle_name: Some("Maria".to_string()),
+            surname: "Rossi".to_string(),
+        };
+
+        let pii = ["luca.rossi", "Luca", "Maria", "Rossi"];
+        assert_debug_masks(&create, &pii);
+        assert_debug_masks(&change_name, &pii);
+        assert_debug_masks(
+            &CreateUsersBatch {
+                users: vec![create],
+                mode: BatchMode::BestEffort,
+            },
+            &pii,
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::User;
+
+/// Part of a profile a user can still fill in after signing up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileField {
+    VerifiedEmail,
+    Avatar,
+    Pronouns,
+}
+
+/// How much of a profile is filled in, for onboarding screens to nudge
+/// users towards the rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completeness {
+    /// Percentage of [`ProfileField`]s filled in, from 0 to 100.
+    pub score: u8,
+    /// Fields still to fill in, in the order they are listed above.
+    pub missing: Vec<ProfileField>,
+}
+
+impl User {
+    /// Recomputed from the user on each call, so it can never go stale.
+    pub fn completeness(&self) -> Completeness {
+        let fields = [
+            (ProfileField::VerifiedEmail, self.is_email_verified()),
+            (ProfileField::Avatar, self.avatar.is_some()),
+            (ProfileField::Pronouns, self.pronouns.is_some()),
+        ];
+        let missing: Vec<ProfileField> = fields
+            .iter()
+            .filter(|(_, filled)| !filled)
+            .map(|(field, _)| *field)
+            .collect();
+        let filled = fields.len() - missing.len();
+        Completeness {
+            score: (filled * 100 / fields.len()) as u8,
+            missing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::avatar::UploadedAvatar;
+    use crate::pronouns::Pronouns;
+    use crate::test_support::UserFixture;
+
+    #[test]
+    fn ok_completeness_lists_missing_fields() {
+        let mut user = UserFixture::new().build();
+
+        assert_eq!(
+            user.completeness(),
+            Completeness {
+                score: 0,
+                missing: vec![
+                    ProfileField::VerifiedEmail,
+                    ProfileField::Avatar,
+                    ProfileField::Pronouns
+                ],
+            }
+        );
+        user.pronouns = Some(Pronouns::TheyThem);
+        assert_eq!(user.completeness().score, 33);
+
+        let mut user = UserFixture::verified().build();
+        user.avatar = Some(UploadedAvatar("avatars/1.png".to_string()));
+        assert_eq!(
+            user.completeness(),
+            Completeness {
+                score: 66,
+                missing: vec![ProfileField::Pronouns],
+            }
+        );
+    }
+}
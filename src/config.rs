@@ -0,0 +1,337 @@
+use anyhow::{Error, Result};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgeLimits {
+    pub min: i32,
+    pub max: i32,
+}
+
+impl Default for AgeLimits {
+    fn default() -> Self {
+        Self { min: 13, max: 120 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationConfig {
+    pub reminder_after: Duration,
+    pub max_attempts: u32,
+    pub attempt_window: Duration,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            reminder_after: Duration::from_secs(24 * 60 * 60),
+            max_attempts: 5,
+            attempt_window: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// Delivery of webhook events to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    /// Attempts per delivery, the first included, before it is abandoned.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled before each later one.
+    pub base_backoff: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// Most connections open at once.
+    pub pool_size: u32,
+    /// How long to wait for a free connection before failing the request.
+    pub acquire_timeout: Duration,
+    pub statement_timeout: Duration,
+    /// Statements taking at least this long are logged.
+    pub slow_query_threshold: Duration,
+    /// Apply pending migrations before serving, instead of with `migrate`.
+    pub migrate_on_startup: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "memory://".to_string(),
+            pool_size: 10,
+            acquire_timeout: Duration::from_secs(5),
+            statement_timeout: Duration::from_secs(30),
+            slow_query_threshold: Duration::from_millis(500),
+            migrate_on_startup: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    pub signups_per_ip: u32,
+    pub signup_window: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            signups_per_ip: 10,
+            signup_window: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// One `key=value` line per span, for reading in a terminal.
+    Text,
+    /// One JSON object per span, for log aggregation.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::msg("Unknown log format")),
+        }
+    }
+}
+
+/// Application settings. Defaults are overridden by a `key = value` file,
+/// which is overridden by `APP_*` environment variables: `age.min` is read
+/// from `APP_AGE_MIN`, and so on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub age: AgeLimits,
+    pub verification: VerificationConfig,
+    pub webhooks: WebhookConfig,
+    pub database: DatabaseConfig,
+    pub rate_limits: RateLimits,
+    /// Time given to in-flight work to finish once the process is told to
+    /// stop.
+    pub shutdown_deadline: Duration,
+    pub log_format: LogFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            age: AgeLimits::default(),
+            verification: VerificationConfig::default(),
+            webhooks: WebhookConfig::default(),
+            database: DatabaseConfig::default(),
+            rate_limits: RateLimits::default(),
+            shutdown_deadline: Duration::from_secs(30),
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+const KEYS: [&str; 17] = [
+    "age.min",
+    "age.max",
+    "verification.reminder_after_secs",
+    "verification.max_attempts",
+    "verification.attempt_window_secs",
+    "webhooks.max_attempts",
+    "webhooks.base_backoff_secs",
+    "database.url",
+    "database.pool_size",
+    "database.acquire_timeout_ms",
+    "database.statement_timeout_ms",
+    "database.slow_query_threshold_ms",
+    "database.migrate_on_startup",
+    "rate_limits.signups_per_ip",
+    "rate_limits.signup_window_secs",
+    "shutdown.deadline_secs",
+    "log.format",
+];
+
+fn env_name(key: &str) -> String {
+    format!("APP_{}", key.replace('.', "_").to_uppercase())
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::msg(format!("Invalid value for {}: {}", key, value)))
+}
+
+/// `line` up to a `#` starting a comment: one at the start of the line or
+/// after whitespace, outside double quotes. Any other `#` is part of the
+/// value, as in `database.url = postgres://db/users#replica`.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut after_space = true;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted && after_space => return &line[..index],
+            _ => {}
+        }
+        after_space = c.is_whitespace();
+    }
+    line
+}
+
+/// `value` without the double quotes around it, if it is quoted.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+impl Config {
+    /// Reads `APP_CONFIG` (if set) and the environment of this process.
+    pub fn from_env() -> Result<Self> {
+        let file = match std::env::var("APP_CONFIG") {
+            Ok(path) => Some(
+                std::fs::read_to_string(&path)
+                    .map_err(|error| Error::msg(format!("Cannot read {}: {}", path, error)))?,
+            ),
+            Err(_) => None,
+        };
+        Self::load(file.as_deref(), |name| std::env::var(name).ok())
+    }
+
+    pub fn load(file: Option<&str>, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config = Self::default();
+        for (index, line) in file.unwrap_or_default().lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| Error::msg(format!("Invalid config line {}", index + 1)))?;
+            let key = key.trim();
+            if !KEYS.contains(&key) {
+                return Err(Error::msg(format!(
+                    "Unknown config key {} on line {}",
+                    key,
+                    index + 1
+                )));
+            }
+            config.set(key, unquote(value.trim()))?;
+        }
+        for key in KEYS {
+            if let Some(value) = env(&env_name(key)) {
+                config.set(key, &value)?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let seconds = |value: &str| parse(key, value).map(Duration::from_secs);
+        let millis = |value: &str| parse(key, value).map(Duration::from_millis);
+        match key {
+            "age.min" => self.age.min = parse(key, value)?,
+            "age.max" => self.age.max = parse(key, value)?,
+            "verification.reminder_after_secs" => {
+                self.verification.reminder_after = seconds(value)?
+            }
+            "verification.max_attempts" => self.verification.max_attempts = parse(key, value)?,
+            "verification.attempt_window_secs" => {
+                self.verification.attempt_window = seconds(value)?
+            }
+            "webhooks.max_attempts" => self.webhooks.max_attempts = parse(key, value)?,
+            "webhooks.base_backoff_secs" => self.webhooks.base_backoff = seconds(value)?,
+            "database.url" => self.database.url = value.to_string(),
+            "database.pool_size" => self.database.pool_size = parse(key, value)?,
+            "database.acquire_timeout_ms" => self.database.acquire_timeout = millis(value)?,
+            "database.statement_timeout_ms" => self.database.statement_timeout = millis(value)?,
+            "database.slow_query_threshold_ms" => {
+                self.database.slow_query_threshold = millis(value)?
+            }
+            "database.migrate_on_startup" => self.database.migrate_on_startup = parse(key, value)?,
+            "rate_limits.signups_per_ip" => self.rate_limits.signups_per_ip = parse(key, value)?,
+            "rate_limits.signup_window_secs" => self.rate_limits.signup_window = seconds(value)?,
+            "shutdown.deadline_secs" => self.shutdown_deadline = seconds(value)?,
+            "log.format" => self.log_format = parse(key, value)?,
+            _ => unreachable!("keys are checked against KEYS"),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let fail = |message: &str| Err(Error::msg(message.to_string()));
+        if self.age.min < 0 || self.age.min > self.age.max {
+            return fail("age.min must be between 0 and age.max");
+        }
+        if self.verification.max_attempts == 0
+            || self.rate_limits.signups_per_ip == 0
+            || self.webhooks.max_attempts == 0
+        {
+            return fail("Rate limits must allow at least one attempt");
+        }
+        if self.database.url.is_empty() {
+            return fail("database.url is required");
+        }
+        if self.database.pool_size == 0 {
+            return fail("database.pool_size must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_env_overrides_file() {
+        let config = Config::load(
+            Some("# limits\nage.min = 16\nwebhooks.max_attempts = 3\n"),
+            |name| (name == "APP_AGE_MIN").then(|| "18".to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.age, AgeLimits { min: 18, max: 120 });
+        assert_eq!(config.webhooks.max_attempts, 3);
+        assert_eq!(config.verification, VerificationConfig::default());
+    }
+
+    #[test]
+    fn err_invalid_config_value() {
+        let result = Config::load(Some("database.pool_size = seventy\n"), |_| None);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid value for database.pool_size: seventy"
+        );
+    }
+
+    #[test]
+    fn ok_hash_inside_value_is_not_a_comment() {
+        let url = |line: &str| Config::load(Some(line), |_| None).unwrap().database.url;
+
+        assert_eq!(
+            url("database.url = memory://users#replica # primary is down"),
+            "memory://users#replica"
+        );
+        assert_eq!(
+            url("database.url = \"memory://users #replica\" # quoted"),
+            "memory://users #replica"
+        );
+        assert_eq!(url("# database.url = memory://other"), "memory://");
+    }
+}
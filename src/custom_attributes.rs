@@ -0,0 +1,210 @@
+use crate::date::Date;
+use crate::error::DomainError;
+use crate::{TenantId, User};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Date(Date),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeKind {
+    String,
+    Number,
+    Bool,
+    Date,
+}
+
+#[derive(Debug)]
+pub struct AttributeDefinition {
+    pub kind: AttributeKind,
+    pub required: bool,
+}
+
+/// Attributes a tenant allows on its users, e.g. `employee_id` or `department`.
+#[derive(Debug, Default)]
+pub struct AttributeSchema(BTreeMap<String, AttributeDefinition>);
+
+/// Each tenant's [`AttributeSchema`]. A tenant without one allows no
+/// attributes.
+#[derive(Debug, Default)]
+pub struct TenantSchemas(HashMap<TenantId, AttributeSchema>);
+
+static NO_ATTRIBUTES: AttributeSchema = AttributeSchema(BTreeMap::new());
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CustomAttributes(pub BTreeMap<String, AttributeValue>);
+
+impl AttributeValue {
+    pub fn kind(&self) -> AttributeKind {
+        match self {
+            AttributeValue::String(_) => AttributeKind::String,
+            AttributeValue::Number(_) => AttributeKind::Number,
+            AttributeValue::Bool(_) => AttributeKind::Bool,
+            AttributeValue::Date(_) => AttributeKind::Date,
+        }
+    }
+}
+
+impl AttributeSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(mut self, name: &str, kind: AttributeKind, required: bool) -> Self {
+        self.0
+            .insert(name.to_string(), AttributeDefinition { kind, required });
+        self
+    }
+}
+
+impl TenantSchemas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_schema(mut self, tenant_id: TenantId, schema: AttributeSchema) -> Self {
+        self.0.insert(tenant_id, schema);
+        self
+    }
+
+    pub fn for_tenant(&self, tenant_id: &TenantId) -> &AttributeSchema {
+        self.0.get(tenant_id).unwrap_or(&NO_ATTRIBUTES)
+    }
+}
+
+pub fn check_custom_attributes(
+    schema: &AttributeSchema,
+    attributes: &CustomAttributes,
+) -> Result<()> {
+    for (name, value) in &attributes.0 {
+        match schema.0.get(name) {
+            None => return Err(DomainError::UnknownAttribute { name: name.clone() }.into()),
+            Some(definition) if definition.kind != value.kind() => {
+                return Err(DomainError::InvalidAttribute {
+                    name: name.clone(),
+                    expected: definition.kind,
+                }
+                .into())
+            }
+            _ => {}
+        }
+    }
+    for (name, definition) in &schema.0 {
+        if definition.required && !attributes.0.contains_key(name) {
+            return Err(DomainError::MissingAttribute { name: name.clone() }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Sets `attributes` on `user` if they match the schema of the user's tenant.
+pub fn set_custom_attributes(
+    user: &mut User,
+    schemas: &TenantSchemas,
+    attributes: CustomAttributes,
+) -> Result<()> {
+    check_custom_attributes(schemas.for_tenant(&user.tenant_id), &attributes)?;
+    user.custom_attributes = attributes;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::date::check_date;
+    use crate::test_support::UserFixture;
+
+    fn schemas() -> TenantSchemas {
+        let schema = AttributeSchema::new()
+            .define("employee_id", AttributeKind::String, true)
+            .define("department", AttributeKind::String, false)
+            .define("hired_on", AttributeKind::Date, false);
+        TenantSchemas::new().with_schema(TenantId::default(), schema)
+    }
+
+    fn user() -> User {
+        UserFixture::new().build()
+    }
+
+    #[test]
+    fn ok_set_custom_attributes() {
+        let mut user = user();
+        let mut attributes = CustomAttributes::default();
+        attributes.0.insert(
+            "employee_id".to_string(),
+            AttributeValue::String("E-042".to_string()),
+        );
+        attributes.0.insert(
+            "hired_on".to_string(),
+            AttributeValue::Date(check_date("2021-09-01".to_string()).unwrap()),
+        );
+
+        let result = set_custom_attributes(&mut user, &schemas(), attributes.clone());
+
+        assert!(result.is_ok());
+        assert_eq!(user.custom_attributes, attributes);
+    }
+
+    #[test]
+    fn err_custom_attributes_against_schema() {
+        let mut user = user();
+        let mut attributes = CustomAttributes::default();
+        attributes
+            .0
+            .insert("hired_on".to_string(), AttributeValue::Bool(true));
+
+        let result = set_custom_attributes(&mut user, &schemas(), attributes.clone());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Attribute hired_on must be a Date"
+        );
+
+        attributes.0.remove("hired_on");
+        let result = set_custom_attributes(&mut user, &schemas(), attributes.clone());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Attribute employee_id is required"
+        );
+
+        attributes
+            .0
+            .insert("badge".to_string(), AttributeValue::Number(7.0));
+        let result = set_custom_attributes(&mut user, &schemas(), attributes);
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Unknown attribute badge");
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::UnknownAttribute {
+                name: "badge".to_string()
+            })
+        );
+        assert_eq!(user.custom_attributes, CustomAttributes::default());
+    }
+
+    #[test]
+    fn err_custom_attributes_of_another_tenant() {
+        let mut user = user();
+        user.tenant_id = TenantId("acme".to_string());
+        let mut attributes = CustomAttributes::default();
+        attributes.0.insert(
+            "employee_id".to_string(),
+            AttributeValue::String("E-042".to_string()),
+        );
+
+        let result = set_custom_attributes(&mut user, &schemas(), attributes);
+
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<DomainError>(),
+            Some(&DomainError::UnknownAttribute {
+                name: "employee_id".to_string()
+            })
+        );
+        assert_eq!(user.custom_attributes, CustomAttributes::default());
+    }
+}
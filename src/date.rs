@@ -0,0 +1,117 @@
+use anyhow::{Error, Result};
+use regex::Regex;
+use std::fmt::Display;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// Calendar date without time zone, as used by profile data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// (year, month, day) of a day count since 1970-01-01, proleptic Gregorian.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl Date {
+    /// UTC calendar date of `time`.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let days = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 86_400;
+        let (year, month, day) = civil_from_days(days as i64);
+        Self {
+            year: year as i32,
+            month,
+            day,
+        }
+    }
+
+    /// Completed years from `self` to `today`, as for an age.
+    pub fn years_until(&self, today: Date) -> i32 {
+        let birthday_passed = (today.month, today.day) >= (self.month, self.day);
+        today.year - self.year - i32::from(!birthday_passed)
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Parses an ISO 8601 calendar date (`YYYY-MM-DD`).
+pub fn check_date(date: String) -> Result<Date> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let re = PATTERN.get_or_init(|| Regex::new(r"^(\d{4})-(\d{2})-(\d{2})$").unwrap());
+    let captures = re
+        .captures(&date)
+        .ok_or_else(|| Error::msg("Invalid date"))?;
+    let year: i32 = captures[1].parse()?;
+    let month: u32 = captures[2].parse()?;
+    let day: u32 = captures[3].parse()?;
+
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(Error::msg("Invalid date"));
+    }
+    Ok(Date { year, month, day })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_check_date() {
+        let date = check_date("2024-02-29".to_string()).unwrap();
+
+        assert_eq!(
+            date,
+            Date {
+                year: 2024,
+                month: 2,
+                day: 29
+            }
+        );
+        assert_eq!(date.to_string(), "2024-02-29");
+    }
+
+    #[test]
+    fn err_invalid_date() {
+        for input in ["2023-02-29", "2023-13-01", "01/02/2023"] {
+            let result = check_date(input.to_string());
+
+            assert!(result.is_err());
+            assert_eq!(result.unwrap_err().to_string(), "Invalid date");
+        }
+    }
+}
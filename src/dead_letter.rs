@@ -0,0 +1,211 @@
+use crate::clock::Clock;
+use crate::events::{DomainEvent, EventHandler};
+use anyhow::{Error, Result};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// An event a handler gave up on, with enough context to decide what to do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub id: u64,
+    pub handler: String,
+    pub event: DomainEvent,
+    pub error: String,
+    pub failed_at: SystemTime,
+}
+
+pub trait DeadLetterStore {
+    fn park(
+        &self,
+        handler: &str,
+        event: DomainEvent,
+        error: String,
+        failed_at: SystemTime,
+    ) -> Result<u64>;
+    /// Every parked entry, oldest first.
+    fn list(&self) -> Result<Vec<DeadLetter>>;
+    fn get(&self, id: u64) -> Result<Option<DeadLetter>>;
+    fn remove(&self, id: u64) -> Result<Option<DeadLetter>>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryDeadLetterStore {
+    entries: Mutex<BTreeMap<u64, DeadLetter>>,
+    next_id: Mutex<u64>,
+}
+
+impl InMemoryDeadLetterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DeadLetterStore for InMemoryDeadLetterStore {
+    fn park(
+        &self,
+        handler: &str,
+        event: DomainEvent,
+        error: String,
+        failed_at: SystemTime,
+    ) -> Result<u64> {
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let id = *next_id;
+        self.entries.lock().unwrap().insert(
+            id,
+            DeadLetter {
+                id,
+                handler: handler.to_string(),
+                event,
+                error,
+                failed_at,
+            },
+        );
+        Ok(id)
+    }
+
+    fn list(&self) -> Result<Vec<DeadLetter>> {
+        Ok(self.entries.lock().unwrap().values().cloned().collect())
+    }
+
+    fn get(&self, id: u64) -> Result<Option<DeadLetter>> {
+        Ok(self.entries.lock().unwrap().get(&id).cloned())
+    }
+
+    fn remove(&self, id: u64) -> Result<Option<DeadLetter>> {
+        Ok(self.entries.lock().unwrap().remove(&id))
+    }
+}
+
+/// Parks events the inner handler fails on instead of failing the consumer,
+/// so one poison message does not block the ones after it. Usually wraps a
+/// [`crate::retry::RetryingHandler`].
+pub struct DeadLetteringHandler<'a, H: EventHandler, D: DeadLetterStore, C: Clock> {
+    inner: H,
+    store: &'a D,
+    clock: C,
+}
+
+impl<'a, H: EventHandler, D: DeadLetterStore, C: Clock> DeadLetteringHandler<'a, H, D, C> {
+    pub fn new(inner: H, store: &'a D, clock: C) -> Self {
+        Self {
+            inner,
+            store,
+            clock,
+        }
+    }
+}
+
+impl<H: EventHandler, D: DeadLetterStore, C: Clock> EventHandler
+    for DeadLetteringHandler<'_, H, D, C>
+{
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn handle(&self, event: &DomainEvent) -> Result<()> {
+        if let Err(error) = self.inner.handle(event) {
+            self.store.park(
+                self.inner.name(),
+                event.clone(),
+                error.to_string(),
+                self.clock.now(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Hands a parked event to `handler` again; the entry is removed only once
+/// the handler succeeds.
+pub fn requeue_dead_letter(
+    store: &impl DeadLetterStore,
+    id: u64,
+    handler: &dyn EventHandler,
+) -> Result<()> {
+    let entry = store
+        .get(id)?
+        .ok_or_else(|| Error::msg("Dead letter not found"))?;
+    if entry.handler != handler.name() {
+        return Err(Error::msg(format!(
+            "Dead letter belongs to handler {}",
+            entry.handler
+        )));
+    }
+    handler.handle(&entry.event)?;
+    store.remove(id)?;
+    Ok(())
+}
+
+pub fn discard_dead_letter(store: &impl DeadLetterStore, id: u64) -> Result<DeadLetter> {
+    store
+        .remove(id)?
+        .ok_or_else(|| Error::msg("Dead letter not found"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::events::EmailVerified;
+    use crate::UserId;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Default)]
+    struct FlakyHandler {
+        healthy: AtomicBool,
+    }
+
+    impl EventHandler for &FlakyHandler {
+        fn name(&self) -> &str {
+            "welcome_email"
+        }
+
+        fn handle(&self, _event: &DomainEvent) -> Result<()> {
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(Error::msg("SMTP relay rejected the message"))
+            }
+        }
+    }
+
+    fn email_verified() -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_park_then_requeue() {
+        let store = InMemoryDeadLetterStore::new();
+        let flaky = FlakyHandler::default();
+        let handler = DeadLetteringHandler::new(&flaky, &store, SystemClock);
+
+        handler.handle(&email_verified()).unwrap();
+
+        let parked = store.list().unwrap();
+        assert_eq!(parked.len(), 1);
+        assert_eq!(parked[0].handler, "welcome_email");
+        assert_eq!(parked[0].error, "SMTP relay rejected the message");
+        assert!(requeue_dead_letter(&store, parked[0].id, &&flaky).is_err());
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        flaky.healthy.store(true, Ordering::SeqCst);
+        requeue_dead_letter(&store, parked[0].id, &&flaky).unwrap();
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn err_discard_unknown_dead_letter() {
+        let store = InMemoryDeadLetterStore::new();
+
+        let result = discard_dead_letter(&store, 42);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Dead letter not found");
+    }
+}
@@ -0,0 +1,165 @@
+//! Who the users are: how old they are and how many verified their email,
+//! computed the same way over a repository or a projection.
+
+use crate::json::JsonValue;
+use crate::repository::UserRepository;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Width in years of each [`AgeBucket`].
+const AGE_BUCKET: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgeBucket {
+    pub from: i32,
+    /// Inclusive, e.g. 29 for the bucket from 20.
+    pub to: i32,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DemographicsReport {
+    pub total: usize,
+    pub verified: usize,
+    /// In age order. Empty buckets are left out.
+    pub age_distribution: Vec<AgeBucket>,
+}
+
+impl DemographicsReport {
+    /// Share of users with a verified email, 0 when there are none.
+    pub fn verified_ratio(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.verified as f64 / self.total as f64
+    }
+
+    /// Fields of the report, for embedding in a larger JSON object.
+    pub fn to_json_fields(&self) -> Vec<(String, JsonValue)> {
+        let number = |value: usize| JsonValue::Number(value as i64);
+        let ages = self
+            .age_distribution
+            .iter()
+            .map(|bucket| {
+                JsonValue::Object(vec![
+                    ("from".to_string(), JsonValue::Number(bucket.from.into())),
+                    ("to".to_string(), JsonValue::Number(bucket.to.into())),
+                    ("count".to_string(), number(bucket.count)),
+                ])
+            })
+            .collect();
+        vec![
+            ("total".to_string(), number(self.total)),
+            ("verified".to_string(), number(self.verified)),
+            (
+                "verified_percent".to_string(),
+                JsonValue::Number((self.verified_ratio() * 100.0).round() as i64),
+            ),
+            ("age_distribution".to_string(), JsonValue::Array(ages)),
+        ]
+    }
+}
+
+/// Tally fed one user at a time, by whatever source holds the users.
+#[derive(Debug, Default)]
+pub struct Demographics {
+    total: usize,
+    verified: usize,
+    ages: BTreeMap<i32, usize>,
+}
+
+impl Demographics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, age: i32, verified: bool) {
+        self.total += 1;
+        self.verified += usize::from(verified);
+        *self
+            .ages
+            .entry(age.div_euclid(AGE_BUCKET) * AGE_BUCKET)
+            .or_insert(0) += 1;
+    }
+
+    pub fn report(&self) -> DemographicsReport {
+        DemographicsReport {
+            total: self.total,
+            verified: self.verified,
+            age_distribution: self
+                .ages
+                .iter()
+                .map(|(from, count)| AgeBucket {
+                    from: *from,
+                    to: from + AGE_BUCKET - 1,
+                    count: *count,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Demographics of the live users of `repository`, read in one pass.
+/// Merged duplicates are left out.
+pub fn repository_demographics(repository: &impl UserRepository) -> Result<DemographicsReport> {
+    let mut demographics = Demographics::new();
+    repository.for_each(&mut |user| {
+        if user.merged_into.is_none() {
+            demographics.add(user.age.0, user.is_email_verified());
+        }
+        Ok(())
+    })?;
+    Ok(demographics.report())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{repository_with, UserFixture};
+    use crate::UserId;
+
+    #[test]
+    fn ok_repository_demographics() {
+        let mut merged = UserFixture::verified()
+            .with_email("merged@ok.com")
+            .with_age(35)
+            .build();
+        merged.merged_into = Some(UserId(1));
+        let repository = repository_with([
+            UserFixture::verified().with_age(22).build(),
+            UserFixture::new()
+                .with_email("second@ok.com")
+                .with_age(29)
+                .build(),
+            UserFixture::new()
+                .with_email("third@ok.com")
+                .with_age(41)
+                .build(),
+            merged,
+        ]);
+
+        let report = repository_demographics(&repository).unwrap();
+
+        assert_eq!(
+            report,
+            DemographicsReport {
+                total: 3,
+                verified: 1,
+                age_distribution: vec![
+                    AgeBucket {
+                        from: 20,
+                        to: 29,
+                        count: 2
+                    },
+                    AgeBucket {
+                        from: 40,
+                        to: 49,
+                        count: 1
+                    },
+                ],
+            }
+        );
+        assert_eq!(report.verified_ratio(), 1.0 / 3.0);
+        assert_eq!(DemographicsReport::default().verified_ratio(), 0.0);
+    }
+}
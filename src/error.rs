@@ -0,0 +1,146 @@
+use crate::custom_attributes::AttributeKind;
+use crate::UserId;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum DomainError {
+    InvalidEmail,
+    NegativeAge,
+    Underage {
+        min_age: i32,
+    },
+    AgeTooHigh,
+    InvalidPronouns,
+    InvalidTag,
+    EmailNotVerified,
+    BelowGrantingAge {
+        min_age: i32,
+    },
+    /// Another live user already owns the address.
+    EmailAlreadyRegistered,
+    /// Another writer saved the user after it was loaded.
+    ConcurrencyConflict {
+        user_id: UserId,
+        expected_version: u64,
+        actual_version: u64,
+    },
+    /// Valid on its own, but not saved because another input of an
+    /// all-or-nothing batch failed.
+    BatchAborted,
+    /// The users of a merge belong to different tenants.
+    CrossTenantMerge,
+    UnsupportedAvatarType,
+    AvatarTooLarge,
+    /// No uploaded file has the given avatar key.
+    AvatarNotFound,
+    /// The user's tenant defines no attribute with this name.
+    UnknownAttribute {
+        name: String,
+    },
+    InvalidAttribute {
+        name: String,
+        expected: AttributeKind,
+    },
+    MissingAttribute {
+        name: String,
+    },
+}
+
+impl DomainError {
+    /// Stable machine-readable identifier, for metrics and API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DomainError::InvalidEmail => "invalid_email",
+            DomainError::NegativeAge => "negative_age",
+            DomainError::Underage { .. } => "underage",
+            DomainError::AgeTooHigh => "age_too_high",
+            DomainError::InvalidPronouns => "invalid_pronouns",
+            DomainError::InvalidTag => "invalid_tag",
+            DomainError::EmailNotVerified => "email_not_verified",
+            DomainError::BelowGrantingAge { .. } => "below_granting_age",
+            DomainError::EmailAlreadyRegistered => "email_already_registered",
+            DomainError::ConcurrencyConflict { .. } => "concurrency_conflict",
+            DomainError::BatchAborted => "batch_aborted",
+            DomainError::CrossTenantMerge => "cross_tenant_merge",
+            DomainError::UnsupportedAvatarType => "unsupported_avatar_type",
+            DomainError::AvatarTooLarge => "avatar_too_large",
+            DomainError::AvatarNotFound => "avatar_not_found",
+            DomainError::UnknownAttribute { .. } => "unknown_attribute",
+            DomainError::InvalidAttribute { .. } => "invalid_attribute",
+            DomainError::MissingAttribute { .. } => "missing_attribute",
+        }
+    }
+
+    /// Input field at fault, for errors caused by one invalid value.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            DomainError::InvalidEmail | DomainError::EmailAlreadyRegistered => Some("email"),
+            DomainError::NegativeAge | DomainError::Underage { .. } | DomainError::AgeTooHigh => {
+                Some("age")
+            }
+            DomainError::InvalidPronouns => Some("pronouns"),
+            DomainError::InvalidTag => Some("tags"),
+            DomainError::UnsupportedAvatarType
+            | DomainError::AvatarTooLarge
+            | DomainError::AvatarNotFound => Some("avatar"),
+            DomainError::UnknownAttribute { .. }
+            | DomainError::InvalidAttribute { .. }
+            | DomainError::MissingAttribute { .. } => Some("custom_attributes"),
+            DomainError::EmailNotVerified
+            | DomainError::BelowGrantingAge { .. }
+            | DomainError::ConcurrencyConflict { .. }
+            | DomainError::BatchAborted
+            | DomainError::CrossTenantMerge => None,
+        }
+    }
+}
+
+impl Display for DomainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomainError::InvalidEmail => write!(f, "Invalid email"),
+            DomainError::NegativeAge => write!(f, "Age cannot be negative"),
+            DomainError::Underage { min_age } => write!(
+                f,
+                "Sorry but this service is unavailable for minor of {} years old",
+                min_age
+            ),
+            DomainError::AgeTooHigh => write!(f, "I don't think you can be immortal"),
+            DomainError::InvalidPronouns => write!(f, "Invalid pronouns"),
+            DomainError::InvalidTag => write!(f, "Invalid tag"),
+            DomainError::EmailNotVerified => write!(f, "Email has not been verified yet"),
+            DomainError::BelowGrantingAge { min_age } => {
+                write!(f, "Must be at least {} years old", min_age)
+            }
+            DomainError::EmailAlreadyRegistered => write!(f, "Email is already registered"),
+            DomainError::ConcurrencyConflict {
+                user_id,
+                expected_version,
+                actual_version,
+            } => write!(
+                f,
+                "User {} was modified concurrently (expected version {}, found {})",
+                user_id, expected_version, actual_version
+            ),
+            DomainError::BatchAborted => {
+                write!(f, "Not saved because another input of the batch failed")
+            }
+            DomainError::CrossTenantMerge => {
+                write!(f, "Cannot merge users of different tenants")
+            }
+            DomainError::UnsupportedAvatarType => {
+                write!(f, "Avatar must be a PNG, JPEG or WebP image")
+            }
+            DomainError::AvatarTooLarge => write!(f, "Avatar cannot be larger than 2 MiB"),
+            DomainError::AvatarNotFound => write!(f, "Avatar not found"),
+            DomainError::UnknownAttribute { name } => write!(f, "Unknown attribute {}", name),
+            DomainError::InvalidAttribute { name, expected } => {
+                write!(f, "Attribute {} must be a {:?}", name, expected)
+            }
+            DomainError::MissingAttribute { name } => write!(f, "Attribute {} is required", name),
+        }
+    }
+}
+
+impl std::error::Error for DomainError {}
@@ -0,0 +1,198 @@
+use crate::clock::{Clock, SystemClock};
+use crate::events::{DomainEvent, EventId, EventPublisher};
+use crate::json::{parse_json, JsonValue};
+use crate::versioning::{EventEnvelope, UpcasterChain};
+use crate::UserId;
+use anyhow::{Error, Result};
+use std::io::{BufRead, Write};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+/// An event as recorded in the store. Ids are assigned in append order, so
+/// they double as the global position of the event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEvent {
+    pub id: EventId,
+    pub recorded_at: SystemTime,
+    pub event: DomainEvent,
+}
+
+impl StoredEvent {
+    /// One line of the event log: id, recording time in seconds since the
+    /// Unix epoch, and the versioned event envelope.
+    pub fn to_json(&self) -> JsonValue {
+        let seconds = self
+            .recorded_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        JsonValue::Object(vec![
+            ("id".to_string(), JsonValue::Number(self.id.0 as i64)),
+            ("recorded_at".to_string(), JsonValue::Number(seconds as i64)),
+            (
+                "event".to_string(),
+                EventEnvelope::wrap(&self.event).to_json(),
+            ),
+        ])
+    }
+
+    pub fn parse(text: &str, upcasters: &UpcasterChain) -> Result<Self> {
+        let value = parse_json(text)?;
+        let invalid = || Error::msg("Invalid event log entry");
+        let number = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_i64)
+                .ok_or_else(invalid)
+        };
+        let envelope = value.get("event").ok_or_else(invalid)?.to_string();
+        Ok(Self {
+            id: EventId(number("id")? as u64),
+            recorded_at: SystemTime::UNIX_EPOCH
+                + Duration::from_secs(number("recorded_at")? as u64),
+            event: upcasters.upcast(EventEnvelope::parse(&envelope)?)?,
+        })
+    }
+}
+
+/// Append-only log of every domain event, the source of truth for replays
+/// and projections.
+pub trait EventStore {
+    fn append(&self, event: DomainEvent) -> Result<EventId>;
+    /// Events with an id greater than `after`, oldest first.
+    fn read_after(&self, after: Option<EventId>, limit: usize) -> Result<Vec<StoredEvent>>;
+    /// Every event of one user's stream, oldest first.
+    fn read_stream(&self, user_id: UserId) -> Result<Vec<StoredEvent>>;
+    /// Id of the most recent event, if any.
+    fn head(&self) -> Result<Option<EventId>>;
+}
+
+pub struct InMemoryEventStore<C: Clock = SystemClock> {
+    clock: C,
+    events: RwLock<Vec<StoredEvent>>,
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl Default for InMemoryEventStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> InMemoryEventStore<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            clock,
+            events: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Writes the whole log as newline-delimited JSON.
+    pub fn dump(&self, output: &mut impl Write) -> Result<()> {
+        for stored in self.events.read().unwrap().iter() {
+            writeln!(output, "{}", stored.to_json())?;
+        }
+        Ok(())
+    }
+
+    /// Restores a log written by [`InMemoryEventStore::dump`], upcasting
+    /// events recorded with older schemas.
+    pub fn load(&self, input: impl BufRead, upcasters: &UpcasterChain) -> Result<usize> {
+        let mut events = self.events.write().unwrap();
+        let mut loaded = 0;
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            events.push(StoredEvent::parse(&line, upcasters)?);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+}
+
+impl<C: Clock> EventStore for InMemoryEventStore<C> {
+    fn append(&self, event: DomainEvent) -> Result<EventId> {
+        let mut events = self.events.write().unwrap();
+        let id = EventId(events.last().map_or(0, |last| last.id.0) + 1);
+        events.push(StoredEvent {
+            id,
+            recorded_at: self.clock.now(),
+            event,
+        });
+        Ok(id)
+    }
+
+    fn read_after(&self, after: Option<EventId>, limit: usize) -> Result<Vec<StoredEvent>> {
+        Ok(self
+            .events
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|stored| after.is_none_or(|after| stored.id > after))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn read_stream(&self, user_id: UserId) -> Result<Vec<StoredEvent>> {
+        Ok(self
+            .events
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|stored| stored.event.user_id() == user_id)
+            .cloned()
+            .collect())
+    }
+
+    fn head(&self) -> Result<Option<EventId>> {
+        Ok(self.events.read().unwrap().last().map(|last| last.id))
+    }
+}
+
+/// Lets a unit of work record its committed events in the store.
+impl<C: Clock> EventPublisher for InMemoryEventStore<C> {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        self.append(event.clone())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::EmailVerified;
+
+    fn email_verified(user_id: u64) -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(user_id),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_dump_and_load() {
+        let store = InMemoryEventStore::new();
+        store.append(email_verified(1)).unwrap();
+        store.append(email_verified(2)).unwrap();
+        let mut log = Vec::new();
+        store.dump(&mut log).unwrap();
+
+        let restored = InMemoryEventStore::new();
+        let loaded = restored.load(&log[..], &UpcasterChain::new()).unwrap();
+
+        assert_eq!(loaded, 2);
+        let events = restored.read_after(Some(EventId(1)), 10).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, EventId(2));
+        assert_eq!(events[0].event, email_verified(2));
+        assert_eq!(restored.read_stream(UserId(1)).unwrap().len(), 1);
+    }
+}
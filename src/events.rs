@@ -0,0 +1,545 @@
+use crate::json::JsonValue;
+use crate::pii::{mask_email, mask_name};
+use crate::{User, UserId};
+use anyhow::{Error, Result};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// Identity of one occurrence of an event, stable across redeliveries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventId(pub u64);
+
+/// Debug output of the events masks emails and names; see [`crate::pii`].
+#[derive(Clone, PartialEq)]
+pub struct UserRegistered {
+    pub user_id: UserId,
+    pub email: String,
+    pub name: String,
+    pub middle_name: Option<String>,
+    pub surname: String,
+    pub age: i32,
+}
+
+impl Debug for UserRegistered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserRegistered")
+            .field("user_id", &self.user_id)
+            .field("email", &mask_email(&self.email))
+            .field("name", &mask_name(&self.name))
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .field("surname", &mask_name(&self.surname))
+            .field("age", &self.age)
+            .finish()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct EmailVerified {
+    pub user_id: UserId,
+    pub email: String,
+}
+
+impl Debug for EmailVerified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailVerified")
+            .field("user_id", &self.user_id)
+            .field("email", &mask_email(&self.email))
+            .finish()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct NameChanged {
+    pub user_id: UserId,
+    pub name: String,
+    pub middle_name: Option<String>,
+    pub surname: String,
+}
+
+impl Debug for NameChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NameChanged")
+            .field("user_id", &self.user_id)
+            .field("name", &mask_name(&self.name))
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .field("surname", &mask_name(&self.surname))
+            .finish()
+    }
+}
+
+/// A verification attempt was refused because the user made too many
+/// attempts recently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationThrottled {
+    pub user_id: UserId,
+    pub attempts: u32,
+}
+
+/// The primary as a merge left it, with what it took from the duplicate.
+/// Custom attributes are left out, as from every other event.
+#[derive(Clone, PartialEq)]
+pub struct MergedProfile {
+    pub email: String,
+    pub email_verified: bool,
+    pub middle_name: Option<String>,
+    pub pronouns: Option<String>,
+    pub avatar: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Debug for MergedProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MergedProfile")
+            .field("email", &mask_email(&self.email))
+            .field("email_verified", &self.email_verified)
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .field("pronouns", &self.pronouns)
+            .field("avatar", &self.avatar)
+            .field("tags", &self.tags)
+            .finish()
+    }
+}
+
+impl MergedProfile {
+    pub fn from_user(user: &User) -> Self {
+        Self {
+            email: user.email.address().0.clone(),
+            email_verified: user.is_email_verified(),
+            middle_name: user.middle_name.clone(),
+            pronouns: user.pronouns.as_ref().map(ToString::to_string),
+            avatar: user.avatar.as_ref().map(|avatar| avatar.0.clone()),
+            tags: user.tags.iter().map(|tag| tag.0.clone()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsersMerged {
+    pub primary_id: UserId,
+    pub duplicate_id: UserId,
+    /// `None` in events recorded before merges carried the profile.
+    pub merged: Option<MergedProfile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DomainEvent {
+    UserRegistered(UserRegistered),
+    EmailVerified(EmailVerified),
+    NameChanged(NameChanged),
+    VerificationThrottled(VerificationThrottled),
+    UsersMerged(UsersMerged),
+}
+
+impl UserRegistered {
+    pub fn from_user(user: &User) -> Self {
+        Self {
+            user_id: user.id,
+            email: user.email.address().as_str().to_string(),
+            name: user.name.clone(),
+            middle_name: user.middle_name.clone(),
+            surname: user.surname.clone(),
+            age: user.age.0,
+        }
+    }
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::UserRegistered(_) => "UserRegistered",
+            DomainEvent::EmailVerified(_) => "EmailVerified",
+            DomainEvent::NameChanged(_) => "NameChanged",
+            DomainEvent::VerificationThrottled(_) => "VerificationThrottled",
+            DomainEvent::UsersMerged(_) => "UsersMerged",
+        }
+    }
+
+    /// Wire representation: the payload fields plus a `type` discriminator.
+    pub fn to_json(&self) -> JsonValue {
+        let string = |value: &str| JsonValue::String(value.to_string());
+        let id = |id: UserId| JsonValue::Number(id.0 as i64);
+        let mut fields = vec![("type".to_string(), string(self.name()))];
+        match self {
+            DomainEvent::UserRegistered(event) => fields.extend([
+                ("user_id".to_string(), id(event.user_id)),
+                ("email".to_string(), string(&event.email)),
+                ("name".to_string(), string(&event.name)),
+                (
+                    "middle_name".to_string(),
+                    event.middle_name.as_deref().map_or(JsonValue::Null, string),
+                ),
+                ("surname".to_string(), string(&event.surname)),
+                ("age".to_string(), JsonValue::Number(event.age as i64)),
+            ]),
+            DomainEvent::EmailVerified(event) => fields.extend([
+                ("user_id".to_string(), id(event.user_id)),
+                ("email".to_string(), string(&event.email)),
+            ]),
+            DomainEvent::NameChanged(event) => fields.extend([
+                ("user_id".to_string(), id(event.user_id)),
+                ("name".to_string(), string(&event.name)),
+                (
+                    "middle_name".to_string(),
+                    event.middle_name.as_deref().map_or(JsonValue::Null, string),
+                ),
+                ("surname".to_string(), string(&event.surname)),
+            ]),
+            DomainEvent::VerificationThrottled(event) => fields.extend([
+                ("user_id".to_string(), id(event.user_id)),
+                (
+                    "attempts".to_string(),
+                    JsonValue::Number(event.attempts as i64),
+                ),
+            ]),
+            DomainEvent::UsersMerged(event) => fields.extend([
+                ("primary_id".to_string(), id(event.primary_id)),
+                ("duplicate_id".to_string(), id(event.duplicate_id)),
+                (
+                    "merged".to_string(),
+                    event.merged.as_ref().map_or(JsonValue::Null, |merged| {
+                        let optional = |value: &Option<String>| {
+                            value.as_deref().map_or(JsonValue::Null, string)
+                        };
+                        JsonValue::Object(vec![
+                            ("email".to_string(), string(&merged.email)),
+                            (
+                                "email_verified".to_string(),
+                                JsonValue::Bool(merged.email_verified),
+                            ),
+                            ("middle_name".to_string(), optional(&merged.middle_name)),
+                            ("pronouns".to_string(), optional(&merged.pronouns)),
+                            ("avatar".to_string(), optional(&merged.avatar)),
+                            (
+                                "tags".to_string(),
+                                JsonValue::Array(
+                                    merged.tags.iter().map(|tag| string(tag)).collect(),
+                                ),
+                            ),
+                        ])
+                    }),
+                ),
+            ]),
+        }
+        JsonValue::Object(fields)
+    }
+
+    /// Reads back the shape produced by [`DomainEvent::to_json`].
+    pub fn from_json(value: &JsonValue) -> Result<Self> {
+        let invalid = || Error::msg("Invalid event payload");
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+                .ok_or_else(invalid)
+        };
+        let optional_string = |key: &str| match value.get(key) {
+            Some(JsonValue::Null) => Ok(None),
+            _ => string(key).map(Some),
+        };
+        let number = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_i64)
+                .ok_or_else(invalid)
+        };
+        let id = |key: &str| number(key).map(|id| UserId(id as u64));
+
+        match value.get("type").and_then(JsonValue::as_str) {
+            Some("UserRegistered") => Ok(DomainEvent::UserRegistered(UserRegistered {
+                user_id: id("user_id")?,
+                email: string("email")?,
+                name: string("name")?,
+                middle_name: optional_string("middle_name")?,
+                surname: string("surname")?,
+                age: number("age")? as i32,
+            })),
+            Some("EmailVerified") => Ok(DomainEvent::EmailVerified(EmailVerified {
+                user_id: id("user_id")?,
+                email: string("email")?,
+            })),
+            Some("NameChanged") => Ok(DomainEvent::NameChanged(NameChanged {
+                user_id: id("user_id")?,
+                name: string("name")?,
+                middle_name: optional_string("middle_name")?,
+                surname: string("surname")?,
+            })),
+            Some("VerificationThrottled") => {
+                Ok(DomainEvent::VerificationThrottled(VerificationThrottled {
+                    user_id: id("user_id")?,
+                    attempts: number("attempts")? as u32,
+                }))
+            }
+            Some("UsersMerged") => Ok(DomainEvent::UsersMerged(UsersMerged {
+                primary_id: id("primary_id")?,
+                duplicate_id: id("duplicate_id")?,
+                merged: match value.get("merged") {
+                    None | Some(JsonValue::Null) => None,
+                    Some(merged) => Some(MergedProfile::from_json(merged)?),
+                },
+            })),
+            _ => Err(Error::msg("Unknown event type")),
+        }
+    }
+
+    /// The user whose stream the event belongs to.
+    pub fn user_id(&self) -> UserId {
+        match self {
+            DomainEvent::UserRegistered(event) => event.user_id,
+            DomainEvent::EmailVerified(event) => event.user_id,
+            DomainEvent::NameChanged(event) => event.user_id,
+            DomainEvent::VerificationThrottled(event) => event.user_id,
+            DomainEvent::UsersMerged(event) => event.primary_id,
+        }
+    }
+}
+
+impl MergedProfile {
+    fn from_json(value: &JsonValue) -> Result<Self> {
+        let invalid = || Error::msg("Invalid event payload");
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+                .ok_or_else(invalid)
+        };
+        let optional_string = |key: &str| match value.get(key) {
+            None | Some(JsonValue::Null) => Ok(None),
+            _ => string(key).map(Some),
+        };
+        let email_verified = match value.get("email_verified") {
+            Some(JsonValue::Bool(verified)) => *verified,
+            _ => return Err(invalid()),
+        };
+        let tags = match value.get("tags") {
+            Some(JsonValue::Array(tags)) => tags
+                .iter()
+                .map(|tag| tag.as_str().map(str::to_string).ok_or_else(invalid))
+                .collect::<Result<_>>()?,
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            email: string("email")?,
+            email_verified,
+            middle_name: optional_string("middle_name")?,
+            pronouns: optional_string("pronouns")?,
+            avatar: optional_string("avatar")?,
+            tags,
+        })
+    }
+}
+
+impl From<UserRegistered> for DomainEvent {
+    fn from(event: UserRegistered) -> Self {
+        DomainEvent::UserRegistered(event)
+    }
+}
+
+impl From<EmailVerified> for DomainEvent {
+    fn from(event: EmailVerified) -> Self {
+        DomainEvent::EmailVerified(event)
+    }
+}
+
+impl From<NameChanged> for DomainEvent {
+    fn from(event: NameChanged) -> Self {
+        DomainEvent::NameChanged(event)
+    }
+}
+
+impl From<VerificationThrottled> for DomainEvent {
+    fn from(event: VerificationThrottled) -> Self {
+        DomainEvent::VerificationThrottled(event)
+    }
+}
+
+impl From<UsersMerged> for DomainEvent {
+    fn from(event: UsersMerged) -> Self {
+        DomainEvent::UsersMerged(event)
+    }
+}
+
+/// Port through which committed domain events leave the application.
+pub trait EventPublisher {
+    fn publish(&self, event: &DomainEvent) -> Result<()>;
+
+    /// Publishes an event the outbox recorded as `id`. Publishers that carry
+    /// an id to consumers override this so redeliveries can be recognised.
+    fn publish_with_id(&self, _id: EventId, event: &DomainEvent) -> Result<()> {
+        self.publish(event)
+    }
+}
+
+/// Consumer of published events (projection, notifier, ...). The name
+/// identifies the handler in bookkeeping such as the inbox.
+pub trait EventHandler {
+    fn name(&self) -> &str;
+    fn handle(&self, event: &DomainEvent) -> Result<()>;
+}
+
+/// Publisher that simply remembers what it was given, for tests and demos.
+#[derive(Debug, Default)]
+pub struct InMemoryEventPublisher {
+    events: Mutex<Vec<DomainEvent>>,
+}
+
+impl InMemoryEventPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn published(&self) -> Vec<DomainEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl EventPublisher for InMemoryEventPublisher {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+}
+
+/// Hands every event to each of its publishers in turn, stopping at the
+/// first that fails.
+#[derive(Default)]
+pub struct FanOutPublisher {
+    publishers: Vec<Arc<dyn EventPublisher + Send + Sync>>,
+}
+
+impl FanOutPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_publisher(mut self, publisher: Arc<dyn EventPublisher + Send + Sync>) -> Self {
+        self.publishers.push(publisher);
+        self
+    }
+}
+
+impl EventPublisher for FanOutPublisher {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        self.publishers
+            .iter()
+            .try_for_each(|publisher| publisher.publish(event))
+    }
+
+    fn publish_with_id(&self, id: EventId, event: &DomainEvent) -> Result<()> {
+        self.publishers
+            .iter()
+            .try_for_each(|publisher| publisher.publish_with_id(id, event))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{assert_debug_masks, assert_json_snapshot};
+
+    #[test]
+    fn ok_event_wire_formats_match_snapshots() {
+        let events = [
+            DomainEvent::UserRegistered(UserRegistered {
+                user_id: UserId(7),
+                email: "foo@ok.com".to_string(),
+                name: "Luca".to_string(),
+                middle_name: Some("Maria".to_string()),
+                surname: "Rossi".to_string(),
+                age: 22,
+            }),
+            DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(7),
+                email: "foo@ok.com".to_string(),
+            }),
+            DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(7),
+                name: "Anna".to_string(),
+                middle_name: None,
+                surname: "Bianchi".to_string(),
+            }),
+            DomainEvent::VerificationThrottled(VerificationThrottled {
+                user_id: UserId(7),
+                attempts: 5,
+            }),
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(7),
+                duplicate_id: UserId(8),
+                merged: Some(MergedProfile {
+                    email: "foo@ok.com".to_string(),
+                    email_verified: true,
+                    middle_name: Some("Maria".to_string()),
+                    pronouns: Some("they/them".to_string()),
+                    avatar: None,
+                    tags: vec!["vip".to_string()],
+                }),
+            }),
+        ];
+
+        for event in events {
+            assert_json_snapshot(&format!("event_{}", event.name()), &event.to_json());
+            assert_eq!(DomainEvent::from_json(&event.to_json()).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn ok_events_debug_masks_pii() {
+        let registered = UserRegistered {
+            user_id: UserId(7),
+            email: "luca.rossi@ok.com".to_string(),
+            name: "Luca".to_string(),
+            middle_name: Some("Maria".to_string()),
+            surname: "Rossi".to_string(),
+            age: 22,
+        };
+        assert!(format!("{:?}", registered).contains("l***@ok.com"));
+        let events = [
+            DomainEvent::UserRegistered(registered),
+            DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(7),
+                email: "luca.rossi@ok.com".to_string(),
+            }),
+            DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(7),
+                name: "Luca".to_string(),
+                middle_name: Some("Maria".to_string()),
+                surname: "Rossi".to_string(),
+            }),
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(7),
+                duplicate_id: UserId(8),
+                merged: Some(MergedProfile {
+                    email: "luca.rossi@ok.com".to_string(),
+                    email_verified: true,
+                    middle_name: Some("Maria".to_string()),
+                    pronouns: None,
+                    avatar: None,
+                    tags: Vec::new(),
+                }),
+            }),
+        ];
+
+        for event in &events {
+            assert_debug_masks(event, &["luca.rossi", "Luca", "Maria", "Rossi"]);
+        }
+    }
+
+    #[test]
+    fn ok_fan_out_to_every_publisher() {
+        let first = Arc::new(InMemoryEventPublisher::new());
+        let second = Arc::new(InMemoryEventPublisher::new());
+        let fan_out = FanOutPublisher::new()
+            .with_publisher(first.clone())
+            .with_publisher(second.clone());
+        let event = DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+        });
+
+        fan_out.publish(&event).unwrap();
+
+        assert_eq!(first.published(), vec![event.clone()]);
+        assert_eq!(second.published(), vec![event]);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::json::JsonValue;
+use crate::pii::{mask_email, mask_name};
+use crate::repository::UserRepository;
+use crate::tags::TagFilter;
+use crate::User;
+use anyhow::Result;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportColumn {
+    Id,
+    Email,
+    EmailVerified,
+    Age,
+    Name,
+    MiddleName,
+    Surname,
+    Tags,
+}
+
+impl ExportColumn {
+    pub const ALL: [ExportColumn; 8] = [
+        ExportColumn::Id,
+        ExportColumn::Email,
+        ExportColumn::EmailVerified,
+        ExportColumn::Age,
+        ExportColumn::Name,
+        ExportColumn::MiddleName,
+        ExportColumn::Surname,
+        ExportColumn::Tags,
+    ];
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            ExportColumn::Id => "id",
+            ExportColumn::Email => "email",
+            ExportColumn::EmailVerified => "email_verified",
+            ExportColumn::Age => "age",
+            ExportColumn::Name => "name",
+            ExportColumn::MiddleName => "middle_name",
+            ExportColumn::Surname => "surname",
+            ExportColumn::Tags => "tags",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub columns: Vec<ExportColumn>,
+    /// Replace emails and names with their masked form.
+    pub mask_pii: bool,
+    pub filter: TagFilter,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Csv,
+            columns: ExportColumn::ALL.to_vec(),
+            mask_pii: false,
+            filter: TagFilter::default(),
+        }
+    }
+}
+
+fn column_value(user: &User, column: ExportColumn, mask_pii: bool) -> JsonValue {
+    let text = |value: &str| {
+        if mask_pii {
+            JsonValue::String(mask_name(value))
+        } else {
+            JsonValue::String(value.to_string())
+        }
+    };
+    match column {
+        ExportColumn::Id => JsonValue::Number(user.id.0 as i64),
+        ExportColumn::Email => {
+            let email = user.email.address().as_str();
+            if mask_pii {
+                JsonValue::String(mask_email(email))
+            } else {
+                JsonValue::String(email.to_string())
+            }
+        }
+        ExportColumn::EmailVerified => JsonValue::Bool(user.is_email_verified()),
+        ExportColumn::Age => JsonValue::Number(user.age.0 as i64),
+        ExportColumn::Name => text(&user.name),
+        ExportColumn::MiddleName => user.middle_name.as_deref().map_or(JsonValue::Null, text),
+        ExportColumn::Surname => text(&user.surname),
+        ExportColumn::Tags => JsonValue::Array(
+            user.tags
+                .iter()
+                .map(|tag| JsonValue::String(tag.0.clone()))
+                .collect(),
+        ),
+    }
+}
+
+fn csv_field(value: JsonValue) -> String {
+    let field = match value {
+        JsonValue::Null => String::new(),
+        JsonValue::String(value) => value,
+        JsonValue::Array(values) => values
+            .into_iter()
+            .map(csv_field)
+            .collect::<Vec<_>>()
+            .join(";"),
+        other => other.to_string(),
+    };
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Writes the matching users to `writer` one row at a time and returns how
+/// many were exported. Merged-away tombstones are skipped.
+pub fn export_users(
+    repository: &impl UserRepository,
+    options: &ExportOptions,
+    writer: &mut impl Write,
+) -> Result<usize> {
+    if options.format == ExportFormat::Csv {
+        let headers: Vec<_> = options
+            .columns
+            .iter()
+            .map(|column| column.header())
+            .collect();
+        writeln!(writer, "{}", headers.join(","))?;
+    }
+
+    let mut exported = 0;
+    repository.for_each(&mut |user| {
+        if user.merged_into.is_some() || !options.filter.matches(user) {
+            return Ok(());
+        }
+        let values = options
+            .columns
+            .iter()
+            .map(|column| (column, column_value(user, *column, options.mask_pii)));
+        match options.format {
+            ExportFormat::Csv => {
+                let fields: Vec<_> = values.map(|(_, value)| csv_field(value)).collect();
+                writeln!(writer, "{}", fields.join(","))?;
+            }
+            ExportFormat::Ndjson => {
+                let fields = values
+                    .map(|(column, value)| (column.header().to_string(), value))
+                    .collect();
+                writeln!(writer, "{}", JsonValue::Object(fields))?;
+            }
+        }
+        exported += 1;
+        Ok(())
+    })?;
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+    use crate::tags::{add_tag, check_tag};
+    use crate::{create_user, grant_user};
+
+    fn repository() -> InMemoryUserRepository {
+        let repository = InMemoryUserRepository::new();
+        let input_email = "foo@ok.com".to_string();
+        let input_age = 22;
+        let name = "Luca".to_string();
+        let surname = "Rossi, Jr".to_string();
+        let middle_name: Option<String> = None;
+
+        let mut user = create_user(input_email, input_age, name, surname, middle_name).unwrap();
+        grant_user(&mut user).unwrap();
+        add_tag(&mut user, check_tag("vip".to_string()).unwrap());
+        repository.save(user).unwrap();
+
+        let other = create_user(
+            "bar@unverified.com".to_string(),
+            40,
+            "Anna".to_string(),
+            "Bianchi".to_string(),
+            Some("Maria".to_string()),
+        )
+        .unwrap();
+        repository.save(other).unwrap();
+        repository
+    }
+
+    #[test]
+    fn ok_export_csv() {
+        let options = ExportOptions {
+            columns: vec![
+                ExportColumn::Email,
+                ExportColumn::Surname,
+                ExportColumn::MiddleName,
+                ExportColumn::Tags,
+            ],
+            ..ExportOptions::default()
+        };
+        let mut output = Vec::new();
+
+        let exported = export_users(&repository(), &options, &mut output).unwrap();
+
+        assert_eq!(exported, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "email,surname,middle_name,tags\n\
+             foo@ok.com,\"Rossi, Jr\",,vip\n\
+             bar@unverified.com,Bianchi,Maria,\n"
+        );
+    }
+
+    #[test]
+    fn ok_export_ndjson_masked_and_filtered() {
+        let options = ExportOptions {
+            format: ExportFormat::Ndjson,
+            columns: vec![
+                ExportColumn::Email,
+                ExportColumn::EmailVerified,
+                ExportColumn::Age,
+                ExportColumn::Name,
+            ],
+            mask_pii: true,
+            filter: TagFilter {
+                all_of: vec![check_tag("vip".to_string()).unwrap()],
+                ..TagFilter::default()
+            },
+        };
+        let mut output = Vec::new();
+
+        let exported = export_users(&repository(), &options, &mut output).unwrap();
+
+        assert_eq!(exported, 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"email\":\"f***@ok.com\",\"email_verified\":true,\"age\":22,\"name\":\"L***\"}\n"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{TenantId, UserId};
+use anyhow::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+
+/// Lets users change their own name through `ChangeName`.
+pub const SELF_SERVICE_NAME_CHANGE: &str = "self_service_name_change";
+
+/// Port to the feature flag provider. Unknown flags are disabled.
+pub trait FeatureFlags {
+    fn is_enabled(&self, flag: &str, tenant_id: &TenantId, user_id: Option<UserId>)
+        -> Result<bool>;
+}
+
+/// Who a flag is enabled for: everyone, or the listed tenants and users.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlagRule {
+    pub enabled: bool,
+    pub tenants: BTreeSet<TenantId>,
+    pub users: BTreeSet<UserId>,
+}
+
+impl FlagRule {
+    fn matches(&self, tenant_id: &TenantId, user_id: Option<UserId>) -> bool {
+        self.enabled
+            || self.tenants.contains(tenant_id)
+            || user_id.is_some_and(|user_id| self.users.contains(&user_id))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryFeatureFlags {
+    rules: RwLock<BTreeMap<String, FlagRule>>,
+}
+
+impl InMemoryFeatureFlags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, flag: &str, rule: FlagRule) {
+        self.rules.write().unwrap().insert(flag.to_string(), rule);
+    }
+
+    /// Reads a provider file with one flag per line:
+    ///
+    /// ```text
+    /// # flag  default  targets...
+    /// self_service_name_change off tenant:acme user:42
+    /// ```
+    pub fn from_file_contents(contents: &str) -> Result<Self> {
+        let flags = Self::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || Error::msg(format!("Invalid feature flag on line {}", index + 1));
+            let mut parts = line.split_whitespace();
+            let flag = parts.next().ok_or_else(invalid)?;
+            let mut rule = FlagRule {
+                enabled: match parts.next() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => return Err(invalid()),
+                },
+                ..FlagRule::default()
+            };
+            for target in parts {
+                match target.split_once(':') {
+                    Some(("tenant", tenant)) => {
+                        rule.tenants.insert(TenantId(tenant.to_string()));
+                    }
+                    Some(("user", user)) => {
+                        rule.users.insert(user.parse().map_err(|_| invalid())?);
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            flags.set(flag, rule);
+        }
+        Ok(flags)
+    }
+}
+
+impl FeatureFlags for InMemoryFeatureFlags {
+    fn is_enabled(
+        &self,
+        flag: &str,
+        tenant_id: &TenantId,
+        user_id: Option<UserId>,
+    ) -> Result<bool> {
+        Ok(self
+            .rules
+            .read()
+            .unwrap()
+            .get(flag)
+            .is_some_and(|rule| rule.matches(tenant_id, user_id)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_targeting_from_file() {
+        let flags = InMemoryFeatureFlags::from_file_contents(
+            "# rollout\nself_service_name_change off tenant:acme user:42\nnew_onboarding on\n",
+        )
+        .unwrap();
+        let acme = TenantId("acme".to_string());
+        let globex = TenantId("globex".to_string());
+
+        assert!(flags
+            .is_enabled(SELF_SERVICE_NAME_CHANGE, &acme, None)
+            .unwrap());
+        assert!(flags
+            .is_enabled(SELF_SERVICE_NAME_CHANGE, &globex, Some(UserId(42)))
+            .unwrap());
+        assert!(!flags
+            .is_enabled(SELF_SERVICE_NAME_CHANGE, &globex, Some(UserId(7)))
+            .unwrap());
+        assert!(flags.is_enabled("new_onboarding", &globex, None).unwrap());
+        assert!(!flags.is_enabled("unknown", &acme, None).unwrap());
+    }
+
+    #[test]
+    fn err_invalid_flag_file() {
+        let result = InMemoryFeatureFlags::from_file_contents("beta maybe\n");
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid feature flag on line 1");
+    }
+}
@@ -0,0 +1,352 @@
+use crate::api::InvalidRequest;
+use crate::clock::Clock;
+use crate::events::{DomainEvent, EventPublisher};
+use crate::http::{HttpRequest, HttpResponse, Router};
+use crate::json::{parse_json, JsonValue};
+use anyhow::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Messages a client can send over the feed, e.g.
+/// `{"action":"subscribe","types":["EmailVerified"]}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+    /// Answer to a heartbeat.
+    Pong,
+}
+
+pub fn parse_client_message(text: &str) -> Result<ClientMessage> {
+    let message = parse_json(text)?;
+    let types = || -> Result<Vec<String>> {
+        match message.get("types") {
+            Some(JsonValue::Array(values)) => values
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| Error::msg("Event types must be strings"))
+                })
+                .collect(),
+            _ => Err(Error::msg("Missing event types")),
+        }
+    };
+
+    match message.get("action").and_then(JsonValue::as_str) {
+        Some("subscribe") => Ok(ClientMessage::Subscribe(types()?)),
+        Some("unsubscribe") => Ok(ClientMessage::Unsubscribe(types()?)),
+        Some("pong") => Ok(ClientMessage::Pong),
+        _ => Err(Error::msg("Unknown action")),
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    event_types: BTreeSet<String>,
+    last_seen: SystemTime,
+    outgoing: Vec<String>,
+}
+
+/// Transport-independent state of a WebSocket event feed. The socket layer
+/// forwards incoming text with [`EventFeed::receive`], writes whatever
+/// [`EventFeed::drain`] returns, and calls [`EventFeed::tick`] periodically.
+pub struct EventFeed<C: Clock> {
+    clock: C,
+    /// Connections silent for longer than this are considered dead.
+    timeout: Duration,
+    connections: Mutex<BTreeMap<u64, Connection>>,
+    next_id: Mutex<u64>,
+}
+
+impl<C: Clock> EventFeed<C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            timeout: Duration::from_secs(90),
+            connections: Mutex::new(BTreeMap::new()),
+            next_id: Mutex::new(1),
+        }
+    }
+
+    pub fn connect(&self) -> u64 {
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = *next_id;
+        *next_id += 1;
+        self.connections.lock().unwrap().insert(
+            id,
+            Connection {
+                event_types: BTreeSet::new(),
+                last_seen: self.clock.now(),
+                outgoing: Vec::new(),
+            },
+        );
+        id
+    }
+
+    pub fn disconnect(&self, connection_id: u64) {
+        self.connections.lock().unwrap().remove(&connection_id);
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_connected(&self, connection_id: u64) -> bool {
+        self.connections
+            .lock()
+            .unwrap()
+            .contains_key(&connection_id)
+    }
+
+    pub fn receive(&self, connection_id: u64, text: &str) -> Result<()> {
+        let mut connections = self.connections.lock().unwrap();
+        let connection = connections
+            .get_mut(&connection_id)
+            .ok_or_else(|| Error::msg("Connection not found"))?;
+        connection.last_seen = self.clock.now();
+
+        match parse_client_message(text)? {
+            ClientMessage::Subscribe(types) => connection.event_types.extend(types),
+            ClientMessage::Unsubscribe(types) => {
+                for event_type in types {
+                    connection.event_types.remove(&event_type);
+                }
+            }
+            ClientMessage::Pong => {}
+        }
+        Ok(())
+    }
+
+    /// Frames waiting to be written to the connection's socket.
+    pub fn drain(&self, connection_id: u64) -> Vec<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get_mut(&connection_id)
+            .map(|connection| std::mem::take(&mut connection.outgoing))
+            .unwrap_or_default()
+    }
+
+    /// Queues a heartbeat on every connection and drops the ones that have
+    /// not answered within the timeout, returning their ids.
+    pub fn tick(&self) -> Vec<u64> {
+        let now = self.clock.now();
+        let mut connections = self.connections.lock().unwrap();
+        let dead: Vec<u64> = connections
+            .iter()
+            .filter(|(_, connection)| {
+                now.duration_since(connection.last_seen)
+                    .is_ok_and(|silence| silence > self.timeout)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &dead {
+            connections.remove(id);
+        }
+
+        let heartbeat = JsonValue::Object(vec![(
+            "type".to_string(),
+            JsonValue::String("heartbeat".to_string()),
+        )])
+        .to_string();
+        for connection in connections.values_mut() {
+            connection.outgoing.push(heartbeat.clone());
+        }
+        dead
+    }
+
+    /// Ticks every `interval` until `stop` is set.
+    pub fn run(&self, interval: Duration, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            self.tick();
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+impl<C: Clock> EventPublisher for EventFeed<C> {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        let frame = event.to_json().to_string();
+        for connection in self.connections.lock().unwrap().values_mut() {
+            if connection.event_types.contains(event.name()) {
+                connection.outgoing.push(frame.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn connection_id(request: &HttpRequest) -> Result<u64, InvalidRequest> {
+    request
+        .query("connection_id")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| InvalidRequest {
+            field: "connection_id".to_string(),
+            reason: "must be a number".to_string(),
+        })
+}
+
+fn frames(frames: Vec<String>) -> HttpResponse {
+    HttpResponse {
+        status: 200,
+        content_type: "application/json".to_string(),
+        body: format!("[{}]", frames.join(",")),
+    }
+}
+
+/// Carries the feed over plain HTTP: `POST /feed/connect` opens a connection
+/// and answers its `connection_id`, `POST /feed/messages?connection_id=...`
+/// sends a [`ClientMessage`] and `GET /feed/messages?connection_id=...`
+/// polls. Both message routes answer the frames queued since the last call.
+/// Connections dropped by [`EventFeed::tick`] answer 404.
+pub fn feed_routes<C: Clock + Send + Sync + 'static>(
+    router: Router,
+    feed: Arc<EventFeed<C>>,
+) -> Router {
+    let (sending, polling) = (Arc::clone(&feed), Arc::clone(&feed));
+    router
+        .route("POST", "/feed/connect", move |_| HttpResponse {
+            status: 201,
+            content_type: "application/json".to_string(),
+            body: JsonValue::Object(vec![(
+                "connection_id".to_string(),
+                JsonValue::Number(feed.connect() as i64),
+            )])
+            .to_string(),
+        })
+        .try_route("POST", "/feed/messages", move |request| {
+            let id = connection_id(request)?;
+            if !sending.is_connected(id) {
+                return Ok(HttpResponse::text(404, "Connection not found"));
+            }
+            sending
+                .receive(id, &request.body)
+                .map_err(|error| InvalidRequest {
+                    field: "body".to_string(),
+                    reason: format!("is not a feed message ({})", error),
+                })?;
+            Ok(frames(sending.drain(id)))
+        })
+        .try_route("GET", "/feed/messages", move |request| {
+            let id = connection_id(request)?;
+            if !polling.is_connected(id) {
+                return Ok(HttpResponse::text(404, "Connection not found"));
+            }
+            Ok(frames(polling.drain(id)))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::events::EmailVerified;
+    use crate::UserId;
+
+    fn email_verified() -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_subscribe_and_unsubscribe() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let feed = EventFeed::new(&clock);
+        let connection = feed.connect();
+
+        feed.publish(&email_verified()).unwrap();
+        assert!(feed.drain(connection).is_empty());
+
+        feed.receive(
+            connection,
+            r#"{"action":"subscribe","types":["EmailVerified"]}"#,
+        )
+        .unwrap();
+        feed.publish(&email_verified()).unwrap();
+        assert_eq!(
+            feed.drain(connection),
+            vec![email_verified().to_json().to_string()]
+        );
+
+        feed.receive(
+            connection,
+            r#"{"action":"unsubscribe","types":["EmailVerified"]}"#,
+        )
+        .unwrap();
+        feed.publish(&email_verified()).unwrap();
+        assert!(feed.drain(connection).is_empty());
+    }
+
+    #[test]
+    fn ok_heartbeat_drops_dead_connections() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let feed = EventFeed::new(&clock);
+        let alive = feed.connect();
+        let dead = feed.connect();
+
+        clock.advance(Duration::from_secs(60));
+        assert!(feed.tick().is_empty());
+        assert_eq!(feed.drain(alive), vec![r#"{"type":"heartbeat"}"#]);
+        feed.receive(alive, r#"{"action":"pong"}"#).unwrap();
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(feed.tick(), vec![dead]);
+        assert_eq!(feed.connection_count(), 1);
+    }
+
+    #[test]
+    fn ok_feed_over_http() {
+        let clock = Box::leak(Box::new(TestClock::new(SystemTime::UNIX_EPOCH)));
+        let feed = Arc::new(EventFeed::new(&*clock));
+        let router = feed_routes(Router::new(), Arc::clone(&feed));
+        let call = |method: &str, path: &str, body: &str| {
+            router.handle(&HttpRequest {
+                method: method.to_string(),
+                path: path.to_string(),
+                headers: Vec::new(),
+                body: body.to_string(),
+                peer: None,
+            })
+        };
+
+        let connected = call("POST", "/feed/connect", "");
+        assert_eq!(connected.status, 201);
+        let id = parse_json(&connected.body)
+            .unwrap()
+            .get("connection_id")
+            .cloned()
+            .unwrap();
+        let messages = format!("/feed/messages?connection_id={}", id);
+        let subscribed = call(
+            "POST",
+            &messages,
+            r#"{"action":"subscribe","types":["EmailVerified"]}"#,
+        );
+        feed.publish(&email_verified()).unwrap();
+        let polled = call("GET", &messages, "");
+        let invalid = call("POST", &messages, r#"{"action":"shout"}"#);
+        clock.advance(Duration::from_secs(120));
+        feed.tick();
+        let dropped = call("GET", &messages, "");
+
+        assert_eq!(subscribed.body, "[]");
+        assert_eq!(polled.body, format!("[{}]", email_verified().to_json()));
+        assert_eq!(invalid.status, 400);
+        assert_eq!(dropped.status, 404);
+    }
+
+    #[test]
+    fn err_unknown_action() {
+        let result = parse_client_message(r#"{"action":"shout"}"#);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Unknown action");
+    }
+}
@@ -0,0 +1,260 @@
+//! C ABI over the validation and user-creation rules, declared in
+//! `include/rust_ddd_playground.h`.
+//!
+//! Functions return a [`DddStatus`]. On failure the error code and message
+//! of the calling thread's last error are available from
+//! [`ddd_last_error_code`] and [`ddd_last_error_message`].
+
+use crate::error::DomainError;
+use crate::{create_user, Age, Email, User};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DddStatus {
+    Ok = 0,
+    /// A domain rule was broken; the last error says which.
+    DomainError = 1,
+    /// A required pointer was null or a string was not UTF-8.
+    InvalidArgument = 2,
+}
+
+/// User created through [`ddd_create_user`], freed with [`ddd_user_free`].
+pub struct DddUser(User);
+
+struct LastError {
+    code: CString,
+    message: CString,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
+fn fail(status: DddStatus, code: &str, message: &str) -> DddStatus {
+    // Neither codes nor messages contain NUL bytes.
+    let error = LastError {
+        code: CString::new(code).unwrap_or_default(),
+        message: CString::new(message).unwrap_or_default(),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(error));
+    status
+}
+
+fn domain_error(error: DomainError) -> DddStatus {
+    fail(DddStatus::DomainError, error.code(), &error.to_string())
+}
+
+/// Reads a required string argument, recording why it is unusable.
+unsafe fn string(value: *const c_char, argument: &str) -> Result<String, DddStatus> {
+    if value.is_null() {
+        let message = format!("{} must not be null", argument);
+        return Err(fail(
+            DddStatus::InvalidArgument,
+            "invalid_argument",
+            &message,
+        ));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map(str::to_string)
+        .map_err(|_| {
+            let message = format!("{} must be UTF-8", argument);
+            fail(DddStatus::InvalidArgument, "invalid_argument", &message)
+        })
+}
+
+/// Machine-readable code of the last error on this thread, such as
+/// `invalid_email`, or null if nothing failed yet. Valid until the next
+/// failing call on this thread.
+#[no_mangle]
+pub extern "C" fn ddd_last_error_code() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.code.as_ptr())
+    })
+}
+
+/// Human-readable message of the last error on this thread, with the same
+/// lifetime as [`ddd_last_error_code`].
+#[no_mangle]
+pub extern "C" fn ddd_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.message.as_ptr())
+    })
+}
+
+/// # Safety
+///
+/// `email` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ddd_check_email(email: *const c_char) -> DddStatus {
+    match string(email, "email") {
+        Ok(email) => Email::try_from(email).map_or_else(domain_error, |_| DddStatus::Ok),
+        Err(status) => status,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ddd_check_age(age: i32) -> DddStatus {
+    Age::try_from(age).map_or_else(domain_error, |_| DddStatus::Ok)
+}
+
+/// Validates and creates a user, storing it in `*user` on success.
+///
+/// # Safety
+///
+/// `email`, `name` and `surname` must be NUL-terminated strings and
+/// `middle_name` one or null. `user` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn ddd_create_user(
+    email: *const c_char,
+    age: i32,
+    name: *const c_char,
+    surname: *const c_char,
+    middle_name: *const c_char,
+    user: *mut *mut DddUser,
+) -> DddStatus {
+    if user.is_null() {
+        return fail(
+            DddStatus::InvalidArgument,
+            "invalid_argument",
+            "user must not be null",
+        );
+    }
+    let arguments = (|| {
+        let middle_name = if middle_name.is_null() {
+            None
+        } else {
+            Some(string(middle_name, "middle_name")?)
+        };
+        Ok((
+            string(email, "email")?,
+            string(name, "name")?,
+            string(surname, "surname")?,
+            middle_name,
+        ))
+    })();
+    let (email, name, surname, middle_name) = match arguments {
+        Ok(arguments) => arguments,
+        Err(status) => return status,
+    };
+    match create_user(email, age, name, surname, middle_name) {
+        Ok(created) => {
+            *user = Box::into_raw(Box::new(DddUser(created)));
+            DddStatus::Ok
+        }
+        Err(error) => domain_error(error),
+    }
+}
+
+/// # Safety
+///
+/// `user` must come from [`ddd_create_user`] and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ddd_user_id(user: *const DddUser) -> u64 {
+    (*user).0.id.0
+}
+
+/// Full name of `user`, to be freed with [`ddd_string_free`].
+///
+/// # Safety
+///
+/// `user` must come from [`ddd_create_user`] and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ddd_user_full_name(user: *const DddUser) -> *mut c_char {
+    CString::new((*user).0.full_name().to_string())
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// # Safety
+///
+/// `user` must be null or come from [`ddd_create_user`], and is invalid
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ddd_user_free(user: *mut DddUser) {
+    if !user.is_null() {
+        drop(Box::from_raw(user));
+    }
+}
+
+/// # Safety
+///
+/// `value` must be null or a string returned by this library, and is
+/// invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ddd_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn last_error() -> (String, String) {
+        unsafe {
+            (
+                CStr::from_ptr(ddd_last_error_code())
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+                CStr::from_ptr(ddd_last_error_message())
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        }
+    }
+
+    #[test]
+    fn ok_create_user_through_c_abi() {
+        let (email, name, surname) = (c"foo@ok.com", c"Luca", c"Rossi");
+        let mut user = ptr::null_mut();
+
+        let status = unsafe {
+            ddd_create_user(
+                email.as_ptr(),
+                22,
+                name.as_ptr(),
+                surname.as_ptr(),
+                ptr::null(),
+                &mut user,
+            )
+        };
+
+        assert_eq!(status, DddStatus::Ok);
+        unsafe {
+            assert!(ddd_user_id(user) > 0);
+            let full_name = ddd_user_full_name(user);
+            assert_eq!(CStr::from_ptr(full_name).to_str().unwrap(), "Luca Rossi");
+            ddd_string_free(full_name);
+            ddd_user_free(user);
+        }
+    }
+
+    #[test]
+    fn err_validation_failures_set_last_error() {
+        let status = unsafe { ddd_check_email(c"foo.at.com".as_ptr()) };
+
+        assert_eq!(status, DddStatus::DomainError);
+        assert_eq!(
+            last_error(),
+            ("invalid_email".to_string(), "Invalid email".to_string())
+        );
+        assert_eq!(ddd_check_age(-1), DddStatus::DomainError);
+        assert_eq!(last_error().0, "negative_age");
+        assert_eq!(
+            unsafe { ddd_check_email(ptr::null()) },
+            DddStatus::InvalidArgument
+        );
+        assert_eq!(last_error().1, "email must not be null");
+    }
+}
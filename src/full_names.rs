@@ -0,0 +1,168 @@
+use crate::event_store::StoredEvent;
+use crate::events::DomainEvent;
+use crate::projections::Projection;
+use crate::{format_fullname, UserId};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FullNameEntry {
+    pub user_id: UserId,
+    /// As [`crate::get_fullname`] renders it.
+    pub full_name: String,
+    /// Lowercase, ASCII-folded and whitespace-collapsed, for matching.
+    pub search_key: String,
+}
+
+/// Lowercases `text`, folds common Latin accents to ASCII and collapses runs
+/// of whitespace, so "  Niccolò  È" and "niccolo e" compare equal.
+pub fn fold_for_search(text: &str) -> String {
+    let folded: String = text
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ñ' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            c => c,
+        })
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Pre-computed full names, kept up to date from registration and
+/// name-change events so listings never format names per row.
+#[derive(Debug, Default)]
+pub struct FullNameReadModel {
+    entries: RwLock<BTreeMap<UserId, FullNameEntry>>,
+}
+
+impl FullNameReadModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, user_id: UserId) -> Option<FullNameEntry> {
+        self.entries.read().unwrap().get(&user_id).cloned()
+    }
+
+    /// Every entry, ordered by user id.
+    pub fn list(&self) -> Vec<FullNameEntry> {
+        self.entries.read().unwrap().values().cloned().collect()
+    }
+
+    /// Entries whose folded full name contains the folded `query`.
+    pub fn search(&self, query: &str) -> Vec<FullNameEntry> {
+        let query = fold_for_search(query);
+        self.entries
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.search_key.contains(&query))
+            .cloned()
+            .collect()
+    }
+
+    fn upsert(&self, user_id: UserId, name: &str, middle_name: Option<&str>, surname: &str) {
+        let full_name = format_fullname(name, middle_name, surname);
+        let search_key = fold_for_search(&full_name);
+        self.entries.write().unwrap().insert(
+            user_id,
+            FullNameEntry {
+                user_id,
+                full_name,
+                search_key,
+            },
+        );
+    }
+}
+
+impl Projection for FullNameReadModel {
+    fn name(&self) -> &str {
+        "full_names"
+    }
+
+    fn apply(&self, stored: &StoredEvent) -> Result<()> {
+        match &stored.event {
+            DomainEvent::UserRegistered(event) => self.upsert(
+                event.user_id,
+                &event.name,
+                event.middle_name.as_deref(),
+                &event.surname,
+            ),
+            DomainEvent::NameChanged(event) => self.upsert(
+                event.user_id,
+                &event.name,
+                event.middle_name.as_deref(),
+                &event.surname,
+            ),
+            DomainEvent::UsersMerged(event) => {
+                self.entries.write().unwrap().remove(&event.duplicate_id);
+            }
+            DomainEvent::EmailVerified(_) | DomainEvent::VerificationThrottled(_) => {}
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.entries.write().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Command, CommandBus, ExecutionMode};
+    use crate::commands::{ChangeName, CreateUser};
+    use crate::event_store::InMemoryEventStore;
+    use crate::projections::{InMemoryCheckpointStore, ProjectionRunner};
+    use crate::repository::InMemoryUserRepository;
+
+    #[test]
+    fn ok_search_after_name_change() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventStore::new());
+        let events = bus
+            .dispatch(
+                Command::CreateUser(CreateUser {
+                    email: "foo@ok.com".to_string(),
+                    age: 22,
+                    name: "Luca".to_string(),
+                    surname: "Rossi".to_string(),
+                    middle_name: None,
+                }),
+                ExecutionMode::Commit,
+            )
+            .unwrap();
+        let user_id = events[0].user_id();
+        bus.dispatch(
+            Command::ChangeName(ChangeName {
+                user_id,
+                name: "Niccolò".to_string(),
+                middle_name: Some("Maria".to_string()),
+                surname: "Rossi".to_string(),
+            }),
+            ExecutionMode::Commit,
+        )
+        .unwrap();
+        let read_model = FullNameReadModel::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+
+        ProjectionRunner::new(bus.publisher(), &checkpoints)
+            .register(&read_model)
+            .run_once()
+            .unwrap();
+
+        let entry = read_model.get(user_id).unwrap();
+        assert_eq!(entry.full_name, "Niccolò Maria Rossi");
+        assert_eq!(entry.search_key, "niccolo maria rossi");
+        assert_eq!(read_model.search("NICCOLO  maria"), vec![entry]);
+        assert!(read_model.search("luca").is_empty());
+    }
+}
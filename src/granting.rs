@@ -0,0 +1,78 @@
+use crate::error::DomainError;
+use crate::{verify_email, User, UserEmail};
+
+/// Rule a user must meet to be granted access. Products combine policies
+/// with tuples, e.g. `(VerifiedEmailRequired, MinimumAge(18))`.
+pub trait GrantingPolicy {
+    fn check(&self, user: &User) -> Result<(), DomainError>;
+}
+
+/// The default rule: the user's email is verified or passes verification.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VerifiedEmailRequired;
+
+impl GrantingPolicy for VerifiedEmailRequired {
+    fn check(&self, user: &User) -> Result<(), DomainError> {
+        match &user.email {
+            UserEmail::VerifiedEmail(_) => Ok(()),
+            UserEmail::UnverifiedEmail(unverified) => verify_email(unverified).map(|_| ()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MinimumAge(pub i32);
+
+impl GrantingPolicy for MinimumAge {
+    fn check(&self, user: &User) -> Result<(), DomainError> {
+        if user.age.0 >= self.0 {
+            Ok(())
+        } else {
+            Err(DomainError::BelowGrantingAge { min_age: self.0 })
+        }
+    }
+}
+
+impl<A: GrantingPolicy, B: GrantingPolicy> GrantingPolicy for (A, B) {
+    fn check(&self, user: &User) -> Result<(), DomainError> {
+        self.0.check(user)?;
+        self.1.check(user)
+    }
+}
+
+/// Grants `user` if `policy` allows it, verifying the email on the way when
+/// it passes verification. The user is left untouched on failure.
+pub fn grant_user_with(user: &mut User, policy: &impl GrantingPolicy) -> Result<(), DomainError> {
+    policy.check(user)?;
+    if let UserEmail::UnverifiedEmail(unverified_email) = &user.email {
+        if let Ok(verified_email) = verify_email(unverified_email) {
+            user.email = UserEmail::VerifiedEmail(verified_email);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::create_user;
+
+    #[test]
+    fn err_combined_policy_requires_adult() {
+        let mut user = create_user(
+            "foo@ok.com".to_string(),
+            16,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+
+        let result = grant_user_with(&mut user, &(VerifiedEmailRequired, MinimumAge(18)));
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Must be at least 18 years old");
+        assert!(matches!(user.email, UserEmail::UnverifiedEmail(_)));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::http::{HttpResponse, Router};
+use crate::json::JsonValue;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Probe of one dependency, implemented by the adapter that talks to it.
+pub trait HealthCheck: Send + Sync {
+    /// Key of the check in the readiness report, e.g. `database`.
+    fn name(&self) -> &str;
+    fn check(&self) -> Result<()>;
+}
+
+/// Lets an adapter shared with request handlers be probed as well.
+impl<H: HealthCheck> HealthCheck for Arc<H> {
+    fn name(&self) -> &str {
+        H::name(self)
+    }
+
+    fn check(&self) -> Result<()> {
+        H::check(self)
+    }
+}
+
+/// Runs every registered check; the service is ready when they all pass.
+#[derive(Default)]
+pub struct Readiness {
+    checks: Vec<Box<dyn HealthCheck>>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_check(mut self, check: impl HealthCheck + 'static) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    /// Name and failure, if any, of each check in registration order.
+    pub fn report(&self) -> Vec<(String, Option<String>)> {
+        self.checks
+            .iter()
+            .map(|check| {
+                let failure = check.check().err().map(|error| error.to_string());
+                (check.name().to_string(), failure)
+            })
+            .collect()
+    }
+
+    /// Response for `/readyz`: 200 when every check passes, 503 otherwise,
+    /// with the outcome of each check in the body.
+    pub fn response(&self) -> HttpResponse {
+        let report = self.report();
+        let ready = report.iter().all(|(_, failure)| failure.is_none());
+        let checks = report
+            .into_iter()
+            .map(|(name, failure)| {
+                let outcome = failure.unwrap_or_else(|| "ok".to_string());
+                (name, JsonValue::String(outcome))
+            })
+            .collect();
+        let body = JsonValue::Object(vec![
+            (
+                "status".to_string(),
+                JsonValue::String(if ready { "ready" } else { "unavailable" }.to_string()),
+            ),
+            ("checks".to_string(), JsonValue::Object(checks)),
+        ]);
+        HttpResponse {
+            status: if ready { 200 } else { 503 },
+            content_type: "application/json".to_string(),
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Adds `/healthz`, which answers as long as the process can serve requests,
+/// and `/readyz`, which also requires its dependencies. Restarting the
+/// process would not bring a dependency back, so liveness ignores them.
+pub fn health_routes(router: Router, readiness: Arc<Readiness>) -> Router {
+    router
+        .route("GET", "/healthz", |_| HttpResponse::text(200, "ok"))
+        .route("GET", "/readyz", move |_| readiness.response())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::circuit_breaker::CircuitBreaker;
+    use crate::clock::SystemClock;
+    use crate::http::HttpRequest;
+    use crate::repository::InMemoryUserRepository;
+    use anyhow::Error;
+    use std::time::Duration;
+
+    fn get(router: &Router, path: &str) -> HttpResponse {
+        router.handle(&HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: String::new(),
+            peer: None,
+        })
+    }
+
+    #[test]
+    fn err_not_ready_while_broker_circuit_is_open() {
+        let broker =
+            CircuitBreaker::new("broker", SystemClock).with_thresholds(1, Duration::from_secs(60));
+        let _ = broker.call(|| Err::<(), _>(Error::msg("Connection refused")));
+        let readiness = Readiness::new()
+            .with_check(InMemoryUserRepository::new())
+            .with_check(broker);
+        let router = health_routes(Router::new(), Arc::new(readiness));
+
+        let response = get(&router, "/readyz");
+
+        assert_eq!(get(&router, "/healthz"), HttpResponse::text(200, "ok"));
+        assert_eq!(response.status, 503);
+        assert_eq!(
+            response.body,
+            r#"{"status":"unavailable","checks":{"database":"ok","broker":"Service broker is unavailable"}}"#
+        );
+    }
+}
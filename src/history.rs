@@ -0,0 +1,200 @@
+use crate::avatar::UploadedAvatar;
+use crate::event_store::{EventStore, StoredEvent};
+use crate::events::{DomainEvent, MergedProfile};
+use crate::pronouns::check_pronouns;
+use crate::tags::Tag;
+use crate::{Age, Email, UnverifiedEmail, User, UserEmail, UserId, VerifiedEmail};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+/// Folds a user's stream into the aggregate. `None` until the stream has a
+/// `UserRegistered` event. The result is a historical view: its `version` is
+/// left at 0 and it must not be saved back.
+///
+/// A primary takes the profile carried by its `UsersMerged` events; merges
+/// recorded before events carried it leave the primary as it was.
+pub fn fold_user<'a>(events: impl IntoIterator<Item = &'a StoredEvent>) -> Option<User> {
+    let mut user: Option<User> = None;
+    for stored in events {
+        match (&stored.event, user.as_mut()) {
+            (DomainEvent::UserRegistered(event), _) => {
+                let mut registered = User::new(
+                    event.name.clone(),
+                    event.middle_name.clone(),
+                    event.surname.clone(),
+                    Age(event.age),
+                    Email(event.email.clone()),
+                );
+                registered.id = event.user_id;
+                registered.registered_at = stored.recorded_at;
+                user = Some(registered);
+            }
+            (DomainEvent::EmailVerified(event), Some(user)) => {
+                user.email = UserEmail::VerifiedEmail(VerifiedEmail(Email(event.email.clone())));
+            }
+            (DomainEvent::NameChanged(event), Some(user)) => {
+                user.name = event.name.clone();
+                user.middle_name = event.middle_name.clone();
+                user.surname = event.surname.clone();
+            }
+            (DomainEvent::UsersMerged(event), Some(user)) => {
+                if let Some(merged) = &event.merged {
+                    apply_merged(user, merged);
+                }
+            }
+            (DomainEvent::VerificationThrottled(_), _) | (_, None) => {}
+        }
+    }
+    user
+}
+
+pub(crate) fn apply_merged(user: &mut User, merged: &MergedProfile) {
+    let email = Email(merged.email.clone());
+    user.email = if merged.email_verified {
+        UserEmail::VerifiedEmail(VerifiedEmail(email))
+    } else {
+        UserEmail::UnverifiedEmail(UnverifiedEmail(email))
+    };
+    user.middle_name = merged.middle_name.clone();
+    user.pronouns = merged
+        .pronouns
+        .clone()
+        .and_then(|pronouns| check_pronouns(pronouns).ok());
+    user.avatar = merged.avatar.clone().map(UploadedAvatar);
+    user.tags = merged.tags.iter().cloned().map(Tag).collect();
+}
+
+/// Events of `user_id` and of every user merged into it, directly or through
+/// earlier merges, in recording order. The duplicates' events keep their own
+/// user ids, so this is a history to show rather than a stream to fold.
+pub fn consolidated_history(store: &impl EventStore, user_id: UserId) -> Result<Vec<StoredEvent>> {
+    let mut events = Vec::new();
+    let mut pending = vec![user_id];
+    let mut seen = HashSet::new();
+    while let Some(id) = pending.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        for stored in store.read_stream(id)? {
+            if let DomainEvent::UsersMerged(merged) = &stored.event {
+                pending.push(merged.duplicate_id);
+            }
+            events.push(stored);
+        }
+    }
+    events.sort_by_key(|stored| stored.id);
+    Ok(events)
+}
+
+/// The user as it was at `at`, folding only events recorded up to then.
+pub fn load_at(store: &impl EventStore, user_id: UserId, at: SystemTime) -> Result<Option<User>> {
+    let events = store.read_stream(user_id)?;
+    Ok(fold_user(
+        events.iter().filter(|stored| stored.recorded_at <= at),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::event_store::InMemoryEventStore;
+    use crate::events::{EmailVerified, UserRegistered, UsersMerged};
+    use std::time::Duration;
+
+    #[test]
+    fn ok_load_at_before_and_after_verification() {
+        let monday = SystemTime::UNIX_EPOCH + Duration::from_secs(86_400);
+        let clock = TestClock::new(monday);
+        let store = InMemoryEventStore::with_clock(&clock);
+        store
+            .append(DomainEvent::UserRegistered(UserRegistered {
+                user_id: UserId(4),
+                email: "foo@ok.com".to_string(),
+                name: "Luca".to_string(),
+                middle_name: None,
+                surname: "Rossi".to_string(),
+                age: 22,
+            }))
+            .unwrap();
+        clock.advance(Duration::from_secs(2 * 86_400));
+        store
+            .append(DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(4),
+                email: "foo@ok.com".to_string(),
+            }))
+            .unwrap();
+
+        let before_registration = load_at(&store, UserId(4), SystemTime::UNIX_EPOCH).unwrap();
+        assert!(before_registration.is_none());
+
+        let tuesday = load_at(&store, UserId(4), monday + Duration::from_secs(86_400))
+            .unwrap()
+            .unwrap();
+        assert_eq!(tuesday.id, UserId(4));
+        assert!(matches!(tuesday.email, UserEmail::UnverifiedEmail(_)));
+
+        let now = load_at(&store, UserId(4), SystemTime::now())
+            .unwrap()
+            .unwrap();
+        assert!(matches!(now.email, UserEmail::VerifiedEmail(_)));
+    }
+
+    fn registered(user_id: u64, email: &str) -> DomainEvent {
+        DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(user_id),
+            email: email.to_string(),
+            name: "Luca".to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+            age: 22,
+        })
+    }
+
+    #[test]
+    fn ok_merged_profile_folded_and_history_consolidated() {
+        let store = InMemoryEventStore::new();
+        store.append(registered(1, "luca@ok.com")).unwrap();
+        store.append(registered(2, "old@ok.com")).unwrap();
+        store.append(registered(3, "older@ok.com")).unwrap();
+        store
+            .append(DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(2),
+                duplicate_id: UserId(3),
+                merged: None,
+            }))
+            .unwrap();
+        store
+            .append(DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(2),
+                merged: Some(MergedProfile {
+                    email: "old@ok.com".to_string(),
+                    email_verified: true,
+                    middle_name: Some("Maria".to_string()),
+                    pronouns: Some("they/them".to_string()),
+                    avatar: None,
+                    tags: vec!["vip".to_string()],
+                }),
+            }))
+            .unwrap();
+
+        let primary = fold_user(&store.read_stream(UserId(1)).unwrap()).unwrap();
+        assert!(matches!(
+            &primary.email,
+            UserEmail::VerifiedEmail(VerifiedEmail(Email(email))) if email == "old@ok.com"
+        ));
+        assert_eq!(primary.middle_name, Some("Maria".to_string()));
+        assert!(primary.tags.contains(&Tag("vip".to_string())));
+
+        let history = consolidated_history(&store, UserId(1)).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|stored| stored.event.user_id().0)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3, 2, 1]
+        );
+    }
+}
@@ -0,0 +1,497 @@
+//! Just enough HTTP/1.1 to expose operational endpoints: one request per
+//! connection, handled by a bounded pool of worker threads. Stream routes
+//! hold their worker for as long as the response body goes on.
+
+use crate::logging::{with_correlation_id, CorrelationId};
+use crate::problem::ProblemDetails;
+use crate::shutdown::WorkerPool;
+use crate::trace::{instrument, with_remote_parent, TraceContext};
+use anyhow::{Error, Result};
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// Of all header lines together, blank line included.
+const MAX_HEADERS_SIZE: usize = 32 * 1024;
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Why a request could not be read, answered with [`RequestError::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    Malformed,
+    RequestLineTooLong,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl RequestError {
+    pub fn status(self) -> u16 {
+        match self {
+            RequestError::Malformed => 400,
+            RequestError::RequestLineTooLong => 414,
+            RequestError::HeadersTooLarge => 431,
+            RequestError::BodyTooLarge => 413,
+        }
+    }
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Malformed => write!(f, "Invalid HTTP request"),
+            RequestError::RequestLineTooLong => {
+                write!(f, "Request line longer than {} bytes", MAX_REQUEST_LINE)
+            }
+            RequestError::HeadersTooLarge => {
+                write!(f, "Headers larger than {} bytes", MAX_HEADERS_SIZE)
+            }
+            RequestError::BodyTooLarge => write!(f, "Body larger than {} bytes", MAX_BODY_SIZE),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Address of the connected client, when served over TCP.
+    pub peer: Option<IpAddr>,
+}
+
+impl HttpRequest {
+    /// Value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Value of query parameter `name`, as sent; percent-encoding is not
+    /// decoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.path.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Trace the caller is part of, from a valid `traceparent` header.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        self.header("traceparent")
+            .and_then(|header| TraceContext::parse_traceparent(header).ok())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: body.to_string(),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        422 => "Unprocessable Content",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Reads one line into `line`, failing with `too_long` past `limit` bytes.
+/// Returns how many bytes were read.
+fn read_line_within(
+    reader: &mut impl BufRead,
+    line: &mut String,
+    limit: usize,
+    too_long: RequestError,
+) -> Result<usize> {
+    let read = reader.take(limit as u64 + 1).read_line(line)?;
+    if read > limit {
+        return Err(too_long.into());
+    }
+    Ok(read)
+}
+
+/// Reads a request, refusing request lines, headers and bodies past their
+/// limits with a [`RequestError`] before buffering them.
+pub fn parse_request(reader: &mut impl BufRead) -> Result<HttpRequest> {
+    let invalid = || Error::from(RequestError::Malformed);
+    let mut line = String::new();
+    read_line_within(
+        reader,
+        &mut line,
+        MAX_REQUEST_LINE,
+        RequestError::RequestLineTooLong,
+    )?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(_version)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid()),
+    };
+
+    let mut headers = Vec::new();
+    let mut headers_left = MAX_HEADERS_SIZE;
+    loop {
+        line.clear();
+        headers_left -= read_line_within(
+            reader,
+            &mut line,
+            headers_left,
+            RequestError::HeadersTooLarge,
+        )?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (key, value) = header.split_once(':').ok_or_else(invalid)?;
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = HttpRequest {
+        method,
+        path,
+        headers,
+        body: String::new(),
+        peer: None,
+    };
+    let length: usize = match request.header("Content-Length") {
+        Some(length) => length.parse().map_err(|_| invalid())?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(RequestError::BodyTooLarge.into());
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    request.body = String::from_utf8(body).map_err(|_| invalid())?;
+    Ok(request)
+}
+
+pub fn write_response(writer: &mut impl Write, response: &HttpResponse) -> Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub type StreamBody = Box<dyn FnOnce(&mut dyn Write) -> Result<()> + Send>;
+
+/// Response whose body is written piece by piece for as long as `body`
+/// keeps going, such as a stream of server-sent events. The body ends when
+/// it returns or fails to write, e.g. because the client hung up.
+pub struct StreamingResponse {
+    pub content_type: String,
+    pub body: StreamBody,
+}
+
+type Handler = Box<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+type StreamHandler = Box<dyn Fn(&HttpRequest) -> Result<StreamingResponse> + Send + Sync>;
+
+/// Dispatches requests to handlers by method and exact path.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(String, String, Handler)>,
+    streams: Vec<(String, String, StreamHandler)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(
+        mut self,
+        method: &str,
+        path: &str,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        self.routes
+            .push((method.to_string(), path.to_string(), Box::new(handler)));
+        self
+    }
+
+    /// Like [`Router::route`], answering errors of `handler` with an
+    /// `application/problem+json` body.
+    pub fn try_route(
+        self,
+        method: &str,
+        path: &str,
+        handler: impl Fn(&HttpRequest) -> Result<HttpResponse> + Send + Sync + 'static,
+    ) -> Self {
+        self.route(method, path, move |request| {
+            handler(request).unwrap_or_else(|error| ProblemDetails::from_error(&error).response())
+        })
+    }
+
+    /// Adds a route answered with a [`StreamingResponse`]. Errors of
+    /// `handler` are answered with an `application/problem+json` body.
+    pub fn stream(
+        mut self,
+        method: &str,
+        path: &str,
+        handler: impl Fn(&HttpRequest) -> Result<StreamingResponse> + Send + Sync + 'static,
+    ) -> Self {
+        self.streams
+            .push((method.to_string(), path.to_string(), Box::new(handler)));
+        self
+    }
+
+    /// Writes the answer to `request`: streamed when it matches a
+    /// [`Router::stream`] route, otherwise as [`Router::handle`] answers it.
+    pub fn respond(&self, request: &HttpRequest, writer: &mut impl Write) -> Result<()> {
+        let path = request.path.split('?').next().unwrap_or_default();
+        let Some((_, _, handler)) = self
+            .streams
+            .iter()
+            .find(|(method, route, _)| *method == request.method && route == path)
+        else {
+            return write_response(writer, &self.handle(request));
+        };
+        let stream = match handler(request) {
+            Ok(stream) => stream,
+            Err(error) => {
+                return write_response(writer, &ProblemDetails::from_error(&error).response())
+            }
+        };
+        write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+            stream.content_type
+        )?;
+        writer.flush()?;
+        (stream.body)(writer)
+    }
+
+    /// Runs the matching handler in an `http.request` span, continuing the
+    /// caller's trace if the request carries one, on behalf of the request's
+    /// `X-Correlation-Id` or a new one.
+    pub fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let path = request.path.split('?').next().unwrap_or_default();
+        let Some((_, _, handler)) = self
+            .routes
+            .iter()
+            .find(|(method, route, _)| *method == request.method && route == path)
+        else {
+            return HttpResponse::text(404, "Not found");
+        };
+        let fields = vec![
+            ("method", request.method.clone()),
+            ("path", path.to_string()),
+        ];
+        let respond = || {
+            instrument("http.request", fields, |span| {
+                let response = handler(request);
+                span.record("status", response.status);
+                Ok(response)
+            })
+        };
+        let correlation_id = match request.header("X-Correlation-Id") {
+            Some(id)
+                if (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                CorrelationId(id.to_string())
+            }
+            _ => CorrelationId::generate(),
+        };
+        let result: Result<HttpResponse> =
+            with_correlation_id(correlation_id, || match request.trace_context() {
+                Some(parent) => with_remote_parent(parent, respond),
+                None => respond(),
+            });
+        result.unwrap_or_else(|_| HttpResponse::text(500, "Internal server error"))
+    }
+}
+
+fn handle_connection(stream: TcpStream, router: &Router) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    match parse_request(&mut reader) {
+        Ok(mut request) => {
+            request.peer = stream.peer_addr().ok().map(|address| address.ip());
+            router.respond(&request, &mut &stream)
+        }
+        Err(error) => {
+            let response = match error.downcast_ref::<RequestError>() {
+                Some(error) => HttpResponse::text(error.status(), &error.to_string()),
+                None => HttpResponse::text(400, "Bad request"),
+            };
+            write_response(&mut &stream, &response)
+        }
+    }
+}
+
+/// Serves `router` on `listener` until `stop` is set, then stops accepting
+/// connections. Connections are handled by the workers of `connections`,
+/// to be drained on shutdown; while all of them are busy and the queue is
+/// full, new connections are answered 503 straight away.
+pub fn serve(
+    listener: TcpListener,
+    router: Arc<Router>,
+    stop: &AtomicBool,
+    connections: &WorkerPool,
+) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                let busy = stream.try_clone()?;
+                let router = Arc::clone(&router);
+                let queued = connections.spawn(move || {
+                    // A client that hangs up early has nothing to be told.
+                    let _ = handle_connection(stream, &router);
+                });
+                if queued.is_err() {
+                    let _ = write_response(
+                        &mut &busy,
+                        &HttpResponse::text(503, "Server busy, try again later"),
+                    );
+                }
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_route_parsed_request() {
+        let router = Router::new().route("GET", "/ping", |request| {
+            HttpResponse::text(200, request.header("x-name").unwrap_or("anonymous"))
+        });
+        let raw = "GET /ping?verbose=1 HTTP/1.1\r\nHost: localhost\r\nX-Name: ops\r\n\r\n";
+
+        let request = parse_request(&mut raw.as_bytes()).unwrap();
+
+        assert_eq!(router.handle(&request), HttpResponse::text(200, "ops"));
+        let mut output = Vec::new();
+        write_response(&mut output, &HttpResponse::text(404, "Not found")).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn err_handler_error_answered_as_problem() {
+        let router = Router::new().try_route("POST", "/users", |_| {
+            Err(crate::error::DomainError::NegativeAge.into())
+        });
+        let raw = "POST /users HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+
+        let response = router.handle(&parse_request(&mut raw.as_bytes()).unwrap());
+
+        assert_eq!(response.status, 422);
+        assert_eq!(response.content_type, "application/problem+json");
+    }
+
+    #[test]
+    fn err_oversized_requests_refused() {
+        let status = |raw: String| {
+            parse_request(&mut raw.as_bytes())
+                .unwrap_err()
+                .downcast_ref::<RequestError>()
+                .map(|error| error.status())
+        };
+        let long_path = "a".repeat(MAX_REQUEST_LINE);
+        let long_header = "b".repeat(MAX_HEADERS_SIZE);
+
+        assert_eq!(
+            status(format!("GET /{} HTTP/1.1\r\n\r\n", long_path)),
+            Some(414)
+        );
+        assert_eq!(
+            status(format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", long_header)),
+            Some(431)
+        );
+        assert_eq!(
+            status(format!(
+                "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY_SIZE + 1
+            )),
+            Some(413)
+        );
+        assert_eq!(status("GET\r\n\r\n".to_string()), Some(400));
+    }
+
+    #[test]
+    fn ok_stream_route_writes_head_then_body() {
+        let router = Router::new()
+            .stream("GET", "/ticks", |_| {
+                Ok(StreamingResponse {
+                    content_type: "text/event-stream".to_string(),
+                    body: Box::new(|writer| {
+                        for tick in 1..=2 {
+                            write!(writer, "data: {}\n\n", tick)?;
+                        }
+                        Ok(())
+                    }),
+                })
+            })
+            .route("GET", "/healthz", |_| HttpResponse::text(200, "ok"));
+        let request = |path: &str| HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: String::new(),
+            peer: None,
+        };
+
+        let mut streamed = Vec::new();
+        router
+            .respond(&request("/ticks?since=0"), &mut streamed)
+            .unwrap();
+        let mut plain = Vec::new();
+        router.respond(&request("/healthz"), &mut plain).unwrap();
+
+        let streamed = String::from_utf8(streamed).unwrap();
+        assert!(streamed.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n"));
+        assert!(streamed.ends_with("\r\n\r\ndata: 1\n\ndata: 2\n\n"));
+        assert!(String::from_utf8(plain).unwrap().ends_with("\r\n\r\nok"));
+    }
+}
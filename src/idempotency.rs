@@ -0,0 +1,245 @@
+use crate::hash::sha256;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+/// Client-supplied token identifying one logical request across retries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey(pub String);
+
+/// Digest of the request payload a key was first used with, so the key
+/// cannot be replayed with a different payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Fingerprint of `fields` in order; each field is length-prefixed, so
+    /// moving text from one field to the next changes it.
+    pub fn of<'a>(fields: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut bytes = Vec::new();
+        for field in fields {
+            bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(field.as_bytes());
+        }
+        Self(sha256(&bytes))
+    }
+}
+
+/// Why a keyed request was refused without running it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyError {
+    /// Another request with the key is still running.
+    InProgress,
+    /// The key was first used with a different payload.
+    KeyReused,
+}
+
+impl IdempotencyError {
+    /// Stable machine-readable identifier, for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            IdempotencyError::InProgress => "idempotency_key_in_progress",
+            IdempotencyError::KeyReused => "idempotency_key_reused",
+        }
+    }
+}
+
+impl Display for IdempotencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdempotencyError::InProgress => {
+                write!(f, "A request with this idempotency key is in progress")
+            }
+            IdempotencyError::KeyReused => {
+                write!(f, "Idempotency key was used with a different request")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IdempotencyError {}
+
+/// What [`IdempotencyStore::reserve`] found for a key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reservation<T> {
+    /// The key was free and is now held by the caller.
+    Reserved,
+    InProgress,
+    Completed(T),
+    /// The key is held or completed for a different fingerprint.
+    Mismatch,
+}
+
+pub trait IdempotencyStore<T> {
+    /// Claims `key` for a request with `fingerprint` in one atomic step,
+    /// unless the key is already held or completed.
+    fn reserve(&self, key: &IdempotencyKey, fingerprint: Fingerprint) -> Result<Reservation<T>>;
+    /// Records the outcome of a reserved key.
+    fn complete(&self, key: &IdempotencyKey, outcome: T) -> Result<()>;
+    /// Frees a reserved key whose request failed, so it can be retried.
+    fn release(&self, key: &IdempotencyKey) -> Result<()>;
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    fingerprint: Fingerprint,
+    /// `None` while the request is running.
+    outcome: Option<T>,
+}
+
+#[derive(Debug)]
+pub struct InMemoryIdempotencyStore<T> {
+    entries: Mutex<HashMap<IdempotencyKey, Entry<T>>>,
+}
+
+impl<T> Default for InMemoryIdempotencyStore<T> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> InMemoryIdempotencyStore<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: Clone> IdempotencyStore<T> for InMemoryIdempotencyStore<T> {
+    fn reserve(&self, key: &IdempotencyKey, fingerprint: Fingerprint) -> Result<Reservation<T>> {
+        let mut entries = self.entries.lock().unwrap();
+        Ok(match entries.get(key) {
+            None => {
+                entries.insert(
+                    key.clone(),
+                    Entry {
+                        fingerprint,
+                        outcome: None,
+                    },
+                );
+                Reservation::Reserved
+            }
+            Some(entry) if entry.fingerprint != fingerprint => Reservation::Mismatch,
+            Some(Entry { outcome: None, .. }) => Reservation::InProgress,
+            Some(Entry {
+                outcome: Some(outcome),
+                ..
+            }) => Reservation::Completed(outcome.clone()),
+        })
+    }
+
+    fn complete(&self, key: &IdempotencyKey, outcome: T) -> Result<()> {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.outcome = Some(outcome);
+        }
+        Ok(())
+    }
+
+    fn release(&self, key: &IdempotencyKey) -> Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Runs `command` unless `key` has already produced an outcome, in which case
+/// that outcome is returned instead. The key is reserved before `command`
+/// runs, so concurrent retries run it once; they fail with
+/// [`IdempotencyError::InProgress`], and a different payload under the same
+/// key fails with [`IdempotencyError::KeyReused`]. Only successes are
+/// remembered: a failed command changed nothing, so retrying it is safe.
+pub fn run_idempotent<T: Clone>(
+    store: &(impl IdempotencyStore<T> + ?Sized),
+    key: Option<&IdempotencyKey>,
+    fingerprint: Fingerprint,
+    command: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let Some(key) = key else {
+        return command();
+    };
+    match store.reserve(key, fingerprint)? {
+        Reservation::Reserved => {}
+        Reservation::Completed(outcome) => return Ok(outcome),
+        Reservation::InProgress => return Err(IdempotencyError::InProgress.into()),
+        Reservation::Mismatch => return Err(IdempotencyError::KeyReused.into()),
+    }
+
+    match command() {
+        Ok(outcome) => {
+            store.complete(key, outcome.clone())?;
+            Ok(outcome)
+        }
+        Err(error) => {
+            store.release(key)?;
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Error;
+
+    fn key() -> IdempotencyKey {
+        IdempotencyKey("request-1".to_string())
+    }
+
+    #[test]
+    fn err_key_reused_with_another_payload() {
+        let store = InMemoryIdempotencyStore::new();
+        let first = Fingerprint::of(["foo@ok.com", "22"]);
+
+        assert_eq!(
+            run_idempotent(&store, Some(&key()), first, || Ok(7)).unwrap(),
+            7
+        );
+        let error = run_idempotent(
+            &store,
+            Some(&key()),
+            Fingerprint::of(["foo@ok.com", "23"]),
+            || Ok(8),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<IdempotencyError>(),
+            Some(&IdempotencyError::KeyReused)
+        );
+        assert_ne!(first, Fingerprint::of(["foo@ok.co", "m22"]));
+    }
+
+    #[test]
+    fn err_concurrent_request_with_key_in_progress() {
+        let store = InMemoryIdempotencyStore::new();
+        let fingerprint = Fingerprint::of(["foo@ok.com"]);
+
+        let outcome = run_idempotent(&store, Some(&key()), fingerprint, || {
+            let retry = run_idempotent(&store, Some(&key()), fingerprint, || Ok(8));
+            assert_eq!(
+                retry.unwrap_err().downcast_ref::<IdempotencyError>(),
+                Some(&IdempotencyError::InProgress)
+            );
+            Ok(7)
+        });
+
+        assert_eq!(outcome.unwrap(), 7);
+    }
+
+    #[test]
+    fn ok_failed_request_releases_key() {
+        let store = InMemoryIdempotencyStore::new();
+        let fingerprint = Fingerprint::of(["foo@ok.com"]);
+
+        let failed = run_idempotent(&store, Some(&key()), fingerprint, || {
+            Err::<u32, _>(Error::msg("Storage unavailable"))
+        });
+        assert!(failed.is_err());
+
+        assert_eq!(
+            run_idempotent(&store, Some(&key()), fingerprint, || Ok(7)).unwrap(),
+            7
+        );
+    }
+}
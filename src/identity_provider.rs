@@ -0,0 +1,314 @@
+//! Anti-corruption layer for the external identity provider: its claims are
+//! translated into our model here and nowhere else.
+
+use crate::config::AgeLimits;
+use crate::pii::{mask_email, mask_name};
+use crate::repository::UserRepository;
+use crate::{
+    check_age_within, Age, Email, TenantId, UnverifiedEmail, User, UserEmail, UserId, VerifiedEmail,
+};
+use anyhow::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::sync::RwLock;
+
+/// A user as the identity provider describes it, with its OIDC claim names.
+#[derive(Clone, PartialEq)]
+pub struct ExternalIdentity {
+    pub sub: String,
+    pub email: Option<String>,
+    /// Absent means the provider makes no claim, which we treat as unverified.
+    pub email_verified: Option<bool>,
+    pub given_name: Option<String>,
+    pub middle_name: Option<String>,
+    pub family_name: Option<String>,
+    pub age: Option<i32>,
+}
+
+impl Debug for ExternalIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExternalIdentity")
+            .field("sub", &self.sub)
+            .field("email", &self.email.as_deref().map(mask_email))
+            .field("email_verified", &self.email_verified)
+            .field("given_name", &self.given_name.as_deref().map(mask_name))
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .field("family_name", &self.family_name.as_deref().map(mask_name))
+            .field("age", &self.age)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum IdentityConflict {
+    /// The subject is linked to a user that no longer exists.
+    DanglingLink { subject: String, user_id: UserId },
+    /// A local user already owns the email but is not linked to the subject.
+    EmailTaken { subject: String, user_id: UserId },
+}
+
+impl Display for IdentityConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentityConflict::DanglingLink { subject, user_id } => write!(
+                f,
+                "Subject {} is linked to missing user {}",
+                subject, user_id
+            ),
+            IdentityConflict::EmailTaken { subject, user_id } => write!(
+                f,
+                "Email of subject {} already belongs to user {}",
+                subject, user_id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IdentityConflict {}
+
+/// Which local user each external subject corresponds to.
+pub trait SubjectMapping {
+    fn find(&self, subject: &str) -> Result<Option<UserId>>;
+    fn link(&self, subject: &str, user_id: UserId) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemorySubjectMapping {
+    links: RwLock<BTreeMap<String, UserId>>,
+}
+
+impl InMemorySubjectMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SubjectMapping for InMemorySubjectMapping {
+    fn find(&self, subject: &str) -> Result<Option<UserId>> {
+        Ok(self.links.read().unwrap().get(subject).copied())
+    }
+
+    fn link(&self, subject: &str, user_id: UserId) -> Result<()> {
+        self.links
+            .write()
+            .unwrap()
+            .insert(subject.to_string(), user_id);
+        Ok(())
+    }
+}
+
+fn required<'a>(claim: &'a Option<String>, name: &str) -> Result<&'a str> {
+    claim
+        .as_deref()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| Error::msg(format!("Missing claim {}", name)))
+}
+
+/// The identity's claims, validated like our own input.
+struct Claims {
+    name: String,
+    middle_name: Option<String>,
+    surname: String,
+    age: Age,
+    email: UserEmail,
+}
+
+fn read_claims(identity: &ExternalIdentity, age_limits: &AgeLimits) -> Result<Claims> {
+    let email = Email::try_from(required(&identity.email, "email")?)?;
+    let age = identity
+        .age
+        .ok_or_else(|| Error::msg("Missing claim age"))?;
+    Ok(Claims {
+        name: required(&identity.given_name, "given_name")?.to_string(),
+        middle_name: identity.middle_name.clone(),
+        surname: required(&identity.family_name, "family_name")?.to_string(),
+        age: check_age_within(age, age_limits)?,
+        email: match identity.email_verified {
+            Some(true) => UserEmail::VerifiedEmail(VerifiedEmail(email)),
+            Some(false) | None => UserEmail::UnverifiedEmail(UnverifiedEmail(email)),
+        },
+    })
+}
+
+fn apply_claims(user: &mut User, claims: Claims) {
+    user.name = claims.name;
+    user.middle_name = claims.middle_name;
+    user.surname = claims.surname;
+    user.age = claims.age;
+    user.email = claims.email;
+}
+
+/// Translates an identity into a new, not yet persisted, user whose age is
+/// within `age_limits`.
+pub fn translate_identity(identity: &ExternalIdentity, age_limits: &AgeLimits) -> Result<User> {
+    let Claims {
+        name,
+        middle_name,
+        surname,
+        age,
+        email,
+    } = read_claims(identity, age_limits)?;
+    let mut user = User::new(name, middle_name, surname, age, address(&email));
+    user.email = email;
+    Ok(user)
+}
+
+/// Keeps local users in step with the identity provider.
+pub struct IdentitySync<'a, R: UserRepository, M: SubjectMapping> {
+    repository: &'a R,
+    mapping: &'a M,
+    age_limits: AgeLimits,
+}
+
+impl<'a, R: UserRepository, M: SubjectMapping> IdentitySync<'a, R, M> {
+    /// Syncs identities whose age is within `age_limits`.
+    pub fn new(repository: &'a R, mapping: &'a M, age_limits: &AgeLimits) -> Self {
+        Self {
+            repository,
+            mapping,
+            age_limits: age_limits.clone(),
+        }
+    }
+
+    /// Creates or updates the local user for `identity` and returns its id.
+    /// A linked user that was merged is followed to the surviving one.
+    pub fn sync(&self, identity: &ExternalIdentity) -> Result<UserId> {
+        let user = match self.mapping.find(&identity.sub)? {
+            Some(user_id) => {
+                let mut user = self.linked_user(&identity.sub, user_id)?;
+                apply_claims(&mut user, read_claims(identity, &self.age_limits)?);
+                user
+            }
+            None => {
+                let user = translate_identity(identity, &self.age_limits)?;
+                if let Some(owner) = self.owner_of(&user.tenant_id, &address(&user.email))? {
+                    return Err(IdentityConflict::EmailTaken {
+                        subject: identity.sub.clone(),
+                        user_id: owner,
+                    }
+                    .into());
+                }
+                user
+            }
+        };
+        let user_id = user.id;
+        self.repository.save(user)?;
+        self.mapping.link(&identity.sub, user_id)?;
+        Ok(user_id)
+    }
+
+    fn linked_user(&self, subject: &str, user_id: UserId) -> Result<User> {
+        let mut current = user_id;
+        loop {
+            let user =
+                self.repository
+                    .find(current)?
+                    .ok_or_else(|| IdentityConflict::DanglingLink {
+                        subject: subject.to_string(),
+                        user_id,
+                    })?;
+            match user.merged_into {
+                Some(primary) => current = primary,
+                None => return Ok(user),
+            }
+        }
+    }
+
+    fn owner_of(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<UserId>> {
+        Ok(self
+            .repository
+            .find_by_email(tenant_id, email)?
+            .map(|user| user.id))
+    }
+}
+
+fn address(email: &UserEmail) -> Email {
+    match email {
+        UserEmail::VerifiedEmail(VerifiedEmail(email))
+        | UserEmail::UnverifiedEmail(UnverifiedEmail(email)) => email.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::create_user;
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::assert_debug_masks;
+
+    fn identity(sub: &str, email: &str) -> ExternalIdentity {
+        ExternalIdentity {
+            sub: sub.to_string(),
+            email: Some(email.to_string()),
+            email_verified: Some(true),
+            given_name: Some("Luca".to_string()),
+            middle_name: None,
+            family_name: Some("Rossi".to_string()),
+            age: Some(22),
+        }
+    }
+
+    #[test]
+    fn ok_sync_creates_then_updates_linked_user() {
+        let repository = InMemoryUserRepository::new();
+        let mapping = InMemorySubjectMapping::new();
+        let sync = IdentitySync::new(&repository, &mapping, &AgeLimits::default());
+
+        let user_id = sync.sync(&identity("idp|42", "foo@idp.com")).unwrap();
+        let mut renamed = identity("idp|42", "foo@idp.com");
+        renamed.family_name = Some("Bianchi".to_string());
+        renamed.email_verified = None;
+
+        assert_eq!(sync.sync(&renamed).unwrap(), user_id);
+        let user = repository.find(user_id).unwrap().unwrap();
+        assert_eq!(user.surname, "Bianchi");
+        assert!(matches!(user.email, UserEmail::UnverifiedEmail(_)));
+        assert_eq!(mapping.find("idp|42").unwrap(), Some(user_id));
+    }
+
+    #[test]
+    fn err_sync_email_owned_by_unlinked_user() {
+        let repository = InMemoryUserRepository::new();
+        let mapping = InMemorySubjectMapping::new();
+        let local = create_user(
+            "foo@ok.com".to_string(),
+            30,
+            "Anna".to_string(),
+            "Bianchi".to_string(),
+            None,
+        )
+        .unwrap();
+        let local_id = local.id;
+        repository.save(local).unwrap();
+
+        let result = IdentitySync::new(&repository, &mapping, &AgeLimits::default())
+            .sync(&identity("idp|7", "foo@ok.com"));
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<IdentityConflict>(),
+            Some(&IdentityConflict::EmailTaken {
+                subject: "idp|7".to_string(),
+                user_id: local_id
+            })
+        );
+        assert_eq!(mapping.find("idp|7").unwrap(), None);
+    }
+
+    #[test]
+    fn ok_external_identity_debug_masks_pii() {
+        let identity = ExternalIdentity {
+            sub: "idp|7".to_string(),
+            email: Some("luca.rossi@ok.com".to_string()),
+            email_verified: Some(true),
+            given_name: Some("Luca".to_string()),
+            middle_name: Some("Maria".to_string()),
+            family_name: Some("Rossi".to_string()),
+            age: Some(22),
+        };
+
+        assert_debug_masks(&identity, &["luca.rossi", "Luca", "Maria", "Rossi"]);
+    }
+}
@@ -0,0 +1,547 @@
+use crate::config::AgeLimits;
+use crate::error::DomainError;
+use crate::parallel::map_ordered;
+use crate::repository::UserRepository;
+use crate::{check_age_within, Email, User, UserId};
+use anyhow::Result;
+use std::fmt::Display;
+use std::io::BufRead;
+
+const HEADER: &str = "email,age,name,surname,middle_name";
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ImportError {
+    /// The line is not a well-formed `email,age,name,surname,middle_name` row.
+    MalformedRow(String),
+    Invalid(DomainError),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::MalformedRow(reason) => write!(f, "Malformed row: {}", reason),
+            ImportError::Invalid(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<DomainError> for ImportError {
+    fn from(error: DomainError) -> Self {
+        ImportError::Invalid(error)
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportedRow {
+    /// 1-based line number in the source file.
+    pub line: usize,
+    pub result: Result<UserId, ImportError>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub rows: Vec<ImportedRow>,
+}
+
+impl ImportReport {
+    pub fn succeeded(&self) -> usize {
+        self.rows.iter().filter(|row| row.result.is_ok()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.rows.len() - self.succeeded()
+    }
+}
+
+/// Where a scan of a CSV record stands within the current field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldState {
+    Start,
+    Unquoted,
+    Quoted,
+    /// Just past a quote closing a quoted field, or starting a `""` escape.
+    AfterQuote,
+}
+
+/// State after scanning `line` from `state`. Only a field opening with `"`
+/// is quoted; a quote anywhere else is part of the field, as
+/// [`split_row`] reads it. A record ending in [`FieldState::Quoted`]
+/// continues on the next line.
+fn scan_line(line: &str, mut state: FieldState) -> FieldState {
+    for c in line.chars() {
+        state = match (state, c) {
+            (FieldState::Quoted, '"') => FieldState::AfterQuote,
+            (FieldState::Quoted, _) => FieldState::Quoted,
+            (FieldState::Start | FieldState::AfterQuote, '"') => FieldState::Quoted,
+            (_, ',') => FieldState::Start,
+            (FieldState::Start | FieldState::AfterQuote, c) if c.is_whitespace() => state,
+            _ => FieldState::Unquoted,
+        };
+    }
+    state
+}
+
+/// Fields of a CSV record, trimmed unless quoted. Quoted fields, as written
+/// by the export, may hold commas and newlines, with `""` for a quote.
+fn split_row(row: &str) -> Result<Vec<String>, ImportError> {
+    let mut fields = Vec::new();
+    let mut chars = row.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => {
+                        return Err(ImportError::MalformedRow(
+                            "unterminated quoted field".to_string(),
+                        ))
+                    }
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.next() {
+                Some(',') => fields.push(field),
+                None => {
+                    fields.push(field);
+                    return Ok(fields);
+                }
+                Some(c) => {
+                    return Err(ImportError::MalformedRow(format!(
+                        "unexpected {:?} after quoted field",
+                        c
+                    )))
+                }
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    Some(',') => break,
+                    Some(c) => field.push(c),
+                    None => {
+                        fields.push(field.trim_end().to_string());
+                        return Ok(fields);
+                    }
+                }
+            }
+            fields.push(field.trim_end().to_string());
+        }
+    }
+}
+
+fn parse_row(row: &str, age_limits: &AgeLimits) -> Result<User, ImportError> {
+    let columns = split_row(row)?;
+    let [email, age, name, surname, middle_name] = &columns[..] else {
+        return Err(ImportError::MalformedRow(format!(
+            "expected 5 columns, found {}",
+            columns.len()
+        )));
+    };
+    let age: i32 = age
+        .parse()
+        .map_err(|_| ImportError::MalformedRow(format!("age {:?} is not a number", age)))?;
+
+    let age = check_age_within(age, age_limits)?;
+    let email = Email::try_from(email.as_str())?;
+    let middle_name = Some(middle_name.clone()).filter(|middle| !middle.is_empty());
+
+    Ok(User::new(
+        name.clone(),
+        middle_name,
+        surname.clone(),
+        age,
+        email,
+    ))
+}
+
+/// Knobs for [`import_users_streaming`].
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Rows held in memory at once; the reader is not polled again until the
+    /// current batch has been validated and saved.
+    pub batch_size: usize,
+    /// Threads used to validate a batch.
+    pub parallelism: usize,
+    /// Longest record, quoted newlines included, before it is reported as
+    /// malformed. Bounds what an unterminated quote can buffer.
+    pub max_record_len: usize,
+    pub age_limits: AgeLimits,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1_000,
+            parallelism: 1,
+            max_record_len: 64 * 1024,
+            age_limits: AgeLimits::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportProgress {
+    pub lines_read: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// A record read by line number, or why it could not be read.
+type Record = (usize, Result<String, ImportError>);
+
+fn validate_batch(batch: &[Record], options: &ImportOptions) -> Vec<Result<User, ImportError>> {
+    map_ordered(
+        batch.iter().collect(),
+        options.parallelism,
+        |(_, row)| match row {
+            Ok(row) => parse_row(row, &options.age_limits),
+            Err(error) => Err(error.clone()),
+        },
+    )
+}
+
+fn flush_batch(
+    repository: &impl UserRepository,
+    batch: &mut Vec<Record>,
+    options: &ImportOptions,
+    progress: &mut ImportProgress,
+    on_row: &mut impl FnMut(ImportedRow),
+) -> Result<()> {
+    let results = validate_batch(batch, options);
+    for ((line, _), result) in batch.drain(..).zip(results) {
+        let result = match result {
+            // A duplicate email fails this row only, like an invalid one.
+            Ok(user) => {
+                let id = user.id;
+                match repository.save(user) {
+                    Ok(()) => Ok(id),
+                    Err(error) => Err(error.downcast::<DomainError>()?.into()),
+                }
+            }
+            Err(error) => Err(error),
+        };
+        if result.is_ok() {
+            progress.succeeded += 1;
+        } else {
+            progress.failed += 1;
+        }
+        on_row(ImportedRow { line, result });
+    }
+    Ok(())
+}
+
+/// Imports users from CSV rows in bounded batches, so arbitrarily large
+/// inputs can be ingested. Every row outcome is handed to `on_row` in input
+/// order and `on_progress` is called after each batch.
+pub fn import_users_streaming(
+    repository: &impl UserRepository,
+    reader: impl BufRead,
+    options: &ImportOptions,
+    mut on_row: impl FnMut(ImportedRow),
+    mut on_progress: impl FnMut(&ImportProgress),
+) -> Result<ImportProgress> {
+    let batch_size = options.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut progress = ImportProgress::default();
+    // Record whose quoted field continues on the next line, by first line.
+    let mut open: Option<(usize, String)> = None;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        progress.lines_read += 1;
+        let (line_number, record, state) = match open.take() {
+            Some((line_number, mut record)) => {
+                let state = scan_line(&line, FieldState::Quoted);
+                record.push('\n');
+                record.push_str(&line);
+                (line_number, record, state)
+            }
+            None if line.trim().is_empty() || (index == 0 && line.trim() == HEADER) => continue,
+            None => {
+                let state = scan_line(&line, FieldState::Start);
+                (index + 1, line, state)
+            }
+        };
+        let record = if record.len() > options.max_record_len {
+            // The lines read into it are dropped with it; the next line
+            // starts a new record.
+            Err(ImportError::MalformedRow(format!(
+                "record is longer than {} bytes",
+                options.max_record_len
+            )))
+        } else if state == FieldState::Quoted {
+            open = Some((line_number, record));
+            continue;
+        } else {
+            Ok(record)
+        };
+
+        batch.push((line_number, record));
+        if batch.len() == batch_size {
+            flush_batch(repository, &mut batch, options, &mut progress, &mut on_row)?;
+            on_progress(&progress);
+        }
+    }
+    // Left open at the end of the input; reported as malformed.
+    batch.extend(open.map(|(line_number, record)| (line_number, Ok(record))));
+    if !batch.is_empty() {
+        flush_batch(repository, &mut batch, options, &mut progress, &mut on_row)?;
+        on_progress(&progress);
+    }
+    Ok(progress)
+}
+
+/// Imports users from CSV rows, saving every valid one whose age is within
+/// `age_limits`. A bad row is reported and skipped; only I/O and repository
+/// failures abort the import.
+pub fn import_users(
+    repository: &impl UserRepository,
+    reader: impl BufRead,
+    age_limits: &AgeLimits,
+) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let options = ImportOptions {
+        age_limits: age_limits.clone(),
+        ..ImportOptions::default()
+    };
+    import_users_streaming(
+        repository,
+        reader,
+        &options,
+        |row| report.rows.push(row),
+        |_| {},
+    )?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::export::{export_users, ExportColumn, ExportOptions};
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::{assert_debug_masks, UserFixture};
+    use crate::Age;
+
+    #[test]
+    fn ok_import_users_with_report() {
+        let repository = InMemoryUserRepository::new();
+        let csv = "email,age,name,surname,middle_name\n\
+                   foo@ok.com,22,Luca,Rossi,\n\
+                   foo.at.com,22,Luca,Rossi,\n\
+                   bar@ok.com,8,Anna,Bianchi,Maria\n\
+                   baz@ok.com,twenty,Anna,Bianchi,\n\
+                   baz@ok.com,30,Anna\n\
+                   qux@ok.com,30,Anna,Bianchi,Maria\n";
+
+        let report = import_users(&repository, csv.as_bytes(), &AgeLimits::default()).unwrap();
+
+        assert_eq!(report.succeeded(), 2);
+        assert_eq!(report.failed(), 4);
+        let lines: Vec<_> = report.rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            report.rows[1].result,
+            Err(ImportError::Invalid(DomainError::InvalidEmail))
+        );
+        assert_eq!(
+            report.rows[2].result,
+            Err(ImportError::Invalid(DomainError::Underage { min_age: 13 }))
+        );
+        assert!(matches!(
+            report.rows[3].result,
+            Err(ImportError::MalformedRow(_))
+        ));
+        assert_eq!(
+            report.rows[4].result.as_ref().unwrap_err().to_string(),
+            "Malformed row: expected 5 columns, found 3"
+        );
+
+        let imported = report.rows[5].result.as_ref().unwrap();
+        let user = repository.find(*imported).unwrap().unwrap();
+        assert_eq!(user.middle_name, Some("Maria".to_string()));
+    }
+
+    #[test]
+    fn ok_import_users_streaming_in_parallel() {
+        let repository = InMemoryUserRepository::new();
+        let csv: String = (0..25)
+            .map(|i| match i % 5 {
+                0 => format!("user{}.at.com,22,Luca,Rossi,\n", i),
+                _ => format!("user{}@ok.com,22,Luca,Rossi,\n", i),
+            })
+            .collect();
+        let options = ImportOptions {
+            batch_size: 10,
+            parallelism: 4,
+            ..ImportOptions::default()
+        };
+        let mut lines = Vec::new();
+        let mut batches = Vec::new();
+
+        let progress = import_users_streaming(
+            &repository,
+            csv.as_bytes(),
+            &options,
+            |row| lines.push(row.line),
+            |progress| batches.push(progress.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(lines, (1..=25).collect::<Vec<_>>());
+        assert_eq!(batches.len(), 3);
+        assert_eq!(
+            progress,
+            ImportProgress {
+                lines_read: 25,
+                succeeded: 20,
+                failed: 5
+            }
+        );
+    }
+
+    #[test]
+    fn ok_duplicate_email_fails_its_row_only() {
+        let repository = InMemoryUserRepository::new();
+        let csv = "foo@ok.com,22,Luca,Rossi,\n\
+                   FOO@ok.com,30,Anna,Bianchi,\n\
+                   bar@ok.com,30,Anna,Bianchi,\n";
+
+        let report = import_users(&repository, csv.as_bytes(), &AgeLimits::default()).unwrap();
+
+        assert_eq!(report.succeeded(), 2);
+        assert_eq!(
+            report.rows[1].result,
+            Err(ImportError::Invalid(DomainError::EmailAlreadyRegistered))
+        );
+    }
+
+    #[test]
+    fn ok_reimports_quoted_export() {
+        let source = InMemoryUserRepository::new();
+        let mut user = UserFixture::new()
+            .with_name("Luca \"Lu\"", Some("Maria,\nAnna"), "Rossi, Jr")
+            .build();
+        user.age = Age::try_from(22).unwrap();
+        source.save(user).unwrap();
+        let options = ExportOptions {
+            columns: vec![
+                ExportColumn::Email,
+                ExportColumn::Age,
+                ExportColumn::Name,
+                ExportColumn::Surname,
+                ExportColumn::MiddleName,
+            ],
+            ..ExportOptions::default()
+        };
+        let mut csv = Vec::new();
+        export_users(&source, &options, &mut csv).unwrap();
+        let repository = InMemoryUserRepository::new();
+
+        let report = import_users(&repository, csv.as_slice(), &AgeLimits::default()).unwrap();
+
+        assert_eq!(report.failed(), 0);
+        let id = report.rows[0].result.as_ref().unwrap();
+        let user = repository.find(*id).unwrap().unwrap();
+        assert_eq!(user.name, "Luca \"Lu\"");
+        assert_eq!(user.surname, "Rossi, Jr");
+        assert_eq!(user.middle_name.as_deref(), Some("Maria,\nAnna"));
+        let unterminated = import_users(
+            &repository,
+            "a@ok.com,22,\"Luca,Rossi,\n".as_bytes(),
+            &AgeLimits::default(),
+        );
+        assert!(matches!(
+            unterminated.unwrap().rows[0].result,
+            Err(ImportError::MalformedRow(_))
+        ));
+    }
+
+    #[test]
+    fn err_import_checks_ages_against_given_limits() {
+        let repository = InMemoryUserRepository::new();
+        let limits = AgeLimits { min: 18, max: 65 };
+        let csv = "foo@ok.com,16,Luca,Rossi,\n\
+                   bar@ok.com,70,Anna,Bianchi,\n";
+
+        let report = import_users(&repository, csv.as_bytes(), &limits).unwrap();
+
+        assert_eq!(
+            report.rows[0].result,
+            Err(ImportError::Invalid(DomainError::Underage { min_age: 18 }))
+        );
+        assert_eq!(
+            report.rows[1].result,
+            Err(ImportError::Invalid(DomainError::AgeTooHigh))
+        );
+    }
+
+    #[test]
+    fn ok_stray_quote_in_unquoted_field_stays_on_its_line() {
+        let repository = InMemoryUserRepository::new();
+        let csv = "foo@ok.com,22,Luca \"Lu,Rossi,\n\
+                   bar@ok.com,30,Anna,Bianchi,\n";
+
+        let report = import_users(&repository, csv.as_bytes(), &AgeLimits::default()).unwrap();
+
+        assert_eq!(report.succeeded(), 2);
+        let lines: Vec<_> = report.rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![1, 2]);
+        let user = repository
+            .find(*report.rows[0].result.as_ref().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.name, "Luca \"Lu");
+    }
+
+    #[test]
+    fn err_record_longer_than_limit_fails_its_row() {
+        let repository = InMemoryUserRepository::new();
+        let csv = "a@ok.com,22,\"Luca,Rossi,\n\
+                   a line swallowed by the open quote\n\
+                   b@ok.com,30,Anna,Bianchi,\n";
+        let options = ImportOptions {
+            max_record_len: 40,
+            ..ImportOptions::default()
+        };
+        let mut rows = Vec::new();
+
+        import_users_streaming(
+            &repository,
+            csv.as_bytes(),
+            &options,
+            |row| rows.push(row),
+            |_| {},
+        )
+        .unwrap();
+
+        let lines: Vec<_> = rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![1, 3]);
+        assert_eq!(
+            rows[0].result,
+            Err(ImportError::MalformedRow(
+                "record is longer than 40 bytes".to_string()
+            ))
+        );
+        assert!(rows[1].result.is_ok());
+    }
+
+    /// Rows report only the line and the outcome, never the values read.
+    #[test]
+    fn ok_imported_rows_debug_holds_no_pii() {
+        let repository = InMemoryUserRepository::new();
+        let csv = "email,age,name,surname,middle_name\n\
+                   luca.rossi@ok.com,22,Luca,Rossi,Maria\n\
+                   anna.bianchi@ok.com,8,Anna,Bianchi,\n";
+
+        let report = import_users(&repository, csv.as_bytes(), &AgeLimits::default()).unwrap();
+
+        assert_debug_masks(
+            &report,
+            &["luca.rossi", "Luca", "Rossi", "Maria", "anna", "Bianchi"],
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use crate::broker::{BrokerMessage, WireFormat};
+use crate::events::{DomainEvent, EventHandler, EventId};
+use anyhow::{Error, Result};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Consumer-side record of which events each handler has already processed.
+pub trait Inbox {
+    fn is_processed(&self, handler: &str, event_id: EventId) -> Result<bool>;
+    fn mark_processed(&self, handler: &str, event_id: EventId) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryInbox {
+    processed: Mutex<HashSet<(String, EventId)>>,
+}
+
+impl InMemoryInbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Inbox for InMemoryInbox {
+    fn is_processed(&self, handler: &str, event_id: EventId) -> Result<bool> {
+        Ok(self
+            .processed
+            .lock()
+            .unwrap()
+            .contains(&(handler.to_string(), event_id)))
+    }
+
+    fn mark_processed(&self, handler: &str, event_id: EventId) -> Result<()> {
+        self.processed
+            .lock()
+            .unwrap()
+            .insert((handler.to_string(), event_id));
+        Ok(())
+    }
+}
+
+/// Runs `handler` at most once per event id, however often the event is
+/// redelivered by the broker or a replay.
+pub struct DeduplicatingConsumer<'a, I: Inbox, H: EventHandler> {
+    inbox: &'a I,
+    handler: &'a H,
+}
+
+impl<'a, I: Inbox, H: EventHandler> DeduplicatingConsumer<'a, I, H> {
+    pub fn new(inbox: &'a I, handler: &'a H) -> Self {
+        Self { inbox, handler }
+    }
+
+    /// Returns `false` when the event was a duplicate and has been skipped.
+    /// A failing handler leaves the event unmarked so it can be retried.
+    pub fn consume(&self, event_id: EventId, event: &DomainEvent) -> Result<bool> {
+        let name = self.handler.name();
+        if self.inbox.is_processed(name, event_id)? {
+            return Ok(false);
+        }
+        self.handler.handle(event)?;
+        self.inbox.mark_processed(name, event_id)?;
+        Ok(true)
+    }
+
+    /// Decodes a message received from the broker and consumes it under its
+    /// [`BrokerMessage::message_id`]. Messages without an id are rejected, as
+    /// a redelivery of one could not be recognised.
+    pub fn consume_message(&self, message: &BrokerMessage) -> Result<bool> {
+        let event_id = message
+            .message_id
+            .ok_or_else(|| Error::msg("Message has no id to deduplicate on"))?;
+        let event =
+            WireFormat::from_content_type(&message.content_type)?.decode(&message.payload)?;
+        self.consume(event_id, &event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::broker::{BrokerPublisher, MessageProducer};
+    use crate::events::{EmailVerified, EventPublisher};
+    use crate::outbox::{InMemoryOutbox, Outbox, OutboxRelay};
+    use crate::UserId;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingHandler(AtomicUsize);
+
+    impl EventHandler for CountingHandler {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn handle(&self, _event: &DomainEvent) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn email_verified(user_id: u64) -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(user_id),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_redelivered_event_is_skipped() {
+        let inbox = InMemoryInbox::new();
+        let handler = CountingHandler::default();
+        let consumer = DeduplicatingConsumer::new(&inbox, &handler);
+        let event = email_verified(1);
+
+        assert!(consumer.consume(EventId(7), &event).unwrap());
+        assert!(!consumer.consume(EventId(7), &event).unwrap());
+        assert!(consumer.consume(EventId(8), &event).unwrap());
+
+        assert_eq!(handler.0.load(Ordering::SeqCst), 2);
+        assert!(inbox.is_processed("counter", EventId(7)).unwrap());
+        assert!(!inbox.is_processed("other", EventId(7)).unwrap());
+    }
+
+    #[derive(Default)]
+    struct RedeliveringProducer(Mutex<Vec<BrokerMessage>>);
+
+    impl MessageProducer for &RedeliveringProducer {
+        fn send(&self, message: &BrokerMessage) -> Result<()> {
+            let mut sent = self.0.lock().unwrap();
+            sent.push(message.clone());
+            sent.push(message.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ok_redelivered_broker_message_is_skipped() {
+        let producer = RedeliveringProducer::default();
+        let outbox = InMemoryOutbox::new();
+        outbox.append(email_verified(1)).unwrap();
+        outbox.append(email_verified(1)).unwrap();
+        OutboxRelay::new(&outbox, &BrokerPublisher::new(&producer, "user-events"))
+            .relay()
+            .unwrap();
+        let inbox = InMemoryInbox::new();
+        let handler = CountingHandler::default();
+        let consumer = DeduplicatingConsumer::new(&inbox, &handler);
+
+        let consumed: Vec<bool> = producer
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|message| consumer.consume_message(message).unwrap())
+            .collect();
+
+        assert_eq!(consumed, vec![true, false, true, false]);
+        assert_eq!(handler.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn err_broker_message_without_id() {
+        let producer = RedeliveringProducer::default();
+        BrokerPublisher::new(&producer, "user-events")
+            .publish(&email_verified(1))
+            .unwrap();
+        let inbox = InMemoryInbox::new();
+        let handler = CountingHandler::default();
+        let consumer = DeduplicatingConsumer::new(&inbox, &handler);
+
+        assert!(consumer
+            .consume_message(&producer.0.lock().unwrap()[0])
+            .is_err());
+        assert_eq!(handler.0.load(Ordering::SeqCst), 0);
+    }
+}
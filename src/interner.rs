@@ -0,0 +1,53 @@
+//! Shared copies of strings that repeat across many users.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Pool handing out one `Arc<str>` per distinct string, so a value held by
+/// a million users, like a popular email domain, is stored once.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: Mutex<HashSet<Arc<str>>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&self, value: &str) -> Arc<str> {
+        let mut strings = self.strings.lock().unwrap();
+        if let Some(interned) = strings.get(value) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(value);
+        strings.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Distinct strings in the pool.
+    pub fn len(&self) -> usize {
+        self.strings.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_equal_strings_share_storage() {
+        let interner = Interner::new();
+
+        let first = interner.intern("ok.com");
+        let second = interner.intern(&String::from("ok.com"));
+        interner.intern("okay.com");
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(interner.len(), 2);
+    }
+}
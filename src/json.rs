@@ -0,0 +1,244 @@
+use anyhow::{Error, Result};
+use std::fmt::{Display, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Minimal JSON value, enough to render payloads without a serialization crate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Field of an object, if this is one and it has the field.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            JsonValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a JSON document. Only integer numbers are supported, matching
+/// [`JsonValue::Number`].
+pub fn parse_json(input: &str) -> Result<JsonValue> {
+    let mut chars = input.chars().peekable();
+    let value = parse_value(&mut chars).ok_or_else(|| Error::msg("Invalid JSON"))?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(value),
+        Some(_) => Err(Error::msg("Invalid JSON")),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars<'_>>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect_word(chars: &mut Peekable<Chars<'_>>, word: &str, value: JsonValue) -> Option<JsonValue> {
+    word.chars()
+        .all(|expected| chars.next() == Some(expected))
+        .then_some(value)
+}
+
+fn parse_value(chars: &mut Peekable<Chars<'_>>) -> Option<JsonValue> {
+    skip_whitespace(chars);
+    match *chars.peek()? {
+        'n' => expect_word(chars, "null", JsonValue::Null),
+        't' => expect_word(chars, "true", JsonValue::Bool(true)),
+        'f' => expect_word(chars, "false", JsonValue::Bool(false)),
+        '"' => parse_string(chars).map(JsonValue::String),
+        '[' => {
+            chars.next();
+            let mut values = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Some(JsonValue::Array(values));
+            }
+            loop {
+                values.push(parse_value(chars)?);
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    ']' => return Some(JsonValue::Array(values)),
+                    _ => return None,
+                }
+            }
+        }
+        '{' => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Some(JsonValue::Object(fields));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                chars.next_if_eq(&':')?;
+                fields.push((key, parse_value(chars)?));
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    '}' => return Some(JsonValue::Object(fields)),
+                    _ => return None,
+                }
+            }
+        }
+        _ => {
+            let mut number = String::new();
+            if let Some(sign) = chars.next_if_eq(&'-') {
+                number.push(sign);
+            }
+            while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                number.push(digit);
+            }
+            number.parse().ok().map(JsonValue::Number)
+        }
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars<'_>>) -> Option<String> {
+    chars.next_if_eq(&'"')?;
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                '"' => value.push('"'),
+                '\\' => value.push('\\'),
+                '/' => value.push('/'),
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let code: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => f.write_str("null"),
+            JsonValue::Bool(value) => write!(f, "{}", value),
+            JsonValue::Number(value) => write!(f, "{}", value),
+            JsonValue::String(value) => write_string(f, value),
+            JsonValue::Array(values) => {
+                f.write_char('[')?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_char(']')
+            }
+            JsonValue::Object(fields) => {
+                f.write_char('{')?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_render_json() {
+        let value = JsonValue::Object(vec![
+            (
+                "name".to_string(),
+                JsonValue::String("Lu\"ca\n".to_string()),
+            ),
+            ("age".to_string(), JsonValue::Number(22)),
+            (
+                "tags".to_string(),
+                JsonValue::Array(vec![JsonValue::Bool(true), JsonValue::Null]),
+            ),
+        ]);
+
+        assert_eq!(
+            value.to_string(),
+            r#"{"name":"Lu\"ca\n","age":22,"tags":[true,null]}"#
+        );
+    }
+
+    #[test]
+    fn ok_parse_json() {
+        let input = r#" {"name":"Lu\"ca\n","age":-22,"tags":[true,null, {}],"empty":[]} "#;
+
+        let value = parse_json(input).unwrap();
+
+        assert_eq!(
+            value.get("name").and_then(JsonValue::as_str),
+            Some("Lu\"ca\n")
+        );
+        assert_eq!(value.get("age").and_then(JsonValue::as_i64), Some(-22));
+        assert_eq!(parse_json(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn err_parse_json_trailing_input() {
+        let result = parse_json("{} x");
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid JSON");
+    }
+}
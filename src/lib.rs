@@ -0,0 +1,808 @@
+use anyhow::Result;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+pub mod actors;
+pub mod api;
+#[cfg(test)]
+mod arbitrary;
+pub mod avatar;
+pub mod avro;
+pub mod broker;
+pub mod bus;
+pub mod cache;
+pub mod circuit_breaker;
+pub mod clock;
+pub mod commands;
+pub mod completeness;
+pub mod config;
+pub mod custom_attributes;
+pub mod date;
+pub mod dead_letter;
+pub mod demographics;
+pub mod error;
+pub mod event_store;
+pub mod events;
+pub mod export;
+pub mod feature_flags;
+pub mod feed;
+pub mod ffi;
+pub mod full_names;
+pub mod granting;
+mod hash;
+pub mod health;
+pub mod history;
+pub mod http;
+pub mod idempotency;
+pub mod identity_provider;
+pub mod import;
+pub mod inbox;
+pub mod interner;
+pub mod json;
+pub mod logging;
+pub mod merge;
+pub mod metrics;
+pub mod migrations;
+pub mod notifications;
+pub mod onboarding;
+pub mod otlp;
+pub mod outbox;
+pub mod parallel;
+pub mod pii;
+pub mod pool;
+pub mod problem;
+pub mod projections;
+pub mod pronouns;
+pub mod protobuf;
+pub mod query;
+pub mod repl;
+pub mod replay;
+pub mod replication;
+pub mod repository;
+pub mod retry;
+pub mod scheduler;
+pub mod schema;
+pub mod search;
+pub mod search_cluster;
+pub mod secrets;
+pub mod sharding;
+pub mod shutdown;
+#[cfg(test)]
+mod simulation;
+pub mod specification;
+pub mod stats;
+pub mod status;
+pub mod stream;
+pub mod tags;
+#[cfg(test)]
+mod test_support;
+pub mod throttle;
+pub mod trace;
+pub mod transaction;
+pub mod typeahead;
+pub mod unit_of_work;
+pub mod users_by_domain;
+pub mod verification;
+pub mod versioning;
+pub mod webhooks;
+
+use avatar::UploadedAvatar;
+use config::AgeLimits;
+use custom_attributes::CustomAttributes;
+use error::DomainError;
+use granting::{grant_user_with, VerifiedEmailRequired};
+use pii::{mask_email, mask_name};
+use pronouns::Pronouns;
+use tags::Tag;
+
+static NEXT_USER_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UserId(pub u64);
+
+/// Organization a user belongs to; users of different tenants never see
+/// each other.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TenantId(pub String);
+
+impl Default for TenantId {
+    fn default() -> Self {
+        Self("default".to_string())
+    }
+}
+
+/// Debug output masks the address, so it stays out of logs; `as_str` and
+/// `Display` give the address itself.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Email(pub(crate) String);
+
+impl Email {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Form in which two addresses are the same: trimmed and lowercased.
+    pub fn normalized(&self) -> String {
+        self.0.trim().to_lowercase()
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VerifiedEmail(pub(crate) Email);
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnverifiedEmail(pub(crate) Email);
+
+impl VerifiedEmail {
+    pub fn address(&self) -> &Email {
+        &self.0
+    }
+}
+
+impl UnverifiedEmail {
+    pub fn address(&self) -> &Email {
+        &self.0
+    }
+}
+
+/// Outside this crate only built through [`check_age_within`] or
+/// `TryFrom<i32>`, so always within limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Age(pub(crate) i32);
+
+impl Age {
+    pub fn years(&self) -> i32 {
+        self.0
+    }
+}
+
+/// More states, such as bounced, may be added; match with a wildcard arm
+/// outside this crate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UserEmail {
+    VerifiedEmail(VerifiedEmail),
+    UnverifiedEmail(UnverifiedEmail),
+}
+
+impl UserEmail {
+    pub fn address(&self) -> &Email {
+        match self {
+            UserEmail::VerifiedEmail(VerifiedEmail(email))
+            | UserEmail::UnverifiedEmail(UnverifiedEmail(email)) => email,
+        }
+    }
+}
+
+/// Debug output masks the name and email; see [`pii`].
+#[derive(Clone)]
+pub struct User {
+    id: UserId,
+    tenant_id: TenantId,
+    name: String,
+    middle_name: Option<String>,
+    surname: String,
+    age: Age,
+    email: UserEmail,
+    pronouns: Option<Pronouns>,
+    avatar: Option<UploadedAvatar>,
+    custom_attributes: CustomAttributes,
+    tags: BTreeSet<Tag>,
+    /// Set once this user has been merged into another one.
+    merged_into: Option<UserId>,
+    registered_at: SystemTime,
+    /// Number of times this user has been saved; 0 until first persisted.
+    version: u64,
+}
+
+/// Users are entities: two values are the same user when their ids match,
+/// whatever else has changed between them.
+impl PartialEq for User {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for User {}
+
+impl Hash for User {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl Display for Email {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for Age {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The address followed by its status, e.g. `foo@ok.com (verified)`.
+impl Display for UserEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserEmail::VerifiedEmail(VerifiedEmail(email)) => write!(f, "{} (verified)", email),
+            UserEmail::UnverifiedEmail(UnverifiedEmail(email)) => {
+                write!(f, "{} (unverified)", email)
+            }
+        }
+    }
+}
+
+/// One-line summary, e.g. `User 7: Luca Rossi, 22 years old, foo@ok.com
+/// (verified)`.
+impl Display for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "User {}: {}, {} years old, {}",
+            self.id,
+            self.full_name(),
+            self.age,
+            self.email
+        )
+    }
+}
+
+impl Debug for Email {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Email").field(&mask_email(&self.0)).finish()
+    }
+}
+
+impl Debug for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("tenant_id", &self.tenant_id)
+            .field("name", &mask_name(&self.name))
+            .field("middle_name", &self.middle_name.as_deref().map(mask_name))
+            .field("surname", &mask_name(&self.surname))
+            .field("age", &self.age)
+            .field("email", &self.email)
+            .field("pronouns", &self.pronouns)
+            .field("avatar", &self.avatar)
+            .field("custom_attributes", &self.custom_attributes)
+            .field("tags", &self.tags)
+            .field("merged_into", &self.merged_into)
+            .field("registered_at", &self.registered_at)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl AsRef<str> for Email {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Full name rendered straight into a formatter, borrowing the parts, so
+/// writing it into a log line or response allocates nothing.
+#[derive(Clone, Copy, PartialEq)]
+pub struct FullName<'a> {
+    pub name: &'a str,
+    pub middle_name: Option<&'a str>,
+    pub surname: &'a str,
+}
+
+impl Display for FullName<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)?;
+        if let Some(middle_name) = self.middle_name {
+            write!(f, " {}", middle_name)?;
+        }
+        write!(f, " {}", self.surname)
+    }
+}
+
+impl Debug for FullName<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FullName")
+            .field("name", &mask_name(self.name))
+            .field("middle_name", &self.middle_name.map(mask_name))
+            .field("surname", &mask_name(self.surname))
+            .finish()
+    }
+}
+
+impl UserId {
+    pub fn generate() -> Self {
+        Self(NEXT_USER_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl User {
+    pub(crate) fn new(
+        name: String,
+        middle_name: Option<String>,
+        surname: String,
+        age: Age,
+        email: Email,
+    ) -> Self {
+        Self {
+            id: UserId::generate(),
+            tenant_id: TenantId::default(),
+            name,
+            middle_name,
+            surname,
+            age,
+            email: UserEmail::UnverifiedEmail(UnverifiedEmail(email)),
+            pronouns: None,
+            avatar: None,
+            custom_attributes: CustomAttributes::default(),
+            tags: BTreeSet::new(),
+            merged_into: None,
+            registered_at: SystemTime::now(),
+            version: 0,
+        }
+    }
+
+    pub fn id(&self) -> UserId {
+        self.id
+    }
+
+    pub fn tenant_id(&self) -> &TenantId {
+        &self.tenant_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn middle_name(&self) -> Option<&str> {
+        self.middle_name.as_deref()
+    }
+
+    pub fn surname(&self) -> &str {
+        &self.surname
+    }
+
+    pub fn age(&self) -> Age {
+        self.age
+    }
+
+    pub fn email(&self) -> &UserEmail {
+        &self.email
+    }
+
+    pub fn is_email_verified(&self) -> bool {
+        matches!(self.email, UserEmail::VerifiedEmail(_))
+    }
+
+    pub fn pronouns(&self) -> Option<&Pronouns> {
+        self.pronouns.as_ref()
+    }
+
+    pub fn tags(&self) -> &BTreeSet<Tag> {
+        &self.tags
+    }
+
+    pub fn full_name(&self) -> FullName<'_> {
+        FullName {
+            name: &self.name,
+            middle_name: self.middle_name.as_deref(),
+            surname: &self.surname,
+        }
+    }
+}
+
+pub fn verify_email(email: &UnverifiedEmail) -> Result<VerifiedEmail, DomainError> {
+    let UnverifiedEmail(unverified_email) = email;
+
+    let is_ok = unverified_email.as_str().contains("ok");
+    // verify email
+    if is_ok {
+        Ok(VerifiedEmail(unverified_email.clone()))
+    } else {
+        Err(DomainError::EmailNotVerified)
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = DomainError;
+
+    fn try_from(email: String) -> Result<Self, DomainError> {
+        // Compiled once: building a `Regex` costs far more than matching one,
+        // which adds up over a bulk import.
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+        let re = PATTERN.get_or_init(|| Regex::new(r"^[\w.]+@[\w.]+\.\w+$").unwrap());
+        if re.is_match(&email) {
+            Ok(Email(email))
+        } else {
+            Err(DomainError::InvalidEmail)
+        }
+    }
+}
+
+impl TryFrom<&str> for Email {
+    type Error = DomainError;
+
+    fn try_from(email: &str) -> Result<Self, DomainError> {
+        Email::try_from(email.to_string())
+    }
+}
+
+impl From<VerifiedEmail> for Email {
+    fn from(VerifiedEmail(email): VerifiedEmail) -> Self {
+        email
+    }
+}
+
+/// Checks `age` against the default [`AgeLimits`]; see [`check_age_within`]
+/// for configured ones.
+impl TryFrom<i32> for Age {
+    type Error = DomainError;
+
+    fn try_from(age: i32) -> Result<Self, DomainError> {
+        check_age_within(age, &AgeLimits::default())
+    }
+}
+
+impl FromStr for UserId {
+    type Err = ParseIntError;
+
+    fn from_str(id: &str) -> Result<Self, ParseIntError> {
+        id.parse().map(UserId)
+    }
+}
+
+pub fn check_age_within(age: i32, limits: &AgeLimits) -> Result<Age, DomainError> {
+    match age {
+        x if x < 0 => Err(DomainError::NegativeAge),
+        x if x < limits.min => Err(DomainError::Underage {
+            min_age: limits.min,
+        }),
+        x if x > limits.max => Err(DomainError::AgeTooHigh),
+        _ => Ok(Age(age)),
+    }
+}
+
+/// Creates a user under the default [`AgeLimits`]; see
+/// [`create_user_within`] for configured ones.
+pub fn create_user(
+    email: String,
+    age: i32,
+    name: String,
+    surname: String,
+    middle_name: Option<String>,
+) -> Result<User, DomainError> {
+    create_user_within(
+        email,
+        age,
+        name,
+        surname,
+        middle_name,
+        &AgeLimits::default(),
+    )
+}
+
+pub fn create_user_within(
+    email: String,
+    age: i32,
+    name: String,
+    surname: String,
+    middle_name: Option<String>,
+    limits: &AgeLimits,
+) -> Result<User, DomainError> {
+    let age = check_age_within(age, limits)?;
+    let email = Email::try_from(email)?;
+
+    let user = User::new(name, middle_name, surname, age, email);
+
+    Ok(user)
+}
+
+/// Grants `user` under the default [`VerifiedEmailRequired`] policy.
+pub fn grant_user(user: &mut User) -> Result<(), DomainError> {
+    grant_user_with(user, &VerifiedEmailRequired)
+}
+
+pub fn get_fullname(user: &User) -> String {
+    user.full_name().to_string()
+}
+
+pub fn format_fullname(name: &str, middle_name: Option<&str>, surname: &str) -> String {
+    FullName {
+        name,
+        middle_name,
+        surname,
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arbitrary::{check, AnyAge, AnyString, InvalidEmail, NamePart, ValidEmail};
+
+    #[test]
+    fn ok_create_user() {
+        let input_email = "foo@ok.com".to_string();
+        let input_age = 22;
+        let name = "Luca".to_string();
+        let surname = "Rossi".to_string();
+        let middle_name: Option<String> = None;
+
+        let user = create_user(input_email, input_age, name, surname, middle_name);
+        assert!(user.is_ok());
+        let mut user = user.unwrap();
+        let result = grant_user(&mut user);
+        assert!(result.is_ok());
+
+        assert_eq!(user.name, "Luca".to_string());
+        assert_eq!(user.surname, "Rossi".to_string());
+        assert!(user.middle_name.is_none());
+        assert_eq!(user.age.0, 22);
+
+        let is_verified_email = match user.email {
+            UserEmail::VerifiedEmail(_) => true,
+            UserEmail::UnverifiedEmail(_) => false,
+        };
+        assert!(is_verified_email);
+    }
+
+    #[test]
+    fn ok_user_accessors() {
+        let mut user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            Some("Maria".to_string()),
+        )
+        .unwrap();
+        user.pronouns = Some(Pronouns::TheyThem);
+        user.tags.insert(Tag("vip".to_string()));
+
+        assert_eq!(user.tenant_id(), &TenantId::default());
+        assert_eq!(
+            (user.name(), user.middle_name(), user.surname()),
+            ("Luca", Some("Maria"), "Rossi")
+        );
+        assert_eq!(user.age().years(), 22);
+        assert_eq!(user.pronouns(), Some(&Pronouns::TheyThem));
+        assert!(user.tags().contains(&Tag("vip".to_string())));
+        assert_eq!(user.email().address().as_str(), "foo@ok.com");
+    }
+
+    #[test]
+    fn ok_create_user_unverified() {
+        let input_email = "foo@unverified.com".to_string();
+        let input_age = 22;
+        let name = "Luca".to_string();
+        let surname = "Rossi".to_string();
+        let middle_name: Option<String> = None;
+
+        let user = create_user(input_email, input_age, name, surname, middle_name);
+        let mut user = user.unwrap();
+        let result = grant_user(&mut user);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Email has not been verified yet");
+
+        let is_unverified_email = match user.email {
+            UserEmail::VerifiedEmail(_) => false,
+            UserEmail::UnverifiedEmail(_) => true,
+        };
+        assert!(is_unverified_email);
+    }
+
+    #[test]
+    fn err_invalid_email() {
+        let input_email = "foo.at.com".to_string();
+        let input_age = 22;
+        let name = "Luca".to_string();
+        let surname = "Rossi".to_string();
+        let middle_name: Option<String> = None;
+
+        let user = create_user(input_email, input_age, name, surname, middle_name);
+
+        assert!(user.is_err());
+        let error = user.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid email");
+    }
+
+    #[test]
+    fn err_invalid_age_negative() {
+        let input_email = "fo@ok.com".to_string();
+        let input_age = -100;
+        let name = "Luca".to_string();
+        let surname = "Rossi".to_string();
+        let middle_name: Option<String> = None;
+
+        let user = create_user(input_email, input_age, name, surname, middle_name);
+
+        assert!(user.is_err());
+        let error = user.unwrap_err();
+        assert_eq!(error.to_string(), "Age cannot be negative");
+    }
+
+    #[test]
+    fn err_invalid_age_immortal() {
+        let input_email = "fo@ok.com".to_string();
+        let input_age = 130;
+        let name = "Luca".to_string();
+        let surname = "Rossi".to_string();
+        let middle_name: Option<String> = None;
+
+        let user = create_user(input_email, input_age, name, surname, middle_name);
+
+        assert!(user.is_err());
+        let error = user.unwrap_err();
+        assert_eq!(error.to_string(), "I don't think you can be immortal");
+    }
+
+    #[test]
+    fn ok_full_name_written_from_borrowed_parts() {
+        let user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            Some("Maria".to_string()),
+        )
+        .unwrap();
+
+        let mut line = String::new();
+        std::fmt::Write::write_fmt(&mut line, format_args!("[{}]", user.full_name())).unwrap();
+
+        assert_eq!(line, "[Luca Maria Rossi]");
+        assert_eq!(get_fullname(&user), "Luca Maria Rossi");
+        assert_eq!(user.email.address().as_ref(), "foo@ok.com");
+    }
+
+    #[test]
+    fn ok_value_objects_convert_with_std_traits() {
+        let email: Email = "foo@ok.com".try_into().unwrap();
+        let verified = verify_email(&UnverifiedEmail(email.clone())).unwrap();
+
+        assert_eq!(Email::from(verified), email);
+        assert_eq!(Age::try_from(22), Ok(Age(22)));
+        assert_eq!("42".parse::<UserId>().unwrap(), UserId(42));
+        assert_eq!(
+            Email::try_from("foo.at.com"),
+            Err(DomainError::InvalidEmail)
+        );
+        assert_eq!(Age::try_from(-1), Err(DomainError::NegativeAge));
+        assert!("one".parse::<UserId>().is_err());
+    }
+
+    #[test]
+    fn ok_display_summarizes_user() {
+        let mut user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        user.id = UserId(7);
+
+        assert_eq!(
+            user.to_string(),
+            "User 7: Luca Rossi, 22 years old, foo@ok.com (unverified)"
+        );
+        grant_user(&mut user).unwrap();
+        assert_eq!(user.email.to_string(), "foo@ok.com (verified)");
+    }
+
+    #[test]
+    fn ok_debug_masks_pii() {
+        let user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            Some("Maria".to_string()),
+        )
+        .unwrap();
+
+        let debug = format!("{:?} {:?}", user, user.full_name());
+
+        for pii in ["foo@", "Luca", "Maria", "Rossi"] {
+            assert!(!debug.contains(pii), "{} in {}", pii, debug);
+        }
+        assert!(debug.contains(r#"Email("f***@ok.com")"#));
+        assert_eq!(user.email.address().as_str(), "foo@ok.com");
+    }
+
+    #[test]
+    fn ok_users_equal_by_identity() {
+        let user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        let mut renamed = user.clone();
+        renamed.name = "Marco".to_string();
+        let mut users = std::collections::HashSet::new();
+        users.insert(user.clone());
+
+        assert_eq!(user, renamed);
+        assert!(!users.insert(renamed));
+        assert_ne!(
+            user.email,
+            UserEmail::VerifiedEmail(VerifiedEmail(Email("foo@ok.com".to_string())))
+        );
+        let mut ages = vec![Age(40), Age(18), Age(22)];
+        ages.sort();
+        assert_eq!(ages, [Age(18), Age(22), Age(40)]);
+    }
+
+    #[test]
+    fn ok_email_parsing_never_panics_and_round_trips() {
+        check(50_000, |AnyString(input): &AnyString| {
+            let Ok(email) = Email::try_from(input.clone()) else {
+                return true;
+            };
+            let normalized = Email(email.normalized());
+            email.to_string() == *input
+                && Email::try_from(email.to_string()).is_ok()
+                && normalized.normalized() == normalized.as_str()
+        });
+    }
+
+    #[test]
+    fn ok_any_valid_email_revalidates() {
+        check(5_000, |ValidEmail(input): &ValidEmail| {
+            Email::try_from(input.clone())
+                .is_ok_and(|email| Email::try_from(email.to_string()).is_ok())
+        });
+    }
+
+    #[test]
+    fn err_any_invalid_email_rejected() {
+        check(5_000, |InvalidEmail(input): &InvalidEmail| {
+            Email::try_from(input.clone()).is_err()
+        });
+    }
+
+    #[test]
+    fn ok_accepted_age_always_within_limits() {
+        check(5_000, |AnyAge(age): &AnyAge| match Age::try_from(*age) {
+            Ok(Age(accepted)) => (13..=120).contains(&accepted),
+            Err(_) => !(13..=120).contains(age),
+        });
+    }
+
+    #[test]
+    fn ok_full_name_has_every_part_once() {
+        type Parts = (NamePart, Option<NamePart>, NamePart);
+        check(5_000, |(name, middle_name, surname): &Parts| {
+            let full_name = format_fullname(
+                &name.0,
+                middle_name.as_ref().map(|part| part.0.as_str()),
+                &surname.0,
+            );
+            let expected: Vec<&str> = [Some(name), middle_name.as_ref(), Some(surname)]
+                .into_iter()
+                .flatten()
+                .map(|part| part.0.as_str())
+                .collect();
+            full_name.split(' ').collect::<Vec<_>>() == expected
+        });
+    }
+}
@@ -0,0 +1,159 @@
+//! JSON log lines, one per closed span, tagged with the correlation id of the
+//! request that caused them.
+
+use crate::hash::to_hex;
+use crate::json::JsonValue;
+use crate::trace::{random_id, Outcome, SpanRecord, Subscriber};
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Ties together everything done on behalf of one request, across log lines
+/// and the events it emitted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CorrelationId(pub String);
+
+impl CorrelationId {
+    pub fn generate() -> Self {
+        Self(to_hex(&random_id()[..16]))
+    }
+}
+
+impl Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CorrelationId>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` on behalf of the request identified by `id`.
+pub fn with_correlation_id<T>(id: CorrelationId, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.borrow_mut().replace(id));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+pub fn current_correlation_id() -> Option<CorrelationId> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Renders a closed span as a single-line JSON log entry.
+pub fn log_line(span: &SpanRecord, correlation_id: Option<&CorrelationId>) -> String {
+    let string = |value: &str| JsonValue::String(value.to_string());
+    let timestamp = (span.started_at + span.duration)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut entry = vec![
+        (
+            "timestamp_ms".to_string(),
+            JsonValue::Number(timestamp as i64),
+        ),
+        (
+            "level".to_string(),
+            string(match span.outcome {
+                Outcome::Ok => "info",
+                Outcome::Error(_) => "error",
+            }),
+        ),
+        ("span".to_string(), string(span.name)),
+        ("trace_id".to_string(), string(&to_hex(&span.trace_id))),
+        (
+            "correlation_id".to_string(),
+            correlation_id.map_or(JsonValue::Null, |id| string(&id.0)),
+        ),
+    ];
+    entry.extend(
+        span.masked_fields()
+            .iter()
+            .map(|(key, value)| (key.to_string(), string(value))),
+    );
+    if let Outcome::Error(error) = &span.outcome {
+        entry.push(("error".to_string(), string(error)));
+    }
+    entry.push((
+        "duration_us".to_string(),
+        JsonValue::Number(span.duration.as_micros() as i64),
+    ));
+    JsonValue::Object(entry).to_string()
+}
+
+/// Writes a [`log_line`] per closed span to `writer`, typically stdout.
+pub struct JsonLogSubscriber<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLogSubscriber<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> Subscriber for JsonLogSubscriber<W> {
+    fn on_close(&self, span: &SpanRecord) {
+        let line = log_line(span, current_correlation_id().as_ref());
+        // Losing a log line must not fail the request that produced it.
+        let _ = writeln!(self.writer.lock().unwrap(), "{}", line);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Command, CommandBus, CommandMetadata, ExecutionMode};
+    use crate::commands::CreateUser;
+    use crate::outbox::{InMemoryOutbox, Outbox};
+    use crate::repository::InMemoryUserRepository;
+    use crate::trace::with_subscriber;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_correlation_id_in_log_lines_and_events() {
+        let logger = Arc::new(JsonLogSubscriber::new(Vec::new()));
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryOutbox::new());
+        let metadata = CommandMetadata {
+            correlation_id: Some(CorrelationId("req-42".to_string())),
+            ..CommandMetadata::default()
+        };
+        let command = Command::CreateUser(CreateUser {
+            email: "foo@ok.com".to_string(),
+            age: 22,
+            name: "Luca".to_string(),
+            surname: "Rossi".to_string(),
+            middle_name: None,
+        });
+
+        with_subscriber(logger.clone(), || {
+            bus.dispatch_with_metadata(command, &metadata, ExecutionMode::Commit)
+        })
+        .unwrap();
+
+        let output = String::from_utf8(Arc::into_inner(logger).unwrap().into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines.len() > 1);
+        assert!(lines
+            .iter()
+            .all(|line| line.contains(r#""correlation_id":"req-42""#)));
+        assert!(lines
+            .last()
+            .unwrap()
+            .contains(r#""level":"info","span":"command""#));
+        let entry = &bus.publisher().pending(1).unwrap()[0];
+        assert_eq!(
+            entry.correlation_id,
+            Some(CorrelationId("req-42".to_string()))
+        );
+    }
+}
@@ -1,240 +1,320 @@
 use anyhow::{Error, Result};
-use regex::Regex;
-use std::fmt::Display;
-
-#[derive(Debug)]
-struct Email(String);
-#[derive(Debug)]
-struct VerifiedEmail(Email);
-#[derive(Debug)]
-struct UnverifiedEmail(Email);
-
-#[derive(Debug)]
-struct Age(i32);
-
-#[derive(Debug)]
-enum UserEmail {
-    VerifiedEmail(VerifiedEmail),
-    UnverifiedEmail(UnverifiedEmail),
+use rust_ddd_playground::api::user_routes;
+use rust_ddd_playground::bus::{CommandBus, ExecutionMode};
+use rust_ddd_playground::clock::SystemClock;
+use rust_ddd_playground::config::{Config, LogFormat};
+use rust_ddd_playground::event_store::InMemoryEventStore;
+use rust_ddd_playground::events::{DomainEvent, EventHandler, FanOutPublisher};
+use rust_ddd_playground::feed::{feed_routes, EventFeed};
+use rust_ddd_playground::full_names::FullNameReadModel;
+use rust_ddd_playground::health::{health_routes, Readiness};
+use rust_ddd_playground::http::{serve, Router};
+use rust_ddd_playground::json::JsonValue;
+use rust_ddd_playground::logging::JsonLogSubscriber;
+use rust_ddd_playground::metrics::PrometheusMetrics;
+use rust_ddd_playground::migrations::{check_schema, migrate, InMemorySchemaStore};
+use rust_ddd_playground::projections::{InMemoryCheckpointStore, ProjectionRunner};
+use rust_ddd_playground::repl::Repl;
+use rust_ddd_playground::replay::{parse_replay_args, replay_events, ReplayTarget};
+use rust_ddd_playground::repository::InMemoryUserRepository;
+use rust_ddd_playground::schema::{schema, schemas};
+use rust_ddd_playground::shutdown::{termination_flag, GracefulShutdown, WorkerPool};
+use rust_ddd_playground::stats::{stats_routes, UserStats};
+use rust_ddd_playground::stream::{event_routes, EventBroadcaster};
+use rust_ddd_playground::throttle::SlidingWindowLimiter;
+use rust_ddd_playground::trace::{set_global_subscriber, StderrSubscriber};
+use rust_ddd_playground::users_by_domain::UsersByDomain;
+use rust_ddd_playground::versioning::UpcasterChain;
+use rust_ddd_playground::{create_user_within, grant_user};
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Threads serving HTTP connections; open event streams each hold one.
+const HTTP_WORKERS: usize = 32;
+/// Connections waiting for a worker before new ones are answered 503.
+const HTTP_QUEUE: usize = 128;
+
+/// How often feed connections get a heartbeat, which they must answer
+/// within the feed's timeout.
+const FEED_HEARTBEAT: Duration = Duration::from_secs(30);
+
+/// How often the served projections poll the event store when caught up.
+const PROJECTION_POLL: Duration = Duration::from_millis(100);
+
+/// Prints every event it receives, one JSON document per line.
+struct PrintHandler;
+
+impl EventHandler for PrintHandler {
+    fn name(&self) -> &str {
+        "print"
+    }
+
+    fn handle(&self, event: &DomainEvent) -> Result<()> {
+        println!("{}", event.to_json());
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
-struct User {
-    name: String,
-    middle_name: Option<String>,
-    surname: String,
-    age: Age,
-    email: UserEmail,
+/// Read models the `replay` subcommand can rebuild. The replay runs in its
+/// own process, so each one is printed once it is done.
+#[derive(Default)]
+struct ReplayProjections {
+    users_by_domain: UsersByDomain,
+    full_names: FullNameReadModel,
+    stats: UserStats,
 }
 
-impl Display for Email {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+impl ReplayProjections {
+    fn targets(&self) -> [ReplayTarget<'_>; 4] {
+        [
+            ReplayTarget::Handler(&PrintHandler),
+            ReplayTarget::Projection(&self.users_by_domain),
+            ReplayTarget::Projection(&self.full_names),
+            ReplayTarget::Projection(&self.stats),
+        ]
     }
-}
 
-impl User {
-    fn new(
-        name: String,
-        middle_name: Option<String>,
-        surname: String,
-        age: Age,
-        email: Email,
-    ) -> Self {
-        Self {
-            name,
-            middle_name,
-            surname,
-            age,
-            email: UserEmail::UnverifiedEmail(UnverifiedEmail(email)),
-        }
+    /// What projection `name` holds, as one JSON document.
+    fn summary(&self, name: &str) -> Option<JsonValue> {
+        let fields = match name {
+            "users_by_domain" => vec![(
+                "domains".to_string(),
+                JsonValue::Array(
+                    self.users_by_domain
+                        .counts()
+                        .into_iter()
+                        .map(|count| {
+                            JsonValue::Object(vec![
+                                ("domain".to_string(), JsonValue::String(count.domain)),
+                                (
+                                    "verified".to_string(),
+                                    JsonValue::Number(count.verified as i64),
+                                ),
+                                (
+                                    "unverified".to_string(),
+                                    JsonValue::Number(count.unverified as i64),
+                                ),
+                            ])
+                        })
+                        .collect(),
+                ),
+            )],
+            "full_names" => vec![(
+                "entries".to_string(),
+                JsonValue::Number(self.full_names.search("").len() as i64),
+            )],
+            "user_stats" => match self.stats.report().to_json() {
+                JsonValue::Object(fields) => fields,
+                _ => Vec::new(),
+            },
+            _ => return None,
+        };
+        let mut summary = vec![(
+            "projection".to_string(),
+            JsonValue::String(name.to_string()),
+        )];
+        summary.extend(fields);
+        Some(JsonValue::Object(summary))
     }
 }
 
-fn verify_email(email: &UnverifiedEmail) -> Result<VerifiedEmail> {
-    let UnverifiedEmail(unverified_email) = email;
+fn replay(args: &[String]) -> Result<()> {
+    let args = parse_replay_args(args)?;
+    let store = InMemoryEventStore::new();
+    store.load(
+        BufReader::new(File::open(&args.event_log)?),
+        &UpcasterChain::new(),
+    )?;
+
+    let projections = ReplayProjections::default();
+    let available = projections.targets();
+    let mut targets = Vec::new();
+    for target in available {
+        if args.handlers.is_empty() || args.handlers.iter().any(|h| h == target.name()) {
+            targets.push(target);
+        }
+    }
+    if let Some(unknown) = args
+        .handlers
+        .iter()
+        .find(|name| !available.iter().any(|target| target.name() == *name))
+    {
+        return Err(Error::msg(format!("Unknown handler {}", unknown)));
+    }
 
-    let is_ok = unverified_email.0.contains("ok");
-    // verify email
-    if is_ok {
-        Ok(VerifiedEmail(Email(unverified_email.0.clone())))
+    let replayed = replay_events(&store, &args.filter, &targets, args.mode)?;
+    if args.mode == ExecutionMode::DryRun {
+        for stored in &replayed {
+            println!("{} {}", stored.id.0, stored.event.name());
+        }
     } else {
-        Err(Error::msg("Email has not been verified yet"))
+        for target in &targets {
+            if let Some(summary) = projections.summary(target.name()) {
+                println!("{}", summary);
+            }
+        }
     }
+    eprintln!("{} events replayed", replayed.len());
+    Ok(())
 }
 
-fn check_email(email: String) -> Result<Email> {
-    let re = Regex::new(r"^[\w.]+@[\w.]+\.\w+$").unwrap();
-    if re.is_match(&email) {
-        Ok(Email(email))
-    } else {
-        Err(Error::msg("Invalid email"))
+/// Schema of the configured database. Only `memory://` is supported, whose
+/// schema starts empty in every process.
+fn schema_store(config: &Config) -> Result<InMemorySchemaStore> {
+    match config.database.url.as_str() {
+        "memory://" => Ok(InMemorySchemaStore::new()),
+        url => Err(Error::msg(format!("Unsupported database url {}", url))),
     }
 }
 
-fn check_age(age: i32) -> Result<Age> {
-    match age {
-        x if x < 0 => Err(Error::msg("Age cannot be negative")),
-        x if x < 13 => Err(Error::msg(
-            "Sorry but this service is unavailable for minor of 13 years old",
-        )),
-        x if x > 120 => Err(Error::msg("I don't think you can be immortal")),
-        _ => Ok(Age(age)),
+/// Users of the configured database; only `memory://` is supported.
+fn user_repository(config: &Config) -> Result<InMemoryUserRepository> {
+    match config.database.url.as_str() {
+        "memory://" => Ok(InMemoryUserRepository::new()),
+        url => Err(Error::msg(format!("Unsupported database url {}", url))),
     }
 }
 
-fn create_user(
-    email: String,
-    age: i32,
-    name: String,
-    surname: String,
-    middle_name: Option<String>,
-) -> Result<User> {
-    let age = check_age(age)?;
-    let email = check_email(email)?;
-
-    let user = User::new(name, middle_name, surname, age, email);
-
-    Ok(user)
+/// Applies pending migrations and prints each one.
+fn run_migrations(config: &Config) -> Result<()> {
+    let store = schema_store(config)?;
+    for migration in migrate(&store)? {
+        println!("Applied {} {}", migration.version, migration.name);
+    }
+    Ok(())
 }
 
-fn grant_user(user: &mut User) -> Result<()> {
-    if let UserEmail::UnverifiedEmail(unverified_email) = &user.email {
-        let verified_email = verify_email(unverified_email)?;
-        user.email = UserEmail::VerifiedEmail(verified_email);
+/// Serves the operational endpoints until SIGTERM, then lets in-flight
+/// requests finish within the configured deadline.
+fn serve_endpoints(args: &[String], config: &Config) -> Result<()> {
+    let address = args.first().map_or("127.0.0.1:8080", String::as_str);
+    let schema = schema_store(config)?;
+    if config.database.migrate_on_startup {
+        migrate(&schema)?;
     }
-    Ok(())
+    check_schema(&schema)?;
+    let metrics = Arc::new(PrometheusMetrics::new());
+    let users = Arc::new(InMemoryUserRepository::new());
+    let readiness = Readiness::new().with_check(Arc::clone(&users));
+    let broadcaster = Arc::new(EventBroadcaster::new());
+    let feed = Arc::new(EventFeed::new(SystemClock));
+    let event_store = Arc::new(InMemoryEventStore::new());
+    let stats = Arc::new(UserStats::new());
+    let live_events = FanOutPublisher::new()
+        .with_publisher(broadcaster.clone())
+        .with_publisher(feed.clone())
+        .with_publisher(event_store.clone());
+    let bus = CommandBus::new(users, live_events)
+        .with_verification_limiter(SlidingWindowLimiter::new(
+            config.verification.max_attempts,
+            config.verification.attempt_window,
+            SystemClock,
+        ))
+        .with_signup_throttle(SlidingWindowLimiter::new(
+            config.rate_limits.signups_per_ip,
+            config.rate_limits.signup_window,
+            SystemClock,
+        ))
+        .with_age_limits(config.age.clone())
+        .with_metrics(metrics.clone());
+    let router = user_routes(
+        health_routes(Router::new(), Arc::new(readiness)),
+        Arc::new(bus),
+        SystemClock,
+    );
+    let router = event_routes(router, Arc::clone(&broadcaster));
+    let router = feed_routes(router, Arc::clone(&feed));
+    let stop = termination_flag();
+    std::thread::spawn(move || feed.run(FEED_HEARTBEAT, stop));
+    let projected = Arc::clone(&stats);
+    std::thread::spawn(move || {
+        let checkpoints = InMemoryCheckpointStore::new();
+        ProjectionRunner::new(&*event_store, &checkpoints)
+            .register(&*projected)
+            .run(PROJECTION_POLL, stop)
+    });
+    let router = stats_routes(router, stats).route("GET", "/metrics", move |_| metrics.response());
+    eprintln!("Listening on {}", address);
+    let connections = WorkerPool::new(HTTP_WORKERS, HTTP_QUEUE);
+    serve(
+        TcpListener::bind(address)?,
+        Arc::new(router),
+        stop,
+        &connections,
+    )?;
+    eprintln!("Shutting down");
+    GracefulShutdown::new(config.shutdown_deadline)
+        .then(&*broadcaster)
+        .then(&connections)
+        .run()
 }
 
-fn get_fullname(user: &User) -> String {
-    let middle_name = user.middle_name.as_ref().map(|middle| middle.to_owned());
-    vec![
-        Some(user.name.to_owned()),
-        middle_name,
-        Some(user.surname.to_owned()),
-    ]
-    .into_iter()
-    .flatten()
-    .collect::<Vec<_>>()
-    .join(" ")
+/// Prints the JSON Schema named in `args`, or the available names.
+fn print_schema(args: &[String]) -> Result<()> {
+    match args.first() {
+        Some(name) => {
+            let schema =
+                schema(name).ok_or_else(|| Error::msg(format!("Unknown schema {}", name)))?;
+            println!("{}", schema);
+        }
+        None => {
+            for (name, _) in schemas() {
+                println!("{}", name);
+            }
+        }
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
+    let config = Config::from_env()?;
+    match config.log_format {
+        LogFormat::Text => set_global_subscriber(Arc::new(StderrSubscriber)),
+        LogFormat::Json => set_global_subscriber(Arc::new(JsonLogSubscriber::new(stdout()))),
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("replay") {
+        return replay(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("schema") {
+        return print_schema(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("migrate") {
+        return run_migrations(&config);
+    }
+    if args.first().map(String::as_str) == Some("serve") {
+        return serve_endpoints(&args[1..], &config);
+    }
+    if args.first().map(String::as_str) == Some("repl") {
+        return Repl::new(user_repository(&config)?)
+            .with_age_limits(config.age.clone())
+            .run(stdin().lock(), stdout());
+    }
+
     let input_email = "foo@ok.com".to_string();
     let input_age = 22;
     let name = "Luca".to_string();
     let surname = "Rossi".to_string();
     let middle_name: Option<String> = None;
 
-    let mut user = create_user(input_email, input_age, name, surname, middle_name)?;
-
-    let fullname = get_fullname(&user);
+    let mut user = create_user_within(
+        input_email,
+        input_age,
+        name,
+        surname,
+        middle_name,
+        &config.age,
+    )?;
 
-    println!("Welcome {} of {} years old", fullname, user.age.0);
+    println!("Welcome {} of {} years old", user.full_name(), user.age());
 
     grant_user(&mut user)?;
-    if let UserEmail::VerifiedEmail(verified_email) = user.email {
-        println!("User email {} is verified!", verified_email.0);
+    if user.is_email_verified() {
+        println!("User email {} is verified!", user.email().address());
     }
 
     Ok(())
 }
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn ok_create_user() {
-        let input_email = "foo@ok.com".to_string();
-        let input_age = 22;
-        let name = "Luca".to_string();
-        let surname = "Rossi".to_string();
-        let middle_name: Option<String> = None;
-
-        let user = create_user(input_email, input_age, name, surname, middle_name);
-        assert!(user.is_ok());
-        let mut user = user.unwrap();
-        let result = grant_user(&mut user);
-        assert!(result.is_ok());
-
-        assert_eq!(user.name, "Luca".to_string());
-        assert_eq!(user.surname, "Rossi".to_string());
-        assert!(user.middle_name.is_none());
-        assert_eq!(user.age.0, 22);
-
-        let is_verified_email = match user.email {
-            UserEmail::VerifiedEmail(_) => true,
-            UserEmail::UnverifiedEmail(_) => false,
-        };
-        assert!(is_verified_email);
-    }
-
-    #[test]
-    fn ok_create_user_unverified() {
-        let input_email = "foo@unverified.com".to_string();
-        let input_age = 22;
-        let name = "Luca".to_string();
-        let surname = "Rossi".to_string();
-        let middle_name: Option<String> = None;
-
-        let user = create_user(input_email, input_age, name, surname, middle_name);
-        let mut user = user.unwrap();
-        let result = grant_user(&mut user);
-
-        assert!(result.is_err());
-        let error = result.unwrap_err();
-        assert_eq!(error.to_string(), "Email has not been verified yet");
-
-        let is_unverified_email = match user.email {
-            UserEmail::VerifiedEmail(_) => false,
-            UserEmail::UnverifiedEmail(_) => true,
-        };
-        assert!(is_unverified_email);
-    }
-
-    #[test]
-    fn err_invalid_email() {
-        let input_email = "foo.at.com".to_string();
-        let input_age = 22;
-        let name = "Luca".to_string();
-        let surname = "Rossi".to_string();
-        let middle_name: Option<String> = None;
-
-        let user = create_user(input_email, input_age, name, surname, middle_name);
-
-        assert!(user.is_err());
-        let error = user.unwrap_err();
-        assert_eq!(error.to_string(), "Invalid email");
-    }
-
-    #[test]
-    fn err_invalid_age_negative() {
-        let input_email = "fo@ok.com".to_string();
-        let input_age = -100;
-        let name = "Luca".to_string();
-        let surname = "Rossi".to_string();
-        let middle_name: Option<String> = None;
-
-        let user = create_user(input_email, input_age, name, surname, middle_name);
-
-        assert!(user.is_err());
-        let error = user.unwrap_err();
-        assert_eq!(error.to_string(), "Age cannot be negative");
-    }
-
-    #[test]
-    fn err_invalid_age_immortal() {
-        let input_email = "fo@ok.com".to_string();
-        let input_age = 130;
-        let name = "Luca".to_string();
-        let surname = "Rossi".to_string();
-        let middle_name: Option<String> = None;
-
-        let user = create_user(input_email, input_age, name, surname, middle_name);
-
-        assert!(user.is_err());
-        let error = user.unwrap_err();
-        assert_eq!(error.to_string(), "I don't think you can be immortal");
-    }
-}
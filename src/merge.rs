@@ -0,0 +1,179 @@
+use crate::error::DomainError;
+use crate::events::{MergedProfile, UsersMerged};
+use crate::repository::UserRepository;
+use crate::{User, UserEmail, UserId, VerifiedEmail};
+use anyhow::{Error, Result};
+
+fn load_active(repository: &impl UserRepository, id: UserId) -> Result<User> {
+    let user = repository
+        .find(id)?
+        .ok_or_else(|| Error::msg("User not found"))?;
+    if user.merged_into.is_some() {
+        return Err(Error::msg("User has already been merged"));
+    }
+    Ok(user)
+}
+
+fn merge_email(primary: &mut User, duplicate: &User) -> Result<()> {
+    match (&primary.email, &duplicate.email) {
+        (
+            UserEmail::VerifiedEmail(VerifiedEmail(primary_email)),
+            UserEmail::VerifiedEmail(VerifiedEmail(duplicate_email)),
+        ) if !primary_email.0.eq_ignore_ascii_case(&duplicate_email.0) => {
+            Err(Error::msg("Users have different verified emails"))
+        }
+        (UserEmail::UnverifiedEmail(_), UserEmail::VerifiedEmail(_)) => {
+            primary.email = duplicate.email.clone();
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Folds `duplicate_id` into `primary_id`. The duplicate is kept as a
+/// tombstone pointing at the primary so old references can be redirected.
+/// Both users must belong to the same tenant.
+pub fn merge_users(
+    repository: &impl UserRepository,
+    primary_id: UserId,
+    duplicate_id: UserId,
+) -> Result<UsersMerged> {
+    if primary_id == duplicate_id {
+        return Err(Error::msg("Cannot merge a user into itself"));
+    }
+    let mut primary = load_active(repository, primary_id)?;
+    let mut duplicate = load_active(repository, duplicate_id)?;
+    if primary.tenant_id != duplicate.tenant_id {
+        return Err(DomainError::CrossTenantMerge.into());
+    }
+
+    merge_email(&mut primary, &duplicate)?;
+    if primary.middle_name.is_none() {
+        primary.middle_name = duplicate.middle_name.clone();
+    }
+    if primary.pronouns.is_none() {
+        primary.pronouns = duplicate.pronouns.clone();
+    }
+    if primary.avatar.is_none() {
+        primary.avatar = duplicate.avatar.clone();
+    }
+    for (name, value) in &duplicate.custom_attributes.0 {
+        primary
+            .custom_attributes
+            .0
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+    primary.tags.extend(duplicate.tags.iter().cloned());
+
+    // One write, so neither user is saved without the other. The tombstone
+    // goes first, releasing the email the primary may take.
+    duplicate.merged_into = Some(primary_id);
+    let merged = MergedProfile::from_user(&primary);
+    repository.save_all(vec![duplicate, primary])?;
+
+    Ok(UsersMerged {
+        primary_id,
+        duplicate_id,
+        merged: Some(merged),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+    use crate::tags::{add_tag, check_tag};
+    use crate::test_support::UserFixture;
+    use crate::TenantId;
+
+    fn user(email: &str, verified: bool) -> User {
+        let fixture = if verified {
+            UserFixture::verified()
+        } else {
+            UserFixture::new()
+        };
+        fixture.with_email(email).build()
+    }
+
+    #[test]
+    fn ok_merge_users() {
+        let repository = InMemoryUserRepository::new();
+        let primary = user("luca@unverified.com", false);
+        let mut duplicate = user("luca@ok.com", true);
+        duplicate.middle_name = Some("Maria".to_string());
+        add_tag(&mut duplicate, check_tag("vip".to_string()).unwrap());
+        let (primary_id, duplicate_id) = (primary.id, duplicate.id);
+        repository.save(primary).unwrap();
+        repository.save(duplicate).unwrap();
+
+        let event = merge_users(&repository, primary_id, duplicate_id).unwrap();
+
+        let primary = repository.find(primary_id).unwrap().unwrap();
+        assert_eq!(
+            event,
+            UsersMerged {
+                primary_id,
+                duplicate_id,
+                merged: Some(MergedProfile::from_user(&primary)),
+            }
+        );
+        let merged = event.merged.unwrap();
+        assert_eq!(
+            (merged.email.as_str(), merged.email_verified),
+            ("luca@ok.com", true)
+        );
+        assert_eq!(merged.tags, vec!["vip".to_string()]);
+        assert!(matches!(primary.email, UserEmail::VerifiedEmail(_)));
+        assert_eq!(primary.middle_name, Some("Maria".to_string()));
+        assert!(primary
+            .tags
+            .contains(&check_tag("vip".to_string()).unwrap()));
+        let duplicate = repository.find(duplicate_id).unwrap().unwrap();
+        assert_eq!(duplicate.merged_into, Some(primary_id));
+
+        let result = merge_users(&repository, primary_id, duplicate_id);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "User has already been merged"
+        );
+    }
+
+    #[test]
+    fn err_merge_different_verified_emails() {
+        let repository = InMemoryUserRepository::new();
+        let primary = user("luca@ok.com", true);
+        let duplicate = user("rossi@ok.com", true);
+        let (primary_id, duplicate_id) = (primary.id, duplicate.id);
+        repository.save(primary).unwrap();
+        repository.save(duplicate).unwrap();
+
+        let result = merge_users(&repository, primary_id, duplicate_id);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Users have different verified emails");
+        let duplicate = repository.find(duplicate_id).unwrap().unwrap();
+        assert!(duplicate.merged_into.is_none());
+    }
+
+    #[test]
+    fn err_merge_users_of_different_tenants() {
+        let repository = InMemoryUserRepository::new();
+        let primary = user("luca@ok.com", true);
+        let mut duplicate = user("luca@ok.com", true);
+        duplicate.tenant_id = TenantId("acme".to_string());
+        let (primary_id, duplicate_id) = (primary.id, duplicate.id);
+        repository.save(primary).unwrap();
+        repository.save(duplicate).unwrap();
+
+        let result = merge_users(&repository, primary_id, duplicate_id);
+
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<DomainError>(),
+            Some(&DomainError::CrossTenantMerge)
+        );
+        let duplicate = repository.find(duplicate_id).unwrap().unwrap();
+        assert!(duplicate.merged_into.is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::http::HttpResponse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Port through which the application reports measurements.
+pub trait Metrics: Send + Sync {
+    fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]);
+    fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64);
+}
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Observations at or below each bound of [`BUCKETS`].
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+fn label_set(labels: &[(&'static str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn braces(labels: &str) -> String {
+    match labels {
+        "" => String::new(),
+        labels => format!("{{{}}}", labels),
+    }
+}
+
+/// In-process registry rendered in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    counters: Mutex<BTreeMap<(&'static str, String), u64>>,
+    histograms: Mutex<BTreeMap<(&'static str, String), Histogram>>,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+        let key = (name, label_set(labels));
+        self.counters
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut last_name = "";
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if *name != last_name {
+                let _ = writeln!(output, "# TYPE {} counter", name);
+                last_name = name;
+            }
+            let _ = writeln!(output, "{}{} {}", name, braces(labels), value);
+        }
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            if *name != last_name {
+                let _ = writeln!(output, "# TYPE {} histogram", name);
+                last_name = name;
+            }
+            let bucket = |bound: &str| match labels.as_str() {
+                "" => format!("le=\"{}\"", bound),
+                labels => format!("{},le=\"{}\"", labels, bound),
+            };
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{{{}}} {}",
+                    name,
+                    bucket(&bound.to_string()),
+                    count
+                );
+            }
+            let _ = writeln!(
+                output,
+                "{}_bucket{{{}}} {}",
+                name,
+                bucket("+Inf"),
+                histogram.count
+            );
+            let _ = writeln!(output, "{}_sum{} {}", name, braces(labels), histogram.sum);
+            let _ = writeln!(
+                output,
+                "{}_count{} {}",
+                name,
+                braces(labels),
+                histogram.count
+            );
+        }
+        output
+    }
+
+    /// Response for the `/metrics` endpoint.
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4".to_string(),
+            body: self.render(),
+        }
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, label_set(labels)))
+            .or_default() += 1;
+    }
+
+    fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry((name, label_set(labels))).or_default();
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_render_exposition_format() {
+        let metrics = PrometheusMetrics::new();
+        metrics.increment("users_created_total", &[]);
+        metrics.increment("validation_errors_total", &[("code", "invalid_email")]);
+        metrics.observe(
+            "command_duration_seconds",
+            &[("command", "CreateUser")],
+            0.02,
+        );
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("# TYPE users_created_total counter\nusers_created_total 1\n"));
+        assert!(rendered.contains("validation_errors_total{code=\"invalid_email\"} 1\n"));
+        assert!(rendered
+            .contains("command_duration_seconds_bucket{command=\"CreateUser\",le=\"0.01\"} 0\n"));
+        assert!(rendered
+            .contains("command_duration_seconds_bucket{command=\"CreateUser\",le=\"0.05\"} 1\n"));
+        assert!(rendered.contains("command_duration_seconds_count{command=\"CreateUser\"} 1\n"));
+    }
+}
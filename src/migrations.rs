@@ -0,0 +1,148 @@
+//! Versioned schema migrations, embedded in the binary from `migrations/`.
+
+use anyhow::{Error, Result};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration, in the order they are applied.
+pub const MIGRATIONS: [Migration; 5] = [
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../migrations/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_outbox",
+        sql: include_str!("../migrations/0002_create_outbox.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_tokens",
+        sql: include_str!("../migrations/0003_create_tokens.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_audit_log",
+        sql: include_str!("../migrations/0004_create_audit_log.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "unique_email_per_tenant",
+        sql: include_str!("../migrations/0005_unique_email_per_tenant.sql"),
+    },
+];
+
+/// Schema version this build expects.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Port to the database whose schema is migrated.
+pub trait SchemaStore {
+    /// Versions applied so far, e.g. from a `schema_migrations` table.
+    fn applied(&self) -> Result<Vec<u32>>;
+    /// Runs `migration` and records its version, in one transaction.
+    fn apply(&self, migration: &Migration) -> Result<()>;
+}
+
+/// Schema of a `memory://` database, which only tracks versions.
+#[derive(Debug, Default)]
+pub struct InMemorySchemaStore {
+    applied: Mutex<Vec<u32>>,
+}
+
+impl InMemorySchemaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SchemaStore for InMemorySchemaStore {
+    fn applied(&self) -> Result<Vec<u32>> {
+        Ok(self.applied.lock().unwrap().clone())
+    }
+
+    fn apply(&self, migration: &Migration) -> Result<()> {
+        self.applied.lock().unwrap().push(migration.version);
+        Ok(())
+    }
+}
+
+/// Applies the migrations `store` has not seen yet and returns them.
+pub fn migrate(store: &impl SchemaStore) -> Result<Vec<Migration>> {
+    let applied = store.applied()?;
+    let mut ran = Vec::new();
+    for migration in MIGRATIONS {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        store.apply(&migration).map_err(|error| {
+            Error::msg(format!(
+                "Migration {} ({}) failed: {}",
+                migration.version, migration.name, error
+            ))
+        })?;
+        ran.push(migration);
+    }
+    Ok(ran)
+}
+
+/// Fails unless `store` has exactly the migrations of this build, so an
+/// instance never serves traffic against a schema it does not know.
+pub fn check_schema(store: &impl SchemaStore) -> Result<()> {
+    let mut applied = store.applied()?;
+    applied.sort_unstable();
+    let expected: Vec<u32> = MIGRATIONS
+        .iter()
+        .map(|migration| migration.version)
+        .collect();
+    if applied != expected {
+        return Err(Error::msg(format!(
+            "Database schema is at version {}, expected {}",
+            applied.last().copied().unwrap_or(0),
+            latest_version()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_migrate_applies_pending_once() {
+        let store = InMemorySchemaStore::new();
+        store.apply(&MIGRATIONS[0]).unwrap();
+
+        let ran = migrate(&store).unwrap();
+        let again = migrate(&store).unwrap();
+
+        let versions: Vec<u32> = ran.iter().map(|migration| migration.version).collect();
+        assert_eq!(versions, vec![2, 3, 4, 5]);
+        assert!(again.is_empty());
+        assert!(check_schema(&store).is_ok());
+    }
+
+    #[test]
+    fn err_schema_behind_build() {
+        let store = InMemorySchemaStore::new();
+        store.apply(&MIGRATIONS[0]).unwrap();
+
+        let result = check_schema(&store);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Database schema is at version 1, expected 5"
+        );
+    }
+}
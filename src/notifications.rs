@@ -0,0 +1,97 @@
+use crate::pii::mask_email;
+use crate::pronouns::Pronouns;
+use crate::User;
+use anyhow::Result;
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+#[derive(Clone, PartialEq)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Debug for EmailMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailMessage")
+            .field("to", &mask_email(&self.to))
+            .field("subject", &self.subject)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Fills the placeholders of a message template for `user`: `{name}`,
+/// `{full_name}`, `{pronouns}` such as "she/her", and the `{subject}` and
+/// `{object}` forms of the pronouns. Users who gave no pronouns are
+/// addressed as they/them.
+pub fn render_template(template: &str, user: &User) -> String {
+    let pronouns = user.pronouns().unwrap_or(&Pronouns::TheyThem);
+    template
+        .replace("{name}", user.name())
+        .replace("{full_name}", &user.full_name().to_string())
+        .replace("{pronouns}", &pronouns.to_string())
+        .replace("{subject}", pronouns.subject())
+        .replace("{object}", pronouns.object())
+}
+
+/// Port to whatever actually delivers email (SMTP relay, provider API, ...).
+pub trait EmailSender {
+    fn send(&self, message: &EmailMessage) -> Result<()>;
+}
+
+/// Sender that keeps every message, for tests and demos.
+#[derive(Debug, Default)]
+pub struct InMemoryEmailSender {
+    sent: Mutex<Vec<EmailMessage>>,
+}
+
+impl InMemoryEmailSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<EmailMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl EmailSender for InMemoryEmailSender {
+    fn send(&self, message: &EmailMessage) -> Result<()> {
+        self.sent.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{assert_debug_masks, UserFixture};
+
+    #[test]
+    fn ok_render_template_with_pronouns() {
+        let template = "{name} ({pronouns}) joined; say hi to {object}, {subject} will reply.";
+        let mut user = UserFixture::new().build();
+
+        assert_eq!(
+            render_template(template, &user),
+            "Luca (they/them) joined; say hi to them, they will reply."
+        );
+        user.set_pronouns(Some("she/her".to_string())).unwrap();
+        assert_eq!(
+            render_template(template, &user),
+            "Luca (she/her) joined; say hi to her, she will reply."
+        );
+    }
+
+    #[test]
+    fn ok_email_message_debug_masks_pii() {
+        let message = EmailMessage {
+            to: "luca.rossi@ok.com".to_string(),
+            subject: "Welcome!".to_string(),
+            body: "Hi Luca, your account is ready.".to_string(),
+        };
+
+        assert_debug_masks(&message, &["luca.rossi", "Luca"]);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::clock::Clock;
+use crate::config::VerificationConfig;
+use crate::events::{DomainEvent, EventHandler};
+use crate::notifications::{EmailMessage, EmailSender};
+use crate::pii::mask_email;
+use crate::UserId;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnboardingStep {
+    /// Challenge sent; a reminder goes out at `remind_at` if still waiting.
+    AwaitingVerification {
+        remind_at: SystemTime,
+    },
+    /// Reminder sent; nothing more happens until the email is verified.
+    Reminded,
+    Completed,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct OnboardingState {
+    pub user_id: UserId,
+    pub email: String,
+    pub step: OnboardingStep,
+}
+
+impl Debug for OnboardingState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnboardingState")
+            .field("user_id", &self.user_id)
+            .field("email", &mask_email(&self.email))
+            .field("step", &self.step)
+            .finish()
+    }
+}
+
+/// Where the saga keeps its progress, so it survives restarts.
+pub trait OnboardingStore {
+    fn load(&self, user_id: UserId) -> Result<Option<OnboardingState>>;
+    fn save(&self, state: OnboardingState) -> Result<()>;
+    /// Sagas not yet completed.
+    fn pending(&self) -> Result<Vec<OnboardingState>>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryOnboardingStore {
+    states: RwLock<BTreeMap<UserId, OnboardingState>>,
+}
+
+impl InMemoryOnboardingStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OnboardingStore for InMemoryOnboardingStore {
+    fn load(&self, user_id: UserId) -> Result<Option<OnboardingState>> {
+        Ok(self.states.read().unwrap().get(&user_id).cloned())
+    }
+
+    fn save(&self, state: OnboardingState) -> Result<()> {
+        self.states.write().unwrap().insert(state.user_id, state);
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<OnboardingState>> {
+        Ok(self
+            .states
+            .read()
+            .unwrap()
+            .values()
+            .filter(|state| state.step != OnboardingStep::Completed)
+            .cloned()
+            .collect())
+    }
+}
+
+/// Process manager for a new user: send the verification challenge, remind
+/// once if the email is not verified in time, and send the welcome email
+/// when it is.
+pub struct OnboardingSaga<'a, S: OnboardingStore, E: EmailSender, C: Clock> {
+    store: &'a S,
+    sender: &'a E,
+    clock: C,
+    reminder_after: Duration,
+}
+
+impl<'a, S: OnboardingStore, E: EmailSender, C: Clock> OnboardingSaga<'a, S, E, C> {
+    /// Reminds users `config.reminder_after` after they registered.
+    pub fn new(store: &'a S, sender: &'a E, clock: C, config: &VerificationConfig) -> Self {
+        Self {
+            store,
+            sender,
+            clock,
+            reminder_after: config.reminder_after,
+        }
+    }
+
+    /// Timeout step, meant to be run periodically: reminds every user whose
+    /// reminder is due and returns how many were reminded.
+    pub fn send_due_reminders(&self) -> Result<usize> {
+        let now = self.clock.now();
+        let mut reminded = 0;
+        for mut state in self.store.pending()? {
+            let OnboardingStep::AwaitingVerification { remind_at } = state.step else {
+                continue;
+            };
+            if remind_at > now {
+                continue;
+            }
+            self.sender.send(&EmailMessage {
+                to: state.email.clone(),
+                subject: "Reminder: verify your email".to_string(),
+                body: "You have not verified your email yet.".to_string(),
+            })?;
+            state.step = OnboardingStep::Reminded;
+            self.store.save(state)?;
+            reminded += 1;
+        }
+        Ok(reminded)
+    }
+}
+
+impl<S: OnboardingStore, E: EmailSender, C: Clock> EventHandler for OnboardingSaga<'_, S, E, C> {
+    fn name(&self) -> &str {
+        "onboarding"
+    }
+
+    fn handle(&self, event: &DomainEvent) -> Result<()> {
+        match event {
+            DomainEvent::UserRegistered(event) => {
+                if self.store.load(event.user_id)?.is_some() {
+                    return Ok(());
+                }
+                self.sender.send(&EmailMessage {
+                    to: event.email.clone(),
+                    subject: "Verify your email".to_string(),
+                    body: format!("Hi {}, please verify your email.", event.name),
+                })?;
+                self.store.save(OnboardingState {
+                    user_id: event.user_id,
+                    email: event.email.clone(),
+                    step: OnboardingStep::AwaitingVerification {
+                        remind_at: self.clock.now() + self.reminder_after,
+                    },
+                })
+            }
+            DomainEvent::EmailVerified(event) => {
+                let Some(mut state) = self.store.load(event.user_id)? else {
+                    return Ok(());
+                };
+                if state.step == OnboardingStep::Completed {
+                    return Ok(());
+                }
+                self.sender.send(&EmailMessage {
+                    to: event.email.clone(),
+                    subject: "Welcome!".to_string(),
+                    body: "Your account is ready.".to_string(),
+                })?;
+                state.step = OnboardingStep::Completed;
+                self.store.save(state)
+            }
+            DomainEvent::NameChanged(_)
+            | DomainEvent::VerificationThrottled(_)
+            | DomainEvent::UsersMerged(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::events::{EmailVerified, UserRegistered};
+    use crate::notifications::InMemoryEmailSender;
+    use crate::test_support::assert_debug_masks;
+
+    #[test]
+    fn ok_onboarding_survives_restart() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let store = InMemoryOnboardingStore::new();
+        let sender = InMemoryEmailSender::new();
+        let saga = OnboardingSaga::new(&store, &sender, &clock, &VerificationConfig::default());
+        saga.handle(&DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+            name: "Luca".to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+            age: 22,
+        }))
+        .unwrap();
+        assert_eq!(saga.send_due_reminders().unwrap(), 0);
+
+        // A new process picks the saga up from the store.
+        let saga = OnboardingSaga::new(&store, &sender, &clock, &VerificationConfig::default());
+        clock.advance(Duration::from_secs(25 * 60 * 60));
+        assert_eq!(saga.send_due_reminders().unwrap(), 1);
+        assert_eq!(saga.send_due_reminders().unwrap(), 0);
+        saga.handle(&DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+        }))
+        .unwrap();
+
+        let subjects: Vec<String> = sender.sent().into_iter().map(|m| m.subject).collect();
+        assert_eq!(
+            subjects,
+            vec![
+                "Verify your email",
+                "Reminder: verify your email",
+                "Welcome!"
+            ]
+        );
+        assert_eq!(
+            store.load(UserId(1)).unwrap().unwrap().step,
+            OnboardingStep::Completed
+        );
+        assert!(store.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ok_onboarding_state_debug_masks_pii() {
+        let state = OnboardingState {
+            user_id: UserId(7),
+            email: "luca.rossi@ok.com".to_string(),
+            step: OnboardingStep::Completed,
+        };
+
+        assert_debug_masks(&state, &["luca.rossi"]);
+    }
+}
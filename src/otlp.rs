@@ -0,0 +1,215 @@
+//! Exports closed spans to an OpenTelemetry collector using OTLP/HTTP with the
+//! JSON encoding.
+
+use crate::hash::to_hex;
+use crate::json::JsonValue;
+use crate::trace::{Outcome, SpanRecord, Subscriber};
+use anyhow::Result;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Port to an HTTP client posting to the collector's `/v1/traces` endpoint.
+pub trait OtlpTransport: Send + Sync {
+    fn export(&self, body: &str) -> Result<()>;
+}
+
+/// Buffers closed spans until [`OtlpExporter::flush`] sends them as a batch.
+pub struct OtlpExporter<T: OtlpTransport> {
+    transport: T,
+    service_name: String,
+    buffer: Mutex<Vec<SpanRecord>>,
+}
+
+fn string(value: &str) -> JsonValue {
+    JsonValue::String(value.to_string())
+}
+
+fn object(fields: Vec<(&str, JsonValue)>) -> JsonValue {
+    JsonValue::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn attribute(key: &str, value: &str) -> JsonValue {
+    object(vec![
+        ("key", string(key)),
+        ("value", object(vec![("stringValue", string(value))])),
+    ])
+}
+
+/// Nanoseconds since the epoch, as a string since they overflow JSON numbers.
+fn unix_nanos(time: SystemTime) -> JsonValue {
+    let nanos = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    JsonValue::String(nanos.to_string())
+}
+
+fn span_json(span: &SpanRecord) -> JsonValue {
+    let mut fields = vec![
+        ("traceId", string(&to_hex(&span.trace_id))),
+        ("spanId", string(&to_hex(&span.id.to_be_bytes()))),
+    ];
+    if let Some(parent) = span.parent {
+        fields.push(("parentSpanId", string(&to_hex(&parent.to_be_bytes()))));
+    }
+    let status = match &span.outcome {
+        Outcome::Ok => object(vec![("code", JsonValue::Number(1))]),
+        Outcome::Error(message) => object(vec![
+            ("code", JsonValue::Number(2)),
+            ("message", string(message)),
+        ]),
+    };
+    fields.extend([
+        ("name", string(span.name)),
+        ("startTimeUnixNano", unix_nanos(span.started_at)),
+        (
+            "endTimeUnixNano",
+            unix_nanos(span.started_at + span.duration),
+        ),
+        (
+            "attributes",
+            JsonValue::Array(
+                span.masked_fields()
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect(),
+            ),
+        ),
+        ("status", status),
+    ]);
+    object(fields)
+}
+
+impl<T: OtlpTransport> OtlpExporter<T> {
+    pub fn new(transport: T, service_name: &str) -> Self {
+        Self {
+            transport,
+            service_name: service_name.to_string(),
+            buffer: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sends the buffered spans and returns how many went out. On failure the
+    /// spans stay buffered for the next flush.
+    pub fn flush(&self) -> Result<usize> {
+        let spans = std::mem::take(&mut *self.buffer.lock().unwrap());
+        if spans.is_empty() {
+            return Ok(0);
+        }
+        let body = object(vec![(
+            "resourceSpans",
+            JsonValue::Array(vec![object(vec![
+                (
+                    "resource",
+                    object(vec![(
+                        "attributes",
+                        JsonValue::Array(vec![attribute("service.name", &self.service_name)]),
+                    )]),
+                ),
+                (
+                    "scopeSpans",
+                    JsonValue::Array(vec![object(vec![
+                        (
+                            "scope",
+                            object(vec![("name", string(env!("CARGO_PKG_NAME")))]),
+                        ),
+                        (
+                            "spans",
+                            JsonValue::Array(spans.iter().map(span_json).collect()),
+                        ),
+                    ])]),
+                ),
+            ])]),
+        )]);
+        if let Err(error) = self.transport.export(&body.to_string()) {
+            let mut buffer = self.buffer.lock().unwrap();
+            let newer = std::mem::replace(&mut *buffer, spans);
+            buffer.extend(newer);
+            return Err(error);
+        }
+        Ok(spans.len())
+    }
+}
+
+impl<T: OtlpTransport> Subscriber for OtlpExporter<T> {
+    fn on_close(&self, span: &SpanRecord) {
+        self.buffer.lock().unwrap().push(span.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Command, CommandBus, CommandMetadata, ExecutionMode};
+    use crate::commands::CreateUser;
+    use crate::events::InMemoryEventPublisher;
+    use crate::outbox::{InMemoryOutbox, OutboxRelay};
+    use crate::repository::InMemoryUserRepository;
+    use crate::trace::{with_subscriber, RecordingSubscriber, TraceContext};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct FakeCollector {
+        bodies: Mutex<Vec<String>>,
+    }
+
+    impl OtlpTransport for Arc<FakeCollector> {
+        fn export(&self, body: &str) -> Result<()> {
+            self.bodies.lock().unwrap().push(body.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ok_registration_traced_through_outbox_relay() {
+        let collector = Arc::new(FakeCollector::default());
+        let exporter = Arc::new(OtlpExporter::new(collector.clone(), "users"));
+        let recorder = Arc::new(RecordingSubscriber::new());
+        let incoming = TraceContext::parse_traceparent(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )
+        .unwrap();
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryOutbox::new());
+        let broker = InMemoryEventPublisher::new();
+
+        with_subscriber(recorder.clone(), || {
+            let metadata = CommandMetadata {
+                trace_context: Some(incoming),
+                ..CommandMetadata::default()
+            };
+            let command = Command::CreateUser(CreateUser {
+                email: "foo@ok.com".to_string(),
+                age: 22,
+                name: "Luca".to_string(),
+                surname: "Rossi".to_string(),
+                middle_name: None,
+            });
+            bus.dispatch_with_metadata(command, &metadata, ExecutionMode::Commit)
+                .unwrap();
+        });
+        with_subscriber(exporter.clone(), || {
+            OutboxRelay::new(bus.publisher(), &broker).relay().unwrap();
+        });
+
+        let command = &recorder.named("command")[0];
+        let save = &recorder.named("repository.save_all")[0];
+        assert_eq!(command.trace_id, incoming.trace_id);
+        assert_eq!(command.parent, Some(incoming.span_id));
+        assert_eq!(save.parent, Some(command.id));
+        assert_eq!(exporter.flush().unwrap(), 1);
+        let body = &collector.bodies.lock().unwrap()[0];
+        assert!(body.contains(r#""traceId":"4bf92f3577b34da6a3ce929d0e0e4736""#));
+        assert!(body.contains(r#""name":"outbox.relay""#));
+        assert!(body.contains(&format!(
+            r#""parentSpanId":"{}""#,
+            to_hex(&save.id.to_be_bytes())
+        )));
+        assert!(body.contains(r#""stringValue":"users""#));
+        assert_eq!(exporter.flush().unwrap(), 0);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::events::{DomainEvent, EventId, EventPublisher};
+use crate::logging::{current_correlation_id, with_correlation_id, CorrelationId};
+use crate::shutdown::Drain;
+use crate::trace::{current_context, instrument, with_remote_parent, TraceContext};
+use anyhow::{Error, Result};
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: EventId,
+    pub event: DomainEvent,
+    pub dispatched: bool,
+    /// Trace the event was recorded in, continued when it is relayed.
+    pub traceparent: Option<String>,
+    /// Request that emitted the event.
+    pub correlation_id: Option<CorrelationId>,
+}
+
+/// Durable list of events waiting to leave the process. Appending is part of
+/// the same commit as the aggregate changes, so no event can be lost between
+/// saving and publishing.
+pub trait Outbox {
+    fn append(&self, event: DomainEvent) -> Result<EventId>;
+    /// Undispatched entries, oldest first.
+    fn pending(&self, limit: usize) -> Result<Vec<OutboxEntry>>;
+    fn mark_dispatched(&self, id: EventId) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryOutbox {
+    entries: Mutex<Vec<OutboxEntry>>,
+}
+
+impl InMemoryOutbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Outbox for InMemoryOutbox {
+    fn append(&self, event: DomainEvent) -> Result<EventId> {
+        let mut entries = self.entries.lock().unwrap();
+        let id = EventId(entries.len() as u64 + 1);
+        entries.push(OutboxEntry {
+            id,
+            event,
+            dispatched: false,
+            traceparent: current_context().map(|context| context.traceparent()),
+            correlation_id: current_correlation_id(),
+        });
+        Ok(id)
+    }
+
+    fn pending(&self, limit: usize) -> Result<Vec<OutboxEntry>> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| !entry.dispatched)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn mark_dispatched(&self, id: EventId) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.id == id) {
+            entry.dispatched = true;
+        }
+        Ok(())
+    }
+}
+
+/// Lets a unit of work write its events to the outbox as its "publisher".
+impl EventPublisher for InMemoryOutbox {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        self.append(event.clone())?;
+        Ok(())
+    }
+}
+
+/// Moves outbox entries to the real publisher (event bus, broker, ...).
+pub struct OutboxRelay<'a, O: Outbox, P: EventPublisher> {
+    outbox: &'a O,
+    publisher: &'a P,
+    batch_size: usize,
+}
+
+impl<'a, O: Outbox, P: EventPublisher> OutboxRelay<'a, O, P> {
+    pub fn new(outbox: &'a O, publisher: &'a P) -> Self {
+        Self {
+            outbox,
+            publisher,
+            batch_size: 100,
+        }
+    }
+
+    /// Publishes pending entries in order and returns how many went out. Stops
+    /// at the first failure, leaving it and everything after it pending for
+    /// the next run, so events are delivered at least once and in order.
+    pub fn relay(&self) -> Result<usize> {
+        let mut dispatched = 0;
+        loop {
+            match self.relay_batch()? {
+                0 => return Ok(dispatched),
+                count => dispatched += count,
+            }
+        }
+    }
+
+    fn relay_batch(&self) -> Result<usize> {
+        let pending = self.outbox.pending(self.batch_size)?;
+        for entry in &pending {
+            self.publish(entry)?;
+            self.outbox.mark_dispatched(entry.id)?;
+        }
+        Ok(pending.len())
+    }
+
+    fn publish(&self, entry: &OutboxEntry) -> Result<()> {
+        let publish = || {
+            instrument(
+                "outbox.relay",
+                vec![("event", entry.event.name().to_string())],
+                |_| self.publisher.publish_with_id(entry.id, &entry.event),
+            )
+        };
+        // An unreadable context only costs the link to the original trace.
+        let parent = entry
+            .traceparent
+            .as_deref()
+            .and_then(|header| TraceContext::parse_traceparent(header).ok());
+        let publish = || match parent {
+            Some(parent) => with_remote_parent(parent, publish),
+            None => publish(),
+        };
+        match &entry.correlation_id {
+            Some(correlation_id) => with_correlation_id(correlation_id.clone(), publish),
+            None => publish(),
+        }
+    }
+}
+
+/// Relays what is left in the outbox, batch by batch until the deadline.
+impl<O: Outbox, P: EventPublisher> Drain for OutboxRelay<'_, O, P> {
+    fn name(&self) -> &str {
+        "outbox"
+    }
+
+    fn drain(&self, deadline: Instant) -> Result<()> {
+        while Instant::now() < deadline {
+            if self.relay_batch()? == 0 {
+                return Ok(());
+            }
+        }
+        let pending = self.outbox.pending(usize::MAX)?.len();
+        Err(Error::msg(format!("{} events still pending", pending)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Command, CommandBus, ExecutionMode};
+    use crate::commands::CreateUser;
+    use crate::events::InMemoryEventPublisher;
+    use crate::repository::InMemoryUserRepository;
+    use anyhow::Error;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Default)]
+    struct UnreliablePublisher {
+        down: AtomicBool,
+        inner: InMemoryEventPublisher,
+    }
+
+    impl EventPublisher for UnreliablePublisher {
+        fn publish(&self, event: &DomainEvent) -> Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(Error::msg("Broker unavailable"));
+            }
+            self.inner.publish(event)
+        }
+    }
+
+    fn create_user_command(email: &str) -> Command {
+        Command::CreateUser(CreateUser {
+            email: email.to_string(),
+            age: 22,
+            name: "Luca".to_string(),
+            surname: "Rossi".to_string(),
+            middle_name: None,
+        })
+    }
+
+    #[test]
+    fn ok_relay_after_broker_recovers() {
+        let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryOutbox::new());
+        for email in ["a@ok.com", "b@ok.com"] {
+            bus.dispatch(create_user_command(email), ExecutionMode::Commit)
+                .unwrap();
+        }
+        let broker = UnreliablePublisher::default();
+        let relay = OutboxRelay::new(bus.publisher(), &broker);
+
+        broker.down.store(true, Ordering::SeqCst);
+        assert!(relay.relay().is_err());
+        assert_eq!(bus.publisher().pending(10).unwrap().len(), 2);
+
+        broker.down.store(false, Ordering::SeqCst);
+        assert_eq!(relay.relay().unwrap(), 2);
+        assert_eq!(relay.relay().unwrap(), 0);
+        assert!(bus.publisher().pending(10).unwrap().is_empty());
+        assert_eq!(broker.inner.published().len(), 2);
+    }
+}
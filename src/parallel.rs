@@ -0,0 +1,51 @@
+//! Data parallelism over scoped threads.
+
+use std::thread;
+
+/// Applies `f` to every item on up to `parallelism` threads, each taking a
+/// contiguous chunk, and returns the results in the order of `items`.
+pub fn map_ordered<T: Send, U: Send>(
+    items: Vec<T>,
+    parallelism: usize,
+    f: impl Fn(T) -> U + Sync,
+) -> Vec<U> {
+    if parallelism <= 1 || items.len() <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let chunk_size = items.len().div_ceil(parallelism);
+    let mut items = items.into_iter();
+    let chunks: Vec<Vec<T>> = std::iter::from_fn(|| {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    })
+    .collect();
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || chunk.into_iter().map(f).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// Threads worth using on this machine, 1 if unknown.
+pub fn available_parallelism() -> usize {
+    thread::available_parallelism().map_or(1, usize::from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_results_in_input_order() {
+        let squares = map_ordered((0..103).collect(), 4, |n: u64| n * n);
+
+        assert_eq!(squares, (0..103).map(|n| n * n).collect::<Vec<_>>());
+    }
+}
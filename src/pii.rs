@@ -0,0 +1,29 @@
+/// Keeps the first character of the local part and the whole domain,
+/// e.g. `foo@ok.com` becomes `f***@ok.com`.
+pub fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => format!("{}@{}", mask_name(local), domain),
+        None => mask_name(email),
+    }
+}
+
+/// Keeps only the first character, e.g. `Luca` becomes `L***`.
+pub fn mask_name(name: &str) -> String {
+    match name.chars().next() {
+        Some(first) => format!("{}***", first),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_mask_pii() {
+        assert_eq!(mask_email("foo@ok.com"), "f***@ok.com");
+        assert_eq!(mask_email("not-an-email"), "n***");
+        assert_eq!(mask_name("Luca"), "L***");
+        assert_eq!(mask_name(""), "");
+    }
+}
@@ -0,0 +1,231 @@
+//! Bounded pool of database connections, sized and timed by
+//! [`DatabaseConfig`].
+
+use crate::config::DatabaseConfig;
+use crate::health::HealthCheck;
+use crate::trace::instrument;
+use anyhow::{Error, Result};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Port to the database driver.
+pub trait Connector: Send + Sync {
+    type Connection: Send;
+    /// Opens a connection on which every statement is cancelled after
+    /// `statement_timeout`, e.g. with `SET statement_timeout`.
+    fn connect(&self, statement_timeout: Duration) -> Result<Self::Connection>;
+}
+
+struct PoolState<T> {
+    idle: Vec<T>,
+    /// Connections handed out or being opened.
+    in_use: u32,
+}
+
+/// Opens connections on demand up to `pool_size`; beyond that `acquire`
+/// waits up to `acquire_timeout` for one to be returned.
+pub struct ConnectionPool<C: Connector> {
+    connector: C,
+    config: DatabaseConfig,
+    state: Mutex<PoolState<C::Connection>>,
+    returned: Condvar,
+}
+
+impl<C: Connector> ConnectionPool<C> {
+    pub fn new(connector: C, config: DatabaseConfig) -> Self {
+        Self {
+            connector,
+            config,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                in_use: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Result<PooledConnection<'_, C>> {
+        let deadline = Instant::now() + self.config.acquire_timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(connection) = state.idle.pop() {
+                state.in_use += 1;
+                return Ok(PooledConnection {
+                    pool: self,
+                    connection: Some(connection),
+                });
+            }
+            if state.in_use < self.config.pool_size {
+                state.in_use += 1;
+                drop(state);
+                return match self.connector.connect(self.config.statement_timeout) {
+                    Ok(connection) => Ok(PooledConnection {
+                        pool: self,
+                        connection: Some(connection),
+                    }),
+                    Err(error) => {
+                        self.state.lock().unwrap().in_use -= 1;
+                        self.returned.notify_one();
+                        Err(error)
+                    }
+                };
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::msg("Timed out waiting for a database connection"));
+            }
+            state = self.returned.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Connections currently handed out.
+    pub fn in_use(&self) -> u32 {
+        self.state.lock().unwrap().in_use
+    }
+
+    fn release(&self, connection: C::Connection) {
+        let mut state = self.state.lock().unwrap();
+        state.in_use -= 1;
+        state.idle.push(connection);
+        self.returned.notify_one();
+    }
+}
+
+/// Fails while every connection is in use, so a saturated instance is taken
+/// out of rotation instead of queueing requests until they time out.
+impl<C: Connector> HealthCheck for ConnectionPool<C> {
+    fn name(&self) -> &str {
+        "database_pool"
+    }
+
+    fn check(&self) -> Result<()> {
+        let in_use = self.in_use();
+        if in_use >= self.config.pool_size {
+            return Err(Error::msg(format!(
+                "Connection pool saturated ({} of {} in use)",
+                in_use, self.config.pool_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Connection borrowed from the pool, returned to it when dropped.
+pub struct PooledConnection<'a, C: Connector> {
+    pool: &'a ConnectionPool<C>,
+    connection: Option<C::Connection>,
+}
+
+impl<C: Connector> PooledConnection<'_, C> {
+    /// Runs `statement` through `f`, logging it in a `database.slow_query`
+    /// span when it takes at least the configured threshold.
+    pub fn run<T>(
+        &mut self,
+        statement: &str,
+        f: impl FnOnce(&mut C::Connection) -> Result<T>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let result = f(self);
+        let elapsed = started.elapsed();
+        if elapsed >= self.pool.config.slow_query_threshold {
+            let fields = vec![
+                ("statement", statement.to_string()),
+                ("duration_ms", elapsed.as_millis().to_string()),
+            ];
+            let _ = instrument("database.slow_query", fields, |_| Ok(()));
+        }
+        result
+    }
+}
+
+impl<C: Connector> Deref for PooledConnection<'_, C> {
+    type Target = C::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl<C: Connector> DerefMut for PooledConnection<'_, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl<C: Connector> Drop for PooledConnection<'_, C> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.release(connection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trace::{with_subscriber, RecordingSubscriber};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    /// Connections are numbered in the order they were opened.
+    #[derive(Default)]
+    struct CountingConnector(AtomicU32);
+
+    impl Connector for &CountingConnector {
+        type Connection = u32;
+
+        fn connect(&self, _statement_timeout: Duration) -> Result<u32> {
+            Ok(self.0.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+    }
+
+    fn config() -> DatabaseConfig {
+        DatabaseConfig {
+            pool_size: 2,
+            acquire_timeout: Duration::from_millis(20),
+            slow_query_threshold: Duration::ZERO,
+            ..DatabaseConfig::default()
+        }
+    }
+
+    #[test]
+    fn ok_connection_reused_and_slow_query_logged() {
+        let connector = CountingConnector::default();
+        let pool = ConnectionPool::new(&connector, config());
+        let subscriber = Arc::new(RecordingSubscriber::new());
+
+        let first = *pool.acquire().unwrap();
+        let second = with_subscriber(subscriber.clone(), || {
+            pool.acquire()
+                .unwrap()
+                .run("SELECT 1", |connection| Ok(*connection))
+        })
+        .unwrap();
+
+        assert_eq!((first, second), (1, 1));
+        let spans = subscriber.named("database.slow_query");
+        assert_eq!(spans[0].field("statement"), Some("SELECT 1"));
+    }
+
+    #[test]
+    fn err_acquire_times_out_when_saturated() {
+        let connector = CountingConnector::default();
+        let pool = ConnectionPool::new(&connector, config());
+        let _first = pool.acquire().unwrap();
+        let _second = pool.acquire().unwrap();
+
+        let result = pool.acquire();
+
+        assert!(result.is_err());
+        let error = result.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Timed out waiting for a database connection"
+        );
+        assert_eq!(
+            pool.check().unwrap_err().to_string(),
+            "Connection pool saturated (2 of 2 in use)"
+        );
+    }
+}
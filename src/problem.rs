@@ -0,0 +1,185 @@
+//! RFC 7807 problem details for failed requests.
+
+use crate::api::InvalidRequest;
+use crate::circuit_breaker::ServiceUnavailable;
+use crate::error::DomainError;
+use crate::http::HttpResponse;
+use crate::idempotency::IdempotencyError;
+use crate::identity_provider::IdentityConflict;
+use crate::json::JsonValue;
+use crate::status::error_status;
+use crate::throttle::SignupThrottled;
+use anyhow::Error;
+
+/// One invalid input field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProblemDetails {
+    /// URI reference identifying the kind of problem.
+    pub problem_type: String,
+    /// Summary of the kind of problem, the same for every occurrence.
+    pub title: String,
+    pub status: u16,
+    /// Explanation of this occurrence.
+    pub detail: Option<String>,
+    /// [`DomainError::code`] or another stable code clients can branch on.
+    pub code: Option<String>,
+    pub violations: Vec<Violation>,
+}
+
+fn domain_title(error: &DomainError) -> &'static str {
+    match error {
+        DomainError::InvalidEmail => "Invalid email",
+        DomainError::NegativeAge => "Negative age",
+        DomainError::Underage { .. } => "Below minimum age",
+        DomainError::AgeTooHigh => "Age too high",
+        DomainError::InvalidPronouns => "Invalid pronouns",
+        DomainError::InvalidTag => "Invalid tag",
+        DomainError::EmailNotVerified => "Email not verified",
+        DomainError::BelowGrantingAge { .. } => "Below granting age",
+        DomainError::EmailAlreadyRegistered => "Email already registered",
+        DomainError::ConcurrencyConflict { .. } => "Concurrent modification",
+        DomainError::BatchAborted => "Batch aborted",
+        DomainError::CrossTenantMerge => "Cross-tenant merge",
+        DomainError::UnsupportedAvatarType => "Unsupported avatar type",
+        DomainError::AvatarTooLarge => "Avatar too large",
+        DomainError::AvatarNotFound => "Avatar not found",
+        DomainError::UnknownAttribute { .. } => "Unknown attribute",
+        DomainError::InvalidAttribute { .. } => "Invalid attribute",
+        DomainError::MissingAttribute { .. } => "Missing attribute",
+    }
+}
+
+impl ProblemDetails {
+    fn typed(code: &str, title: &str, error: &Error) -> Self {
+        Self {
+            problem_type: format!("/problems/{}", code.replace('_', "-")),
+            title: title.to_string(),
+            status: error_status(error).http,
+            detail: Some(error.to_string()),
+            code: Some(code.to_string()),
+            violations: Vec::new(),
+        }
+    }
+
+    /// Describes `error` for the client. Errors the domain does not type are
+    /// reported as a bare 500, since their message may reveal internals.
+    pub fn from_error(error: &Error) -> Self {
+        if let Some(domain_error) = error.downcast_ref::<DomainError>() {
+            let mut problem = Self::typed(domain_error.code(), domain_title(domain_error), error);
+            if let Some(field) = domain_error.field() {
+                problem.violations.push(Violation {
+                    field: field.to_string(),
+                    code: domain_error.code().to_string(),
+                    message: domain_error.to_string(),
+                });
+            }
+            return problem;
+        }
+        if let Some(invalid) = error.downcast_ref::<InvalidRequest>() {
+            let mut problem = Self::typed("invalid_request", "Invalid request body", error);
+            problem.violations.push(Violation {
+                field: invalid.field.clone(),
+                code: "invalid_request".to_string(),
+                message: invalid.to_string(),
+            });
+            return problem;
+        }
+        if error.downcast_ref::<IdentityConflict>().is_some() {
+            return Self::typed("identity_conflict", "Identity conflict", error);
+        }
+        if let Some(idempotency_error) = error.downcast_ref::<IdempotencyError>() {
+            return Self::typed(idempotency_error.code(), "Idempotency key conflict", error);
+        }
+        if error.downcast_ref::<SignupThrottled>().is_some() {
+            return Self::typed("signup_throttled", "Too many signups", error);
+        }
+        if error.downcast_ref::<ServiceUnavailable>().is_some() {
+            return Self::typed("service_unavailable", "Service unavailable", error);
+        }
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: "Internal Server Error".to_string(),
+            status: 500,
+            detail: None,
+            code: None,
+            violations: Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let string = |value: &str| JsonValue::String(value.to_string());
+        let mut fields = vec![
+            ("type".to_string(), string(&self.problem_type)),
+            ("title".to_string(), string(&self.title)),
+            ("status".to_string(), JsonValue::Number(self.status.into())),
+        ];
+        if let Some(detail) = &self.detail {
+            fields.push(("detail".to_string(), string(detail)));
+        }
+        if let Some(code) = &self.code {
+            fields.push(("code".to_string(), string(code)));
+        }
+        if !self.violations.is_empty() {
+            let violations = self
+                .violations
+                .iter()
+                .map(|violation| {
+                    JsonValue::Object(vec![
+                        ("field".to_string(), string(&violation.field)),
+                        ("code".to_string(), string(&violation.code)),
+                        ("message".to_string(), string(&violation.message)),
+                    ])
+                })
+                .collect();
+            fields.push(("violations".to_string(), JsonValue::Array(violations)));
+        }
+        JsonValue::Object(fields)
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse {
+            status: self.status,
+            content_type: "application/problem+json".to_string(),
+            body: self.to_json().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_domain_error_as_problem_json() {
+        let error = Error::from(DomainError::InvalidEmail);
+
+        let response = ProblemDetails::from_error(&error).response();
+
+        assert_eq!(response.status, 422);
+        assert_eq!(response.content_type, "application/problem+json");
+        assert_eq!(
+            response.body,
+            r#"{"type":"/problems/invalid-email","title":"Invalid email","status":422,"detail":"Invalid email","code":"invalid_email","violations":[{"field":"email","code":"invalid_email","message":"Invalid email"}]}"#
+        );
+    }
+
+    #[test]
+    fn err_untyped_error_does_not_leak_detail() {
+        let error = Error::msg("Connection to db-primary:5432 refused");
+
+        let problem = ProblemDetails::from_error(&error);
+
+        assert_eq!(problem.status, 500);
+        assert_eq!(
+            problem.to_json().to_string(),
+            r#"{"type":"about:blank","title":"Internal Server Error","status":500}"#
+        );
+    }
+}
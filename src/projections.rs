@@ -0,0 +1,271 @@
+use crate::event_store::{EventStore, StoredEvent};
+use crate::events::EventId;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Read model maintained from the event store. `apply` must tolerate seeing
+/// an event again after a crash between applying it and checkpointing.
+pub trait Projection {
+    fn name(&self) -> &str;
+    fn apply(&self, stored: &StoredEvent) -> Result<()>;
+    /// Empties the read model before a rebuild.
+    fn reset(&self) -> Result<()>;
+}
+
+/// Remembers, per projection, the last event it has applied.
+pub trait CheckpointStore {
+    fn load(&self, projection: &str) -> Result<Option<EventId>>;
+    fn save(&self, projection: &str, checkpoint: EventId) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryCheckpointStore {
+    checkpoints: Mutex<HashMap<String, EventId>>,
+}
+
+impl InMemoryCheckpointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CheckpointStore for InMemoryCheckpointStore {
+    fn load(&self, projection: &str) -> Result<Option<EventId>> {
+        Ok(self.checkpoints.lock().unwrap().get(projection).copied())
+    }
+
+    fn save(&self, projection: &str, checkpoint: EventId) -> Result<()> {
+        self.checkpoints
+            .lock()
+            .unwrap()
+            .insert(projection.to_string(), checkpoint);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RebuildProgress {
+    pub applied: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectionLag {
+    pub projection: String,
+    pub checkpoint: Option<EventId>,
+    pub head: Option<EventId>,
+    /// Events recorded but not yet applied.
+    pub behind: u64,
+}
+
+/// Feeds new events from the store to every registered projection, each
+/// resuming from its own checkpoint.
+pub struct ProjectionRunner<'a, S: EventStore, C: CheckpointStore> {
+    store: &'a S,
+    checkpoints: &'a C,
+    projections: Vec<&'a dyn Projection>,
+    batch_size: usize,
+}
+
+impl<'a, S: EventStore, C: CheckpointStore> ProjectionRunner<'a, S, C> {
+    pub fn new(store: &'a S, checkpoints: &'a C) -> Self {
+        Self {
+            store,
+            checkpoints,
+            projections: Vec::new(),
+            batch_size: 500,
+        }
+    }
+
+    pub fn register(mut self, projection: &'a dyn Projection) -> Self {
+        self.projections.push(projection);
+        self
+    }
+
+    /// Catches every projection up with the store and returns how many
+    /// events were applied in total.
+    pub fn run_once(&self) -> Result<usize> {
+        let mut applied = 0;
+        for projection in &self.projections {
+            let mut checkpoint = self.checkpoints.load(projection.name())?;
+            loop {
+                let batch = self.store.read_after(checkpoint, self.batch_size)?;
+                if batch.is_empty() {
+                    break;
+                }
+                for stored in &batch {
+                    projection.apply(stored)?;
+                    self.checkpoints.save(projection.name(), stored.id)?;
+                    checkpoint = Some(stored.id);
+                    applied += 1;
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Tails the store, polling every `interval` until `stop` is set.
+    pub fn run(&self, interval: Duration, stop: &AtomicBool) -> Result<()> {
+        while !stop.load(Ordering::SeqCst) {
+            if self.run_once()? == 0 {
+                std::thread::sleep(interval);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn lag(&self) -> Result<Vec<ProjectionLag>> {
+        let head = self.store.head()?;
+        self.projections
+            .iter()
+            .map(|projection| {
+                let checkpoint = self.checkpoints.load(projection.name())?;
+                Ok(ProjectionLag {
+                    projection: projection.name().to_string(),
+                    checkpoint,
+                    head,
+                    behind: head
+                        .map_or(0, |head| head.0)
+                        .saturating_sub(checkpoint.map_or(0, |id| id.0)),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Truncates `projection` and replays the whole store into it, saving the
+/// checkpoint and reporting progress after every batch. Returns how many
+/// events were applied.
+pub fn rebuild_projection(
+    store: &impl EventStore,
+    checkpoints: &impl CheckpointStore,
+    projection: &dyn Projection,
+    mut on_progress: impl FnMut(RebuildProgress),
+) -> Result<u64> {
+    let total = store.head()?.map_or(0, |head| head.0);
+    projection.reset()?;
+    let mut applied = 0;
+    let mut after = None;
+    loop {
+        let batch = store.read_after(after, 500)?;
+        let Some(last) = batch.last() else {
+            return Ok(applied);
+        };
+        after = Some(last.id);
+        for stored in &batch {
+            projection.apply(stored)?;
+            applied += 1;
+        }
+        checkpoints.save(projection.name(), last.id)?;
+        on_progress(RebuildProgress { applied, total });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_store::InMemoryEventStore;
+    use crate::events::{DomainEvent, EmailVerified};
+    use crate::UserId;
+
+    #[derive(Default)]
+    struct CountingProjection {
+        seen: Mutex<Vec<EventId>>,
+    }
+
+    impl Projection for CountingProjection {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn apply(&self, stored: &StoredEvent) -> Result<()> {
+            self.seen.lock().unwrap().push(stored.id);
+            Ok(())
+        }
+
+        fn reset(&self) -> Result<()> {
+            self.seen.lock().unwrap().clear();
+            Ok(())
+        }
+    }
+
+    fn append_email_verified(store: &InMemoryEventStore) {
+        store
+            .append(DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(1),
+                email: "foo@ok.com".to_string(),
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn ok_resume_from_checkpoint() {
+        let store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        append_email_verified(&store);
+        append_email_verified(&store);
+
+        let projection = CountingProjection::default();
+        let runner = ProjectionRunner::new(&store, &checkpoints).register(&projection);
+        assert_eq!(runner.lag().unwrap()[0].behind, 2);
+        assert_eq!(runner.run_once().unwrap(), 2);
+        assert_eq!(runner.lag().unwrap()[0].behind, 0);
+
+        // A restarted process starts with an empty read model and the
+        // persisted checkpoint.
+        append_email_verified(&store);
+        let restarted = CountingProjection::default();
+        let runner = ProjectionRunner::new(&store, &checkpoints).register(&restarted);
+        assert_eq!(runner.run_once().unwrap(), 1);
+        assert_eq!(*restarted.seen.lock().unwrap(), vec![EventId(3)]);
+    }
+
+    #[test]
+    fn ok_rebuild_projection() {
+        let store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        append_email_verified(&store);
+        append_email_verified(&store);
+        let projection = CountingProjection::default();
+        ProjectionRunner::new(&store, &checkpoints)
+            .register(&projection)
+            .run_once()
+            .unwrap();
+
+        let mut progress = Vec::new();
+        let applied =
+            rebuild_projection(&store, &checkpoints, &projection, |p| progress.push(p)).unwrap();
+
+        assert_eq!(applied, 2);
+        assert_eq!(
+            *projection.seen.lock().unwrap(),
+            vec![EventId(1), EventId(2)]
+        );
+        assert_eq!(
+            progress,
+            vec![RebuildProgress {
+                applied: 2,
+                total: 2
+            }]
+        );
+        assert_eq!(checkpoints.load("counting").unwrap(), Some(EventId(2)));
+    }
+
+    /// A checkpoint past the head, e.g. after the store was restored from an
+    /// older backup, is not behind.
+    #[test]
+    fn ok_lag_with_checkpoint_past_head() {
+        let store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        append_email_verified(&store);
+        checkpoints.save("counting", EventId(5)).unwrap();
+
+        let projection = CountingProjection::default();
+        let runner = ProjectionRunner::new(&store, &checkpoints).register(&projection);
+
+        assert_eq!(runner.lag().unwrap()[0].behind, 0);
+    }
+}
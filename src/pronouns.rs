@@ -0,0 +1,120 @@
+use crate::error::DomainError;
+use crate::User;
+use regex::Regex;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Pronouns {
+    SheHer,
+    HeHim,
+    TheyThem,
+    Custom(String),
+}
+
+impl Pronouns {
+    /// Form used as the subject of a sentence, e.g. "they" in "they signed up".
+    pub fn subject(&self) -> &str {
+        match self {
+            Pronouns::SheHer => "she",
+            Pronouns::HeHim => "he",
+            Pronouns::TheyThem => "they",
+            Pronouns::Custom(pronouns) => pronouns.split('/').next().unwrap_or_default(),
+        }
+    }
+
+    /// Form used as the object of a sentence, e.g. "them" in "we welcome them".
+    pub fn object(&self) -> &str {
+        match self {
+            Pronouns::SheHer => "her",
+            Pronouns::HeHim => "him",
+            Pronouns::TheyThem => "them",
+            Pronouns::Custom(pronouns) => pronouns.split('/').nth(1).unwrap_or_default(),
+        }
+    }
+}
+
+impl Display for Pronouns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pronouns::Custom(pronouns) => write!(f, "{}", pronouns),
+            preset => write!(f, "{}/{}", preset.subject(), preset.object()),
+        }
+    }
+}
+
+pub fn check_pronouns(pronouns: String) -> Result<Pronouns, DomainError> {
+    let pronouns = pronouns.trim().to_lowercase();
+    match pronouns.as_str() {
+        "she/her" => return Ok(Pronouns::SheHer),
+        "he/him" => return Ok(Pronouns::HeHim),
+        "they/them" => return Ok(Pronouns::TheyThem),
+        _ => {}
+    }
+
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let re = PATTERN.get_or_init(|| Regex::new(r"^[a-z]+(/[a-z]+){1,2}$").unwrap());
+    if pronouns.len() <= 30 && re.is_match(&pronouns) {
+        Ok(Pronouns::Custom(pronouns))
+    } else {
+        Err(DomainError::InvalidPronouns)
+    }
+}
+
+impl User {
+    /// Validates `pronouns` with [`check_pronouns`]; `None` clears them.
+    pub fn set_pronouns(&mut self, pronouns: Option<String>) -> Result<(), DomainError> {
+        self.pronouns = pronouns.map(check_pronouns).transpose()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::UserFixture;
+
+    #[test]
+    fn ok_preset_pronouns() {
+        let pronouns = check_pronouns(" They/Them ".to_string()).unwrap();
+
+        assert_eq!(pronouns, Pronouns::TheyThem);
+        assert_eq!(pronouns.subject(), "they");
+        assert_eq!(pronouns.object(), "them");
+        assert_eq!(pronouns.to_string(), "they/them");
+    }
+
+    #[test]
+    fn ok_custom_pronouns() {
+        let pronouns = check_pronouns("xe/xem/xyr".to_string()).unwrap();
+
+        assert_eq!(pronouns, Pronouns::Custom("xe/xem/xyr".to_string()));
+        assert_eq!(pronouns.subject(), "xe");
+        assert_eq!(pronouns.object(), "xem");
+    }
+
+    #[test]
+    fn err_invalid_pronouns() {
+        let result = check_pronouns("she".to_string());
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error, DomainError::InvalidPronouns);
+        assert_eq!(error.to_string(), "Invalid pronouns");
+    }
+
+    #[test]
+    fn ok_set_pronouns() {
+        let mut user = UserFixture::new().build();
+
+        user.set_pronouns(Some("He/Him".to_string())).unwrap();
+        assert_eq!(user.pronouns(), Some(&Pronouns::HeHim));
+        assert_eq!(
+            user.set_pronouns(Some("he".to_string())),
+            Err(DomainError::InvalidPronouns)
+        );
+        assert_eq!(user.pronouns(), Some(&Pronouns::HeHim));
+        user.set_pronouns(None).unwrap();
+        assert_eq!(user.pronouns(), None);
+    }
+}
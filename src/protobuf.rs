@@ -0,0 +1,417 @@
+//! Protocol Buffers encoding of domain events, following
+//! `proto/events.proto`.
+
+use crate::events::{
+    DomainEvent, EmailVerified, MergedProfile, NameChanged, UserRegistered, UsersMerged,
+    VerificationThrottled,
+};
+use crate::versioning::current_version;
+use crate::UserId;
+use anyhow::{Error, Result};
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+fn invalid() -> Error {
+    Error::msg("Invalid protobuf message")
+}
+
+/// Fields are written in field number order. Like proto3, scalar fields
+/// holding their default value are left out; `optional` ones are written
+/// whenever present.
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    fn uint(&mut self, field: u32, value: u64) -> &mut Self {
+        if value != 0 {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+        self
+    }
+
+    /// `int32` values are sign-extended, so negative ones take ten bytes.
+    fn int(&mut self, field: u32, value: i32) -> &mut Self {
+        self.uint(field, i64::from(value) as u64)
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    fn string(&mut self, field: u32, value: &str) -> &mut Self {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+        self
+    }
+
+    fn optional_string(&mut self, field: u32, value: Option<&str>) -> &mut Self {
+        if let Some(value) = value {
+            self.bytes(field, value.as_bytes());
+        }
+        self
+    }
+
+    fn bool(&mut self, field: u32, value: bool) -> &mut Self {
+        self.uint(field, value.into())
+    }
+
+    /// A `repeated string`, one field per value.
+    fn strings(&mut self, field: u32, values: &[String]) -> &mut Self {
+        for value in values {
+            self.bytes(field, value.as_bytes());
+        }
+        self
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// Fixed-width field; none are defined, but unknown ones are skipped.
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    fn uint(&self) -> Result<u64> {
+        match self {
+            Value::Varint(value) => Ok(*value),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn string(&self) -> Result<String> {
+        match self {
+            Value::Bytes(bytes) => String::from_utf8(bytes.to_vec()).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn bytes(&self) -> Result<&'a [u8]> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if length > self.bytes.len() {
+            return Err(invalid());
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.take(1)? else {
+                unreachable!("took one byte")
+            };
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid())
+    }
+
+    /// Next field number and value, or `None` at the end of the message.
+    fn field(&mut self) -> Result<Option<(u32, Value<'a>)>> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).map_err(|_| invalid())?;
+        let value = match (key & 0x7) as u8 {
+            VARINT => Value::Varint(self.varint()?),
+            LENGTH_DELIMITED => {
+                let length = usize::try_from(self.varint()?).map_err(|_| invalid())?;
+                Value::Bytes(self.take(length)?)
+            }
+            FIXED64 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            FIXED32 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+/// Fields of one message. Unknown fields are kept but never asked for, so
+/// messages from newer writers still decode; missing ones read as defaults.
+struct Fields<'a>(Vec<(u32, Value<'a>)>);
+
+impl<'a> Fields<'a> {
+    fn read(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        let mut fields = Vec::new();
+        while let Some(field) = reader.field()? {
+            fields.push(field);
+        }
+        Ok(Self(fields))
+    }
+
+    /// Last value of `field`, as a later occurrence overrides earlier ones.
+    fn get(&self, field: u32) -> Option<&Value<'a>> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(number, _)| *number == field)
+            .map(|(_, value)| value)
+    }
+
+    fn uint(&self, field: u32) -> Result<u64> {
+        self.get(field).map_or(Ok(0), Value::uint)
+    }
+
+    fn id(&self, field: u32) -> Result<UserId> {
+        self.uint(field).map(UserId)
+    }
+
+    fn string(&self, field: u32) -> Result<String> {
+        self.get(field).map_or(Ok(String::new()), Value::string)
+    }
+
+    fn optional_string(&self, field: u32) -> Result<Option<String>> {
+        self.get(field).map(Value::string).transpose()
+    }
+
+    fn bool(&self, field: u32) -> Result<bool> {
+        Ok(self.uint(field)? != 0)
+    }
+
+    /// Every value of a `repeated string` field, in order.
+    fn strings(&self, field: u32) -> Result<Vec<String>> {
+        self.0
+            .iter()
+            .filter(|(number, _)| *number == field)
+            .map(|(_, value)| value.string())
+            .collect()
+    }
+}
+
+fn encode_body(event: &DomainEvent) -> (u32, Vec<u8>) {
+    let mut body = Writer::default();
+    match event {
+        DomainEvent::UserRegistered(event) => (
+            10,
+            body.uint(1, event.user_id.0)
+                .string(2, &event.email)
+                .string(3, &event.name)
+                .optional_string(4, event.middle_name.as_deref())
+                .string(5, &event.surname)
+                .int(6, event.age)
+                .finish(),
+        ),
+        DomainEvent::EmailVerified(event) => (
+            11,
+            body.uint(1, event.user_id.0)
+                .string(2, &event.email)
+                .finish(),
+        ),
+        DomainEvent::NameChanged(event) => (
+            12,
+            body.uint(1, event.user_id.0)
+                .string(2, &event.name)
+                .optional_string(3, event.middle_name.as_deref())
+                .string(4, &event.surname)
+                .finish(),
+        ),
+        DomainEvent::VerificationThrottled(event) => (
+            13,
+            body.uint(1, event.user_id.0)
+                .uint(2, event.attempts.into())
+                .finish(),
+        ),
+        DomainEvent::UsersMerged(event) => {
+            body.uint(1, event.primary_id.0)
+                .uint(2, event.duplicate_id.0);
+            if let Some(merged) = &event.merged {
+                let merged = Writer::default()
+                    .string(1, &merged.email)
+                    .bool(2, merged.email_verified)
+                    .optional_string(3, merged.middle_name.as_deref())
+                    .optional_string(4, merged.pronouns.as_deref())
+                    .optional_string(5, merged.avatar.as_deref())
+                    .strings(6, &merged.tags)
+                    .finish();
+                body.bytes(3, &merged);
+            }
+            (14, body.finish())
+        }
+    }
+}
+
+/// Encodes `event` as an `EventEnvelope` message.
+pub fn encode_event(event: &DomainEvent) -> Vec<u8> {
+    let (field, body) = encode_body(event);
+    Writer::default()
+        .string(1, event.name())
+        .uint(2, current_version(event.name()).into())
+        .bytes(field, &body)
+        .finish()
+}
+
+fn decode_body(field: u32, bytes: &[u8]) -> Result<DomainEvent> {
+    let body = Fields::read(bytes)?;
+    Ok(match field {
+        10 => DomainEvent::UserRegistered(UserRegistered {
+            user_id: body.id(1)?,
+            email: body.string(2)?,
+            name: body.string(3)?,
+            middle_name: body.optional_string(4)?,
+            surname: body.string(5)?,
+            age: body.uint(6)? as i32,
+        }),
+        11 => DomainEvent::EmailVerified(EmailVerified {
+            user_id: body.id(1)?,
+            email: body.string(2)?,
+        }),
+        12 => DomainEvent::NameChanged(NameChanged {
+            user_id: body.id(1)?,
+            name: body.string(2)?,
+            middle_name: body.optional_string(3)?,
+            surname: body.string(4)?,
+        }),
+        13 => DomainEvent::VerificationThrottled(VerificationThrottled {
+            user_id: body.id(1)?,
+            attempts: u32::try_from(body.uint(2)?).map_err(|_| invalid())?,
+        }),
+        14 => DomainEvent::UsersMerged(UsersMerged {
+            primary_id: body.id(1)?,
+            duplicate_id: body.id(2)?,
+            merged: match body.get(3) {
+                Some(merged) => {
+                    let merged = Fields::read(merged.bytes()?)?;
+                    Some(MergedProfile {
+                        email: merged.string(1)?,
+                        email_verified: merged.bool(2)?,
+                        middle_name: merged.optional_string(3)?,
+                        pronouns: merged.optional_string(4)?,
+                        avatar: merged.optional_string(5)?,
+                        tags: merged.strings(6)?,
+                    })
+                }
+                None => None,
+            },
+        }),
+        _ => return Err(invalid()),
+    })
+}
+
+/// Decodes an `EventEnvelope` message written by [`encode_event`].
+pub fn decode_event(bytes: &[u8]) -> Result<DomainEvent> {
+    let envelope = Fields::read(bytes)?;
+    let (field, body) = (10..=14)
+        .find_map(|field| envelope.get(field).map(|body| (field, body)))
+        .ok_or_else(invalid)?;
+    decode_body(field, body.bytes()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_round_trip_events() {
+        let events = [
+            DomainEvent::UserRegistered(UserRegistered {
+                user_id: UserId(300),
+                email: "foo@ok.com".to_string(),
+                name: "Luca".to_string(),
+                middle_name: Some("Maria".to_string()),
+                surname: "Rossi".to_string(),
+                age: 22,
+            }),
+            DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(1),
+                name: "Luca".to_string(),
+                middle_name: None,
+                surname: "Bianchi".to_string(),
+            }),
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(2),
+                merged: None,
+            }),
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(2),
+                merged: Some(MergedProfile {
+                    email: "foo@ok.com".to_string(),
+                    email_verified: true,
+                    middle_name: None,
+                    pronouns: Some("they/them".to_string()),
+                    avatar: Some("avatars/1.png".to_string()),
+                    tags: vec!["beta".to_string(), "vip".to_string()],
+                }),
+            }),
+        ];
+
+        for event in events {
+            assert_eq!(decode_event(&encode_event(&event)).unwrap(), event);
+        }
+        let verified = DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "a@ok.com".to_string(),
+        });
+        assert_eq!(
+            encode_event(&verified),
+            b"\x0a\x0dEmailVerified\x10\x01\x5a\x0c\x08\x01\x12\x08a@ok.com"
+        );
+    }
+
+    #[test]
+    fn err_truncated_message() {
+        let event = DomainEvent::UsersMerged(UsersMerged {
+            primary_id: UserId(1),
+            duplicate_id: UserId(2),
+            merged: None,
+        });
+        let encoded = encode_event(&event);
+
+        let result = decode_event(&encoded[..encoded.len() - 1]);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid protobuf message");
+    }
+}
@@ -0,0 +1,165 @@
+//! Typed filters and ordering for user queries, which each repository
+//! translates to its own storage through [`UserRepository::query`].
+//!
+//! [`UserRepository::query`]: crate::repository::UserRepository::query
+
+use crate::specification::Specification;
+use crate::User;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Name,
+    Surname,
+    Age,
+    RegisteredAt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Filters are combined with AND; unset ones match every user. Merged users
+/// never match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserQuery {
+    pub verified: Option<bool>,
+    pub min_age: Option<i32>,
+    pub max_age: Option<i32>,
+    /// Domain of the email address, e.g. `ok.com`, ignoring case.
+    pub email_domain: Option<String>,
+    /// Sort keys by priority; ties are broken by id, so results are stable.
+    pub order: Vec<(SortField, SortOrder)>,
+    pub limit: Option<usize>,
+}
+
+impl UserQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn verified(mut self, verified: bool) -> Self {
+        self.verified = Some(verified);
+        self
+    }
+
+    pub fn min_age(mut self, age: i32) -> Self {
+        self.min_age = Some(age);
+        self
+    }
+
+    pub fn max_age(mut self, age: i32) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    pub fn email_domain(mut self, domain: &str) -> Self {
+        self.email_domain = Some(domain.to_string());
+        self
+    }
+
+    /// Adds a sort key, less significant than the ones added before.
+    pub fn order_by(mut self, field: SortField, order: SortOrder) -> Self {
+        self.order.push((field, order));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Order of `a` and `b` in the query's results.
+    pub fn compare(&self, a: &User, b: &User) -> Ordering {
+        self.order
+            .iter()
+            .map(|(field, order)| {
+                let ordering = match field {
+                    SortField::Name => a.name.cmp(&b.name),
+                    SortField::Surname => a.surname.cmp(&b.surname),
+                    SortField::Age => a.age.0.cmp(&b.age.0),
+                    SortField::RegisteredAt => a.registered_at.cmp(&b.registered_at),
+                };
+                match order {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.id.cmp(&b.id))
+    }
+}
+
+impl Specification<User> for UserQuery {
+    fn is_satisfied_by(&self, user: &User) -> bool {
+        let verified = user.is_email_verified();
+        user.merged_into.is_none()
+            && self.verified.is_none_or(|wanted| wanted == verified)
+            && self.min_age.is_none_or(|age| user.age.0 >= age)
+            && self.max_age.is_none_or(|age| user.age.0 <= age)
+            && self.email_domain.as_ref().is_none_or(|domain| {
+                user.email
+                    .address()
+                    .as_str()
+                    .rsplit_once('@')
+                    .is_some_and(|(_, actual)| actual.eq_ignore_ascii_case(domain))
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::{InMemoryUserRepository, UserRepository};
+    use crate::{create_user, verify_email, UserEmail};
+
+    #[test]
+    fn ok_query_filters_and_sorts() {
+        let repository = InMemoryUserRepository::new();
+        let people = [
+            ("rossi@ok.com", 30, "Luca", "Rossi", true),
+            ("bianchi@ok.com", 40, "Anna", "Bianchi", true),
+            ("verdi@ok.com", 16, "Marco", "Verdi", true),
+            ("neri@ok.com", 50, "Paolo", "Neri", false),
+            ("ricci@okay.com", 35, "Sara", "Ricci", true),
+        ];
+        for (email, age, name, surname, verified) in people {
+            let mut user = create_user(
+                email.to_string(),
+                age,
+                name.to_string(),
+                surname.to_string(),
+                None,
+            )
+            .unwrap();
+            if let (true, UserEmail::UnverifiedEmail(email)) = (verified, &user.email) {
+                user.email = UserEmail::VerifiedEmail(verify_email(email).unwrap());
+            }
+            repository.save(user).unwrap();
+        }
+
+        let query = UserQuery::new()
+            .verified(true)
+            .min_age(18)
+            .email_domain("OK.com")
+            .order_by(SortField::Surname, SortOrder::Asc);
+        let surnames: Vec<String> = repository
+            .query(&query)
+            .unwrap()
+            .into_iter()
+            .map(|user| user.surname)
+            .collect();
+        let oldest = repository
+            .query(
+                &UserQuery::new()
+                    .order_by(SortField::Age, SortOrder::Desc)
+                    .limit(1),
+            )
+            .unwrap();
+
+        assert_eq!(surnames, vec!["Bianchi", "Rossi"]);
+        assert_eq!(oldest[0].surname, "Neri");
+    }
+}
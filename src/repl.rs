@@ -0,0 +1,230 @@
+//! Interactive shell over the command bus, for exploring the domain by hand.
+
+use crate::bus::{Command, CommandBus, ExecutionMode};
+use crate::commands::CreateUser;
+use crate::config::AgeLimits;
+use crate::event_store::{EventStore, InMemoryEventStore};
+use crate::history::{consolidated_history, fold_user};
+use crate::repository::UserRepository;
+use crate::UserId;
+use anyhow::{Error, Result};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+create <email> <age> <name> <surname> [middle name]
+verify <id>
+show <id>
+events <id> the user's events, with those of users merged into it
+replay      rebuild every user from the event log and compare
+history     list the lines entered so far; !<n> runs line n again
+help
+exit";
+
+fn user_id(id: &str) -> Result<UserId> {
+    id.parse().map_err(|_| Error::msg("Expected a user id"))
+}
+
+/// Shell state: the bus it drives, which records every event in its own
+/// log, and the lines entered so far.
+pub struct Repl<R: UserRepository> {
+    bus: CommandBus<R, InMemoryEventStore>,
+    history: Vec<String>,
+}
+
+impl<R: UserRepository> Repl<R> {
+    pub fn new(repository: R) -> Self {
+        Self {
+            bus: CommandBus::new(repository, InMemoryEventStore::new()),
+            history: Vec::new(),
+        }
+    }
+
+    /// Limits `create` checks ages against, the defaults until set.
+    pub fn with_age_limits(mut self, age_limits: AgeLimits) -> Self {
+        self.bus = self.bus.with_age_limits(age_limits);
+        self
+    }
+
+    /// Runs one line and returns what to print.
+    pub fn execute(&mut self, line: &str) -> Result<String> {
+        let line = line.trim();
+        if let Some(number) = line.strip_prefix('!') {
+            let again = number
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| self.history.get(number.wrapping_sub(1)))
+                .cloned()
+                .ok_or_else(|| Error::msg(format!("No line {} in history", number)))?;
+            return self.execute(&again);
+        }
+        if !line.is_empty() && line != "history" {
+            self.history.push(line.to_string());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => Ok(String::new()),
+            ["help"] => Ok(HELP.to_string()),
+            ["history"] => Ok(self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(index, line)| format!("{} {}", index + 1, line))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ["create", email, age, name, surname, middle_name @ ..] if middle_name.len() <= 1 => {
+                let age = age
+                    .parse()
+                    .map_err(|_| Error::msg("Age must be a number"))?;
+                let command = Command::CreateUser(CreateUser {
+                    email: email.to_string(),
+                    age,
+                    name: name.to_string(),
+                    surname: surname.to_string(),
+                    middle_name: middle_name.first().map(|name| name.to_string()),
+                });
+                let events = self.bus.dispatch(command, ExecutionMode::Commit)?;
+                Ok(format!("Created user {}", events[0].user_id()))
+            }
+            ["verify", id] => {
+                let user_id = user_id(id)?;
+                let events = self
+                    .bus
+                    .dispatch(Command::VerifyEmail(user_id), ExecutionMode::Commit)?;
+                Ok(events[0].name().to_string())
+            }
+            ["show", id] => {
+                let user = self
+                    .bus
+                    .repository()
+                    .find(user_id(id)?)?
+                    .ok_or_else(|| Error::msg("User not found"))?;
+                Ok(user.to_string())
+            }
+            ["events", id] => {
+                let events = consolidated_history(self.bus.publisher(), user_id(id)?)?;
+                Ok(events
+                    .iter()
+                    .map(|stored| format!("{} {}", stored.id.0, stored.event.to_json()))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            ["replay"] => self.replay(),
+            _ => Err(Error::msg(format!(
+                "Unknown command {:?}; type help for the list",
+                line
+            ))),
+        }
+    }
+
+    /// Folds every stream of the event log and reports users whose stored
+    /// state disagrees with it.
+    fn replay(&self) -> Result<String> {
+        let events = self.bus.publisher().read_after(None, usize::MAX)?;
+        let mut streams: BTreeMap<UserId, Vec<_>> = BTreeMap::new();
+        for stored in &events {
+            streams
+                .entry(stored.event.user_id())
+                .or_default()
+                .push(stored);
+        }
+        let mut lines = vec![format!(
+            "Replayed {} events into {} users",
+            events.len(),
+            streams.len()
+        )];
+        for (user_id, stream) in streams {
+            let rebuilt = fold_user(stream).map(|user| user.to_string());
+            let stored = self
+                .bus
+                .repository()
+                .find(user_id)?
+                .map(|user| user.to_string());
+            if rebuilt != stored {
+                lines.push(format!("User {} differs from the repository", user_id));
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Reads commands from `input` until `exit` or its end, printing each
+    /// result, or the error, to `output`.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> Result<()> {
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "exit" | "quit") {
+                break;
+            }
+            match self.execute(&line) {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(error) => writeln!(output, "Error: {}", error)?,
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+
+    #[test]
+    fn ok_session_creates_verifies_and_replays() {
+        let mut repl = Repl::new(InMemoryUserRepository::new());
+        let input = "create foo@ok.com 22 Luca Rossi Maria\nverify 0\nexit\n";
+
+        let created = repl
+            .execute("create foo@ok.com 22 Luca Rossi Maria")
+            .unwrap();
+        let id = created.strip_prefix("Created user ").unwrap().to_string();
+        repl.execute(&format!("verify {}", id)).unwrap();
+
+        assert_eq!(
+            repl.execute(&format!("show {}", id)).unwrap(),
+            format!(
+                "User {}: Luca Maria Rossi, 22 years old, foo@ok.com (verified)",
+                id
+            )
+        );
+        assert_eq!(
+            repl.execute(&format!("events {}", id))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        assert_eq!(
+            repl.execute("replay").unwrap(),
+            "Replayed 2 events into 1 users"
+        );
+        assert_eq!(
+            repl.execute("!3").unwrap(),
+            repl.execute(&format!("show {}", id)).unwrap()
+        );
+        let mut output = Vec::new();
+        repl.run(input.as_bytes(), &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Error: User not found"));
+    }
+
+    #[test]
+    fn err_unknown_command() {
+        let mut repl = Repl::new(InMemoryUserRepository::new());
+
+        let result = repl.execute("delete 1");
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown command \"delete 1\"; type help for the list"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::bus::ExecutionMode;
+use crate::event_store::{EventStore, StoredEvent};
+use crate::events::EventHandler;
+use crate::projections::Projection;
+use crate::stream::EventFilter;
+use crate::UserId;
+use anyhow::{Error, Result};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayFilter {
+    pub events: EventFilter,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl ReplayFilter {
+    pub fn matches(&self, stored: &StoredEvent) -> bool {
+        self.events.matches(&stored.event)
+            && self.since.is_none_or(|since| stored.recorded_at >= since)
+            && self.until.is_none_or(|until| stored.recorded_at <= until)
+    }
+}
+
+/// Where replayed events go. A projection also sees when each event was
+/// recorded, a handler only the event.
+#[derive(Clone, Copy)]
+pub enum ReplayTarget<'a> {
+    Handler(&'a dyn EventHandler),
+    Projection(&'a dyn Projection),
+}
+
+impl ReplayTarget<'_> {
+    pub fn name(&self) -> &str {
+        match self {
+            ReplayTarget::Handler(handler) => handler.name(),
+            ReplayTarget::Projection(projection) => projection.name(),
+        }
+    }
+
+    fn replay(&self, stored: &StoredEvent) -> Result<()> {
+        match self {
+            ReplayTarget::Handler(handler) => handler.handle(&stored.event),
+            ReplayTarget::Projection(projection) => projection.apply(stored),
+        }
+    }
+}
+
+/// Re-dispatches the matching events from the store to `targets`, oldest
+/// first, and returns them. A dry run only returns what would be replayed.
+pub fn replay_events(
+    store: &impl EventStore,
+    filter: &ReplayFilter,
+    targets: &[ReplayTarget],
+    mode: ExecutionMode,
+) -> Result<Vec<StoredEvent>> {
+    let mut replayed = Vec::new();
+    let mut after = None;
+    loop {
+        let batch = store.read_after(after, 500)?;
+        let Some(last) = batch.last() else {
+            return Ok(replayed);
+        };
+        after = Some(last.id);
+
+        for stored in batch.into_iter().filter(|stored| filter.matches(stored)) {
+            if mode == ExecutionMode::Commit {
+                for target in targets {
+                    target.replay(&stored)?;
+                }
+            }
+            replayed.push(stored);
+        }
+    }
+}
+
+/// Arguments of the `replay` subcommand:
+/// `replay <event log> [--stream <user id>] [--type <event type>]...
+/// [--since <unix seconds>] [--until <unix seconds>] [--handler <name>]...
+/// [--dry-run]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayArgs {
+    pub event_log: String,
+    pub filter: ReplayFilter,
+    /// Handlers and projections to replay into; empty means all of them.
+    pub handlers: Vec<String>,
+    pub mode: ExecutionMode,
+}
+
+pub fn parse_replay_args(args: &[String]) -> Result<ReplayArgs> {
+    let mut event_log = None;
+    let mut filter = ReplayFilter::default();
+    let mut handlers = Vec::new();
+    let mut mode = ExecutionMode::Commit;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| Error::msg(format!("Missing value for {}", arg)))
+        };
+        let instant = |value: &String| -> Result<SystemTime> {
+            let seconds = value
+                .parse()
+                .map_err(|_| Error::msg(format!("Invalid timestamp {}", value)))?;
+            Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+        };
+
+        match arg.as_str() {
+            "--stream" => {
+                let value = value()?;
+                let id = value
+                    .parse()
+                    .map_err(|_| Error::msg(format!("Invalid user id {}", value)))?;
+                filter.events.user_id = Some(UserId(id));
+            }
+            "--type" => filter.events.event_types.push(value()?.clone()),
+            "--since" => filter.since = Some(instant(value()?)?),
+            "--until" => filter.until = Some(instant(value()?)?),
+            "--handler" => handlers.push(value()?.clone()),
+            "--dry-run" => mode = ExecutionMode::DryRun,
+            flag if flag.starts_with("--") => {
+                return Err(Error::msg(format!("Unknown option {}", flag)))
+            }
+            path if event_log.is_none() => event_log = Some(path.to_string()),
+            extra => return Err(Error::msg(format!("Unexpected argument {}", extra))),
+        }
+    }
+
+    Ok(ReplayArgs {
+        event_log: event_log.ok_or_else(|| Error::msg("Missing event log path"))?,
+        filter,
+        handlers,
+        mode,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_store::InMemoryEventStore;
+    use crate::events::{DomainEvent, EmailVerified, EventPublisher, InMemoryEventPublisher};
+    use crate::users_by_domain::UsersByDomain;
+
+    struct RecordingHandler(InMemoryEventPublisher);
+
+    impl EventHandler for RecordingHandler {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        fn handle(&self, event: &DomainEvent) -> Result<()> {
+            self.0.publish(event)
+        }
+    }
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn ok_replay_stream_and_dry_run() {
+        let store = InMemoryEventStore::new();
+        for user_id in [1, 2, 1] {
+            store
+                .append(DomainEvent::EmailVerified(EmailVerified {
+                    user_id: UserId(user_id),
+                    email: "foo@ok.com".to_string(),
+                }))
+                .unwrap();
+        }
+        let handler = RecordingHandler(InMemoryEventPublisher::new());
+        let replay = parse_replay_args(&args("events.ndjson --stream 1 --dry-run")).unwrap();
+
+        let planned = replay_events(
+            &store,
+            &replay.filter,
+            &[ReplayTarget::Handler(&handler)],
+            replay.mode,
+        )
+        .unwrap();
+        assert_eq!(planned.len(), 2);
+        assert!(handler.0.published().is_empty());
+
+        replay_events(
+            &store,
+            &replay.filter,
+            &[ReplayTarget::Handler(&handler)],
+            ExecutionMode::Commit,
+        )
+        .unwrap();
+        assert_eq!(handler.0.published().len(), 2);
+    }
+
+    #[test]
+    fn ok_replay_into_projection() {
+        let store = InMemoryEventStore::new();
+        for (user_id, email) in [(1, "a@ok.com"), (2, "b@acme.com")] {
+            store
+                .append(DomainEvent::EmailVerified(EmailVerified {
+                    user_id: UserId(user_id),
+                    email: email.to_string(),
+                }))
+                .unwrap();
+        }
+        let by_domain = UsersByDomain::new();
+        let mut filter = ReplayFilter::default();
+        filter.events.user_id = Some(UserId(2));
+
+        replay_events(
+            &store,
+            &filter,
+            &[ReplayTarget::Projection(&by_domain)],
+            ExecutionMode::Commit,
+        )
+        .unwrap();
+
+        let domains: Vec<String> = by_domain
+            .counts()
+            .into_iter()
+            .map(|count| count.domain)
+            .collect();
+        assert_eq!(domains, vec!["acme.com"]);
+    }
+
+    #[test]
+    fn err_parse_replay_args_unknown_option() {
+        let result = parse_replay_args(&args("events.ndjson --verbose"));
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Unknown option --verbose");
+    }
+}
@@ -0,0 +1,734 @@
+//! Exchange of user events between deployments in different regions.
+//!
+//! Each region publishes its own events to a replication log through a
+//! [`ReplicationProducer`] and applies the other regions' events with a
+//! [`ReplicationConsumer`]. Every user carries a vector clock, so a region
+//! can tell an event it has already seen from one that is newer, and both
+//! from one made concurrently with a local change.
+//!
+//! Regions number their users independently, so users travel under a
+//! [`ReplicaId`]: the region that registered them and their id there.
+
+use crate::clock::Clock;
+use crate::events::{DomainEvent, EventPublisher};
+use crate::history::apply_merged;
+use crate::json::{parse_json, JsonValue};
+use crate::pii::mask_email;
+use crate::repository::UserRepository;
+use crate::{Age, Email, TenantId, UnverifiedEmail, User, UserEmail, UserId, VerifiedEmail};
+use anyhow::{Error, Result};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Count of events seen from each region.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorClock(pub BTreeMap<String, u64>);
+
+impl VectorClock {
+    fn tick(&mut self, region: &str) {
+        *self.0.entry(region.to_string()).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &VectorClock) {
+        for (region, count) in &other.0 {
+            let entry = self.0.entry(region.clone()).or_default();
+            *entry = (*entry).max(*count);
+        }
+    }
+
+    /// `None` when neither clock has seen everything the other has, i.e.
+    /// the changes were concurrent.
+    pub fn compare(&self, other: &VectorClock) -> Option<Ordering> {
+        let count = |clock: &VectorClock, region: &str| clock.0.get(region).copied().unwrap_or(0);
+        let mut ordering = Ordering::Equal;
+        for region in self.0.keys().chain(other.0.keys()) {
+            match (ordering, count(self, region).cmp(&count(other, region))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, next) => ordering = next,
+                (current, next) if current != next => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+}
+
+/// A user as every region knows it. Replicas keep the id the user was
+/// registered with, so the ids in a replicated event are those of its
+/// origin only for users registered there.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ReplicaId {
+    /// Region the user was registered in.
+    pub home: String,
+    pub user_id: UserId,
+}
+
+impl ReplicaId {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Object(vec![
+            ("home".to_string(), JsonValue::String(self.home.clone())),
+            (
+                "user_id".to_string(),
+                JsonValue::Number(self.user_id.0 as i64),
+            ),
+        ])
+    }
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        Some(Self {
+            home: value.get("home")?.as_str()?.to_string(),
+            user_id: UserId(value.get("user_id")?.as_i64()? as u64),
+        })
+    }
+}
+
+/// A user event as it travels between regions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicatedEvent {
+    pub origin: String,
+    /// Clock of the user once the event was applied in its origin.
+    pub clock: VectorClock,
+    pub recorded_at: SystemTime,
+    pub tenant_id: TenantId,
+    /// The user the event belongs to.
+    pub user: ReplicaId,
+    /// The duplicate of a `UsersMerged` event.
+    pub duplicate: Option<ReplicaId>,
+    pub event: DomainEvent,
+}
+
+impl ReplicatedEvent {
+    pub fn to_json(&self) -> JsonValue {
+        let recorded_at = self
+            .recorded_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let clock = self
+            .clock
+            .0
+            .iter()
+            .map(|(region, count)| (region.clone(), JsonValue::Number(*count as i64)))
+            .collect();
+        JsonValue::Object(vec![
+            ("origin".to_string(), JsonValue::String(self.origin.clone())),
+            ("clock".to_string(), JsonValue::Object(clock)),
+            (
+                "recorded_at_ms".to_string(),
+                JsonValue::Number(recorded_at as i64),
+            ),
+            (
+                "tenant_id".to_string(),
+                JsonValue::String(self.tenant_id.0.clone()),
+            ),
+            ("user".to_string(), self.user.to_json()),
+            (
+                "duplicate".to_string(),
+                self.duplicate
+                    .as_ref()
+                    .map_or(JsonValue::Null, ReplicaId::to_json),
+            ),
+            ("event".to_string(), self.event.to_json()),
+        ])
+    }
+
+    pub fn from_json(value: &JsonValue) -> Result<Self> {
+        let invalid = || Error::msg("Invalid replicated event");
+        let clock = match value.get("clock") {
+            Some(JsonValue::Object(fields)) => fields
+                .iter()
+                .map(|(region, count)| Some((region.clone(), count.as_i64()? as u64)))
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        let recorded_at = value
+            .get("recorded_at_ms")
+            .and_then(JsonValue::as_i64)
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            origin: value
+                .get("origin")
+                .and_then(JsonValue::as_str)
+                .ok_or_else(invalid)?
+                .to_string(),
+            clock: VectorClock(clock),
+            recorded_at: SystemTime::UNIX_EPOCH + Duration::from_millis(recorded_at as u64),
+            tenant_id: TenantId(
+                value
+                    .get("tenant_id")
+                    .and_then(JsonValue::as_str)
+                    .ok_or_else(invalid)?
+                    .to_string(),
+            ),
+            user: value
+                .get("user")
+                .and_then(ReplicaId::from_json)
+                .ok_or_else(invalid)?,
+            duplicate: match value.get("duplicate") {
+                None | Some(JsonValue::Null) => None,
+                Some(duplicate) => Some(ReplicaId::from_json(duplicate).ok_or_else(invalid)?),
+            },
+            event: DomainEvent::from_json(value.get("event").ok_or_else(invalid)?)?,
+        })
+    }
+}
+
+/// Log shared by the regions, e.g. a topic mirrored between them. Entries
+/// are numbered from 1 in the order they were appended.
+pub trait ReplicationLog {
+    fn append(&self, line: &str) -> Result<()>;
+    fn read_after(&self, offset: u64, limit: usize) -> Result<Vec<(u64, String)>>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryReplicationLog {
+    lines: Mutex<Vec<String>>,
+}
+
+impl InMemoryReplicationLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplicationLog for InMemoryReplicationLog {
+    fn append(&self, line: &str) -> Result<()> {
+        self.lines.lock().unwrap().push(line.to_string());
+        Ok(())
+    }
+
+    fn read_after(&self, offset: u64, limit: usize) -> Result<Vec<(u64, String)>> {
+        let lines = self.lines.lock().unwrap();
+        Ok((offset as usize..lines.len())
+            .take(limit)
+            .map(|index| (index as u64 + 1, lines[index].clone()))
+            .collect())
+    }
+}
+
+/// Who last wrote a field; later wins, ties go to the greater region name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Stamp {
+    at: SystemTime,
+    region: String,
+}
+
+#[derive(Debug, Default)]
+struct Replica {
+    clock: VectorClock,
+    name: Option<Stamp>,
+    verified_email: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Replicas {
+    by_id: HashMap<ReplicaId, Replica>,
+    /// Local users registered in another region.
+    remote: HashMap<UserId, ReplicaId>,
+}
+
+/// Replication state of this region, shared by its producer and consumer.
+#[derive(Debug)]
+pub struct Region {
+    name: String,
+    replicas: Mutex<Replicas>,
+}
+
+impl Region {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            replicas: Mutex::new(Replicas::default()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn replica_id(&self, replicas: &Replicas, user_id: UserId) -> ReplicaId {
+        replicas
+            .remote
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_else(|| ReplicaId {
+                home: self.name.clone(),
+                user_id,
+            })
+    }
+
+    /// `None` for a remote user that was never replicated here.
+    fn local_id(&self, replicas: &Replicas, user: &ReplicaId) -> Option<UserId> {
+        let replicated = replicas.remote.get(&user.user_id) == Some(user);
+        (user.home == self.name || replicated).then_some(user.user_id)
+    }
+}
+
+/// Records what a replica knows once `event` is applied.
+fn note(replica: &mut Replica, event: &DomainEvent, stamp: Stamp) {
+    match event {
+        DomainEvent::NameChanged(_) => replica.name = Some(stamp),
+        DomainEvent::EmailVerified(event) => {
+            replica.verified_email = Some(Email(event.email.clone()).normalized())
+        }
+        DomainEvent::UsersMerged(event) => {
+            if let Some(merged) = event.merged.as_ref().filter(|merged| merged.email_verified) {
+                replica.verified_email = Some(Email(merged.email.clone()).normalized())
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Publishes this region's events to the replication log, stamped with the
+/// user's clock. Register it next to the other publishers, so it runs once
+/// the user is saved; `repository` tells it the user's tenant.
+pub struct ReplicationProducer<'a, L: ReplicationLog, R: UserRepository, C: Clock> {
+    region: &'a Region,
+    log: &'a L,
+    repository: &'a R,
+    clock: C,
+}
+
+impl<'a, L: ReplicationLog, R: UserRepository, C: Clock> ReplicationProducer<'a, L, R, C> {
+    pub fn new(region: &'a Region, log: &'a L, repository: &'a R, clock: C) -> Self {
+        Self {
+            region,
+            log,
+            repository,
+            clock,
+        }
+    }
+}
+
+impl<L: ReplicationLog, R: UserRepository, C: Clock> EventPublisher
+    for ReplicationProducer<'_, L, R, C>
+{
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        let tenant_id = self
+            .repository
+            .find(event.user_id())?
+            .ok_or_else(|| Error::msg(format!("User {} not found", event.user_id())))?
+            .tenant_id;
+        let recorded_at = self.clock.now();
+        let (user, duplicate, clock) = {
+            let mut replicas = self.region.replicas.lock().unwrap();
+            let user = self.region.replica_id(&replicas, event.user_id());
+            let duplicate = match event {
+                DomainEvent::UsersMerged(event) => {
+                    Some(self.region.replica_id(&replicas, event.duplicate_id))
+                }
+                _ => None,
+            };
+            let replica = replicas.by_id.entry(user.clone()).or_default();
+            replica.clock.tick(&self.region.name);
+            let stamp = Stamp {
+                at: recorded_at,
+                region: self.region.name.clone(),
+            };
+            note(replica, event, stamp);
+            (user, duplicate, replica.clock.clone())
+        };
+        let replicated = ReplicatedEvent {
+            origin: self.region.name.clone(),
+            clock,
+            recorded_at,
+            tenant_id,
+            user,
+            duplicate,
+            event: event.clone(),
+        };
+        self.log.append(&replicated.to_json().to_string())
+    }
+}
+
+/// Both regions verified an email for the user, but not the same one. No
+/// policy can pick the right address, so someone has to.
+#[derive(Clone, PartialEq)]
+pub struct EmailConflict {
+    pub user_id: UserId,
+    pub local_email: String,
+    pub remote_email: String,
+    pub remote_region: String,
+}
+
+impl Debug for EmailConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailConflict")
+            .field("user_id", &self.user_id)
+            .field("local_email", &mask_email(&self.local_email))
+            .field("remote_email", &mask_email(&self.remote_email))
+            .field("remote_region", &self.remote_region)
+            .finish()
+    }
+}
+
+/// A remote user could not be replicated because the id it was registered
+/// with belongs to another user here. Its events are reported, not applied,
+/// until someone moves one of the two users.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdConflict {
+    pub user: ReplicaId,
+    pub remote_region: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplicationOutcome {
+    Applied,
+    /// Already seen, or lost to a later local write.
+    Skipped,
+    Conflict(EmailConflict),
+    IdConflict(IdConflict),
+}
+
+/// Applies the other regions' events from the replication log to this
+/// region's repository.
+pub struct ReplicationConsumer<'a, L: ReplicationLog, R: UserRepository> {
+    region: &'a Region,
+    log: &'a L,
+    repository: &'a R,
+    offset: Mutex<u64>,
+}
+
+impl<'a, L: ReplicationLog, R: UserRepository> ReplicationConsumer<'a, L, R> {
+    pub fn new(region: &'a Region, log: &'a L, repository: &'a R) -> Self {
+        Self {
+            region,
+            log,
+            repository,
+            offset: Mutex::new(0),
+        }
+    }
+
+    /// Applies up to `limit` new log entries and returns the outcome of each
+    /// remote one. Stops at the first failure, which is retried next time.
+    pub fn poll(&self, limit: usize) -> Result<Vec<ReplicationOutcome>> {
+        let mut offset = self.offset.lock().unwrap();
+        let mut outcomes = Vec::new();
+        for (position, line) in self.log.read_after(*offset, limit)? {
+            let replicated = ReplicatedEvent::from_json(&parse_json(&line)?)?;
+            if replicated.origin != self.region.name {
+                outcomes.push(self.receive(&replicated)?);
+            }
+            *offset = position;
+        }
+        Ok(outcomes)
+    }
+
+    /// Last-writer-wins for names, which are safe to overwrite; concurrent
+    /// verifications of different emails are reported instead of applied.
+    fn receive(&self, replicated: &ReplicatedEvent) -> Result<ReplicationOutcome> {
+        let event = &replicated.event;
+        let mut replicas = self.region.replicas.lock().unwrap();
+        let id_conflict = |user: &ReplicaId| {
+            ReplicationOutcome::IdConflict(IdConflict {
+                user: user.clone(),
+                remote_region: replicated.origin.clone(),
+            })
+        };
+        let user_id = match self.region.local_id(&replicas, &replicated.user) {
+            Some(user_id) => user_id,
+            None => {
+                let user_id = replicated.user.user_id;
+                let taken = replicas.remote.contains_key(&user_id)
+                    || self.repository.find(user_id)?.is_some();
+                if taken || !matches!(event, DomainEvent::UserRegistered(_)) {
+                    return Ok(id_conflict(&replicated.user));
+                }
+                user_id
+            }
+        };
+        let duplicate_id = match &replicated.duplicate {
+            Some(duplicate) => match self.region.local_id(&replicas, duplicate) {
+                Some(duplicate_id) => Some(duplicate_id),
+                None => return Ok(id_conflict(duplicate)),
+            },
+            None => None,
+        };
+        let replica = replicas.by_id.entry(replicated.user.clone()).or_default();
+        let stamp = Stamp {
+            at: replicated.recorded_at,
+            region: replicated.origin.clone(),
+        };
+        let outcome = match replicated.clock.compare(&replica.clock) {
+            Some(Ordering::Less | Ordering::Equal) => ReplicationOutcome::Skipped,
+            Some(Ordering::Greater) => ReplicationOutcome::Applied,
+            None => match event {
+                DomainEvent::NameChanged(_) if replica.name.as_ref() > Some(&stamp) => {
+                    ReplicationOutcome::Skipped
+                }
+                DomainEvent::EmailVerified(remote) => match &replica.verified_email {
+                    Some(local) if *local != Email(remote.email.clone()).normalized() => {
+                        ReplicationOutcome::Conflict(EmailConflict {
+                            user_id,
+                            local_email: local.clone(),
+                            remote_email: remote.email.clone(),
+                            remote_region: replicated.origin.clone(),
+                        })
+                    }
+                    _ => ReplicationOutcome::Applied,
+                },
+                _ => ReplicationOutcome::Applied,
+            },
+        };
+        if outcome == ReplicationOutcome::Applied {
+            apply(self.repository, replicated, user_id, duplicate_id)?;
+            note(replica, event, stamp);
+        }
+        replica.clock.merge(&replicated.clock);
+        if replicated.user.home != self.region.name {
+            replicas.remote.insert(user_id, replicated.user.clone());
+        }
+        Ok(outcome)
+    }
+}
+
+/// Writes the change `replicated` describes to `repository`, for the local
+/// user `user_id` and, in a merge, the local duplicate `duplicate_id`.
+fn apply(
+    repository: &impl UserRepository,
+    replicated: &ReplicatedEvent,
+    user_id: UserId,
+    duplicate_id: Option<UserId>,
+) -> Result<()> {
+    let load = |id: UserId| {
+        repository
+            .find(id)?
+            .ok_or_else(|| Error::msg(format!("User {} not replicated yet", id)))
+    };
+    match &replicated.event {
+        DomainEvent::UserRegistered(event) => repository.save(User {
+            id: user_id,
+            tenant_id: replicated.tenant_id.clone(),
+            name: event.name.clone(),
+            middle_name: event.middle_name.clone(),
+            surname: event.surname.clone(),
+            age: Age(event.age),
+            email: UserEmail::UnverifiedEmail(UnverifiedEmail(Email(event.email.clone()))),
+            pronouns: None,
+            avatar: None,
+            custom_attributes: Default::default(),
+            tags: Default::default(),
+            merged_into: None,
+            registered_at: replicated.recorded_at,
+            version: 0,
+        }),
+        DomainEvent::EmailVerified(event) => {
+            let mut user = load(user_id)?;
+            user.email = UserEmail::VerifiedEmail(VerifiedEmail(Email(event.email.clone())));
+            repository.save(user)
+        }
+        DomainEvent::NameChanged(event) => {
+            let mut user = load(user_id)?;
+            user.name = event.name.clone();
+            user.middle_name = event.middle_name.clone();
+            user.surname = event.surname.clone();
+            repository.save(user)
+        }
+        DomainEvent::VerificationThrottled(_) => Ok(()),
+        DomainEvent::UsersMerged(event) => {
+            let duplicate_id =
+                duplicate_id.ok_or_else(|| Error::msg("Invalid replicated event"))?;
+            let mut duplicate = load(duplicate_id)?;
+            duplicate.merged_into = Some(user_id);
+            let mut primary = load(user_id)?;
+            if let Some(merged) = &event.merged {
+                apply_merged(&mut primary, merged);
+            }
+            repository.save_all(vec![duplicate, primary])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::events::{EmailVerified, MergedProfile, NameChanged, UserRegistered, UsersMerged};
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::{assert_debug_masks, UserFixture};
+
+    /// Saves a user with the given id to `repository`, as registering it
+    /// there would, and returns its event.
+    fn registered_in(
+        repository: &InMemoryUserRepository,
+        user_id: u64,
+        email: &str,
+        tenant: &str,
+    ) -> DomainEvent {
+        let mut user = UserFixture::new().with_email(email).build();
+        user.id = UserId(user_id);
+        user.tenant_id = TenantId(tenant.to_string());
+        let event = UserRegistered::from_user(&user);
+        repository.save(user).unwrap();
+        DomainEvent::UserRegistered(event)
+    }
+
+    fn renamed(name: &str) -> DomainEvent {
+        DomainEvent::NameChanged(NameChanged {
+            user_id: UserId(7),
+            name: name.to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+        })
+    }
+
+    fn verified(email: &str) -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(7),
+            email: email.to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_concurrent_names_resolved_by_last_writer() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let log = InMemoryReplicationLog::new();
+        let (east, west) = (Region::new("east"), Region::new("west"));
+        let (east_users, west_users) =
+            (InMemoryUserRepository::new(), InMemoryUserRepository::new());
+        let east_producer = ReplicationProducer::new(&east, &log, &east_users, &clock);
+        let west_producer = ReplicationProducer::new(&west, &log, &west_users, &clock);
+        let west_consumer = ReplicationConsumer::new(&west, &log, &west_users);
+
+        let registered = registered_in(&east_users, 7, "foo@ok.com", "acme");
+        east_producer.publish(&registered).unwrap();
+        west_consumer.poll(10).unwrap();
+        west_producer.publish(&renamed("Marco")).unwrap();
+        clock.advance(Duration::from_secs(1));
+        east_producer.publish(&renamed("Paolo")).unwrap();
+        let outcomes = west_consumer.poll(10).unwrap();
+
+        assert_eq!(outcomes, vec![ReplicationOutcome::Applied]);
+        let user = west_users.find(UserId(7)).unwrap().unwrap();
+        assert_eq!(user.name, "Paolo");
+        assert!(west_consumer.poll(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn err_concurrent_email_verifications_conflict() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let log = InMemoryReplicationLog::new();
+        let (east, west) = (Region::new("east"), Region::new("west"));
+        let (east_users, west_users) =
+            (InMemoryUserRepository::new(), InMemoryUserRepository::new());
+        let east_producer = ReplicationProducer::new(&east, &log, &east_users, &clock);
+        let west_producer = ReplicationProducer::new(&west, &log, &west_users, &clock);
+        let west_consumer = ReplicationConsumer::new(&west, &log, &west_users);
+
+        let registered = registered_in(&east_users, 7, "foo@ok.com", "acme");
+        east_producer.publish(&registered).unwrap();
+        west_consumer.poll(10).unwrap();
+        west_producer.publish(&verified("foo@ok.com")).unwrap();
+        east_producer.publish(&verified("bar@ok.com")).unwrap();
+        let outcomes = west_consumer.poll(10).unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![ReplicationOutcome::Conflict(EmailConflict {
+                user_id: UserId(7),
+                local_email: "foo@ok.com".to_string(),
+                remote_email: "bar@ok.com".to_string(),
+                remote_region: "east".to_string(),
+            })]
+        );
+    }
+
+    #[test]
+    fn ok_replica_keeps_tenant_and_takes_merged_profile() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let log = InMemoryReplicationLog::new();
+        let (east, west) = (Region::new("east"), Region::new("west"));
+        let (east_users, west_users) =
+            (InMemoryUserRepository::new(), InMemoryUserRepository::new());
+        let east_producer = ReplicationProducer::new(&east, &log, &east_users, &clock);
+        let west_consumer = ReplicationConsumer::new(&west, &log, &west_users);
+
+        east_producer
+            .publish(&registered_in(&east_users, 7, "foo@ok.com", "acme"))
+            .unwrap();
+        east_producer
+            .publish(&registered_in(&east_users, 8, "old@ok.com", "acme"))
+            .unwrap();
+        east_producer
+            .publish(&DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(7),
+                duplicate_id: UserId(8),
+                merged: Some(MergedProfile {
+                    email: "old@ok.com".to_string(),
+                    email_verified: true,
+                    middle_name: Some("Maria".to_string()),
+                    pronouns: None,
+                    avatar: None,
+                    tags: Vec::new(),
+                }),
+            }))
+            .unwrap();
+        let outcomes = west_consumer.poll(10).unwrap();
+
+        assert_eq!(outcomes, vec![ReplicationOutcome::Applied; 3]);
+        let primary = west_users.find(UserId(7)).unwrap().unwrap();
+        assert_eq!(primary.tenant_id, TenantId("acme".to_string()));
+        assert!(matches!(
+            &primary.email,
+            UserEmail::VerifiedEmail(VerifiedEmail(Email(email))) if email == "old@ok.com"
+        ));
+        assert_eq!(primary.middle_name, Some("Maria".to_string()));
+        let duplicate = west_users.find(UserId(8)).unwrap().unwrap();
+        assert_eq!(duplicate.merged_into, Some(UserId(7)));
+    }
+
+    #[test]
+    fn err_id_taken_by_local_user_conflicts() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let log = InMemoryReplicationLog::new();
+        let (east, west) = (Region::new("east"), Region::new("west"));
+        let (east_users, west_users) =
+            (InMemoryUserRepository::new(), InMemoryUserRepository::new());
+        let east_producer = ReplicationProducer::new(&east, &log, &east_users, &clock);
+        let west_producer = ReplicationProducer::new(&west, &log, &west_users, &clock);
+        let east_consumer = ReplicationConsumer::new(&east, &log, &east_users);
+        let west_consumer = ReplicationConsumer::new(&west, &log, &west_users);
+
+        east_producer
+            .publish(&registered_in(&east_users, 7, "foo@ok.com", "acme"))
+            .unwrap();
+        west_producer
+            .publish(&registered_in(&west_users, 7, "bar@ok.com", "acme"))
+            .unwrap();
+        east_producer.publish(&renamed("Paolo")).unwrap();
+        let outcomes = west_consumer.poll(10).unwrap();
+
+        let conflict = ReplicationOutcome::IdConflict(IdConflict {
+            user: ReplicaId {
+                home: "east".to_string(),
+                user_id: UserId(7),
+            },
+            remote_region: "east".to_string(),
+        });
+        assert_eq!(outcomes, vec![conflict.clone(), conflict]);
+        let local = west_users.find(UserId(7)).unwrap().unwrap();
+        assert_eq!(local.email.address().0, "bar@ok.com");
+        assert_eq!(local.name, "Luca");
+        assert!(matches!(
+            east_consumer.poll(10).unwrap()[..],
+            [ReplicationOutcome::IdConflict(_)]
+        ));
+    }
+
+    #[test]
+    fn ok_email_conflict_debug_masks_pii() {
+        let conflict = EmailConflict {
+            user_id: UserId(7),
+            local_email: "luca.rossi@ok.com".to_string(),
+            remote_email: "l.rossi@ok.com".to_string(),
+            remote_region: "east".to_string(),
+        };
+
+        assert_debug_masks(&conflict, &["luca.rossi", "l.rossi"]);
+    }
+}
@@ -0,0 +1,985 @@
+use crate::error::DomainError;
+use crate::hash::to_hex;
+use crate::health::HealthCheck;
+use crate::query::UserQuery;
+use crate::specification::Specification;
+use crate::tags::TagFilter;
+use crate::trace::instrument;
+use crate::{Email, TenantId, User, UserId};
+use anyhow::{Error, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+/// Position after the last user of a page. Pages are keyed on the user id
+/// rather than an offset, so users added or removed meanwhile do not shift
+/// later pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor(UserId);
+
+impl Cursor {
+    /// Reads a cursor rendered by its `Display` impl.
+    pub fn parse(value: &str) -> Result<Self> {
+        if value.len() != 16 {
+            return Err(Error::msg("Invalid cursor"));
+        }
+        u64::from_str_radix(value, 16)
+            .map(|id| Self(UserId(id)))
+            .map_err(|_| Error::msg("Invalid cursor"))
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&to_hex(&self.0 .0.to_be_bytes()))
+    }
+}
+
+/// Live users in id order, from [`UserRepository::list_users`].
+#[derive(Debug, Clone)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    /// Where the next page starts, if there is one.
+    pub next_cursor: Option<Cursor>,
+    pub has_more: bool,
+}
+
+impl UserPage {
+    /// Page of the first `limit` of `users`, which are sorted by id and
+    /// include at least one more user when there is a next page.
+    fn first(mut users: Vec<User>, limit: usize) -> Self {
+        let has_more = users.len() > limit;
+        users.truncate(limit);
+        Self {
+            next_cursor: users
+                .last()
+                .filter(|_| has_more)
+                .map(|user| Cursor(user.id)),
+            users,
+            has_more,
+        }
+    }
+}
+
+pub trait UserRepository {
+    /// Persists `user`, failing with [`DomainError::ConcurrencyConflict`] if
+    /// the stored version is no longer the one `user` was loaded at, and with
+    /// [`DomainError::EmailAlreadyRegistered`] if another live user owns its
+    /// email. Both checks are atomic with the write, so callers need not
+    /// look for a free email beforehand.
+    fn save(&self, user: User) -> Result<()>;
+
+    /// Persists `users` in order, as one write: each is checked as [`save`]
+    /// would check it against the store as the earlier ones leave it, and
+    /// if any check fails nothing is written.
+    ///
+    /// [`save`]: UserRepository::save
+    fn save_all(&self, users: Vec<User>) -> Result<()> {
+        self.save_all_with(users, &mut || Ok(()))
+    }
+
+    /// Like [`save_all`], running `alongside` as part of the same write:
+    /// after every check has passed and before anything is written, so if
+    /// it fails nothing is. A unit of work publishes its events here. It
+    /// runs while the store is locked, so it must not use the repository.
+    ///
+    /// [`save_all`]: UserRepository::save_all
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()>;
+
+    fn find(&self, id: UserId) -> Result<Option<User>>;
+
+    /// Live user of `tenant_id` owning `email`, compared in
+    /// [`Email::normalized`] form. Emails are unique per tenant only.
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        let normalized = email.normalized();
+        let mut owner = None;
+        self.for_each(&mut |user| {
+            if user.merged_into.is_none()
+                && &user.tenant_id == tenant_id
+                && user.email.address().normalized() == normalized
+            {
+                owner = Some(user.clone());
+            }
+            Ok(())
+        })?;
+        Ok(owner)
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>>;
+    /// Visits every stored user, tombstones included, without collecting them.
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()>;
+
+    /// Live users satisfying `specification`.
+    fn find_matching(&self, specification: &dyn Specification<User>) -> Result<Vec<User>> {
+        let mut users = Vec::new();
+        self.for_each(&mut |user| {
+            if user.merged_into.is_none() && specification.is_satisfied_by(user) {
+                users.push(user.clone());
+            }
+            Ok(())
+        })?;
+        Ok(users)
+    }
+
+    /// Users matching `query`, in its order.
+    fn query(&self, query: &UserQuery) -> Result<Vec<User>> {
+        let mut users = self.find_matching(query)?;
+        users.sort_by(|a, b| query.compare(a, b));
+        if let Some(limit) = query.limit {
+            users.truncate(limit);
+        }
+        Ok(users)
+    }
+
+    /// Up to `limit` live users after `cursor`, or from the first one.
+    fn list_users(&self, cursor: Option<&Cursor>, limit: usize) -> Result<UserPage> {
+        let mut users = Vec::new();
+        self.for_each(&mut |user| {
+            if user.merged_into.is_none() && cursor.is_none_or(|cursor| user.id > cursor.0) {
+                users.push(user.clone());
+            }
+            Ok(())
+        })?;
+        users.sort_by_key(|user| user.id);
+        Ok(UserPage::first(users, limit))
+    }
+}
+
+/// Lets a repository be shared, e.g. between a command bus and a health
+/// check.
+impl<R: UserRepository + ?Sized> UserRepository for Arc<R> {
+    fn save(&self, user: User) -> Result<()> {
+        (**self).save(user)
+    }
+
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        (**self).save_all_with(users, alongside)
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        (**self).find(id)
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        (**self).find_by_email(tenant_id, email)
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        (**self).find_by_tags(filter)
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        (**self).for_each(visit)
+    }
+
+    fn find_matching(&self, specification: &dyn Specification<User>) -> Result<Vec<User>> {
+        (**self).find_matching(specification)
+    }
+
+    fn query(&self, query: &UserQuery) -> Result<Vec<User>> {
+        (**self).query(query)
+    }
+
+    fn list_users(&self, cursor: Option<&Cursor>, limit: usize) -> Result<UserPage> {
+        (**self).list_users(cursor, limit)
+    }
+}
+
+/// Owner of each normalized email of one tenant, by domain and then local
+/// part, so each domain is stored once however many users share it.
+type EmailIndex = HashMap<Arc<str>, HashMap<Box<str>, UserId>>;
+
+#[derive(Debug, Default)]
+struct Users {
+    by_id: BTreeMap<UserId, User>,
+    /// Email of every live user, by tenant.
+    by_email: HashMap<TenantId, EmailIndex>,
+}
+
+/// Local part and domain of a normalized email.
+fn split_email(email: &str) -> (&str, &str) {
+    email.rsplit_once('@').unwrap_or((email, ""))
+}
+
+/// Tenant and normalized email of a live user, which no other live user
+/// may hold.
+pub(crate) type EmailKey = (TenantId, String);
+
+fn email_key(user: &User) -> Option<EmailKey> {
+    user.merged_into
+        .is_none()
+        .then(|| (user.tenant_id.clone(), user.email.address().normalized()))
+}
+
+/// Stored state a batch of saves is checked against.
+pub(crate) trait SavedState {
+    /// Version and [`email_key`] of the stored user `id`.
+    fn stored(&self, id: UserId) -> Result<Option<(u64, Option<EmailKey>)>>;
+    /// Live user holding the email `key`.
+    fn owner(&self, key: &EmailKey) -> Result<Option<UserId>>;
+}
+
+/// Any repository, read through its lookups.
+pub(crate) struct Lookups<'a, R: UserRepository>(pub &'a R);
+
+impl<R: UserRepository> SavedState for Lookups<'_, R> {
+    fn stored(&self, id: UserId) -> Result<Option<(u64, Option<EmailKey>)>> {
+        Ok(self
+            .0
+            .find(id)?
+            .map(|user| (user.version, email_key(&user))))
+    }
+
+    fn owner(&self, (tenant_id, email): &EmailKey) -> Result<Option<UserId>> {
+        Ok(self
+            .0
+            .find_by_email(tenant_id, &Email(email.clone()))?
+            .map(|user| user.id))
+    }
+}
+
+/// Checks that saving `users` in order against `state` breaks neither
+/// optimistic versioning nor email uniqueness, following the versions and
+/// emails each save leaves behind for the next.
+pub(crate) fn check_saves(state: &impl SavedState, users: &[User]) -> Result<()> {
+    // Version and email key of each user as the batch has left it so far.
+    let mut current: HashMap<UserId, (u64, Option<EmailKey>)> = HashMap::new();
+    let mut owners: HashMap<EmailKey, Option<UserId>> = HashMap::new();
+    for user in users {
+        let (actual_version, previous_key) = match current.remove(&user.id) {
+            Some(current) => current,
+            None => state.stored(user.id)?.unwrap_or((0, None)),
+        };
+        if actual_version != user.version {
+            return Err(DomainError::ConcurrencyConflict {
+                user_id: user.id,
+                expected_version: user.version,
+                actual_version,
+            }
+            .into());
+        }
+        if let Some(previous_key) = previous_key {
+            owners.insert(previous_key, None);
+        }
+        let key = email_key(user);
+        if let Some(key) = &key {
+            let owner = match owners.get(key) {
+                Some(owner) => *owner,
+                None => state.owner(key)?,
+            };
+            if owner.is_some_and(|owner| owner != user.id) {
+                return Err(DomainError::EmailAlreadyRegistered.into());
+            }
+            owners.insert(key.clone(), Some(user.id));
+        }
+        current.insert(user.id, (user.version + 1, key));
+    }
+    Ok(())
+}
+
+impl SavedState for Users {
+    fn stored(&self, id: UserId) -> Result<Option<(u64, Option<EmailKey>)>> {
+        Ok(self
+            .by_id
+            .get(&id)
+            .map(|user| (user.version, email_key(user))))
+    }
+
+    fn owner(&self, (tenant_id, email): &EmailKey) -> Result<Option<UserId>> {
+        let (local, domain) = split_email(email);
+        Ok(self
+            .by_email
+            .get(tenant_id)
+            .and_then(|domains| domains.get(domain))
+            .and_then(|locals| locals.get(local))
+            .copied())
+    }
+}
+
+impl Users {
+    fn insert(&mut self, user: User) {
+        if let Some(previous) = self.by_id.get(&user.id) {
+            let email = previous.email.address().normalized();
+            let (local, domain) = split_email(&email);
+            if let Some(domains) = self.by_email.get_mut(&previous.tenant_id) {
+                if let Some(locals) = domains.get_mut(domain) {
+                    if locals.get(local) == Some(&user.id) {
+                        locals.remove(local);
+                    }
+                    if locals.is_empty() {
+                        domains.remove(domain);
+                    }
+                }
+                if domains.is_empty() {
+                    self.by_email.remove(&previous.tenant_id);
+                }
+            }
+        }
+        if user.merged_into.is_none() {
+            let email = user.email.address().normalized();
+            let (local, domain) = split_email(&email);
+            let domains = self.by_email.entry(user.tenant_id.clone()).or_default();
+            // Not `entry`, which would allocate the domain for every user.
+            if !domains.contains_key(domain) {
+                domains.insert(Arc::from(domain), HashMap::new());
+            }
+            domains
+                .get_mut(domain)
+                .unwrap()
+                .insert(Box::from(local), user.id);
+        }
+        self.by_id.insert(user.id, user);
+    }
+}
+
+/// Keeps an index of emails next to the users, so lookups by email do not
+/// scan the store.
+#[derive(Debug, Default)]
+pub struct InMemoryUserRepository {
+    users: RwLock<Users>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl HealthCheck for InMemoryUserRepository {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.users.read() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::msg("User store is poisoned")),
+        }
+    }
+}
+
+impl UserRepository for InMemoryUserRepository {
+    fn save(&self, user: User) -> Result<()> {
+        self.save_all(vec![user])
+    }
+
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        let mut stored = self.users.write().unwrap();
+        // Checked under the same write lock as the inserts, the in-memory
+        // counterpart of a unique index on the email column.
+        check_saves(&*stored, &users)?;
+        alongside()?;
+        for mut user in users {
+            user.version += 1;
+            stored.insert(user);
+        }
+        Ok(())
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        Ok(self.users.read().unwrap().by_id.get(&id).cloned())
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        let users = self.users.read().unwrap();
+        Ok(users
+            .owner(&(tenant_id.clone(), email.normalized()))?
+            .and_then(|id| users.by_id.get(&id))
+            .cloned())
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        Ok(self
+            .users
+            .read()
+            .unwrap()
+            .by_id
+            .values()
+            .filter(|user| user.merged_into.is_none() && filter.matches(user))
+            .cloned()
+            .collect())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        for user in self.users.read().unwrap().by_id.values() {
+            visit(user)?;
+        }
+        Ok(())
+    }
+
+    fn list_users(&self, cursor: Option<&Cursor>, limit: usize) -> Result<UserPage> {
+        let start = cursor.map_or(Bound::Unbounded, |cursor| Bound::Excluded(cursor.0));
+        let users = self
+            .users
+            .read()
+            .unwrap()
+            .by_id
+            .range((start, Bound::Unbounded))
+            .map(|(_, user)| user)
+            .filter(|user| user.merged_into.is_none())
+            .take(limit.saturating_add(1))
+            .cloned()
+            .collect();
+        Ok(UserPage::first(users, limit))
+    }
+}
+
+/// Repository overlay that reads through to `base` but keeps every write to
+/// itself, so commands can run without touching the underlying store.
+pub struct StagingRepository<'a, R: UserRepository> {
+    base: &'a R,
+    staged: RwLock<Staged>,
+}
+
+#[derive(Default)]
+struct Staged {
+    /// Writes as they were saved, in order, to be replayed onto the base.
+    journal: Vec<User>,
+    /// Latest write of each user, at the version the base will give it.
+    latest: BTreeMap<UserId, User>,
+}
+
+/// The base as the staged writes leave it.
+struct StagedState<'s, R: UserRepository> {
+    base: &'s R,
+    staged: &'s Staged,
+}
+
+impl<R: UserRepository> SavedState for StagedState<'_, R> {
+    fn stored(&self, id: UserId) -> Result<Option<(u64, Option<EmailKey>)>> {
+        match self.staged.latest.get(&id) {
+            Some(user) => Ok(Some((user.version, email_key(user)))),
+            None => Lookups(self.base).stored(id),
+        }
+    }
+
+    fn owner(&self, key: &EmailKey) -> Result<Option<UserId>> {
+        let staged = self
+            .staged
+            .latest
+            .values()
+            .find(|user| email_key(user).as_ref() == Some(key));
+        if let Some(user) = staged {
+            return Ok(Some(user.id));
+        }
+        // A staged write of the base owner has moved it off `key`.
+        Ok(Lookups(self.base)
+            .owner(key)?
+            .filter(|owner| !self.staged.latest.contains_key(owner)))
+    }
+}
+
+impl<'a, R: UserRepository> StagingRepository<'a, R> {
+    pub fn new(base: &'a R) -> Self {
+        Self {
+            base,
+            staged: RwLock::new(Staged::default()),
+        }
+    }
+
+    /// Writes saved through this overlay, in the order they were saved, for
+    /// [`UserRepository::save_all`] on the base.
+    pub fn into_staged(self) -> Vec<User> {
+        self.staged.into_inner().unwrap().journal
+    }
+
+    /// Number of writes staged so far.
+    pub fn staged_len(&self) -> usize {
+        self.staged.read().unwrap().journal.len()
+    }
+
+    /// Discards every write after the first `len`.
+    pub fn truncate(&self, len: usize) {
+        let mut staged = self.staged.write().unwrap();
+        staged.journal.truncate(len);
+        let mut latest = BTreeMap::new();
+        for user in &staged.journal {
+            let mut user = user.clone();
+            user.version += 1;
+            latest.insert(user.id, user);
+        }
+        staged.latest = latest;
+    }
+
+    fn merged(&self) -> Result<BTreeMap<UserId, User>> {
+        let mut users = BTreeMap::new();
+        self.base.for_each(&mut |user| {
+            users.insert(user.id, user.clone());
+            Ok(())
+        })?;
+        users.extend(self.staged.read().unwrap().latest.clone());
+        Ok(users)
+    }
+}
+
+impl<R: UserRepository> UserRepository for StagingRepository<'_, R> {
+    fn save(&self, user: User) -> Result<()> {
+        self.save_all(vec![user])
+    }
+
+    /// Checked as the base would check them after the writes staged so far,
+    /// so a dry run fails where the commit would.
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        let mut staged = self.staged.write().unwrap();
+        let state = StagedState {
+            base: self.base,
+            staged: &staged,
+        };
+        check_saves(&state, &users)?;
+        alongside()?;
+        for user in users {
+            let mut latest = user.clone();
+            latest.version += 1;
+            staged.latest.insert(latest.id, latest);
+            staged.journal.push(user);
+        }
+        Ok(())
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        match self.staged.read().unwrap().latest.get(&id) {
+            Some(user) => Ok(Some(user.clone())),
+            None => self.base.find(id),
+        }
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        let staged = self.staged.read().unwrap();
+        let state = StagedState {
+            base: self.base,
+            staged: &staged,
+        };
+        match state.owner(&(tenant_id.clone(), email.normalized()))? {
+            Some(id) => match staged.latest.get(&id) {
+                Some(user) => Ok(Some(user.clone())),
+                None => self.base.find(id),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        Ok(self
+            .merged()?
+            .into_values()
+            .filter(|user| user.merged_into.is_none() && filter.matches(user))
+            .collect())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        for user in self.merged()?.values() {
+            visit(user)?;
+        }
+        Ok(())
+    }
+}
+
+/// View of `base` limited to one tenant. Users of other tenants cannot be
+/// found or overwritten, and every saved user is stamped with the tenant.
+pub struct TenantScopedRepository<'a, R: UserRepository> {
+    base: &'a R,
+    tenant_id: TenantId,
+}
+
+impl<'a, R: UserRepository> TenantScopedRepository<'a, R> {
+    pub fn new(base: &'a R, tenant_id: TenantId) -> Self {
+        Self { base, tenant_id }
+    }
+
+    pub fn tenant_id(&self) -> &TenantId {
+        &self.tenant_id
+    }
+}
+
+impl<R: UserRepository> TenantScopedRepository<'_, R> {
+    /// `user` stamped with the tenant, unless it belongs to another one.
+    fn stamped(&self, mut user: User) -> Result<User> {
+        if let Some(stored) = self.base.find(user.id)? {
+            if stored.tenant_id != self.tenant_id {
+                return Err(Error::msg("User belongs to another tenant"));
+            }
+        }
+        user.tenant_id = self.tenant_id.clone();
+        Ok(user)
+    }
+}
+
+impl<R: UserRepository> UserRepository for TenantScopedRepository<'_, R> {
+    fn save(&self, user: User) -> Result<()> {
+        self.base.save(self.stamped(user)?)
+    }
+
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        let users = users
+            .into_iter()
+            .map(|user| self.stamped(user))
+            .collect::<Result<_>>()?;
+        self.base.save_all_with(users, alongside)
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        Ok(self
+            .base
+            .find(id)?
+            .filter(|user| user.tenant_id == self.tenant_id))
+    }
+
+    /// Nothing is found in other tenants.
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        if *tenant_id != self.tenant_id {
+            return Ok(None);
+        }
+        self.base.find_by_email(tenant_id, email)
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        Ok(self
+            .base
+            .find_by_tags(filter)?
+            .into_iter()
+            .filter(|user| user.tenant_id == self.tenant_id)
+            .collect())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        self.base.for_each(&mut |user| {
+            if user.tenant_id == self.tenant_id {
+                visit(user)
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
+
+/// Runs every call to `base` in a `repository.*` span.
+pub struct TracedRepository<'a, R: UserRepository> {
+    base: &'a R,
+}
+
+impl<'a, R: UserRepository> TracedRepository<'a, R> {
+    pub fn new(base: &'a R) -> Self {
+        Self { base }
+    }
+}
+
+impl<R: UserRepository> UserRepository for TracedRepository<'_, R> {
+    fn save(&self, user: User) -> Result<()> {
+        let fields = vec![("user_id", user.id.0.to_string())];
+        instrument("repository.save", fields, |_| self.base.save(user))
+    }
+
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        let fields = vec![("count", users.len().to_string())];
+        instrument("repository.save_all", fields, |_| {
+            self.base.save_all_with(users, alongside)
+        })
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        let fields = vec![("user_id", id.0.to_string())];
+        instrument("repository.find", fields, |span| {
+            let user = self.base.find(id)?;
+            span.record("found", user.is_some());
+            Ok(user)
+        })
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        instrument("repository.find_by_email", Vec::new(), |span| {
+            let user = self.base.find_by_email(tenant_id, email)?;
+            span.record("found", user.is_some());
+            Ok(user)
+        })
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        instrument("repository.find_by_tags", Vec::new(), |span| {
+            let users = self.base.find_by_tags(filter)?;
+            span.record("count", users.len());
+            Ok(users)
+        })
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        instrument("repository.for_each", Vec::new(), |_| {
+            self.base.for_each(visit)
+        })
+    }
+
+    fn query(&self, query: &UserQuery) -> Result<Vec<User>> {
+        instrument("repository.query", Vec::new(), |span| {
+            let users = self.base.query(query)?;
+            span.record("count", users.len());
+            Ok(users)
+        })
+    }
+
+    fn list_users(&self, cursor: Option<&Cursor>, limit: usize) -> Result<UserPage> {
+        instrument("repository.list_users", Vec::new(), |span| {
+            let page = self.base.list_users(cursor, limit)?;
+            span.record("count", page.users.len());
+            Ok(page)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tags::{add_tag, check_tag};
+    use crate::test_support::{user_repository_contract, UserFixture};
+    use crate::{UnverifiedEmail, UserEmail};
+
+    fn user(email: &str, tags: &[&str]) -> User {
+        let mut user = UserFixture::new().with_email(email).build();
+        for tag in tags {
+            add_tag(&mut user, check_tag(tag.to_string()).unwrap());
+        }
+        user
+    }
+
+    #[test]
+    fn ok_in_memory_repository_meets_contract() {
+        user_repository_contract(InMemoryUserRepository::new);
+    }
+
+    #[test]
+    fn ok_save_and_find() {
+        let repository = InMemoryUserRepository::new();
+        let user = user("foo@ok.com", &[]);
+        let id = user.id;
+
+        repository.save(user).unwrap();
+
+        let found = repository.find(id).unwrap();
+        assert!(found.is_some());
+        assert_eq!(found.unwrap().id, id);
+        assert!(repository.find(UserId(0)).unwrap().is_none());
+    }
+
+    #[test]
+    fn ok_find_by_email_follows_changes() {
+        let repository = InMemoryUserRepository::new();
+        let user = user("Foo@ok.com", &[]);
+        let id = user.id;
+        repository.save(user).unwrap();
+        let lookup = |email: &str| {
+            repository
+                .find_by_email(&TenantId::default(), &Email(email.to_string()))
+                .unwrap()
+                .map(|user| user.id)
+        };
+
+        assert_eq!(lookup(" foo@OK.com"), Some(id));
+        let mut changed = repository.find(id).unwrap().unwrap();
+        changed.email =
+            UserEmail::UnverifiedEmail(UnverifiedEmail(Email("bar@ok.com".to_string())));
+        repository.save(changed).unwrap();
+        assert_eq!(lookup("foo@ok.com"), None);
+        assert_eq!(lookup("bar@ok.com"), Some(id));
+    }
+
+    #[test]
+    fn ok_emails_unique_per_tenant() {
+        let repository = InMemoryUserRepository::new();
+        let acme = TenantId("acme".to_string());
+        let mut first = user("foo@ok.com", &[]);
+        first.tenant_id = acme.clone();
+        let second = user("foo@ok.com", &[]);
+        let (first_id, second_id) = (first.id, second.id);
+
+        repository.save(first).unwrap();
+        repository.save(second).unwrap();
+
+        let lookup = |tenant_id: &TenantId| {
+            repository
+                .find_by_email(tenant_id, &Email("foo@ok.com".to_string()))
+                .unwrap()
+                .map(|user| user.id)
+        };
+        assert_eq!(lookup(&acme), Some(first_id));
+        assert_eq!(lookup(&TenantId::default()), Some(second_id));
+        let scoped = TenantScopedRepository::new(&repository, acme.clone());
+        let found = scoped
+            .find_by_email(&TenantId::default(), &Email("foo@ok.com".to_string()))
+            .unwrap();
+        assert!(found.is_none());
+        let error = scoped.save(user("FOO@ok.com", &[])).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::EmailAlreadyRegistered)
+        );
+    }
+
+    #[test]
+    fn ok_find_by_tags() {
+        let repository = InMemoryUserRepository::new();
+        let beta = user("beta@ok.com", &["beta-tester"]);
+        let beta_vip = user("vip@ok.com", &["beta-tester", "vip"]);
+        let plain = user("plain@ok.com", &[]);
+        let (beta_id, beta_vip_id) = (beta.id, beta_vip.id);
+        for user in [beta, beta_vip, plain] {
+            repository.save(user).unwrap();
+        }
+
+        let filter = TagFilter {
+            all_of: vec![check_tag("beta-tester".to_string()).unwrap()],
+            ..TagFilter::default()
+        };
+        let ids: Vec<_> = repository
+            .find_by_tags(&filter)
+            .unwrap()
+            .iter()
+            .map(|user| user.id)
+            .collect();
+        assert_eq!(ids, vec![beta_id, beta_vip_id]);
+
+        let filter = TagFilter {
+            all_of: vec![check_tag("beta-tester".to_string()).unwrap()],
+            none_of: vec![check_tag("vip".to_string()).unwrap()],
+            ..TagFilter::default()
+        };
+        let ids: Vec<_> = repository
+            .find_by_tags(&filter)
+            .unwrap()
+            .iter()
+            .map(|user| user.id)
+            .collect();
+        assert_eq!(ids, vec![beta_id]);
+    }
+
+    #[test]
+    fn ok_list_users_page_by_page() {
+        let repository = InMemoryUserRepository::new();
+        for email in ["a@ok.com", "b@ok.com", "c@ok.com"] {
+            repository.save(user(email, &[])).unwrap();
+        }
+        let mut ids = Vec::new();
+        repository
+            .for_each(&mut |user| {
+                ids.push(user.id);
+                Ok(())
+            })
+            .unwrap();
+
+        let first = repository.list_users(None, 2).unwrap();
+        let cursor = Cursor::parse(&first.next_cursor.unwrap().to_string()).unwrap();
+        let second = repository.list_users(Some(&cursor), 2).unwrap();
+        let scoped = TenantScopedRepository::new(&repository, TenantId::default())
+            .list_users(Some(&cursor), 2)
+            .unwrap();
+
+        let page_ids = |page: &UserPage| page.users.iter().map(|user| user.id).collect::<Vec<_>>();
+        assert_eq!(page_ids(&first), ids[..2]);
+        assert!(first.has_more);
+        assert_eq!(page_ids(&second), ids[2..]);
+        assert!(!second.has_more);
+        assert_eq!(second.next_cursor, None);
+        assert_eq!(page_ids(&scoped), ids[2..]);
+    }
+
+    #[test]
+    fn err_concurrent_saves_of_same_email() {
+        let repository = InMemoryUserRepository::new();
+
+        let results: Vec<Result<()>> = std::thread::scope(|scope| {
+            let saves: Vec<_> = ["foo@ok.com", "FOO@ok.com"]
+                .map(|email| scope.spawn(|| repository.save(user(email, &[]))))
+                .into_iter()
+                .collect();
+            saves.into_iter().map(|save| save.join().unwrap()).collect()
+        });
+
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        let error = results.into_iter().find_map(Result::err).unwrap();
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::EmailAlreadyRegistered)
+        );
+    }
+
+    #[test]
+    fn err_save_stale_version() {
+        let repository = InMemoryUserRepository::new();
+        let user = user("foo@ok.com", &[]);
+        let id = user.id;
+        repository.save(user).unwrap();
+
+        let mut first = repository.find(id).unwrap().unwrap();
+        let mut second = repository.find(id).unwrap().unwrap();
+        first.name = "Marco".to_string();
+        second.name = "Paolo".to_string();
+        repository.save(first).unwrap();
+        let result = repository.save(second);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::ConcurrencyConflict {
+                user_id: id,
+                expected_version: 1,
+                actual_version: 2
+            })
+        );
+        let stored = repository.find(id).unwrap().unwrap();
+        assert_eq!(stored.name, "Marco");
+        assert_eq!(stored.version, 2);
+    }
+
+    #[test]
+    fn err_staging_checks_writes_like_base() {
+        let repository = InMemoryUserRepository::new();
+        let owner = user("foo@ok.com", &[]);
+        let id = owner.id;
+        repository.save(owner).unwrap();
+        let staging = StagingRepository::new(&repository);
+
+        let error = staging.save(user("FOO@ok.com", &[])).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::EmailAlreadyRegistered)
+        );
+        let mut tombstone = staging.find(id).unwrap().unwrap();
+        let stale = tombstone.clone();
+        tombstone.merged_into = Some(UserId(u64::MAX));
+        staging.save(tombstone).unwrap();
+        assert!(staging.save(stale).is_err());
+        let taker = user("foo@ok.com", &[]);
+        let taker_id = taker.id;
+        staging.save(taker).unwrap();
+
+        let found = staging
+            .find_by_email(&TenantId::default(), &Email("foo@ok.com".to_string()))
+            .unwrap();
+        assert_eq!(found.map(|user| user.id), Some(taker_id));
+        let staged: Vec<UserId> = staging.into_staged().iter().map(|user| user.id).collect();
+        assert_eq!(staged, vec![id, taker_id]);
+    }
+}
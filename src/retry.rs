@@ -0,0 +1,273 @@
+use crate::error::DomainError;
+use crate::events::{DomainEvent, EventHandler};
+use anyhow::{Error, Result};
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Wrap an error in this to tell the retry policy that trying again cannot
+/// help (malformed address, rejected recipient, ...).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FatalError(pub String);
+
+impl Display for FatalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FatalError {}
+
+/// Errors are retryable unless marked [`FatalError`] or a domain rule
+/// violation; a concurrency conflict is worth another attempt.
+pub fn is_retryable(error: &Error) -> bool {
+    if error.downcast_ref::<FatalError>().is_some() {
+        return false;
+    }
+    match error.downcast_ref::<DomainError>() {
+        Some(DomainError::ConcurrencyConflict { .. }) | None => true,
+        Some(_) => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Including the first attempt.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Fraction of each delay that is randomized, between 0 and 1.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fails on a jitter outside `0.0..=1.0`, which would make delays
+    /// negative or longer than the backoff.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(Error::msg(format!(
+                "Retry jitter must be between 0 and 1, got {}",
+                self.jitter
+            )));
+        }
+        Ok(())
+    }
+
+    /// Delay before retrying after failed attempt number `attempt` (1-based).
+    /// `random` in `[0, 1)` picks where in the jitter band the delay falls.
+    pub fn delay_for(&self, attempt: u32, random: f64) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        exponential.mul_f64(1.0 - self.jitter * random)
+    }
+}
+
+/// Small xorshift generator; jitter only needs to spread retries apart.
+struct Jitter(Mutex<u64>);
+
+impl Jitter {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Self(Mutex::new(seed | 1))
+    }
+
+    fn next(&self) -> f64 {
+        let mut state = self.0.lock().unwrap();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Runs a handler again after transient failures, waiting with jittered
+/// exponential backoff between attempts. Fatal errors and the error of the
+/// last attempt are returned to the caller.
+pub struct RetryingHandler<H: EventHandler> {
+    inner: H,
+    policy: RetryPolicy,
+    jitter: Jitter,
+    sleep: Box<dyn Fn(Duration) + Send + Sync>,
+}
+
+impl<H: EventHandler> RetryingHandler<H> {
+    /// Fails if `policy` does not [validate](RetryPolicy::validate).
+    pub fn new(inner: H, policy: RetryPolicy) -> Result<Self> {
+        policy.validate()?;
+        Ok(Self {
+            inner,
+            policy,
+            jitter: Jitter::new(),
+            sleep: Box::new(std::thread::sleep),
+        })
+    }
+
+    /// Replaces how the handler waits between attempts.
+    pub fn with_sleep(mut self, sleep: impl Fn(Duration) + Send + Sync + 'static) -> Self {
+        self.sleep = Box::new(sleep);
+        self
+    }
+
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<H: EventHandler> EventHandler for RetryingHandler<H> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn handle(&self, event: &DomainEvent) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            match self.inner.handle(event) {
+                Ok(()) => return Ok(()),
+                Err(error) if attempt >= self.policy.max_attempts || !is_retryable(&error) => {
+                    return Err(error)
+                }
+                Err(_) => {
+                    (self.sleep)(self.policy.delay_for(attempt, self.jitter.next()));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::EmailVerified;
+    use crate::UserId;
+    use std::sync::Arc;
+
+    /// Fails with the scripted errors in order, then succeeds.
+    struct ScriptedHandler {
+        failures: Mutex<Vec<Error>>,
+        calls: Mutex<u32>,
+    }
+
+    impl EventHandler for ScriptedHandler {
+        fn name(&self) -> &str {
+            "welcome_email"
+        }
+
+        fn handle(&self, _event: &DomainEvent) -> Result<()> {
+            *self.calls.lock().unwrap() += 1;
+            let mut failures = self.failures.lock().unwrap();
+            if failures.is_empty() {
+                Ok(())
+            } else {
+                Err(failures.remove(0))
+            }
+        }
+    }
+
+    fn email_verified() -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    fn handler(failures: Vec<Error>) -> ScriptedHandler {
+        ScriptedHandler {
+            failures: Mutex::new(failures),
+            calls: Mutex::new(0),
+        }
+    }
+
+    #[test]
+    fn ok_retry_transient_failures_with_backoff() {
+        let delays = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&delays);
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let retrying = RetryingHandler::new(
+            handler(vec![Error::msg("SMTP timeout"), Error::msg("SMTP timeout")]),
+            policy,
+        )
+        .unwrap()
+        .with_sleep(move |delay| recorded.lock().unwrap().push(delay));
+
+        retrying.handle(&email_verified()).unwrap();
+
+        assert_eq!(*retrying.inner().calls.lock().unwrap(), 3);
+        assert_eq!(
+            *delays.lock().unwrap(),
+            vec![Duration::from_millis(200), Duration::from_millis(400)]
+        );
+    }
+
+    #[test]
+    fn err_fatal_error_is_not_retried() {
+        let retrying = RetryingHandler::new(
+            handler(vec![FatalError("Mailbox does not exist".to_string()).into()]),
+            RetryPolicy::default(),
+        )
+        .unwrap()
+        .with_sleep(|_| {});
+
+        let result = retrying.handle(&email_verified());
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Mailbox does not exist");
+        assert_eq!(*retrying.inner().calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn ok_jittered_delay_is_bounded() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.delay_for(3, 0.0), Duration::from_millis(800));
+        assert_eq!(policy.delay_for(3, 0.5), Duration::from_millis(600));
+        assert_eq!(policy.delay_for(20, 0.0), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn err_jitter_out_of_range() {
+        for jitter in [-0.1, 1.5, f64::NAN] {
+            let policy = RetryPolicy {
+                jitter,
+                ..RetryPolicy::default()
+            };
+
+            let result = RetryingHandler::new(handler(Vec::new()), policy);
+
+            assert!(result.is_err());
+        }
+        let error = RetryingHandler::new(
+            handler(Vec::new()),
+            RetryPolicy {
+                jitter: 1.5,
+                ..RetryPolicy::default()
+            },
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Retry jitter must be between 0 and 1, got 1.5"
+        );
+    }
+}
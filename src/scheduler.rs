@@ -0,0 +1,399 @@
+use crate::clock::Clock;
+use crate::date::civil_from_days;
+use crate::shutdown::{join_until, Drain};
+use anyhow::{Error, Result};
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Standard five-field cron expression (minute, hour, day of month, month,
+/// day of week), evaluated in UTC. Fields accept `*`, numbers, ranges
+/// (`1-5`), steps (`*/15`, `0-30/10`) and comma-separated lists. Sunday is 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days_of_month: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    days_of_week: BTreeSet<u32>,
+    /// When both day fields are restricted, cron matches either of them.
+    day_fields_restricted: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Option<BTreeSet<u32>> {
+    let mut values = BTreeSet::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        values.extend((start..=end).step_by(step));
+    }
+    Some(values)
+}
+
+pub fn parse_cron(expression: &str) -> Result<CronSchedule> {
+    let invalid = || Error::msg("Invalid cron expression");
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+        return Err(invalid());
+    };
+    Ok(CronSchedule {
+        minutes: parse_field(minutes, 0, 59).ok_or_else(invalid)?,
+        hours: parse_field(hours, 0, 23).ok_or_else(invalid)?,
+        days_of_month: parse_field(days_of_month, 1, 31).ok_or_else(invalid)?,
+        months: parse_field(months, 1, 12).ok_or_else(invalid)?,
+        days_of_week: parse_field(days_of_week, 0, 6).ok_or_else(invalid)?,
+        day_fields_restricted: days_of_month != "*" && days_of_week != "*",
+    })
+}
+
+impl CronSchedule {
+    pub fn matches(&self, at: SystemTime) -> bool {
+        let seconds = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let days = seconds.div_euclid(86_400);
+        let (_, month, day) = civil_from_days(days);
+        let minute = (seconds / 60 % 60) as u32;
+        let hour = (seconds / 3600 % 24) as u32;
+        let weekday = (days + 4).rem_euclid(7) as u32;
+
+        let day_of_month = self.days_of_month.contains(&day);
+        let day_of_week = self.days_of_week.contains(&weekday);
+        let day_matches = if self.day_fields_restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        };
+        self.minutes.contains(&minute)
+            && self.hours.contains(&hour)
+            && self.months.contains(&month)
+            && day_matches
+    }
+}
+
+/// Background work such as verification reminders or retention purges.
+pub trait Job: Send + Sync {
+    fn name(&self) -> &str;
+    fn run(&self) -> Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobOutcome {
+    Succeeded,
+    Failed(String),
+    /// The previous run was still going when the job fell due again.
+    SkippedOverlap,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobReport {
+    pub job: String,
+    pub due_at: SystemTime,
+    pub outcome: JobOutcome,
+}
+
+/// Reports kept by [`JobScheduler::reports`]; older ones are dropped.
+const MAX_REPORTS: usize = 1000;
+
+fn record(reports: &Mutex<VecDeque<JobReport>>, report: JobReport) {
+    let mut reports = reports.lock().unwrap();
+    if reports.len() == MAX_REPORTS {
+        reports.pop_front();
+    }
+    reports.push_back(report);
+}
+
+/// Clears a job's running flag when its run ends, even by panicking, so a
+/// failed run never blocks the job for good.
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+struct ScheduledJob {
+    schedule: CronSchedule,
+    job: Arc<dyn Job>,
+    running: Arc<AtomicBool>,
+    /// Minute (since the epoch) the job was last considered, so several
+    /// ticks within one minute start it only once.
+    last_due_minute: Option<u64>,
+}
+
+/// Runs registered jobs on their cron schedules, each on its own thread, and
+/// never starts a job while its previous run is still in progress.
+pub struct JobScheduler<C: Clock> {
+    clock: C,
+    jobs: Mutex<Vec<ScheduledJob>>,
+    reports: Arc<Mutex<VecDeque<JobReport>>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl<C: Clock> JobScheduler<C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            jobs: Mutex::new(Vec::new()),
+            reports: Arc::new(Mutex::new(VecDeque::new())),
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, cron: &str, job: Arc<dyn Job>) -> Result<()> {
+        self.jobs.lock().unwrap().push(ScheduledJob {
+            schedule: parse_cron(cron)?,
+            job,
+            running: Arc::new(AtomicBool::new(false)),
+            last_due_minute: None,
+        });
+        Ok(())
+    }
+
+    /// Starts every job due in the current minute and returns their names.
+    /// Threads of runs that have finished are joined first.
+    pub fn tick(&self) -> Vec<String> {
+        self.handles
+            .lock()
+            .unwrap()
+            .retain(|handle| !handle.is_finished());
+        let now = self.clock.now();
+        let minute = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 60;
+        let mut started = Vec::new();
+
+        for scheduled in self.jobs.lock().unwrap().iter_mut() {
+            if scheduled.last_due_minute == Some(minute) || !scheduled.schedule.matches(now) {
+                continue;
+            }
+            scheduled.last_due_minute = Some(minute);
+            let name = scheduled.job.name().to_string();
+
+            if scheduled.running.swap(true, Ordering::SeqCst) {
+                record(
+                    &self.reports,
+                    JobReport {
+                        job: name,
+                        due_at: now,
+                        outcome: JobOutcome::SkippedOverlap,
+                    },
+                );
+                continue;
+            }
+
+            let job = Arc::clone(&scheduled.job);
+            let running = RunningGuard(Arc::clone(&scheduled.running));
+            let reports = Arc::clone(&self.reports);
+            started.push(name.clone());
+            self.handles
+                .lock()
+                .unwrap()
+                .push(std::thread::spawn(move || {
+                    let _running = running;
+                    let outcome = match job.run() {
+                        Ok(()) => JobOutcome::Succeeded,
+                        Err(error) => JobOutcome::Failed(error.to_string()),
+                    };
+                    record(
+                        &reports,
+                        JobReport {
+                            job: name,
+                            due_at: now,
+                            outcome,
+                        },
+                    );
+                }));
+        }
+        started
+    }
+
+    /// Ticks every `interval` until `stop` is set, then waits for running
+    /// jobs to finish.
+    pub fn run(&self, interval: Duration, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            self.tick();
+            std::thread::sleep(interval);
+        }
+        self.wait_idle();
+    }
+
+    /// Blocks until every started job has finished.
+    pub fn wait_idle(&self) {
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            // A panicking job has nothing left to report.
+            let _ = handle.join();
+        }
+    }
+
+    /// The latest reports, oldest first.
+    pub fn reports(&self) -> Vec<JobReport> {
+        self.reports.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Waits for running jobs; no new ones start once the tick loop has stopped.
+impl<C: Clock> Drain for JobScheduler<C> {
+    fn name(&self) -> &str {
+        "scheduler"
+    }
+
+    fn drain(&self, deadline: Instant) -> Result<()> {
+        if join_until(&self.handles, deadline) {
+            return Ok(());
+        }
+        let running = self.handles.lock().unwrap().len();
+        Err(Error::msg(format!("{} jobs still running", running)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+
+    /// Blocks until released, then fails.
+    struct GatedJob {
+        release: AtomicBool,
+    }
+
+    impl Job for GatedJob {
+        fn name(&self) -> &str {
+            "purge"
+        }
+
+        fn run(&self) -> Result<()> {
+            while !self.release.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err(Error::msg("Storage unavailable"))
+        }
+    }
+
+    #[test]
+    fn ok_cron_matches() {
+        // 2024-03-04 was a Monday.
+        let monday_0930 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_544_600);
+
+        assert!(parse_cron("30 9 * * 1-5").unwrap().matches(monday_0930));
+        assert!(parse_cron("*/15 * 4 3 *").unwrap().matches(monday_0930));
+        assert!(parse_cron("30 9 1 * 1").unwrap().matches(monday_0930));
+        assert!(!parse_cron("30 9 * * 0,6").unwrap().matches(monday_0930));
+        assert!(!parse_cron("0 9 * * *").unwrap().matches(monday_0930));
+    }
+
+    #[test]
+    fn ok_overlap_is_skipped_and_errors_reported() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let scheduler = JobScheduler::new(&clock);
+        let job = Arc::new(GatedJob {
+            release: AtomicBool::new(false),
+        });
+        scheduler.register("* * * * *", job.clone()).unwrap();
+
+        assert_eq!(scheduler.tick(), vec!["purge"]);
+        assert!(scheduler.tick().is_empty());
+        clock.advance(Duration::from_secs(60));
+        assert!(scheduler.tick().is_empty());
+        job.release.store(true, Ordering::SeqCst);
+        scheduler.wait_idle();
+
+        let outcomes: Vec<JobOutcome> = scheduler
+            .reports()
+            .into_iter()
+            .map(|report| report.outcome)
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                JobOutcome::SkippedOverlap,
+                JobOutcome::Failed("Storage unavailable".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn err_invalid_cron() {
+        let result = parse_cron("61 * * * *");
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid cron expression");
+    }
+
+    struct PanickingJob;
+
+    impl Job for PanickingJob {
+        fn name(&self) -> &str {
+            "reminders"
+        }
+
+        fn run(&self) -> Result<()> {
+            panic!("reminder template missing")
+        }
+    }
+
+    #[test]
+    fn ok_panicked_run_does_not_block_the_job() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let scheduler = JobScheduler::new(&clock);
+        scheduler
+            .register("* * * * *", Arc::new(PanickingJob))
+            .unwrap();
+
+        assert_eq!(scheduler.tick(), vec!["reminders"]);
+        while !scheduler.handles.lock().unwrap()[0].is_finished() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        clock.advance(Duration::from_secs(60));
+
+        assert_eq!(scheduler.tick(), vec!["reminders"]);
+        assert_eq!(scheduler.handles.lock().unwrap().len(), 1);
+        scheduler.wait_idle();
+        assert!(scheduler.reports().is_empty());
+    }
+
+    #[test]
+    fn ok_report_history_is_capped() {
+        let reports = Mutex::new(VecDeque::new());
+        for minute in 0..=MAX_REPORTS as u64 {
+            record(
+                &reports,
+                JobReport {
+                    job: "purge".to_string(),
+                    due_at: SystemTime::UNIX_EPOCH + Duration::from_secs(minute * 60),
+                    outcome: JobOutcome::Succeeded,
+                },
+            );
+        }
+
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(reports.len(), MAX_REPORTS);
+        assert_eq!(
+            reports[0].due_at,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(60)
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! JSON Schemas (draft 2020-12) of the payloads exchanged with other systems:
+//! API requests and responses, and event payloads.
+
+use crate::json::JsonValue;
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+#[derive(Clone, Copy)]
+enum Type {
+    String,
+    NullableString,
+    Integer,
+    Boolean,
+    /// ISO 8601 calendar date.
+    Date,
+    /// String that must equal the given value, such as an event type.
+    Const(&'static str),
+    /// Array of strings.
+    Strings,
+    /// Closed object with the given title and properties, or null.
+    NullableObject(&'static str, &'static [(&'static str, Type)]),
+}
+
+fn type_schema(property: &Type) -> JsonValue {
+    let string = |value: &str| JsonValue::String(value.to_string());
+    let fields = match property {
+        Type::String => vec![("type", string("string"))],
+        Type::NullableString => vec![(
+            "type",
+            JsonValue::Array(vec![string("string"), string("null")]),
+        )],
+        Type::Integer => vec![("type", string("integer"))],
+        Type::Boolean => vec![("type", string("boolean"))],
+        Type::Date => vec![("type", string("string")), ("format", string("date"))],
+        Type::Const(value) => vec![("const", string(value))],
+        Type::Strings => vec![
+            ("type", string("array")),
+            ("items", type_schema(&Type::String)),
+        ],
+        Type::NullableObject(title, properties) => vec![(
+            "oneOf",
+            JsonValue::Array(vec![
+                object(title, properties),
+                JsonValue::Object(vec![("type".to_string(), string("null"))]),
+            ]),
+        )],
+    };
+    JsonValue::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+/// Closed object schema. Nullable properties may be omitted; all others are
+/// required.
+fn object(title: &str, properties: &[(&str, Type)]) -> JsonValue {
+    let required = properties
+        .iter()
+        .filter(|(_, property)| {
+            !matches!(property, Type::NullableString | Type::NullableObject(..))
+        })
+        .map(|(name, _)| JsonValue::String(name.to_string()))
+        .collect();
+    JsonValue::Object(vec![
+        ("title".to_string(), JsonValue::String(title.to_string())),
+        ("type".to_string(), JsonValue::String("object".to_string())),
+        (
+            "properties".to_string(),
+            JsonValue::Object(
+                properties
+                    .iter()
+                    .map(|(name, property)| (name.to_string(), type_schema(property)))
+                    .collect(),
+            ),
+        ),
+        ("required".to_string(), JsonValue::Array(required)),
+        ("additionalProperties".to_string(), JsonValue::Bool(false)),
+    ])
+}
+
+fn event(name: &'static str, properties: &[(&'static str, Type)]) -> JsonValue {
+    let mut all = vec![("type", Type::Const(name))];
+    all.extend_from_slice(properties);
+    object(name, &all)
+}
+
+/// Primary's profile after a merge, carried by `UsersMerged`.
+const MERGED_PROFILE: &[(&str, Type)] = &[
+    ("email", Type::String),
+    ("email_verified", Type::Boolean),
+    ("middle_name", Type::NullableString),
+    ("pronouns", Type::NullableString),
+    ("avatar", Type::NullableString),
+    ("tags", Type::Strings),
+];
+
+fn event_schemas() -> Vec<(&'static str, JsonValue)> {
+    vec![
+        (
+            "UserRegistered",
+            event(
+                "UserRegistered",
+                &[
+                    ("user_id", Type::Integer),
+                    ("email", Type::String),
+                    ("name", Type::String),
+                    ("middle_name", Type::NullableString),
+                    ("surname", Type::String),
+                    ("age", Type::Integer),
+                ],
+            ),
+        ),
+        (
+            "EmailVerified",
+            event(
+                "EmailVerified",
+                &[("user_id", Type::Integer), ("email", Type::String)],
+            ),
+        ),
+        (
+            "NameChanged",
+            event(
+                "NameChanged",
+                &[
+                    ("user_id", Type::Integer),
+                    ("name", Type::String),
+                    ("middle_name", Type::NullableString),
+                    ("surname", Type::String),
+                ],
+            ),
+        ),
+        (
+            "VerificationThrottled",
+            event(
+                "VerificationThrottled",
+                &[("user_id", Type::Integer), ("attempts", Type::Integer)],
+            ),
+        ),
+        (
+            "UsersMerged",
+            event(
+                "UsersMerged",
+                &[
+                    ("primary_id", Type::Integer),
+                    ("duplicate_id", Type::Integer),
+                    (
+                        "merged",
+                        Type::NullableObject("MergedProfile", MERGED_PROFILE),
+                    ),
+                ],
+            ),
+        ),
+    ]
+}
+
+/// Every schema by name, in a stable order.
+pub fn schemas() -> Vec<(&'static str, JsonValue)> {
+    let mut schemas = vec![
+        (
+            "v1.CreateUserRequest",
+            object(
+                "v1.CreateUserRequest",
+                &[
+                    ("email", Type::String),
+                    ("age", Type::Integer),
+                    ("name", Type::String),
+                    ("surname", Type::String),
+                    ("middle_name", Type::NullableString),
+                ],
+            ),
+        ),
+        (
+            "v1.UserResponse",
+            object(
+                "v1.UserResponse",
+                &[
+                    ("id", Type::Integer),
+                    ("email", Type::String),
+                    ("email_verified", Type::Boolean),
+                    ("age", Type::Integer),
+                    ("name", Type::String),
+                    ("surname", Type::String),
+                    ("middle_name", Type::NullableString),
+                ],
+            ),
+        ),
+        (
+            "v2.CreateUserRequest",
+            object(
+                "v2.CreateUserRequest",
+                &[
+                    ("email", Type::String),
+                    ("date_of_birth", Type::Date),
+                    ("given_name", Type::String),
+                    ("middle_name", Type::NullableString),
+                    ("family_name", Type::String),
+                ],
+            ),
+        ),
+        (
+            "v2.UserResponse",
+            object(
+                "v2.UserResponse",
+                &[
+                    ("id", Type::String),
+                    ("email", Type::String),
+                    ("email_verified", Type::Boolean),
+                    ("age", Type::Integer),
+                    ("given_name", Type::String),
+                    ("middle_name", Type::NullableString),
+                    ("family_name", Type::String),
+                ],
+            ),
+        ),
+    ];
+    let events = event_schemas();
+    schemas.push((
+        "DomainEvent",
+        JsonValue::Object(vec![
+            (
+                "title".to_string(),
+                JsonValue::String("DomainEvent".to_string()),
+            ),
+            (
+                "oneOf".to_string(),
+                JsonValue::Array(events.iter().map(|(_, schema)| schema.clone()).collect()),
+            ),
+        ]),
+    ));
+    schemas.extend(events);
+    schemas
+}
+
+/// Schema `name` as a standalone document.
+pub fn schema(name: &str) -> Option<JsonValue> {
+    let (_, schema) = schemas().into_iter().find(|(known, _)| *known == name)?;
+    let JsonValue::Object(fields) = schema else {
+        return None;
+    };
+    let mut document = vec![("$schema".to_string(), JsonValue::String(DRAFT.to_string()))];
+    document.extend(fields);
+    Some(JsonValue::Object(document))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{v1, v2};
+    use crate::events::{
+        DomainEvent, EmailVerified, NameChanged, UserRegistered, UsersMerged, VerificationThrottled,
+    };
+    use crate::{create_user, UserId};
+
+    fn keys(value: &JsonValue) -> Vec<String> {
+        match value {
+            JsonValue::Object(fields) => fields.iter().map(|(key, _)| key.clone()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Schemas are written by hand, so check they describe exactly the
+    /// fields the payloads carry.
+    #[test]
+    fn ok_schemas_match_payloads() {
+        let user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        let events = [
+            DomainEvent::UserRegistered(UserRegistered::from_user(&user)),
+            DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(1),
+                email: "foo@ok.com".to_string(),
+            }),
+            DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(1),
+                name: "Luca".to_string(),
+                middle_name: None,
+                surname: "Rossi".to_string(),
+            }),
+            DomainEvent::VerificationThrottled(VerificationThrottled {
+                user_id: UserId(1),
+                attempts: 5,
+            }),
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(2),
+                merged: None,
+            }),
+        ];
+        let mut payloads: Vec<(&str, JsonValue)> = events
+            .iter()
+            .map(|event| (event.name(), event.to_json()))
+            .collect();
+        payloads.push((
+            "v1.UserResponse",
+            v1::UserResponse::from_user(&user).to_json(),
+        ));
+        payloads.push((
+            "v2.UserResponse",
+            v2::UserResponse::from_user(&user).to_json(),
+        ));
+
+        for (name, payload) in payloads {
+            let schema = schema(name).unwrap();
+            assert_eq!(
+                keys(schema.get("properties").unwrap()),
+                keys(&payload),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            schema("DomainEvent")
+                .unwrap()
+                .get("$schema")
+                .and_then(JsonValue::as_str),
+            Some(DRAFT)
+        );
+        assert_eq!(schema("Unknown"), None);
+    }
+}
@@ -0,0 +1,457 @@
+//! Full-text search over full names and emails, maintained from events.
+
+use crate::event_store::StoredEvent;
+use crate::events::DomainEvent;
+use crate::format_fullname;
+use crate::json::{parse_json, JsonValue};
+use crate::pii::{mask_email, mask_name};
+use crate::projections::Projection;
+use crate::UserId;
+use anyhow::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Every query word is a word of the user.
+    Exact,
+    /// Every query word starts a word of the user, for search-as-you-type.
+    Prefix,
+    /// Every query word is within a few typos of a word of the user.
+    Fuzzy,
+    /// The query words appear consecutively in the full name.
+    Phrase,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchUsers {
+    pub text: String,
+    pub mode: MatchMode,
+    pub limit: usize,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct SearchHit {
+    pub user_id: UserId,
+    pub full_name: String,
+    pub email: String,
+    /// Higher for closer matches: an exact word scores more than a prefix,
+    /// which scores more than a fuzzy one.
+    pub score: u32,
+}
+
+impl Debug for SearchHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchHit")
+            .field("user_id", &self.user_id)
+            .field("full_name", &mask_name(&self.full_name))
+            .field("email", &mask_email(&self.email))
+            .field("score", &self.score)
+            .finish()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct Document {
+    full_name: String,
+    email: String,
+    name_words: Vec<String>,
+}
+
+impl Debug for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Document")
+            .field("full_name", &mask_name(&self.full_name))
+            .field("email", &mask_email(&self.email))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Document {
+    fn new(full_name: String, email: String) -> Self {
+        Self {
+            name_words: words(&full_name),
+            full_name,
+            email,
+        }
+    }
+
+    fn words(&self) -> impl Iterator<Item = String> + '_ {
+        self.name_words.iter().cloned().chain(words(&self.email))
+    }
+}
+
+/// Lowercased alphanumeric runs, so `luca.rossi@ok.com` gives `luca`,
+/// `rossi`, `ok` and `com`.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Typos tolerated by fuzzy matching: none for short words, where one edit
+/// already matches too much.
+fn max_typos(word: &str) -> usize {
+    match word.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    documents: HashMap<UserId, Document>,
+    /// Users by word, sorted so prefixes are a range scan.
+    postings: BTreeMap<String, BTreeSet<UserId>>,
+}
+
+impl Index {
+    fn insert(&mut self, user_id: UserId, document: Document) {
+        self.remove(user_id);
+        for word in document.words() {
+            self.postings.entry(word).or_default().insert(user_id);
+        }
+        self.documents.insert(user_id, document);
+    }
+
+    fn remove(&mut self, user_id: UserId) {
+        let Some(document) = self.documents.remove(&user_id) else {
+            return;
+        };
+        for word in document.words() {
+            if let Some(users) = self.postings.get_mut(&word) {
+                users.remove(&user_id);
+                if users.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Users matching `word`, with the score of their best match.
+    fn matches(&self, word: &str, mode: MatchMode) -> HashMap<UserId, u32> {
+        let mut scores = HashMap::new();
+        let mut add = |users: &BTreeSet<UserId>, score: u32| {
+            for user_id in users {
+                let best = scores.entry(*user_id).or_insert(0);
+                *best = score.max(*best);
+            }
+        };
+        match mode {
+            MatchMode::Exact | MatchMode::Phrase => {
+                if let Some(users) = self.postings.get(word) {
+                    add(users, 3);
+                }
+            }
+            MatchMode::Prefix => {
+                let range = self
+                    .postings
+                    .range::<str, _>((Bound::Included(word), Bound::Unbounded))
+                    .take_while(|(candidate, _)| candidate.starts_with(word));
+                for (candidate, users) in range {
+                    add(users, if candidate == word { 3 } else { 2 });
+                }
+            }
+            MatchMode::Fuzzy => {
+                let typos = max_typos(word);
+                for (candidate, users) in &self.postings {
+                    match edit_distance(word, candidate) {
+                        0 => add(users, 3),
+                        distance if distance <= typos => add(users, 1),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        scores
+    }
+}
+
+/// Search index over every live user. Kept in memory and written to disk
+/// with [`SearchIndex::persist`], so a restart resumes from the file and the
+/// projection checkpoint instead of replaying every event.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    index: RwLock<Index>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index written by [`SearchIndex::persist`], or an empty one if the file
+    /// does not exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let index = Self::new();
+        match File::open(path) {
+            Ok(file) => {
+                index.load(BufReader::new(file))?;
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        Ok(index)
+    }
+
+    /// Writes the index to `path`, replacing it only once fully written.
+    pub fn persist(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        let mut output = BufWriter::new(File::create(&partial)?);
+        self.dump(&mut output)?;
+        output.flush()?;
+        std::fs::rename(partial, path)?;
+        Ok(())
+    }
+
+    /// Writes every indexed user as newline-delimited JSON.
+    pub fn dump(&self, output: &mut impl Write) -> Result<()> {
+        let index = self.index.read().unwrap();
+        let mut user_ids: Vec<&UserId> = index.documents.keys().collect();
+        user_ids.sort();
+        for user_id in user_ids {
+            let document = &index.documents[user_id];
+            let line = JsonValue::Object(vec![
+                ("user_id".to_string(), JsonValue::Number(user_id.0 as i64)),
+                (
+                    "full_name".to_string(),
+                    JsonValue::String(document.full_name.clone()),
+                ),
+                (
+                    "email".to_string(),
+                    JsonValue::String(document.email.clone()),
+                ),
+            ]);
+            writeln!(output, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the users written by [`SearchIndex::dump`].
+    pub fn load(&self, input: impl BufRead) -> Result<usize> {
+        let invalid = || Error::msg("Invalid search index line");
+        let mut index = self.index.write().unwrap();
+        let mut loaded = 0;
+        for line in input.lines() {
+            let line = parse_json(&line?)?;
+            let user_id = line
+                .get("user_id")
+                .and_then(JsonValue::as_i64)
+                .ok_or_else(invalid)?;
+            let text = |field: &str| {
+                line.get(field)
+                    .and_then(JsonValue::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(invalid)
+            };
+            index.insert(
+                UserId(user_id as u64),
+                Document::new(text("full_name")?, text("email")?),
+            );
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Answers the `SearchUsers` query, best matches first.
+    pub fn search(&self, query: &SearchUsers) -> Vec<SearchHit> {
+        let query_words = words(&query.text);
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+        let index = self.index.read().unwrap();
+        let mut scores: Option<HashMap<UserId, u32>> = None;
+        for word in &query_words {
+            let matches = index.matches(word, query.mode);
+            scores = Some(match scores {
+                None => matches,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(user_id, score)| {
+                        matches.get(&user_id).map(|other| (user_id, score + other))
+                    })
+                    .collect(),
+            });
+        }
+        let mut hits: Vec<SearchHit> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(user_id, score)| (user_id, score, &index.documents[&user_id]))
+            .filter(|(_, _, document)| {
+                query.mode != MatchMode::Phrase
+                    || document
+                        .name_words
+                        .windows(query_words.len())
+                        .any(|window| window == query_words.as_slice())
+            })
+            .map(|(user_id, score, document)| SearchHit {
+                user_id,
+                full_name: document.full_name.clone(),
+                email: document.email.clone(),
+                score,
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then(a.user_id.cmp(&b.user_id)));
+        hits.truncate(query.limit);
+        hits
+    }
+}
+
+impl Projection for SearchIndex {
+    fn name(&self) -> &str {
+        "search_index"
+    }
+
+    fn apply(&self, stored: &StoredEvent) -> Result<()> {
+        let mut index = self.index.write().unwrap();
+        match &stored.event {
+            DomainEvent::UserRegistered(event) => {
+                let full_name =
+                    format_fullname(&event.name, event.middle_name.as_deref(), &event.surname);
+                index.insert(event.user_id, Document::new(full_name, event.email.clone()));
+            }
+            DomainEvent::NameChanged(event) => {
+                if let Some(document) = index.documents.get(&event.user_id) {
+                    let email = document.email.clone();
+                    let full_name =
+                        format_fullname(&event.name, event.middle_name.as_deref(), &event.surname);
+                    index.insert(event.user_id, Document::new(full_name, email));
+                }
+            }
+            DomainEvent::EmailVerified(_) | DomainEvent::VerificationThrottled(_) => {}
+            DomainEvent::UsersMerged(event) => index.remove(event.duplicate_id),
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        *self.index.write().unwrap() = Index::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{EventId, NameChanged, UserRegistered};
+    use crate::test_support::assert_debug_masks;
+    use std::time::SystemTime;
+
+    fn apply(index: &SearchIndex, event: DomainEvent) {
+        let stored = StoredEvent {
+            id: EventId(1),
+            recorded_at: SystemTime::UNIX_EPOCH,
+            event,
+        };
+        index.apply(&stored).unwrap();
+    }
+
+    fn registered(
+        user_id: u64,
+        name: &str,
+        middle_name: Option<&str>,
+        surname: &str,
+    ) -> DomainEvent {
+        DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(user_id),
+            email: format!("{}.{}@ok.com", name, surname).to_lowercase(),
+            name: name.to_string(),
+            middle_name: middle_name.map(str::to_string),
+            surname: surname.to_string(),
+            age: 22,
+        })
+    }
+
+    fn search(index: &SearchIndex, text: &str, mode: MatchMode) -> Vec<u64> {
+        let query = SearchUsers {
+            text: text.to_string(),
+            mode,
+            limit: 10,
+        };
+        index
+            .search(&query)
+            .iter()
+            .map(|hit| hit.user_id.0)
+            .collect()
+    }
+
+    #[test]
+    fn ok_search_modes() {
+        let index = SearchIndex::new();
+        apply(&index, registered(1, "Luca", Some("Maria"), "Rossi"));
+        apply(&index, registered(2, "Maria", None, "Rossini"));
+        apply(&index, registered(3, "Anna", None, "Bianchi"));
+        apply(
+            &index,
+            DomainEvent::NameChanged(NameChanged {
+                user_id: UserId(3),
+                name: "Anna".to_string(),
+                middle_name: None,
+                surname: "Verdi".to_string(),
+            }),
+        );
+
+        assert_eq!(search(&index, "rossi", MatchMode::Exact), vec![1]);
+        assert_eq!(search(&index, "ross", MatchMode::Prefix), vec![1, 2]);
+        assert_eq!(search(&index, "rosi luka", MatchMode::Fuzzy), vec![1]);
+        assert_eq!(search(&index, "maria rossi", MatchMode::Phrase), vec![1]);
+        assert_eq!(search(&index, "verdi", MatchMode::Exact), vec![3]);
+        assert_eq!(
+            search(&index, "anna bianchi", MatchMode::Phrase),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn ok_dump_and_load() {
+        let index = SearchIndex::new();
+        apply(&index, registered(1, "Luca", None, "Rossi"));
+        let mut file = Vec::new();
+        index.dump(&mut file).unwrap();
+
+        let reopened = SearchIndex::new();
+        let loaded = reopened.load(file.as_slice()).unwrap();
+
+        assert_eq!(loaded, 1);
+        assert_eq!(
+            search(&reopened, "luca.rossi@ok.com", MatchMode::Exact),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn ok_search_types_debug_masks_pii() {
+        let hit = SearchHit {
+            user_id: UserId(7),
+            full_name: "Luca Rossi".to_string(),
+            email: "luca.rossi@ok.com".to_string(),
+            score: 3,
+        };
+        let document = Document::new("Luca Rossi".to_string(), "luca.rossi@ok.com".to_string());
+
+        let pii = ["luca", "Luca", "Rossi", "rossi"];
+        assert_debug_masks(&hit, &pii);
+        assert_debug_masks(&document, &pii);
+    }
+}
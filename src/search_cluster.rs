@@ -0,0 +1,276 @@
+//! Mirrors users into an Elasticsearch or OpenSearch index.
+
+use crate::events::{DomainEvent, EventPublisher, UserRegistered};
+use crate::json::JsonValue;
+use crate::repository::UserRepository;
+use crate::{format_fullname, UserId};
+use anyhow::{Error, Result};
+
+/// Users sent per `_bulk` request when reindexing.
+const BULK_SIZE: usize = 500;
+
+/// Port to an HTTP client for the cluster. Returns the status code and body.
+pub trait SearchClusterTransport {
+    fn request(&self, method: &str, path: &str, body: Option<&str>) -> Result<(u16, String)>;
+}
+
+fn string(value: &str) -> JsonValue {
+    JsonValue::String(value.to_string())
+}
+
+fn optional_string(value: Option<&str>) -> JsonValue {
+    value.map_or(JsonValue::Null, string)
+}
+
+fn document(user: &UserRegistered, email_verified: bool) -> JsonValue {
+    let full_name = format_fullname(&user.name, user.middle_name.as_deref(), &user.surname);
+    JsonValue::Object(vec![
+        (
+            "user_id".to_string(),
+            JsonValue::Number(user.user_id.0 as i64),
+        ),
+        ("email".to_string(), string(&user.email)),
+        (
+            "email_verified".to_string(),
+            JsonValue::Bool(email_verified),
+        ),
+        ("full_name".to_string(), string(&full_name)),
+        ("name".to_string(), string(&user.name)),
+        (
+            "middle_name".to_string(),
+            optional_string(user.middle_name.as_deref()),
+        ),
+        ("surname".to_string(), string(&user.surname)),
+        ("age".to_string(), JsonValue::Number(user.age.into())),
+    ])
+}
+
+/// Applies each event to the index: registrations create a document, later
+/// changes update it and merges delete the duplicate. Run as the outbox
+/// relay's publisher, so events the cluster fails to take stay in the
+/// outbox and are retried on the next relay.
+pub struct SearchClusterIndexer<T: SearchClusterTransport> {
+    transport: T,
+    index: String,
+}
+
+impl<T: SearchClusterTransport> SearchClusterIndexer<T> {
+    pub fn new(transport: T, index: &str) -> Self {
+        Self {
+            transport,
+            index: index.to_string(),
+        }
+    }
+
+    fn send(&self, method: &str, path: &str, body: Option<&JsonValue>) -> Result<u16> {
+        let body = body.map(JsonValue::to_string);
+        let (status, _) = self.transport.request(method, path, body.as_deref())?;
+        match status {
+            200..=299 | 404 => Ok(status),
+            status => Err(Error::msg(format!(
+                "Search cluster responded with {}",
+                status
+            ))),
+        }
+    }
+
+    fn update(&self, user_id: UserId, fields: Vec<(&str, JsonValue)>) -> Result<()> {
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        let body = JsonValue::Object(vec![("doc".to_string(), JsonValue::Object(fields))]);
+        let path = format!("/{}/_update/{}", self.index, user_id);
+        // A missing document was never indexed, e.g. it predates the
+        // indexer; the next reindex creates it whole.
+        self.send("POST", &path, Some(&body)).map(|_| ())
+    }
+
+    /// Sends every live user of `repository` in `_bulk` batches, for a new
+    /// index or after the mapping changed. Returns the number indexed.
+    pub fn reindex_all(&self, repository: &impl UserRepository) -> Result<usize> {
+        let mut batch = String::new();
+        let mut batched = 0;
+        let mut indexed = 0;
+        repository.for_each(&mut |user| {
+            if user.merged_into.is_some() {
+                return Ok(());
+            }
+            let action = format!(
+                r#"{{"index":{{"_index":"{}","_id":"{}"}}}}"#,
+                self.index, user.id
+            );
+            let verified = user.is_email_verified();
+            let source = document(&UserRegistered::from_user(user), verified);
+            batch.push_str(&format!("{}\n{}\n", action, source));
+            batched += 1;
+            if batched == BULK_SIZE {
+                self.bulk(&batch)?;
+                indexed += batched;
+                batch.clear();
+                batched = 0;
+            }
+            Ok(())
+        })?;
+        if batched > 0 {
+            self.bulk(&batch)?;
+            indexed += batched;
+        }
+        Ok(indexed)
+    }
+
+    fn bulk(&self, body: &str) -> Result<()> {
+        let (status, response) = self.transport.request("POST", "/_bulk", Some(body))?;
+        // `_bulk` answers 200 even when single items fail.
+        if !(200..=299).contains(&status) || response.contains(r#""errors":true"#) {
+            return Err(Error::msg("Search cluster rejected the bulk request"));
+        }
+        Ok(())
+    }
+}
+
+impl<T: SearchClusterTransport> EventPublisher for SearchClusterIndexer<T> {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        match event {
+            DomainEvent::UserRegistered(event) => {
+                let path = format!("/{}/_doc/{}", self.index, event.user_id);
+                self.send("PUT", &path, Some(&document(event, false)))
+                    .map(|_| ())
+            }
+            DomainEvent::EmailVerified(event) => self.update(
+                event.user_id,
+                vec![
+                    ("email", string(&event.email)),
+                    ("email_verified", JsonValue::Bool(true)),
+                ],
+            ),
+            DomainEvent::NameChanged(event) => self.update(
+                event.user_id,
+                vec![
+                    (
+                        "full_name",
+                        string(&format_fullname(
+                            &event.name,
+                            event.middle_name.as_deref(),
+                            &event.surname,
+                        )),
+                    ),
+                    ("name", string(&event.name)),
+                    ("middle_name", optional_string(event.middle_name.as_deref())),
+                    ("surname", string(&event.surname)),
+                ],
+            ),
+            DomainEvent::VerificationThrottled(_) => Ok(()),
+            DomainEvent::UsersMerged(event) => {
+                let path = format!("/{}/_doc/{}", self.index, event.duplicate_id);
+                self.send("DELETE", &path, None).map(|_| ())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::create_user;
+    use crate::events::{EmailVerified, UsersMerged};
+    use crate::outbox::{InMemoryOutbox, Outbox, OutboxRelay};
+    use crate::repository::InMemoryUserRepository;
+    use std::sync::Mutex;
+
+    /// Cluster recording the requests it takes, answering 503 while down.
+    #[derive(Default)]
+    struct FakeCluster {
+        requests: Mutex<Vec<(String, String)>>,
+        down: Mutex<bool>,
+    }
+
+    impl SearchClusterTransport for &FakeCluster {
+        fn request(&self, method: &str, path: &str, _body: Option<&str>) -> Result<(u16, String)> {
+            if *self.down.lock().unwrap() {
+                return Ok((503, String::new()));
+            }
+            self.requests
+                .lock()
+                .unwrap()
+                .push((method.to_string(), path.to_string()));
+            Ok((200, r#"{"errors":false}"#.to_string()))
+        }
+    }
+
+    #[test]
+    fn ok_events_mirrored_after_outage() {
+        let cluster = FakeCluster::default();
+        let indexer = SearchClusterIndexer::new(&cluster, "users");
+        let outbox = InMemoryOutbox::new();
+        let user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        outbox
+            .append(DomainEvent::UserRegistered(UserRegistered::from_user(
+                &user,
+            )))
+            .unwrap();
+        outbox
+            .append(DomainEvent::EmailVerified(EmailVerified {
+                user_id: user.id,
+                email: "foo@ok.com".to_string(),
+            }))
+            .unwrap();
+        outbox
+            .append(DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(2),
+                merged: None,
+            }))
+            .unwrap();
+
+        *cluster.down.lock().unwrap() = true;
+        assert!(OutboxRelay::new(&outbox, &indexer).relay().is_err());
+        *cluster.down.lock().unwrap() = false;
+        OutboxRelay::new(&outbox, &indexer).relay().unwrap();
+
+        let id = user.id.0;
+        assert_eq!(
+            *cluster.requests.lock().unwrap(),
+            vec![
+                ("PUT".to_string(), format!("/users/_doc/{}", id)),
+                ("POST".to_string(), format!("/users/_update/{}", id)),
+                ("DELETE".to_string(), "/users/_doc/2".to_string()),
+            ]
+        );
+        assert!(outbox.pending(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ok_reindex_all_live_users() {
+        let cluster = FakeCluster::default();
+        let repository = InMemoryUserRepository::new();
+        for email in ["a@ok.com", "b@ok.com"] {
+            let user = create_user(
+                email.to_string(),
+                22,
+                "Luca".to_string(),
+                "Rossi".to_string(),
+                None,
+            )
+            .unwrap();
+            repository.save(user).unwrap();
+        }
+
+        let indexed = SearchClusterIndexer::new(&cluster, "users")
+            .reindex_all(&repository)
+            .unwrap();
+
+        assert_eq!(indexed, 2);
+        assert_eq!(
+            *cluster.requests.lock().unwrap(),
+            vec![("POST".to_string(), "/_bulk".to_string())]
+        );
+    }
+}
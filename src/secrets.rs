@@ -0,0 +1,402 @@
+use crate::clock::Clock;
+use crate::json::{parse_json, JsonValue};
+use anyhow::{Error, Result};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+pub const SMTP_PASSWORD: &str = "smtp_password";
+pub const JWT_SIGNING_KEY: &str = "jwt_signing_key";
+pub const ENCRYPTION_KEY: &str = "encryption_key";
+
+/// Secret value that stays out of logs and debug output, and is wiped from
+/// memory when dropped.
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Overwrites `bytes` with zeros in a way the compiler cannot elide as a
+/// dead store, like the `zeroize` crate does.
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Empties `bytes` and overwrites its whole allocation, including capacity
+/// past the length where earlier contents may linger.
+fn wipe_allocation(bytes: &mut Vec<u8>) {
+    bytes.clear();
+    for byte in bytes.spare_capacity_mut() {
+        // SAFETY: `byte` is within the allocation and properly aligned.
+        unsafe { std::ptr::write_volatile(byte.as_mut_ptr(), 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+fn wipe_string(value: &mut String) {
+    // SAFETY: the string is left empty, which is valid UTF-8.
+    wipe_allocation(unsafe { value.as_mut_vec() });
+}
+
+/// Wipes every string of `value`, object keys included.
+fn wipe_json(value: &mut JsonValue) {
+    match value {
+        JsonValue::String(value) => wipe_string(value),
+        JsonValue::Array(values) => values.iter_mut().for_each(wipe_json),
+        JsonValue::Object(fields) => {
+            for (key, value) in fields {
+                wipe_string(key);
+                wipe_json(value);
+            }
+        }
+        JsonValue::Null | JsonValue::Bool(_) | JsonValue::Number(_) => {}
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe_string(&mut self.0);
+    }
+}
+
+/// Bytes of a secret on their way to becoming a [`Secret`], wiped if they
+/// never get there.
+struct SecretBuffer(Vec<u8>);
+
+impl SecretBuffer {
+    /// Becomes a secret without copying the bytes.
+    fn into_secret(mut self) -> Result<Secret> {
+        match String::from_utf8(std::mem::take(&mut self.0)) {
+            Ok(value) => Ok(Secret(value)),
+            Err(error) => {
+                self.0 = error.into_bytes();
+                Err(Error::msg("Secret is not valid UTF-8"))
+            }
+        }
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        wipe_allocation(&mut self.0);
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+/// Port to wherever secrets are kept. Callers ask for a secret each time they
+/// use it instead of holding on to it, so rotated key material is picked up
+/// without a restart.
+pub trait SecretsProvider {
+    fn get(&self, name: &str) -> Result<Secret>;
+}
+
+fn not_found(name: &str) -> Error {
+    Error::msg(format!("Secret {} not found", name))
+}
+
+/// Reads `smtp_password` from `APP_SECRET_SMTP_PASSWORD`, and so on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EnvSecretsProvider;
+
+impl SecretsProvider for EnvSecretsProvider {
+    fn get(&self, name: &str) -> Result<Secret> {
+        let value = std::env::var_os(format!("APP_SECRET_{}", name.to_uppercase()))
+            .ok_or_else(|| not_found(name))?;
+        SecretBuffer(value.into_encoded_bytes()).into_secret()
+    }
+}
+
+/// One file per secret in a directory, as mounted by Docker or Kubernetes.
+#[derive(Debug, Clone)]
+pub struct FileSecretsProvider {
+    directory: PathBuf,
+}
+
+impl FileSecretsProvider {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+impl SecretsProvider for FileSecretsProvider {
+    fn get(&self, name: &str) -> Result<Secret> {
+        if name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(not_found(name));
+        }
+        let mut file = File::open(self.directory.join(name)).map_err(|_| not_found(name))?;
+        // Sized up front, so reading never moves the secret to a larger
+        // allocation and leaves a copy behind.
+        let mut buffer = SecretBuffer(vec![0; file.metadata()?.len() as usize]);
+        file.read_exact(&mut buffer.0)?;
+        // Trimmed in place: a trimmed copy would leave the original unwiped.
+        let len = buffer.0.len()
+            - buffer
+                .0
+                .iter()
+                .rev()
+                .take_while(|byte| matches!(byte, b'\r' | b'\n'))
+                .count();
+        wipe(&mut buffer.0[len..]);
+        buffer.0.truncate(len);
+        buffer.into_secret()
+    }
+}
+
+/// Port to an HTTP client for Vault. Returns the status code and body.
+pub trait VaultTransport {
+    fn get(&self, url: &str, headers: &[(&str, String)]) -> Result<(u16, String)>;
+}
+
+/// Reads secrets from one path of a Vault KV version 2 engine, each secret
+/// being a key of that path.
+pub struct VaultSecretsProvider<T: VaultTransport> {
+    transport: T,
+    address: String,
+    token: Secret,
+    mount: String,
+    path: String,
+}
+
+impl<T: VaultTransport> VaultSecretsProvider<T> {
+    pub fn new(transport: T, address: &str, token: Secret, mount: &str, path: &str) -> Self {
+        Self {
+            transport,
+            address: address.trim_end_matches('/').to_string(),
+            token,
+            mount: mount.to_string(),
+            path: path.to_string(),
+        }
+    }
+}
+
+impl<T: VaultTransport> SecretsProvider for VaultSecretsProvider<T> {
+    fn get(&self, name: &str) -> Result<Secret> {
+        let url = format!("{}/v1/{}/data/{}", self.address, self.mount, self.path);
+        let headers = [("X-Vault-Token", self.token.expose().to_string())];
+        let response = self.transport.get(&url, &headers);
+        // The header's copy of the token is wiped like the token itself.
+        let [(_, token)] = headers;
+        drop(Secret(token));
+        let (status, body) = response?;
+        let body = Secret(body);
+        match status {
+            200 => {}
+            404 => return Err(not_found(name)),
+            status => return Err(Error::msg(format!("Vault responded with {}", status))),
+        }
+        let mut json = parse_json(body.expose())?;
+        // Moved out rather than copied, then every other secret of the path
+        // is wiped along with the rest of the tree.
+        let secret = field_mut(&mut json, "data")
+            .and_then(|data| field_mut(data, "data"))
+            .and_then(|secrets| field_mut(secrets, name))
+            .and_then(|value| match value {
+                JsonValue::String(value) => Some(Secret(std::mem::take(value))),
+                _ => None,
+            });
+        wipe_json(&mut json);
+        secret.ok_or_else(|| not_found(name))
+    }
+}
+
+fn field_mut<'a>(value: &'a mut JsonValue, key: &str) -> Option<&'a mut JsonValue> {
+    match value {
+        JsonValue::Object(fields) => fields
+            .iter_mut()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value),
+        _ => None,
+    }
+}
+
+/// Keeps fetched secrets for `ttl`, so rotation shows up within that delay
+/// without hitting the provider on every use.
+pub struct CachingSecretsProvider<P: SecretsProvider, C: Clock> {
+    inner: P,
+    clock: C,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Secret, SystemTime)>>,
+}
+
+impl<P: SecretsProvider, C: Clock> CachingSecretsProvider<P, C> {
+    pub fn new(inner: P, ttl: Duration, clock: C) -> Self {
+        Self {
+            inner,
+            clock,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: SecretsProvider, C: Clock> SecretsProvider for CachingSecretsProvider<P, C> {
+    fn get(&self, name: &str) -> Result<Secret> {
+        let now = self.clock.now();
+        if let Some((secret, fetched_at)) = self.cache.lock().unwrap().get(name) {
+            if *fetched_at + self.ttl > now {
+                return Ok(secret.clone());
+            }
+        }
+        let secret = self.inner.get(name)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (secret.clone(), now));
+        Ok(secret)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+
+    /// Vault whose stored key can be rotated by the test.
+    struct FakeVault {
+        key: Mutex<String>,
+    }
+
+    impl VaultTransport for &FakeVault {
+        fn get(&self, url: &str, headers: &[(&str, String)]) -> Result<(u16, String)> {
+            assert_eq!(url, "https://vault:8200/v1/secret/data/users");
+            assert_eq!(headers, [("X-Vault-Token", "s.token".to_string())]);
+            Ok((
+                200,
+                format!(
+                    r#"{{"data":{{"data":{{"jwt_signing_key":"{}"}},"metadata":{{"version":1}}}}}}"#,
+                    self.key.lock().unwrap()
+                ),
+            ))
+        }
+    }
+
+    #[test]
+    fn ok_rotated_vault_secret_is_picked_up_after_ttl() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let vault = FakeVault {
+            key: Mutex::new("first".to_string()),
+        };
+        let provider = CachingSecretsProvider::new(
+            VaultSecretsProvider::new(
+                &vault,
+                "https://vault:8200/",
+                Secret::new("s.token".to_string()),
+                "secret",
+                "users",
+            ),
+            Duration::from_secs(60),
+            &clock,
+        );
+
+        assert_eq!(provider.get(JWT_SIGNING_KEY).unwrap().expose(), "first");
+        *vault.key.lock().unwrap() = "second".to_string();
+        assert_eq!(provider.get(JWT_SIGNING_KEY).unwrap().expose(), "first");
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(provider.get(JWT_SIGNING_KEY).unwrap().expose(), "second");
+    }
+
+    #[test]
+    fn err_missing_secret() {
+        let result = VaultSecretsProvider::new(
+            &FakeVault {
+                key: Mutex::new("first".to_string()),
+            },
+            "https://vault:8200",
+            Secret::new("s.token".to_string()),
+            "secret",
+            "users",
+        )
+        .get(SMTP_PASSWORD);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Secret smtp_password not found");
+        assert_eq!(
+            format!("{:?}", Secret::new("hunter2".to_string())),
+            "Secret(***)"
+        );
+    }
+
+    #[test]
+    fn ok_wipe_zeroes_buffer() {
+        let mut buffer = *b"hunter2";
+
+        wipe(&mut buffer);
+
+        assert_eq!(buffer, [0; 7]);
+    }
+
+    #[test]
+    fn ok_wipe_allocation_zeroes_capacity() {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(b"hunter2hunter2");
+        bytes.truncate(7);
+        let capacity = bytes.capacity();
+
+        wipe_allocation(&mut bytes);
+
+        assert!(bytes.is_empty());
+        // SAFETY: the whole allocation was just initialised with zeros.
+        unsafe { bytes.set_len(capacity) };
+        assert!(bytes.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn ok_wipe_json_empties_every_string() {
+        let mut json = parse_json(
+            r#"{"data":{"data":{"smtp_password":"hunter2","keys":["k1"]},"version":1}}"#,
+        )
+        .unwrap();
+
+        wipe_json(&mut json);
+
+        assert_eq!(json.to_string(), r#"{"":{"":{"":"","":[""]},"":1}}"#);
+    }
+
+    #[test]
+    fn err_file_secret_not_utf8() {
+        let directory = std::env::temp_dir().join(format!("secrets-utf8-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join(SMTP_PASSWORD), [0xff, 0xfe]).unwrap();
+
+        let secret = FileSecretsProvider::new(&directory).get(SMTP_PASSWORD);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(secret.unwrap_err().to_string(), "Secret is not valid UTF-8");
+    }
+
+    #[test]
+    fn ok_file_secret_trimmed() {
+        let directory = std::env::temp_dir().join(format!("secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join(SMTP_PASSWORD), "hunter2\r\n").unwrap();
+
+        let secret = FileSecretsProvider::new(&directory).get(SMTP_PASSWORD);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(secret.unwrap().expose(), "hunter2");
+    }
+}
@@ -0,0 +1,203 @@
+//! Horizontal partitioning of users across several repositories.
+
+use crate::error::DomainError;
+use crate::hash::sha256;
+use crate::repository::{check_saves, Lookups, UserRepository};
+use crate::tags::TagFilter;
+use crate::{Email, TenantId, User, UserId};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Points per shard on the ring; more points spread users more evenly.
+const VIRTUAL_NODES: usize = 64;
+
+fn ring_position(key: &[u8]) -> u64 {
+    u64::from_be_bytes(sha256(key)[..8].try_into().unwrap())
+}
+
+/// Routes each user to one of its shards by consistent hashing of the id,
+/// so adding a shard moves only about a share of the users to it.
+///
+/// Lookups by id touch one shard; other lookups fan out to every shard. Lists
+/// and searches across shards are meant to be served by the projections
+/// (`SearchIndex`, `Typeahead`, `UserStats`) fed by the events instead.
+pub struct ShardRouter<R: UserRepository> {
+    shards: Vec<R>,
+    /// Ring position to shard index.
+    ring: BTreeMap<u64, usize>,
+}
+
+impl<R: UserRepository> ShardRouter<R> {
+    pub fn new(shards: Vec<R>) -> Self {
+        let mut ring = BTreeMap::new();
+        for shard in 0..shards.len() {
+            for node in 0..VIRTUAL_NODES {
+                ring.insert(
+                    ring_position(format!("{}-{}", shard, node).as_bytes()),
+                    shard,
+                );
+            }
+        }
+        Self { shards, ring }
+    }
+
+    /// Index of the shard owning `id`.
+    pub fn shard_for(&self, id: UserId) -> usize {
+        let position = ring_position(&id.0.to_be_bytes());
+        self.ring
+            .range(position..)
+            .chain(self.ring.iter())
+            .map(|(_, shard)| *shard)
+            .next()
+            .expect("a shard router needs at least one shard")
+    }
+
+    fn shard(&self, id: UserId) -> &R {
+        &self.shards[self.shard_for(id)]
+    }
+}
+
+impl<R: UserRepository> UserRepository for ShardRouter<R> {
+    /// The owning shard checks the email atomically. Other shards are
+    /// checked beforehand, which narrows but does not close the window for
+    /// two concurrent saves of one email on different shards.
+    fn save(&self, user: User) -> Result<()> {
+        if user.merged_into.is_none() {
+            if let Some(owner) = self.find_by_email(&user.tenant_id, user.email.address())? {
+                if owner.id != user.id {
+                    return Err(DomainError::EmailAlreadyRegistered.into());
+                }
+            }
+        }
+        self.shard(user.id).save(user)
+    }
+
+    /// Checked across every shard first, then `alongside` runs, then the
+    /// users are written shard by shard. Each shard's share is written
+    /// atomically, but a shard failing after another one has written, e.g.
+    /// on a concurrent save, splits the batch.
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        check_saves(&Lookups(self), &users)?;
+        alongside()?;
+        let mut by_shard: BTreeMap<usize, Vec<User>> = BTreeMap::new();
+        for user in users {
+            by_shard
+                .entry(self.shard_for(user.id))
+                .or_default()
+                .push(user);
+        }
+        for (shard, users) in by_shard {
+            self.shards[shard].save_all(users)?;
+        }
+        Ok(())
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        self.shard(id).find(id)
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        for shard in &self.shards {
+            if let Some(user) = shard.find_by_email(tenant_id, email)? {
+                return Ok(Some(user));
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        let mut users = Vec::new();
+        for shard in &self.shards {
+            users.extend(shard.find_by_tags(filter)?);
+        }
+        users.sort_by_key(|user| user.id);
+        Ok(users)
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        for shard in &self.shards {
+            shard.for_each(visit)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::create_user;
+    use crate::repository::InMemoryUserRepository;
+    use crate::test_support::user_repository_contract;
+
+    fn shards(count: usize) -> ShardRouter<InMemoryUserRepository> {
+        ShardRouter::new((0..count).map(|_| InMemoryUserRepository::new()).collect())
+    }
+
+    fn user(id: UserId) -> User {
+        let mut user = create_user(
+            "foo@ok.com".to_string(),
+            22,
+            "Luca".to_string(),
+            "Rossi".to_string(),
+            None,
+        )
+        .unwrap();
+        user.id = id;
+        user
+    }
+
+    #[test]
+    fn ok_shard_router_meets_contract() {
+        user_repository_contract(|| shards(3));
+    }
+
+    #[test]
+    fn ok_user_stored_on_its_shard_only() {
+        let router = shards(3);
+        let id = UserId(1);
+        router.save(user(id)).unwrap();
+
+        let holding: Vec<usize> = (0..3)
+            .filter(|shard| router.shards[*shard].find(id).unwrap().is_some())
+            .collect();
+        assert_eq!(holding, vec![router.shard_for(id)]);
+        assert!(router
+            .find_by_email(&TenantId::default(), &Email("FOO@ok.com".to_string()))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn ok_adding_shard_moves_a_share_of_users() {
+        let (before, after) = (shards(4), shards(5));
+        let ids = (1..=1000).map(UserId);
+
+        let moved = ids
+            .filter(|id| before.shard_for(*id) != after.shard_for(*id))
+            .count();
+
+        // About a fifth of the users belong on the new shard; the rest stay.
+        assert!((100..=300).contains(&moved), "{} users moved", moved);
+    }
+
+    #[test]
+    fn err_email_registered_on_another_shard() {
+        let router = shards(2);
+        let first = UserId(1);
+        let other = (2..)
+            .map(UserId)
+            .find(|id| router.shard_for(*id) != router.shard_for(first))
+            .unwrap();
+        router.save(user(first)).unwrap();
+
+        let result = router.save(user(other));
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Email is already registered");
+    }
+}
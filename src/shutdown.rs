@@ -0,0 +1,276 @@
+use anyhow::{Error, Result};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Outstanding work to finish before the process exits.
+pub trait Drain {
+    fn name(&self) -> &str;
+    /// Finishes the work, giving up at `deadline`.
+    fn drain(&self, deadline: Instant) -> Result<()>;
+}
+
+/// Waits for `handles` to finish until `deadline`, keeping the ones that did
+/// not. Returns whether all of them finished.
+pub(crate) fn join_until(handles: &Mutex<Vec<JoinHandle<()>>>, deadline: Instant) -> bool {
+    loop {
+        let mut handles = handles.lock().unwrap();
+        let (finished, running): (Vec<_>, Vec<_>) = std::mem::take(&mut *handles)
+            .into_iter()
+            .partition(JoinHandle::is_finished);
+        *handles = running;
+        for handle in finished {
+            // A panicking thread has nothing left to hand over.
+            let _ = handle.join();
+        }
+        if handles.is_empty() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        drop(handles);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Work run by a [`WorkerPool`].
+pub type Work = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads running work from a bounded queue, so a burst of
+/// connections can neither spawn threads without limit nor queue without
+/// limit. Draining stops taking work and waits for what was queued.
+#[derive(Debug)]
+pub struct WorkerPool {
+    queue: Mutex<Option<SyncSender<Work>>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl WorkerPool {
+    /// Starts `workers` threads, with room for `queued` more pieces of work
+    /// waiting for one of them.
+    pub fn new(workers: usize, queued: usize) -> Self {
+        let (sender, receiver) = sync_channel::<Work>(queued);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handles = (0..workers.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                std::thread::spawn(move || loop {
+                    let next = receiver.lock().unwrap().recv();
+                    let Ok(work) = next else {
+                        return;
+                    };
+                    // A panicking piece of work must not take its worker
+                    // with it.
+                    let _ = catch_unwind(AssertUnwindSafe(work));
+                })
+            })
+            .collect();
+        Self {
+            queue: Mutex::new(Some(sender)),
+            handles: Mutex::new(handles),
+        }
+    }
+
+    /// Queues `work`, handing it back when the queue is full or the pool is
+    /// draining.
+    pub fn spawn(&self, work: impl FnOnce() + Send + 'static) -> Result<(), Work> {
+        let work: Work = Box::new(work);
+        match &*self.queue.lock().unwrap() {
+            Some(queue) => queue.try_send(work).map_err(|error| match error {
+                TrySendError::Full(work) | TrySendError::Disconnected(work) => work,
+            }),
+            None => Err(work),
+        }
+    }
+}
+
+impl Drain for WorkerPool {
+    fn name(&self) -> &str {
+        "requests"
+    }
+
+    fn drain(&self, deadline: Instant) -> Result<()> {
+        // Workers finish the queue, then stop once it is closed and empty.
+        self.queue.lock().unwrap().take();
+        if join_until(&self.handles, deadline) {
+            return Ok(());
+        }
+        let running = self.handles.lock().unwrap().len();
+        Err(Error::msg(format!("{} still running", running)))
+    }
+}
+
+/// Drains each step in order once the process is asked to stop. All steps
+/// share one deadline, so a slow step shortens the time left to later ones
+/// but never skips them.
+pub struct GracefulShutdown<'a> {
+    timeout: Duration,
+    steps: Vec<&'a dyn Drain>,
+}
+
+impl<'a> GracefulShutdown<'a> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn then(mut self, step: &'a dyn Drain) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let failures: Vec<String> = self
+            .steps
+            .iter()
+            .filter_map(|step| {
+                let error = step.drain(deadline).err()?;
+                Some(format!("{}: {}", step.name(), error))
+            })
+            .collect();
+        if !failures.is_empty() {
+            return Err(Error::msg(format!(
+                "Shutdown incomplete ({})",
+                failures.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Returns a flag that is set when the process receives SIGTERM or SIGINT.
+#[cfg(unix)]
+pub fn termination_flag() -> &'static AtomicBool {
+    use std::os::raw::c_int;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn request_termination(_: c_int) {
+        TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        signal(SIGTERM, request_termination);
+        signal(SIGINT, request_termination);
+    }
+    &TERMINATION_REQUESTED
+}
+
+/// Without signals to listen to, the returned flag is never set.
+#[cfg(not(unix))]
+pub fn termination_flag() -> &'static AtomicBool {
+    static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+    &TERMINATION_REQUESTED
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{DomainEvent, EmailVerified, InMemoryEventPublisher};
+    use crate::outbox::{InMemoryOutbox, Outbox, OutboxRelay};
+    use crate::UserId;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn ok_in_flight_work_and_outbox_drained() {
+        let connections = WorkerPool::new(1, 1);
+        let finished = Arc::new(AtomicBool::new(false));
+        let done = Arc::clone(&finished);
+        assert!(connections
+            .spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                done.store(true, Ordering::SeqCst);
+            })
+            .is_ok());
+        let outbox = InMemoryOutbox::new();
+        outbox
+            .append(DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(1),
+                email: "foo@ok.com".to_string(),
+            }))
+            .unwrap();
+        let broker = InMemoryEventPublisher::new();
+        let relay = OutboxRelay::new(&outbox, &broker);
+
+        GracefulShutdown::new(Duration::from_secs(5))
+            .then(&connections)
+            .then(&relay)
+            .run()
+            .unwrap();
+
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(broker.published().len(), 1);
+    }
+
+    #[test]
+    fn err_deadline_passed_with_work_running() {
+        let connections = WorkerPool::new(1, 1);
+        assert!(connections
+            .spawn(|| std::thread::sleep(Duration::from_millis(200)))
+            .is_ok());
+
+        let result = GracefulShutdown::new(Duration::from_millis(20))
+            .then(&connections)
+            .run();
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Shutdown incomplete (requests: 1 still running)"
+        );
+    }
+
+    #[test]
+    fn err_full_queue_hands_work_back() {
+        let pool = WorkerPool::new(1, 1);
+        let (started, wait_started) = channel();
+        let (release, wait_release) = channel::<()>();
+        assert!(pool
+            .spawn(move || {
+                started.send(()).unwrap();
+                let _ = wait_release.recv();
+            })
+            .is_ok());
+        wait_started.recv().unwrap();
+
+        assert!(pool.spawn(|| {}).is_ok());
+        assert!(pool.spawn(|| {}).is_err());
+
+        drop(release);
+        pool.drain(Instant::now() + Duration::from_secs(5)).unwrap();
+        assert!(pool.spawn(|| {}).is_err());
+    }
+
+    #[test]
+    fn ok_worker_survives_panicking_work() {
+        let pool = WorkerPool::new(1, 1);
+        let (done, wait_done) = channel();
+        assert!(pool.spawn(|| panic!("request failed")).is_ok());
+        while pool
+            .spawn({
+                let done = done.clone();
+                move || done.send(()).unwrap()
+            })
+            .is_err()
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(wait_done.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
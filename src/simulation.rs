@@ -0,0 +1,302 @@
+//! Deterministic simulation of the user lifecycle over simulated weeks.
+//!
+//! Users register, try to verify (sometimes in bursts, sometimes never) and
+//! get reminded, all on a virtual clock that jumps from one scheduled action
+//! to the next. The same seed replays the same run exactly, so ordering and
+//! timeout bugs found here reproduce every time.
+
+use crate::arbitrary::Gen;
+use crate::bus::{Command, CommandBus, ExecutionMode};
+use crate::clock::TestClock;
+use crate::commands::CreateUser;
+use crate::config::VerificationConfig;
+use crate::events::{DomainEvent, EventHandler, InMemoryEventPublisher};
+use crate::notifications::{EmailMessage, InMemoryEmailSender};
+use crate::onboarding::{InMemoryOnboardingStore, OnboardingSaga};
+use crate::repository::InMemoryUserRepository;
+use crate::throttle::SlidingWindowLimiter;
+use crate::UserId;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub seed: u64,
+    pub users: u64,
+    /// Span over which users register.
+    pub duration: Duration,
+    pub reminder_after: Duration,
+    /// How often the reminder sweep runs, like its cron job would.
+    pub sweep_every: Duration,
+    pub max_attempts: u32,
+    pub attempt_window: Duration,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            users: 200,
+            duration: 4 * 7 * DAY,
+            reminder_after: DAY,
+            sweep_every: HOUR,
+            max_attempts: 5,
+            attempt_window: HOUR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttemptOutcome {
+    Verified,
+    Rejected,
+    Throttled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub at: SystemTime,
+    pub outcome: AttemptOutcome,
+}
+
+/// What happened to one user over the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    pub email: String,
+    pub registered_at: SystemTime,
+    pub attempts: Vec<Attempt>,
+}
+
+impl Timeline {
+    pub fn verified_at(&self) -> Option<SystemTime> {
+        self.attempts
+            .iter()
+            .find(|attempt| attempt.outcome == AttemptOutcome::Verified)
+            .map(|attempt| attempt.at)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// By the user's number in the scenario; ids depend on what else ran in
+    /// the process.
+    pub timelines: BTreeMap<u64, Timeline>,
+    /// Every email sent, in order.
+    pub mail: Vec<EmailMessage>,
+}
+
+enum Action {
+    Register(u64),
+    Verify(u64, UserId),
+}
+
+/// Runs `scenario` to the end, until every reminder that can fall due has.
+pub fn simulate(scenario: &Scenario) -> Report {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+    let clock = TestClock::new(start);
+    let rng = &mut Gen::new(scenario.seed);
+    let verification = VerificationConfig {
+        reminder_after: scenario.reminder_after,
+        max_attempts: scenario.max_attempts,
+        attempt_window: scenario.attempt_window,
+    };
+    let bus = CommandBus::new(InMemoryUserRepository::new(), InMemoryEventPublisher::new())
+        .with_verification_limiter(SlidingWindowLimiter::new(
+            verification.max_attempts,
+            verification.attempt_window,
+            &clock,
+        ));
+    let store = InMemoryOnboardingStore::new();
+    let sender = InMemoryEmailSender::new();
+    let saga = OnboardingSaga::new(&store, &sender, &clock, &verification);
+
+    // Keyed by time, then by insertion, so simultaneous actions keep their
+    // order.
+    let mut queue = BTreeMap::new();
+    let mut sequence = 0u64;
+    let mut schedule = |queue: &mut BTreeMap<_, _>, at: SystemTime, action: Action| {
+        sequence += 1;
+        queue.insert((at, sequence), action);
+    };
+    let seconds = scenario.duration.as_secs();
+    for user in 0..scenario.users {
+        let at = start + Duration::from_secs(rng.next_u64() % seconds);
+        schedule(&mut queue, at, Action::Register(user));
+    }
+
+    let end = start + scenario.duration + 7 * DAY + scenario.reminder_after;
+    let mut next_sweep = start;
+    let mut timelines = BTreeMap::new();
+    loop {
+        let next_action = queue.keys().next().map(|(at, _)| *at);
+        if next_action.is_none_or(|at| next_sweep <= at) {
+            if next_sweep > end {
+                break;
+            }
+            clock.set(next_sweep);
+            saga.send_due_reminders().unwrap();
+            next_sweep += scenario.sweep_every;
+            continue;
+        }
+        let ((at, _), action) = queue.pop_first().unwrap();
+        clock.set(at);
+        match action {
+            Action::Register(user) => {
+                // One in five addresses can never be verified.
+                let email = match rng.below(5) {
+                    0 => format!("user{}@mail.com", user),
+                    _ => format!("user{}@ok.com", user),
+                };
+                let events = bus
+                    .dispatch(
+                        Command::CreateUser(CreateUser {
+                            email: email.clone(),
+                            age: rng.range(13..=90),
+                            name: "Luca".to_string(),
+                            surname: format!("Rossi{}", user),
+                            middle_name: None,
+                        }),
+                        ExecutionMode::Commit,
+                    )
+                    .unwrap();
+                for event in &events {
+                    saga.handle(event).unwrap();
+                }
+                let user_id = events[0].user_id();
+                timelines.insert(
+                    user,
+                    Timeline {
+                        email,
+                        registered_at: at,
+                        attempts: Vec::new(),
+                    },
+                );
+                // Some never try; the rest try after up to three days, in a
+                // burst of impatient clicks, and some try again later.
+                if rng.below(4) > 0 {
+                    let first = at + Duration::from_secs(rng.next_u64() % (3 * DAY).as_secs());
+                    for click in 0..1 + rng.below(8) {
+                        let delay = Duration::from_secs(click as u64 * 5);
+                        schedule(&mut queue, first + delay, Action::Verify(user, user_id));
+                    }
+                    if rng.below(3) == 0 {
+                        schedule(&mut queue, first + 2 * HOUR, Action::Verify(user, user_id));
+                    }
+                }
+            }
+            Action::Verify(user, user_id) => {
+                let outcome =
+                    match bus.dispatch(Command::VerifyEmail(user_id), ExecutionMode::Commit) {
+                        Ok(events) => {
+                            for event in &events {
+                                saga.handle(event).unwrap();
+                            }
+                            match events.first() {
+                                Some(DomainEvent::VerificationThrottled(_)) => {
+                                    AttemptOutcome::Throttled
+                                }
+                                _ => AttemptOutcome::Verified,
+                            }
+                        }
+                        Err(_) => AttemptOutcome::Rejected,
+                    };
+                let timeline: &mut Timeline = timelines.get_mut(&user).unwrap();
+                timeline.attempts.push(Attempt { at, outcome });
+            }
+        }
+    }
+
+    Report {
+        timelines,
+        mail: sender.sent(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sent(report: &Report, to: &str, subject: &str) -> usize {
+        report
+            .mail
+            .iter()
+            .filter(|message| message.to == to && message.subject == subject)
+            .count()
+    }
+
+    #[test]
+    fn ok_same_seed_replays_same_run() {
+        let scenario = Scenario {
+            users: 50,
+            ..Scenario::default()
+        };
+
+        assert_eq!(simulate(&scenario), simulate(&scenario));
+        let other = simulate(&Scenario {
+            seed: 2,
+            ..scenario.clone()
+        });
+        assert_ne!(other.mail, simulate(&scenario).mail);
+    }
+
+    #[test]
+    fn ok_lifecycle_invariants_hold_over_four_weeks() {
+        let scenario = Scenario::default();
+
+        let report = simulate(&scenario);
+
+        let mut outcomes = Vec::new();
+        for timeline in report.timelines.values() {
+            let email = timeline.email.as_str();
+            let verified_at = timeline.verified_at();
+            assert_eq!(sent(&report, email, "Verify your email"), 1, "{}", email);
+            assert_eq!(
+                sent(&report, email, "Welcome!"),
+                usize::from(verified_at.is_some()),
+                "{}",
+                email
+            );
+            // Reminded exactly when still unverified once the reminder fell
+            // due, give or take one sweep.
+            let due = timeline.registered_at + scenario.reminder_after;
+            let reminded = sent(&report, email, "Reminder: verify your email");
+            match verified_at {
+                Some(at) if at < due => assert_eq!(reminded, 0, "{}", email),
+                Some(at) if at >= due + scenario.sweep_every => {
+                    assert_eq!(reminded, 1, "{}", email)
+                }
+                Some(_) => assert!(reminded <= 1, "{}", email),
+                None => assert_eq!(reminded, 1, "{}", email),
+            }
+            // An attempt is throttled exactly when the window already holds
+            // the maximum of accepted attempts.
+            for (index, attempt) in timeline.attempts.iter().enumerate() {
+                let accepted_in_window = timeline.attempts[..index]
+                    .iter()
+                    .filter(|earlier| earlier.outcome != AttemptOutcome::Throttled)
+                    .filter(|earlier| earlier.at + scenario.attempt_window > attempt.at)
+                    .count();
+                assert_eq!(
+                    attempt.outcome == AttemptOutcome::Throttled,
+                    accepted_in_window >= scenario.max_attempts as usize,
+                    "{} at {:?}",
+                    email,
+                    attempt.at
+                );
+                outcomes.push(attempt.outcome);
+            }
+        }
+
+        assert_eq!(report.timelines.len(), 200);
+        for outcome in [
+            AttemptOutcome::Verified,
+            AttemptOutcome::Rejected,
+            AttemptOutcome::Throttled,
+        ] {
+            assert!(outcomes.contains(&outcome), "no {:?} attempt", outcome);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::User;
+use std::time::SystemTime;
+
+/// A business rule a candidate either satisfies or not, composable with
+/// [`Specification::and`], [`Specification::or`] and [`Specification::not`].
+pub trait Specification<T> {
+    fn is_satisfied_by(&self, candidate: &T) -> bool;
+
+    fn and<S: Specification<T>>(self, other: S) -> And<Self, S>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    fn or<S: Specification<T>>(self, other: S) -> Or<Self, S>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+pub struct And<A, B>(pub A, pub B);
+
+pub struct Or<A, B>(pub A, pub B);
+
+pub struct Not<A>(pub A);
+
+impl<T, A: Specification<T>, B: Specification<T>> Specification<T> for And<A, B> {
+    fn is_satisfied_by(&self, candidate: &T) -> bool {
+        self.0.is_satisfied_by(candidate) && self.1.is_satisfied_by(candidate)
+    }
+}
+
+impl<T, A: Specification<T>, B: Specification<T>> Specification<T> for Or<A, B> {
+    fn is_satisfied_by(&self, candidate: &T) -> bool {
+        self.0.is_satisfied_by(candidate) || self.1.is_satisfied_by(candidate)
+    }
+}
+
+impl<T, A: Specification<T>> Specification<T> for Not<A> {
+    fn is_satisfied_by(&self, candidate: &T) -> bool {
+        !self.0.is_satisfied_by(candidate)
+    }
+}
+
+pub struct EmailVerified;
+
+impl Specification<User> for EmailVerified {
+    fn is_satisfied_by(&self, user: &User) -> bool {
+        user.is_email_verified()
+    }
+}
+
+/// Users strictly older than the given age.
+pub struct OlderThan(pub i32);
+
+impl Specification<User> for OlderThan {
+    fn is_satisfied_by(&self, user: &User) -> bool {
+        user.age.0 > self.0
+    }
+}
+
+pub struct RegisteredBefore(pub SystemTime);
+
+impl Specification<User> for RegisteredBefore {
+    fn is_satisfied_by(&self, user: &User) -> bool {
+        user.registered_at < self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repository::{InMemoryUserRepository, UserRepository};
+    use crate::test_support::UserFixture;
+    use crate::{Email, UserEmail, UserId, VerifiedEmail};
+    use std::time::Duration;
+
+    #[test]
+    fn ok_find_matching_composed_specification() {
+        let repository = InMemoryUserRepository::new();
+        let verified_adult = UserFixture::verified().with_age(30).build();
+        let mut recent = verified_adult.clone();
+        recent.id = UserId(verified_adult.id.0 + 1_000);
+        recent.email = UserEmail::VerifiedEmail(VerifiedEmail(Email("new@ok.com".to_string())));
+        recent.registered_at += Duration::from_secs(3600);
+        let unverified_adult = UserFixture::new()
+            .with_email("bar@ok.com")
+            .with_age(40)
+            .with_name("Anna", None, "Bianchi")
+            .build();
+        let cutoff = verified_adult.registered_at + Duration::from_secs(60);
+        let expected = verified_adult.id;
+        for user in [verified_adult, recent, unverified_adult] {
+            repository.save(user).unwrap();
+        }
+
+        let specification = EmailVerified
+            .and(OlderThan(18))
+            .and(RegisteredBefore(cutoff).or(OlderThan(65)))
+            .and(OlderThan(35).not());
+        let found = repository.find_matching(&specification).unwrap();
+
+        let ids: Vec<_> = found.iter().map(|user| user.id).collect();
+        assert_eq!(ids, vec![expected]);
+    }
+}
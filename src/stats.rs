@@ -0,0 +1,222 @@
+//! Aggregate figures about users, kept up to date from events.
+
+use crate::date::Date;
+use crate::demographics::{Demographics, DemographicsReport};
+use crate::event_store::StoredEvent;
+use crate::events::DomainEvent;
+use crate::http::{HttpResponse, Router};
+use crate::json::JsonValue;
+use crate::projections::Projection;
+use crate::UserId;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Copy)]
+struct CountedUser {
+    age: i32,
+    verified: bool,
+    signed_up_on: Date,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserStatsReport {
+    pub demographics: DemographicsReport,
+    /// Signups per UTC day of registration, in date order.
+    pub signups_per_day: Vec<(Date, usize)>,
+}
+
+impl UserStatsReport {
+    pub fn to_json(&self) -> JsonValue {
+        let signups = self
+            .signups_per_day
+            .iter()
+            .map(|(date, count)| {
+                JsonValue::Object(vec![
+                    ("date".to_string(), JsonValue::String(date.to_string())),
+                    ("count".to_string(), JsonValue::Number(*count as i64)),
+                ])
+            })
+            .collect();
+        let mut fields = self.demographics.to_json_fields();
+        fields.push(("signups_per_day".to_string(), JsonValue::Array(signups)));
+        JsonValue::Object(fields)
+    }
+}
+
+/// Live users with their age, verification state and signup day. Merged
+/// duplicates are no longer counted.
+#[derive(Debug, Default)]
+pub struct UserStats {
+    /// Kept per user, so re-applied events are harmless.
+    users: RwLock<HashMap<UserId, CountedUser>>,
+}
+
+impl UserStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers the `GetUserStats` query.
+    pub fn report(&self) -> UserStatsReport {
+        let users = self.users.read().unwrap();
+        let mut demographics = Demographics::new();
+        let mut signups = BTreeMap::new();
+        for user in users.values() {
+            demographics.add(user.age, user.verified);
+            *signups.entry(user.signed_up_on).or_insert(0) += 1;
+        }
+        UserStatsReport {
+            demographics: demographics.report(),
+            signups_per_day: signups.into_iter().collect(),
+        }
+    }
+}
+
+impl Projection for UserStats {
+    fn name(&self) -> &str {
+        "user_stats"
+    }
+
+    fn apply(&self, stored: &StoredEvent) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        match &stored.event {
+            DomainEvent::UserRegistered(event) => {
+                users.entry(event.user_id).or_insert(CountedUser {
+                    age: event.age,
+                    verified: false,
+                    signed_up_on: Date::from_system_time(stored.recorded_at),
+                });
+            }
+            DomainEvent::EmailVerified(event) => {
+                if let Some(user) = users.get_mut(&event.user_id) {
+                    user.verified = true;
+                }
+            }
+            DomainEvent::NameChanged(_) | DomainEvent::VerificationThrottled(_) => {}
+            DomainEvent::UsersMerged(event) => {
+                users.remove(&event.duplicate_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.users.write().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// Adds `/stats`, answering with the current [`UserStatsReport`].
+pub fn stats_routes(router: Router, stats: Arc<UserStats>) -> Router {
+    router.route("GET", "/stats", move |_| HttpResponse {
+        status: 200,
+        content_type: "application/json".to_string(),
+        body: stats.report().to_json().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{user_routes, TENANT_HEADER};
+    use crate::bus::CommandBus;
+    use crate::clock::SystemClock;
+    use crate::event_store::InMemoryEventStore;
+    use crate::events::{EmailVerified, EventId, UserRegistered, UsersMerged};
+    use crate::http::HttpRequest;
+    use crate::json::parse_json;
+    use crate::projections::{InMemoryCheckpointStore, ProjectionRunner};
+    use crate::repository::InMemoryUserRepository;
+    use std::time::{Duration, SystemTime};
+
+    fn stored(id: u64, day: u64, event: DomainEvent) -> StoredEvent {
+        StoredEvent {
+            id: EventId(id),
+            recorded_at: SystemTime::UNIX_EPOCH + Duration::from_secs(day * 86_400 + 3_600),
+            event,
+        }
+    }
+
+    fn registered(user_id: u64, age: i32) -> DomainEvent {
+        DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(user_id),
+            email: format!("user{}@ok.com", user_id),
+            name: "Luca".to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+            age,
+        })
+    }
+
+    #[test]
+    fn ok_report_aggregates() {
+        let stats = UserStats::new();
+        let events = [
+            stored(1, 0, registered(1, 22)),
+            stored(2, 0, registered(2, 29)),
+            stored(3, 1, registered(3, 41)),
+            stored(4, 1, registered(4, 35)),
+            stored(
+                5,
+                1,
+                DomainEvent::EmailVerified(EmailVerified {
+                    user_id: UserId(1),
+                    email: "user1@ok.com".to_string(),
+                }),
+            ),
+            stored(
+                6,
+                2,
+                DomainEvent::UsersMerged(UsersMerged {
+                    primary_id: UserId(1),
+                    duplicate_id: UserId(4),
+                    merged: None,
+                }),
+            ),
+        ];
+        for event in events.iter().chain(&events) {
+            stats.apply(event).unwrap();
+        }
+
+        let report = stats.report();
+
+        assert_eq!(
+            report.to_json().to_string(),
+            r#"{"total":3,"verified":1,"verified_percent":33,"age_distribution":[{"from":20,"to":29,"count":2},{"from":40,"to":49,"count":1}],"signups_per_day":[{"date":"1970-01-01","count":2},{"date":"1970-01-02","count":1}]}"#
+        );
+    }
+
+    #[test]
+    fn ok_stats_count_users_created_over_http() {
+        let bus = Arc::new(CommandBus::new(
+            InMemoryUserRepository::new(),
+            InMemoryEventStore::new(),
+        ));
+        let stats = Arc::new(UserStats::new());
+        let router = user_routes(Router::new(), Arc::clone(&bus), SystemClock);
+        let router = stats_routes(router, Arc::clone(&stats));
+        let request = |method: &str, path: &str, body: &str| HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: vec![(TENANT_HEADER.to_string(), "acme".to_string())],
+            body: body.to_string(),
+            peer: None,
+        };
+
+        let created = router.handle(&request(
+            "POST",
+            "/v1/users",
+            r#"{"email":"foo@ok.com","age":22,"name":"Luca","surname":"Rossi"}"#,
+        ));
+        let checkpoints = InMemoryCheckpointStore::new();
+        ProjectionRunner::new(bus.publisher(), &checkpoints)
+            .register(&*stats)
+            .run_once()
+            .unwrap();
+        let report = parse_json(&router.handle(&request("GET", "/stats", "")).body).unwrap();
+
+        assert_eq!(created.status, 201);
+        assert_eq!(report.get("total"), Some(&JsonValue::Number(1)));
+    }
+}
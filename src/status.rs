@@ -0,0 +1,230 @@
+//! The one place deciding how errors surface on each transport.
+
+use crate::api::InvalidRequest;
+use crate::circuit_breaker::ServiceUnavailable;
+use crate::error::DomainError;
+use crate::idempotency::IdempotencyError;
+use crate::identity_provider::IdentityConflict;
+use crate::throttle::SignupThrottled;
+use anyhow::Error;
+
+/// gRPC status codes, with their wire values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrpcCode {
+    InvalidArgument = 3,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    Internal = 13,
+    Unavailable = 14,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportStatus {
+    pub http: u16,
+    pub grpc: GrpcCode,
+}
+
+const fn status(http: u16, grpc: GrpcCode) -> TransportStatus {
+    TransportStatus { http, grpc }
+}
+
+/// Deliberately lists every variant: a new one does not compile until it is
+/// given a status.
+pub fn domain_status(error: &DomainError) -> TransportStatus {
+    match error {
+        DomainError::InvalidEmail
+        | DomainError::NegativeAge
+        | DomainError::Underage { .. }
+        | DomainError::AgeTooHigh
+        | DomainError::InvalidPronouns
+        | DomainError::InvalidTag => status(422, GrpcCode::InvalidArgument),
+        DomainError::EmailNotVerified | DomainError::BelowGrantingAge { .. } => {
+            status(403, GrpcCode::FailedPrecondition)
+        }
+        DomainError::CrossTenantMerge => status(409, GrpcCode::FailedPrecondition),
+        DomainError::UnsupportedAvatarType
+        | DomainError::UnknownAttribute { .. }
+        | DomainError::InvalidAttribute { .. }
+        | DomainError::MissingAttribute { .. } => status(400, GrpcCode::InvalidArgument),
+        DomainError::AvatarTooLarge => status(413, GrpcCode::InvalidArgument),
+        DomainError::AvatarNotFound => status(404, GrpcCode::NotFound),
+        DomainError::EmailAlreadyRegistered => status(409, GrpcCode::AlreadyExists),
+        DomainError::ConcurrencyConflict { .. } | DomainError::BatchAborted => {
+            status(409, GrpcCode::Aborted)
+        }
+    }
+}
+
+pub fn identity_conflict_status(error: &IdentityConflict) -> TransportStatus {
+    match error {
+        IdentityConflict::DanglingLink { .. } => status(409, GrpcCode::FailedPrecondition),
+        IdentityConflict::EmailTaken { .. } => status(409, GrpcCode::AlreadyExists),
+    }
+}
+
+/// A retry racing the original request is told to try again; a key reused
+/// for another request is a client error.
+pub fn idempotency_status(error: &IdempotencyError) -> TransportStatus {
+    match error {
+        IdempotencyError::InProgress => status(409, GrpcCode::Aborted),
+        IdempotencyError::KeyReused => status(422, GrpcCode::InvalidArgument),
+    }
+}
+
+/// Status of any error a handler may return. Errors without a type of their
+/// own are internal errors.
+pub fn error_status(error: &Error) -> TransportStatus {
+    if let Some(error) = error.downcast_ref::<DomainError>() {
+        return domain_status(error);
+    }
+    if error.downcast_ref::<InvalidRequest>().is_some() {
+        return status(400, GrpcCode::InvalidArgument);
+    }
+    if let Some(error) = error.downcast_ref::<IdentityConflict>() {
+        return identity_conflict_status(error);
+    }
+    if let Some(error) = error.downcast_ref::<IdempotencyError>() {
+        return idempotency_status(error);
+    }
+    if error.downcast_ref::<SignupThrottled>().is_some() {
+        return status(429, GrpcCode::ResourceExhausted);
+    }
+    if error.downcast_ref::<ServiceUnavailable>().is_some() {
+        return status(503, GrpcCode::Unavailable);
+    }
+    status(500, GrpcCode::Internal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::custom_attributes::AttributeKind;
+    use crate::UserId;
+
+    /// One value of every variant. The match makes this fail to compile when
+    /// a variant is added, so the table below has to be extended with it.
+    fn every_domain_error() -> Vec<DomainError> {
+        let errors = vec![
+            DomainError::InvalidEmail,
+            DomainError::NegativeAge,
+            DomainError::Underage { min_age: 13 },
+            DomainError::AgeTooHigh,
+            DomainError::InvalidPronouns,
+            DomainError::InvalidTag,
+            DomainError::EmailNotVerified,
+            DomainError::BelowGrantingAge { min_age: 18 },
+            DomainError::EmailAlreadyRegistered,
+            DomainError::ConcurrencyConflict {
+                user_id: UserId(1),
+                expected_version: 1,
+                actual_version: 2,
+            },
+            DomainError::BatchAborted,
+            DomainError::CrossTenantMerge,
+            DomainError::UnsupportedAvatarType,
+            DomainError::AvatarTooLarge,
+            DomainError::AvatarNotFound,
+            DomainError::UnknownAttribute {
+                name: "badge".to_string(),
+            },
+            DomainError::InvalidAttribute {
+                name: "hired_on".to_string(),
+                expected: AttributeKind::Date,
+            },
+            DomainError::MissingAttribute {
+                name: "employee_id".to_string(),
+            },
+        ];
+        for error in &errors {
+            match error {
+                DomainError::InvalidEmail
+                | DomainError::NegativeAge
+                | DomainError::Underage { .. }
+                | DomainError::AgeTooHigh
+                | DomainError::InvalidPronouns
+                | DomainError::InvalidTag
+                | DomainError::EmailNotVerified
+                | DomainError::BelowGrantingAge { .. }
+                | DomainError::EmailAlreadyRegistered
+                | DomainError::ConcurrencyConflict { .. }
+                | DomainError::BatchAborted
+                | DomainError::CrossTenantMerge
+                | DomainError::UnsupportedAvatarType
+                | DomainError::AvatarTooLarge
+                | DomainError::AvatarNotFound
+                | DomainError::UnknownAttribute { .. }
+                | DomainError::InvalidAttribute { .. }
+                | DomainError::MissingAttribute { .. } => {}
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn ok_every_domain_error_mapped() {
+        let statuses: Vec<(&str, u16, GrpcCode)> = every_domain_error()
+            .iter()
+            .map(|error| {
+                let status = domain_status(error);
+                (error.code(), status.http, status.grpc)
+            })
+            .collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                ("invalid_email", 422, GrpcCode::InvalidArgument),
+                ("negative_age", 422, GrpcCode::InvalidArgument),
+                ("underage", 422, GrpcCode::InvalidArgument),
+                ("age_too_high", 422, GrpcCode::InvalidArgument),
+                ("invalid_pronouns", 422, GrpcCode::InvalidArgument),
+                ("invalid_tag", 422, GrpcCode::InvalidArgument),
+                ("email_not_verified", 403, GrpcCode::FailedPrecondition),
+                ("below_granting_age", 403, GrpcCode::FailedPrecondition),
+                ("email_already_registered", 409, GrpcCode::AlreadyExists),
+                ("concurrency_conflict", 409, GrpcCode::Aborted),
+                ("batch_aborted", 409, GrpcCode::Aborted),
+                ("cross_tenant_merge", 409, GrpcCode::FailedPrecondition),
+                ("unsupported_avatar_type", 400, GrpcCode::InvalidArgument),
+                ("avatar_too_large", 413, GrpcCode::InvalidArgument),
+                ("avatar_not_found", 404, GrpcCode::NotFound),
+                ("unknown_attribute", 400, GrpcCode::InvalidArgument),
+                ("invalid_attribute", 400, GrpcCode::InvalidArgument),
+                ("missing_attribute", 400, GrpcCode::InvalidArgument),
+            ]
+        );
+    }
+
+    #[test]
+    fn ok_other_errors_mapped() {
+        let taken = Error::from(IdentityConflict::EmailTaken {
+            subject: "auth0|1".to_string(),
+            user_id: UserId(1),
+        });
+        let unavailable = Error::from(ServiceUnavailable {
+            service: "smtp".to_string(),
+        });
+
+        assert_eq!(error_status(&taken), status(409, GrpcCode::AlreadyExists));
+        assert_eq!(
+            error_status(&Error::from(IdempotencyError::KeyReused)),
+            status(422, GrpcCode::InvalidArgument)
+        );
+        assert_eq!(
+            error_status(&Error::from(SignupThrottled)),
+            status(429, GrpcCode::ResourceExhausted)
+        );
+        assert_eq!(
+            error_status(&unavailable),
+            status(503, GrpcCode::Unavailable)
+        );
+        assert_eq!(
+            error_status(&Error::msg("boom")),
+            status(500, GrpcCode::Internal)
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::api::InvalidRequest;
+use crate::events::{DomainEvent, EventPublisher};
+use crate::http::{HttpRequest, Router, StreamingResponse};
+use crate::shutdown::Drain;
+use crate::UserId;
+use anyhow::Result;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Silence after which a stream sends a comment, so proxies keep it open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Which events a live subscriber wants; empty criteria match everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    pub user_id: Option<UserId>,
+    pub event_types: Vec<String>,
+}
+
+impl EventFilter {
+    /// Filter of a `GET /events?user_id=7&types=EmailVerified,UsersMerged`
+    /// request.
+    pub fn from_request(request: &HttpRequest) -> Result<Self, InvalidRequest> {
+        let user_id = request
+            .query("user_id")
+            .map(|id| {
+                id.parse().map(UserId).map_err(|_| InvalidRequest {
+                    field: "user_id".to_string(),
+                    reason: "must be a number".to_string(),
+                })
+            })
+            .transpose()?;
+        let event_types = request
+            .query("types")
+            .map(|types| types.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+        Ok(Self {
+            user_id,
+            event_types,
+        })
+    }
+
+    pub fn matches(&self, event: &DomainEvent) -> bool {
+        self.user_id.is_none_or(|id| event.user_id() == id)
+            && (self.event_types.is_empty()
+                || self.event_types.iter().any(|name| name == event.name()))
+    }
+}
+
+/// Fans published events out to in-process subscribers, e.g. one per open
+/// HTTP stream. Subscribers whose receiver was dropped are forgotten on the
+/// next publish.
+#[derive(Debug, Default)]
+pub struct EventBroadcaster {
+    subscribers: Mutex<Vec<(EventFilter, Sender<DomainEvent>)>>,
+}
+
+impl EventBroadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<DomainEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push((filter, sender));
+        receiver
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+impl EventPublisher for EventBroadcaster {
+    fn publish(&self, event: &DomainEvent) -> Result<()> {
+        self.subscribers.lock().unwrap().retain(|(filter, sender)| {
+            !filter.matches(event) || sender.send(event.clone()).is_ok()
+        });
+        Ok(())
+    }
+}
+
+/// Ends every open stream by dropping its sender, so clients reconnect to
+/// another instance instead of holding up shutdown.
+impl Drain for EventBroadcaster {
+    fn name(&self) -> &str {
+        "event streams"
+    }
+
+    fn drain(&self, _deadline: Instant) -> Result<()> {
+        self.subscribers.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// Encodes an event as a server-sent events frame.
+pub fn sse_frame(event: &DomainEvent) -> String {
+    format!("event: {}\ndata: {}\n\n", event.name(), event.to_json())
+}
+
+/// Adds `GET /events`, streaming the events published to `broadcaster` as
+/// server-sent events, narrowed by [`EventFilter::from_request`]. The
+/// stream lasts until the client hangs up or the broadcaster is drained.
+pub fn event_routes(router: Router, broadcaster: Arc<EventBroadcaster>) -> Router {
+    router.stream("GET", "/events", move |request| {
+        let events = broadcaster.subscribe(EventFilter::from_request(request)?);
+        Ok(StreamingResponse {
+            content_type: "text/event-stream".to_string(),
+            body: Box::new(move |writer| loop {
+                match events.recv_timeout(KEEP_ALIVE) {
+                    Ok(event) => writer.write_all(sse_frame(&event).as_bytes())?,
+                    Err(RecvTimeoutError::Timeout) => writer.write_all(b": keep-alive\n\n")?,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                writer.flush()?;
+            }),
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{EmailVerified, UsersMerged};
+
+    fn email_verified(user_id: u64) -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(user_id),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_filtered_subscribers() {
+        let broadcaster = EventBroadcaster::new();
+        let everything = broadcaster.subscribe(EventFilter::default());
+        let one_user = broadcaster.subscribe(EventFilter {
+            user_id: Some(UserId(1)),
+            event_types: vec!["EmailVerified".to_string()],
+        });
+
+        broadcaster.publish(&email_verified(1)).unwrap();
+        broadcaster.publish(&email_verified(2)).unwrap();
+        broadcaster
+            .publish(&DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(3),
+                merged: None,
+            }))
+            .unwrap();
+
+        assert_eq!(everything.try_iter().count(), 3);
+        assert_eq!(
+            one_user.try_iter().collect::<Vec<_>>(),
+            vec![email_verified(1)]
+        );
+    }
+
+    #[test]
+    fn ok_disconnected_subscriber_is_dropped() {
+        let broadcaster = EventBroadcaster::new();
+        drop(broadcaster.subscribe(EventFilter::default()));
+        assert_eq!(broadcaster.subscriber_count(), 1);
+
+        broadcaster.publish(&email_verified(1)).unwrap();
+
+        assert_eq!(broadcaster.subscriber_count(), 0);
+    }
+
+    #[test]
+    fn ok_events_route_streams_filtered_events() {
+        let broadcaster = Arc::new(EventBroadcaster::new());
+        let router = event_routes(Router::new(), Arc::clone(&broadcaster));
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/events?user_id=1&types=EmailVerified".to_string(),
+            headers: Vec::new(),
+            body: String::new(),
+            peer: None,
+        };
+
+        let output = std::thread::scope(|scope| {
+            let stream = scope.spawn(|| {
+                let mut output = Vec::new();
+                router.respond(&request, &mut output).unwrap();
+                String::from_utf8(output).unwrap()
+            });
+            while broadcaster.subscriber_count() == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            broadcaster.publish(&email_verified(1)).unwrap();
+            broadcaster.publish(&email_verified(2)).unwrap();
+            broadcaster.drain(Instant::now()).unwrap();
+            stream.join().unwrap()
+        });
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n"));
+        assert!(output.ends_with(&format!("\r\n\r\n{}", sse_frame(&email_verified(1)))));
+    }
+
+    #[test]
+    fn err_events_route_with_invalid_user_id() {
+        let router = event_routes(Router::new(), Arc::new(EventBroadcaster::new()));
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/events?user_id=me".to_string(),
+            headers: Vec::new(),
+            body: String::new(),
+            peer: None,
+        };
+
+        let mut output = Vec::new();
+        router.respond(&request, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(output.contains("Field user_id must be a number"));
+    }
+
+    #[test]
+    fn ok_sse_frame() {
+        assert_eq!(
+            sse_frame(&email_verified(7)),
+            "event: EmailVerified\ndata: {\"type\":\"EmailVerified\",\"user_id\":7,\"email\":\"foo@ok.com\"}\n\n"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::error::DomainError;
+use crate::User;
+use anyhow::Result;
+use regex::Regex;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tag(pub String);
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Tag combination used to segment users, e.g. `beta-tester` but not `vip`.
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    pub all_of: Vec<Tag>,
+    pub any_of: Vec<Tag>,
+    pub none_of: Vec<Tag>,
+}
+
+impl TagFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        self.all_of.iter().all(|tag| user.tags.contains(tag))
+            && (self.any_of.is_empty() || self.any_of.iter().any(|tag| user.tags.contains(tag)))
+            && !self.none_of.iter().any(|tag| user.tags.contains(tag))
+    }
+}
+
+pub fn check_tag(tag: String) -> Result<Tag> {
+    let tag = tag.trim().to_lowercase();
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let re = PATTERN.get_or_init(|| Regex::new(r"^[a-z0-9][a-z0-9-]{0,31}$").unwrap());
+    if re.is_match(&tag) {
+        Ok(Tag(tag))
+    } else {
+        Err(DomainError::InvalidTag.into())
+    }
+}
+
+pub fn add_tag(user: &mut User, tag: Tag) {
+    user.tags.insert(tag);
+}
+
+pub fn remove_tag(user: &mut User, tag: &Tag) {
+    user.tags.remove(tag);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::UserFixture;
+
+    #[test]
+    fn ok_add_and_remove_tags() {
+        let mut user = UserFixture::new().build();
+
+        let beta = check_tag(" Beta-Tester".to_string()).unwrap();
+        let vip = check_tag("vip".to_string()).unwrap();
+        add_tag(&mut user, beta.clone());
+        add_tag(&mut user, vip.clone());
+        remove_tag(&mut user, &vip);
+
+        assert_eq!(beta, Tag("beta-tester".to_string()));
+        assert!(user.tags.contains(&beta));
+        assert!(!user.tags.contains(&vip));
+    }
+
+    #[test]
+    fn err_invalid_tag() {
+        let result = check_tag("not a tag".to_string());
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid tag");
+        assert_eq!(
+            error.downcast_ref::<DomainError>(),
+            Some(&DomainError::InvalidTag)
+        );
+    }
+}
@@ -0,0 +1,540 @@
+//! Ready-made users and repositories for tests, so a test spells out only
+//! what it cares about.
+
+use crate::arbitrary::{Arbitrary, Gen, NamePart, ValidEmail};
+use crate::error::DomainError;
+use crate::json::JsonValue;
+use crate::repository::{InMemoryUserRepository, UserRepository};
+use crate::tags::TagFilter;
+use crate::{create_user, grant_user, Email, TenantId, User, UserId};
+use anyhow::{Error, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{env, fs, thread};
+
+/// Valid user under construction; [`UserFixture::build`] runs it through
+/// `create_user` like any real registration.
+#[derive(Debug, Clone)]
+pub struct UserFixture {
+    email: String,
+    age: i32,
+    name: String,
+    middle_name: Option<String>,
+    surname: String,
+    verified: bool,
+}
+
+impl Default for UserFixture {
+    fn default() -> Self {
+        Self {
+            email: "foo@ok.com".to_string(),
+            age: 22,
+            name: "Luca".to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+            verified: false,
+        }
+    }
+}
+
+impl UserFixture {
+    /// Luca Rossi, 22, with the unverified address `foo@ok.com`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn verified() -> Self {
+        Self {
+            verified: true,
+            ..Self::default()
+        }
+    }
+
+    /// Old enough to register but not an adult.
+    pub fn minor() -> Self {
+        Self {
+            age: 15,
+            ..Self::default()
+        }
+    }
+
+    /// Random but valid user; the same seed gives the same user.
+    pub fn random(seed: u64) -> Self {
+        let rng = &mut Gen::new(seed);
+        Self {
+            email: ValidEmail::arbitrary(rng).0,
+            age: rng.range(13..=120),
+            name: NamePart::arbitrary(rng).0,
+            middle_name: Option::<NamePart>::arbitrary(rng).map(|part| part.0),
+            surname: NamePart::arbitrary(rng).0,
+            verified: false,
+        }
+    }
+
+    pub fn with_email(mut self, email: &str) -> Self {
+        self.email = email.to_string();
+        self
+    }
+
+    pub fn with_age(mut self, age: i32) -> Self {
+        self.age = age;
+        self
+    }
+
+    pub fn with_name(mut self, name: &str, middle_name: Option<&str>, surname: &str) -> Self {
+        self.name = name.to_string();
+        self.middle_name = middle_name.map(str::to_string);
+        self.surname = surname.to_string();
+        self
+    }
+
+    /// Panics if the fixture was given invalid values.
+    pub fn build(self) -> User {
+        let mut user = create_user(
+            self.email,
+            self.age,
+            self.name,
+            self.surname,
+            self.middle_name,
+        )
+        .unwrap();
+        if self.verified {
+            grant_user(&mut user).unwrap();
+        }
+        user
+    }
+}
+
+/// Repository holding `users`.
+pub fn repository_with(users: impl IntoIterator<Item = User>) -> InMemoryUserRepository {
+    let repository = InMemoryUserRepository::new();
+    for user in users {
+        repository.save(user).unwrap();
+    }
+    repository
+}
+
+/// Repository holding `count` random users, at `user0@ok.com`,
+/// `user1@ok.com` and so on.
+pub fn populated_repository(count: u64) -> InMemoryUserRepository {
+    repository_with((0..count).map(|seed| {
+        UserFixture::random(seed)
+            .with_email(&format!("user{}@ok.com", seed))
+            .build()
+    }))
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    calls: u64,
+    failing_calls: BTreeSet<u64>,
+    /// Finds left that return the previous version, by user.
+    stale_finds: HashMap<UserId, u32>,
+    /// Version each user had before its last save.
+    previous: HashMap<UserId, User>,
+}
+
+/// Decorator that misbehaves on cue, so retries, conflict handling and
+/// circuit breakers can be tested without timing luck.
+///
+/// Every port call counts, starting at 1, and a failing call fails before
+/// reaching `base`.
+pub struct FlakyRepository<R: UserRepository> {
+    base: R,
+    latency: Duration,
+    faults: Mutex<Faults>,
+}
+
+impl<R: UserRepository> FlakyRepository<R> {
+    pub fn new(base: R) -> Self {
+        Self {
+            base,
+            latency: Duration::ZERO,
+            faults: Mutex::new(Faults::default()),
+        }
+    }
+
+    /// Sleeps this long before every call.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Makes call number `call` fail.
+    pub fn fail_call(&self, call: u64) {
+        self.faults.lock().unwrap().failing_calls.insert(call);
+    }
+
+    /// Makes the next `finds` finds of `id` return the version from before
+    /// its last save, like a lagging replica.
+    pub fn serve_stale(&self, id: UserId, finds: u32) {
+        self.faults.lock().unwrap().stale_finds.insert(id, finds);
+    }
+
+    /// Calls made so far.
+    pub fn calls(&self) -> u64 {
+        self.faults.lock().unwrap().calls
+    }
+
+    fn call(&self) -> Result<()> {
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+        let mut faults = self.faults.lock().unwrap();
+        faults.calls += 1;
+        let call = faults.calls;
+        if faults.failing_calls.remove(&call) {
+            return Err(Error::msg(format!("Injected failure on call {}", call)));
+        }
+        Ok(())
+    }
+}
+
+impl<R: UserRepository> UserRepository for FlakyRepository<R> {
+    fn save(&self, user: User) -> Result<()> {
+        self.call()?;
+        let id = user.id;
+        let previous = self.base.find(id)?;
+        self.base.save(user)?;
+        if let Some(previous) = previous {
+            self.faults.lock().unwrap().previous.insert(id, previous);
+        }
+        Ok(())
+    }
+
+    fn save_all_with(
+        &self,
+        users: Vec<User>,
+        alongside: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        self.call()?;
+        let mut previous = Vec::new();
+        for user in &users {
+            if let Some(stored) = self.base.find(user.id)? {
+                previous.push(stored);
+            }
+        }
+        self.base.save_all_with(users, alongside)?;
+        let mut faults = self.faults.lock().unwrap();
+        for user in previous {
+            faults.previous.insert(user.id, user);
+        }
+        Ok(())
+    }
+
+    fn find(&self, id: UserId) -> Result<Option<User>> {
+        self.call()?;
+        let mut faults = self.faults.lock().unwrap();
+        if let Some(finds) = faults.stale_finds.get_mut(&id).filter(|finds| **finds > 0) {
+            *finds -= 1;
+            if let Some(previous) = faults.previous.get(&id) {
+                return Ok(Some(previous.clone()));
+            }
+        }
+        drop(faults);
+        self.base.find(id)
+    }
+
+    fn find_by_email(&self, tenant_id: &TenantId, email: &Email) -> Result<Option<User>> {
+        self.call()?;
+        self.base.find_by_email(tenant_id, email)
+    }
+
+    fn find_by_tags(&self, filter: &TagFilter) -> Result<Vec<User>> {
+        self.call()?;
+        self.base.find_by_tags(filter)
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&User) -> Result<()>) -> Result<()> {
+        self.call()?;
+        self.base.for_each(visit)
+    }
+}
+
+/// Behavior every [`UserRepository`] must share, checked on fresh
+/// repositories from `new_repository`: round-trips, optimistic versioning
+/// under concurrent saves, email uniqueness and paging.
+pub fn user_repository_contract<R: UserRepository + Sync>(new_repository: impl Fn() -> R) {
+    // Round-trip.
+    let repository = new_repository();
+    let user = UserFixture::verified()
+        .with_name("Luca", Some("Maria"), "Rossi")
+        .build();
+    let id = user.id;
+    repository.save(user.clone()).unwrap();
+    let stored = repository.find(id).unwrap().expect("saved user is found");
+    assert_eq!(stored.full_name(), user.full_name());
+    assert_eq!(stored.email.address().as_str(), "foo@ok.com");
+    assert_eq!(stored.version, 1, "first save sets version 1");
+    assert!(repository.find(UserId(u64::MAX)).unwrap().is_none());
+
+    // Stale versions are rejected, not overwritten.
+    let (mut first, second) = (stored.clone(), stored);
+    first.name = "Marco".to_string();
+    repository.save(first).unwrap();
+    let error = repository.save(second).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<DomainError>(),
+        Some(&DomainError::ConcurrencyConflict {
+            user_id: id,
+            expected_version: 1,
+            actual_version: 2
+        })
+    );
+    assert_eq!(repository.find(id).unwrap().unwrap().name, "Marco");
+
+    // Of concurrent saves from one version, exactly one wins.
+    let loaded = repository.find(id).unwrap().unwrap();
+    let results: Vec<Result<()>> = thread::scope(|scope| {
+        let saves: Vec<_> = (0..4)
+            .map(|n| {
+                let mut user = loaded.clone();
+                user.name = format!("Luca{}", n);
+                let repository = &repository;
+                scope.spawn(move || repository.save(user))
+            })
+            .collect();
+        saves.into_iter().map(|save| save.join().unwrap()).collect()
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert_eq!(repository.find(id).unwrap().unwrap().version, 3);
+
+    // Emails are unique in normalized form.
+    let repository = new_repository();
+    repository
+        .save(UserFixture::new().with_email("foo@ok.com").build())
+        .unwrap();
+    let error = repository
+        .save(UserFixture::new().with_email("FOO@ok.com").build())
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<DomainError>(),
+        Some(&DomainError::EmailAlreadyRegistered)
+    );
+    let owner = repository
+        .find_by_email(&TenantId::default(), &Email("Foo@Ok.com".to_string()))
+        .unwrap();
+    assert!(owner.is_some(), "lookup by email ignores case");
+
+    // Batches are checked in order and written in full or not at all.
+    let repository = new_repository();
+    let taker = UserFixture::new().with_email("taker@ok.com").build();
+    let taken = UserFixture::verified().with_email("taken@ok.com").build();
+    repository.save(taker.clone()).unwrap();
+    repository.save(taken.clone()).unwrap();
+    let mut taken = repository.find(taken.id).unwrap().unwrap();
+    let mut taker = repository.find(taker.id).unwrap().unwrap();
+    taken.merged_into = Some(taker.id);
+    taker.email = taken.email.clone();
+    let error = repository
+        .save_all(vec![taker.clone(), taken.clone()])
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<DomainError>(),
+        Some(&DomainError::EmailAlreadyRegistered)
+    );
+    let mut stale = taker.clone();
+    stale.version = 0;
+    let error = repository.save_all(vec![taken.clone(), stale]).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<DomainError>(),
+        Some(DomainError::ConcurrencyConflict { .. })
+    ));
+    let untouched = repository.find(taken.id).unwrap().unwrap();
+    assert_eq!((untouched.version, untouched.merged_into), (1, None));
+    repository.save_all(vec![taken, taker.clone()]).unwrap();
+    let owner = repository
+        .find_by_email(&TenantId::default(), &Email("taken@ok.com".to_string()))
+        .unwrap();
+    assert_eq!(owner.map(|user| user.id), Some(taker.id));
+
+    // Paging visits every live user once, in id order.
+    let repository = new_repository();
+    let mut ids: Vec<UserId> = (0..5)
+        .map(|n| {
+            let user = UserFixture::new()
+                .with_email(&format!("user{}@ok.com", n))
+                .build();
+            let id = user.id;
+            repository.save(user).unwrap();
+            id
+        })
+        .collect();
+    ids.sort();
+    let mut listed = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = repository.list_users(cursor.as_ref(), 2).unwrap();
+        assert!(page.users.len() <= 2);
+        listed.extend(page.users.iter().map(|user| user.id));
+        if !page.has_more {
+            break;
+        }
+        cursor = page.next_cursor;
+    }
+    assert_eq!(listed, ids);
+}
+
+fn pretty(value: &JsonValue, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent + 1);
+    match value {
+        JsonValue::Array(values) if !values.is_empty() => {
+            out.push_str("[\n");
+            for (index, value) in values.iter().enumerate() {
+                out.push_str(&pad);
+                pretty(value, indent + 1, out);
+                out.push_str(if index + 1 < values.len() {
+                    ",\n"
+                } else {
+                    "\n"
+                });
+            }
+            write!(out, "{}]", "  ".repeat(indent)).unwrap();
+        }
+        JsonValue::Object(fields) if !fields.is_empty() => {
+            out.push_str("{\n");
+            for (index, (key, value)) in fields.iter().enumerate() {
+                write!(out, "{}{}: ", pad, JsonValue::String(key.clone())).unwrap();
+                pretty(value, indent + 1, out);
+                out.push_str(if index + 1 < fields.len() {
+                    ",\n"
+                } else {
+                    "\n"
+                });
+            }
+            write!(out, "{}}}", "  ".repeat(indent)).unwrap();
+        }
+        value => write!(out, "{}", value).unwrap(),
+    }
+}
+
+/// Compares `value`, pretty-printed, with `src/snapshots/<name>.snap`, so a
+/// change to a wire format shows up as a diff in review.
+///
+/// Run the tests with `UPDATE_SNAPSHOTS=1` to write new or changed
+/// snapshots, then review them like any other change.
+pub fn assert_json_snapshot(name: &str, value: &JsonValue) {
+    let mut actual = String::new();
+    pretty(value, 0, &mut actual);
+    actual.push('\n');
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "snapshots"]
+        .iter()
+        .collect::<PathBuf>()
+        .join(format!("{}.snap", name));
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "No snapshot at {}; run with UPDATE_SNAPSHOTS=1 to create it",
+            path.display()
+        )
+    });
+    assert_eq!(
+        actual, expected,
+        "{} no longer matches its snapshot; run with UPDATE_SNAPSHOTS=1 to accept the change",
+        name
+    );
+}
+
+/// Checks that the `Debug` output of `value` contains none of `pii`, so it
+/// is safe to log.
+pub fn assert_debug_masks(value: &impl std::fmt::Debug, pii: &[&str]) {
+    let debug = format!("{:?}", value);
+    for raw in pii {
+        assert!(!debug.contains(raw), "{:?} leaked in {}", raw, debug);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+    use crate::clock::SystemClock;
+    use crate::commands::{handle_change_name, ChangeName};
+    use crate::UserEmail;
+
+    #[test]
+    fn ok_presets_and_random_users_are_valid() {
+        assert!(matches!(
+            UserFixture::verified().build().email,
+            UserEmail::VerifiedEmail(_)
+        ));
+        assert_eq!(UserFixture::minor().build().age.0, 15);
+        let repository = populated_repository(50);
+
+        let user = repository
+            .find_by_email(&TenantId::default(), &Email("user42@ok.com".to_string()))
+            .unwrap()
+            .unwrap();
+
+        assert!((13..=120).contains(&user.age.0));
+        assert_eq!(
+            UserFixture::random(42).build().full_name().to_string(),
+            user.full_name().to_string()
+        );
+    }
+
+    fn rename(user_id: UserId) -> ChangeName {
+        ChangeName {
+            user_id,
+            name: "Anna".to_string(),
+            middle_name: None,
+            surname: "Bianchi".to_string(),
+        }
+    }
+
+    #[test]
+    fn ok_breaker_opens_on_injected_failures() {
+        let repository = FlakyRepository::new(InMemoryUserRepository::new())
+            .with_latency(Duration::from_millis(1));
+        let user = UserFixture::new().build();
+        let user_id = user.id;
+        repository.save(user).unwrap();
+        let breaker =
+            CircuitBreaker::new("users", SystemClock).with_thresholds(2, Duration::from_secs(60));
+        repository.fail_call(2);
+        repository.fail_call(3);
+
+        for _ in 0..2 {
+            assert!(breaker.call(|| repository.find(user_id)).is_err());
+        }
+
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        assert!(repository.find(user_id).unwrap().is_some());
+        assert_eq!(repository.calls(), 4);
+    }
+
+    #[test]
+    fn err_stale_read_conflicts_on_save() {
+        let repository = FlakyRepository::new(InMemoryUserRepository::new());
+        let user = UserFixture::new().build();
+        let user_id = user.id;
+        repository.save(user).unwrap();
+        handle_change_name(&repository, rename(user_id)).unwrap();
+        repository.serve_stale(user_id, 1);
+
+        let result = handle_change_name(&repository, rename(user_id));
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "User {} was modified concurrently (expected version 1, found 2)",
+                user_id
+            )
+        );
+        assert!(handle_change_name(&repository, rename(user_id)).is_ok());
+    }
+
+    #[test]
+    fn ok_flaky_repository_meets_contract() {
+        user_repository_contract(|| FlakyRepository::new(InMemoryUserRepository::new()));
+    }
+}
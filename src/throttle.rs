@@ -0,0 +1,197 @@
+use crate::clock::Clock;
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Allows at most `max_attempts` per key within any `window`-long span.
+/// Refused attempts are not counted, so a key recovers as soon as its oldest
+/// accepted attempt leaves the window.
+pub struct SlidingWindowLimiter<K: Eq + Hash, C: Clock> {
+    clock: C,
+    max_attempts: u32,
+    window: Duration,
+    attempts: Mutex<HashMap<K, VecDeque<SystemTime>>>,
+}
+
+impl<K: Eq + Hash, C: Clock> SlidingWindowLimiter<K, C> {
+    pub fn new(max_attempts: u32, window: Duration, clock: C) -> Self {
+        Self {
+            clock,
+            max_attempts,
+            window,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an attempt for `key` if it is within the limit.
+    pub fn try_acquire(&self, key: K) -> bool {
+        let now = self.clock.now();
+        let mut attempts = self.attempts.lock().unwrap();
+        let recent = attempts.entry(key).or_default();
+        self.expire(recent, now);
+        if recent.len() as u32 >= self.max_attempts {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+
+    /// Whether [`SlidingWindowLimiter::try_acquire`] would succeed, without
+    /// recording anything.
+    pub fn would_allow(&self, key: &K) -> bool {
+        self.attempts(key) < self.max_attempts
+    }
+
+    /// Attempts recorded for `key` within the current window.
+    pub fn attempts(&self, key: &K) -> u32 {
+        let now = self.clock.now();
+        let mut attempts = self.attempts.lock().unwrap();
+        match attempts.get_mut(key) {
+            Some(recent) => {
+                self.expire(recent, now);
+                recent.len() as u32
+            }
+            None => 0,
+        }
+    }
+
+    fn expire(&self, recent: &mut VecDeque<SystemTime>, now: SystemTime) {
+        while recent
+            .front()
+            .is_some_and(|attempt| *attempt + self.window <= now)
+        {
+            recent.pop_front();
+        }
+    }
+}
+
+/// A signup refused because its source address reached the bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignupThrottled;
+
+impl Display for SignupThrottled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Too many signups from this address")
+    }
+}
+
+impl std::error::Error for SignupThrottled {}
+
+/// Bounds how many signups one source address may make. Implementations
+/// shared by every instance keep the bound global.
+pub trait SignupThrottle {
+    /// Counts a signup from `source_ip` if it is within the bound.
+    fn try_acquire(&self, source_ip: IpAddr) -> Result<bool>;
+    /// Whether a signup would be allowed, without counting it.
+    fn would_allow(&self, source_ip: IpAddr) -> Result<bool>;
+}
+
+/// Per-process throttle; each instance enforces the bound on its own.
+impl<C: Clock> SignupThrottle for SlidingWindowLimiter<IpAddr, C> {
+    fn try_acquire(&self, source_ip: IpAddr) -> Result<bool> {
+        Ok(SlidingWindowLimiter::try_acquire(self, source_ip))
+    }
+
+    fn would_allow(&self, source_ip: IpAddr) -> Result<bool> {
+        Ok(SlidingWindowLimiter::would_allow(self, &source_ip))
+    }
+}
+
+/// The slice of a Redis client the shared throttle needs.
+pub trait RedisScripting {
+    /// Runs a Lua script with `EVAL` and returns its integer reply.
+    fn eval(&self, script: &str, keys: &[String], args: &[String]) -> Result<i64>;
+}
+
+/// Sliding window kept in one sorted set per address, so every instance
+/// sharing the Redis server enforces the same bound. The script trims,
+/// checks and records atomically.
+pub struct RedisSignupThrottle<R: RedisScripting, C: Clock> {
+    redis: R,
+    clock: C,
+    max_signups: u32,
+    window: Duration,
+}
+
+const SLIDING_WINDOW_SCRIPT: &str = r#"
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[1] - ARGV[2])
+local count = redis.call('ZCARD', KEYS[1])
+if count >= tonumber(ARGV[3]) then
+  return 0
+end
+if ARGV[4] == '1' then
+  redis.call('ZADD', KEYS[1], ARGV[1], ARGV[1] .. '-' .. redis.call('INCR', KEYS[1] .. ':seq'))
+  redis.call('PEXPIRE', KEYS[1], ARGV[2])
+  redis.call('PEXPIRE', KEYS[1] .. ':seq', ARGV[2])
+end
+return 1
+"#;
+
+impl<R: RedisScripting, C: Clock> RedisSignupThrottle<R, C> {
+    pub fn new(redis: R, max_signups: u32, window: Duration, clock: C) -> Self {
+        Self {
+            redis,
+            clock,
+            max_signups,
+            window,
+        }
+    }
+
+    fn run(&self, source_ip: IpAddr, record: bool) -> Result<bool> {
+        let now = self
+            .clock
+            .now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let allowed = self.redis.eval(
+            SLIDING_WINDOW_SCRIPT,
+            &[format!("signups:{}", source_ip)],
+            &[
+                now.to_string(),
+                self.window.as_millis().to_string(),
+                self.max_signups.to_string(),
+                if record { "1" } else { "0" }.to_string(),
+            ],
+        )?;
+        Ok(allowed == 1)
+    }
+}
+
+impl<R: RedisScripting, C: Clock> SignupThrottle for RedisSignupThrottle<R, C> {
+    fn try_acquire(&self, source_ip: IpAddr) -> Result<bool> {
+        self.run(source_ip, true)
+    }
+
+    fn would_allow(&self, source_ip: IpAddr) -> Result<bool> {
+        self.run(source_ip, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+
+    #[test]
+    fn ok_window_slides() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let limiter = SlidingWindowLimiter::new(2, Duration::from_secs(60), &clock);
+
+        assert!(limiter.try_acquire("a"));
+        clock.advance(Duration::from_secs(30));
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.try_acquire("a"));
+        assert!(limiter.try_acquire("b"));
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(limiter.attempts(&"a"), 1);
+        assert!(limiter.would_allow(&"a"));
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.would_allow(&"a"));
+    }
+}
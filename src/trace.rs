@@ -0,0 +1,405 @@
+//! Minimal structured tracing: named spans with fields and an outcome, nested
+//! per thread and handed to a [`Subscriber`] when they close. Spans belong to
+//! a trace whose context travels between processes as a W3C `traceparent`.
+
+use crate::hash::{sha256, to_hex};
+use crate::pii::{mask_email, mask_name};
+use anyhow::{Error, Result};
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Ok,
+    Error(String),
+}
+
+/// A closed span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanRecord {
+    pub trace_id: [u8; 16],
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub fields: Vec<(&'static str, String)>,
+    pub outcome: Outcome,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+impl SpanRecord {
+    /// Fields as they may be written out, with emails and names masked; see
+    /// [`crate::pii`].
+    pub fn masked_fields(&self) -> Vec<(&'static str, String)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| {
+                let value = match *key {
+                    "email" => mask_email(value),
+                    "name" | "middle_name" | "surname" => mask_name(value),
+                    _ => value.clone(),
+                };
+                (*key, value)
+            })
+            .collect()
+    }
+}
+
+impl SpanRecord {
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Identifies a span across process boundaries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` header such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    pub fn parse_traceparent(header: &str) -> Result<Self> {
+        let invalid = || Error::msg(format!("Invalid traceparent {}", header));
+        let parts: Vec<&str> = header.trim().split('-').collect();
+        let [version, trace_id, span_id, flags] = parts[..] else {
+            return Err(invalid());
+        };
+        if version != "00" {
+            return Err(invalid());
+        }
+        let trace_id = parse_hex::<16>(trace_id).ok_or_else(invalid)?;
+        let span_id = u64::from_be_bytes(parse_hex::<8>(span_id).ok_or_else(invalid)?);
+        let [flags] = parse_hex::<1>(flags).ok_or_else(invalid)?;
+        if trace_id == [0; 16] || span_id == 0 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            to_hex(&self.trace_id),
+            to_hex(&self.span_id.to_be_bytes()),
+            u8::from(self.sampled)
+        )
+    }
+}
+
+pub trait Subscriber: Send + Sync {
+    fn on_close(&self, span: &SpanRecord);
+}
+
+static NEXT_SEED: AtomicU64 = AtomicU64::new(1);
+static GLOBAL: RwLock<Option<Arc<dyn Subscriber>>> = RwLock::new(None);
+
+thread_local! {
+    static LOCAL: RefCell<Option<Arc<dyn Subscriber>>> = const { RefCell::new(None) };
+    /// Open spans and remote parents on this thread, innermost last.
+    static OPEN: RefCell<Vec<TraceContext>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Unpredictable bytes for trace and span ids, which must not collide with
+/// the ids of other processes taking part in the same trace.
+pub(crate) fn random_id() -> [u8; 32] {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = NEXT_SEED.fetch_add(1, Ordering::Relaxed);
+    sha256(format!("{}-{}-{}", std::process::id(), nanos, seed).as_bytes())
+}
+
+pub fn set_global_subscriber(subscriber: Arc<dyn Subscriber>) {
+    *GLOBAL.write().unwrap() = Some(subscriber);
+}
+
+/// Sends the spans closed on this thread while `f` runs to `subscriber`
+/// instead of the global one. Meant for tests.
+pub fn with_subscriber<T>(subscriber: Arc<dyn Subscriber>, f: impl FnOnce() -> T) -> T {
+    let previous = LOCAL.with(|local| local.borrow_mut().replace(subscriber));
+    let result = f();
+    LOCAL.with(|local| *local.borrow_mut() = previous);
+    result
+}
+
+/// Id of the innermost open span on this thread.
+pub fn current_span_id() -> Option<u64> {
+    current_context().map(|context| context.span_id)
+}
+
+/// Context of the innermost open span on this thread, to be handed to work
+/// that continues the trace elsewhere.
+pub fn current_context() -> Option<TraceContext> {
+    OPEN.with(|open| open.borrow().last().copied())
+}
+
+/// Runs `f` as a continuation of `parent`, typically a context received from
+/// another process: spans opened by `f` join its trace.
+pub fn with_remote_parent<T>(parent: TraceContext, f: impl FnOnce() -> T) -> T {
+    OPEN.with(|open| open.borrow_mut().push(parent));
+    let result = f();
+    OPEN.with(|open| {
+        let mut open = open.borrow_mut();
+        if let Some(index) = open.iter().rposition(|context| *context == parent) {
+            open.remove(index);
+        }
+    });
+    result
+}
+
+fn emit(record: &SpanRecord) {
+    let local = LOCAL.with(|local| local.borrow().clone());
+    let subscriber = local.or_else(|| GLOBAL.read().unwrap().clone());
+    if let Some(subscriber) = subscriber {
+        subscriber.on_close(record);
+    }
+}
+
+/// An open span; see [`instrument`].
+pub struct Span {
+    context: TraceContext,
+    parent: Option<u64>,
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    started_at: SystemTime,
+    started: Instant,
+    outcome: Option<Outcome>,
+}
+
+impl Span {
+    /// Adds a field learnt while the span is open, e.g. a generated id.
+    pub fn record(&mut self, key: &'static str, value: impl Display) {
+        self.fields.push((key, value.to_string()));
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        OPEN.with(|open| {
+            let mut open = open.borrow_mut();
+            if let Some(index) = open.iter().rposition(|context| *context == self.context) {
+                open.remove(index);
+            }
+        });
+        if !self.context.sampled {
+            return;
+        }
+        emit(&SpanRecord {
+            trace_id: self.context.trace_id,
+            id: self.context.span_id,
+            parent: self.parent,
+            name: self.name,
+            fields: std::mem::take(&mut self.fields),
+            outcome: self
+                .outcome
+                .take()
+                .unwrap_or_else(|| Outcome::Error("panicked".to_string())),
+            started_at: self.started_at,
+            duration: self.started.elapsed(),
+        });
+    }
+}
+
+/// Runs `f` inside a span named `name`, closing it with the outcome of `f`.
+pub fn instrument<T>(
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    f: impl FnOnce(&mut Span) -> Result<T>,
+) -> Result<T> {
+    let random = random_id();
+    let parent = current_context();
+    let context = TraceContext {
+        trace_id: match parent {
+            Some(parent) => parent.trace_id,
+            None => random[8..24].try_into().unwrap(),
+        },
+        span_id: u64::from_be_bytes(random[..8].try_into().unwrap()),
+        sampled: parent.is_none_or(|parent| parent.sampled),
+    };
+    OPEN.with(|open| open.borrow_mut().push(context));
+    let mut span = Span {
+        context,
+        parent: parent.map(|parent| parent.span_id),
+        name,
+        fields,
+        started_at: SystemTime::now(),
+        started: Instant::now(),
+        outcome: None,
+    };
+    let result = f(&mut span);
+    span.outcome = Some(match &result {
+        Ok(_) => Outcome::Ok,
+        Err(error) => Outcome::Error(error.to_string()),
+    });
+    result
+}
+
+/// Writes one line per closed span to stderr.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StderrSubscriber;
+
+/// Line [`StderrSubscriber`] writes for `span`, with PII fields masked.
+fn stderr_line(span: &SpanRecord) -> String {
+    let fields: String = span
+        .masked_fields()
+        .iter()
+        .map(|(key, value)| format!(" {}={}", key, value))
+        .collect();
+    let outcome = match &span.outcome {
+        Outcome::Ok => "ok".to_string(),
+        Outcome::Error(error) => format!("error ({})", error),
+    };
+    format!(
+        "{}{} outcome={} duration_us={}",
+        span.name,
+        fields,
+        outcome,
+        span.duration.as_micros()
+    )
+}
+
+impl Subscriber for StderrSubscriber {
+    fn on_close(&self, span: &SpanRecord) {
+        eprintln!("{}", stderr_line(span));
+    }
+}
+
+/// Keeps every closed span, for assertions in tests.
+#[derive(Debug, Default)]
+pub struct RecordingSubscriber {
+    spans: Mutex<Vec<SpanRecord>>,
+}
+
+impl RecordingSubscriber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Closed spans, in the order they closed.
+    pub fn spans(&self) -> Vec<SpanRecord> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    pub fn named(&self, name: &str) -> Vec<SpanRecord> {
+        self.spans()
+            .into_iter()
+            .filter(|span| span.name == name)
+            .collect()
+    }
+}
+
+impl Subscriber for RecordingSubscriber {
+    fn on_close(&self, span: &SpanRecord) {
+        self.spans.lock().unwrap().push(span.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Error;
+
+    #[test]
+    fn ok_nested_spans_are_recorded() {
+        let recorder = Arc::new(RecordingSubscriber::new());
+
+        let result: Result<()> = with_subscriber(recorder.clone(), || {
+            instrument("outer", vec![("command", "Demo".to_string())], |span| {
+                span.record("user_id", 7);
+                instrument("inner", Vec::new(), |_| Err(Error::msg("boom")))
+            })
+        });
+
+        assert!(result.is_err());
+        let spans = recorder.spans();
+        assert_eq!(spans[0].name, "inner");
+        assert_eq!(spans[0].outcome, Outcome::Error("boom".to_string()));
+        assert_eq!(spans[0].parent, Some(spans[1].id));
+        assert_eq!(spans[1].field("command"), Some("Demo"));
+        assert_eq!(spans[1].field("user_id"), Some("7"));
+        assert_eq!(spans[0].trace_id, spans[1].trace_id);
+        assert_eq!(current_span_id(), None);
+    }
+
+    #[test]
+    fn ok_spans_continue_remote_trace() {
+        let recorder = Arc::new(RecordingSubscriber::new());
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let remote = TraceContext::parse_traceparent(header).unwrap();
+
+        let propagated = with_subscriber(recorder.clone(), || {
+            with_remote_parent(remote, || {
+                instrument("handler", Vec::new(), |_| Ok(current_context().unwrap()))
+            })
+        })
+        .unwrap();
+
+        let span = &recorder.named("handler")[0];
+        assert_eq!(remote.traceparent(), header);
+        assert_eq!(span.trace_id, remote.trace_id);
+        assert_eq!(span.parent, Some(0x00f067aa0ba902b7));
+        assert_eq!(propagated.span_id, span.id);
+        assert_eq!(current_context(), None);
+    }
+
+    #[test]
+    fn err_invalid_traceparent() {
+        let result = TraceContext::parse_traceparent("00-abc-00f067aa0ba902b7-01");
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid traceparent 00-abc-00f067aa0ba902b7-01"
+        );
+    }
+
+    #[test]
+    fn ok_stderr_line_masks_pii_fields() {
+        let span = SpanRecord {
+            trace_id: [0; 16],
+            id: 1,
+            parent: None,
+            name: "command",
+            fields: vec![
+                ("email", "luca.rossi@ok.com".to_string()),
+                ("surname", "Rossi".to_string()),
+                ("user_id", "7".to_string()),
+            ],
+            outcome: Outcome::Ok,
+            started_at: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_micros(12),
+        };
+
+        assert_eq!(
+            stderr_line(&span),
+            "command email=l***@ok.com surname=R*** user_id=7 outcome=ok duration_us=12"
+        );
+    }
+}
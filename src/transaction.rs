@@ -0,0 +1,165 @@
+use crate::repository::{InMemoryUserRepository, StagingRepository, UserRepository};
+use anyhow::{Error, Result};
+
+/// Marker returned by [`Transaction::savepoint`]; savepoints nest, so rolling
+/// back to one also discards every savepoint taken after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Savepoint(usize);
+
+/// Application-level transaction, implemented by infrastructure so handlers
+/// can span several aggregates without depending on database types.
+pub trait Transaction {
+    type Users: UserRepository;
+
+    fn users(&self) -> &Self::Users;
+    fn savepoint(&mut self) -> Result<Savepoint>;
+    fn rollback_to(&mut self, savepoint: Savepoint) -> Result<()>;
+    fn commit(self) -> Result<()>;
+    fn rollback(self);
+}
+
+pub trait TransactionManager {
+    type Transaction<'a>: Transaction
+    where
+        Self: 'a;
+
+    fn begin(&self) -> Result<Self::Transaction<'_>>;
+}
+
+/// Stages writes in the same overlay as
+/// [`InMemoryUnitOfWork`](crate::unit_of_work::InMemoryUnitOfWork);
+/// savepoints are positions in its journal and commit replays it into the
+/// base in one [`save_all`](UserRepository::save_all).
+pub struct InMemoryTransaction<'a, R: UserRepository> {
+    users: StagingRepository<'a, R>,
+    /// Live savepoints, oldest first, as (savepoint id, journal length).
+    savepoints: Vec<(usize, usize)>,
+    next_savepoint: usize,
+    base: &'a R,
+}
+
+impl<'a, R: UserRepository> InMemoryTransaction<'a, R> {
+    pub fn begin(base: &'a R) -> Self {
+        Self {
+            users: StagingRepository::new(base),
+            savepoints: Vec::new(),
+            next_savepoint: 0,
+            base,
+        }
+    }
+}
+
+impl<'a, R: UserRepository> Transaction for InMemoryTransaction<'a, R> {
+    type Users = StagingRepository<'a, R>;
+
+    fn users(&self) -> &Self::Users {
+        &self.users
+    }
+
+    fn savepoint(&mut self) -> Result<Savepoint> {
+        let id = self.next_savepoint;
+        self.next_savepoint += 1;
+        self.savepoints.push((id, self.users.staged_len()));
+        Ok(Savepoint(id))
+    }
+
+    fn rollback_to(&mut self, savepoint: Savepoint) -> Result<()> {
+        let index = self
+            .savepoints
+            .iter()
+            .position(|(id, _)| *id == savepoint.0)
+            .ok_or_else(|| Error::msg("Savepoint has already been rolled back"))?;
+        let (_, position) = self.savepoints[index];
+        self.savepoints.truncate(index + 1);
+        self.users.truncate(position);
+        Ok(())
+    }
+
+    fn commit(self) -> Result<()> {
+        self.base.save_all(self.users.into_staged())
+    }
+
+    fn rollback(self) {}
+}
+
+impl TransactionManager for InMemoryUserRepository {
+    type Transaction<'a> = InMemoryTransaction<'a, InMemoryUserRepository>;
+
+    fn begin(&self) -> Result<Self::Transaction<'_>> {
+        Ok(InMemoryTransaction::begin(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::UserFixture;
+    use crate::User;
+
+    fn user(email: &str) -> User {
+        UserFixture::new().with_email(email).build()
+    }
+
+    #[test]
+    fn ok_commit_with_nested_savepoints() {
+        let repository = InMemoryUserRepository::new();
+        let (first, second, third) = (user("a@ok.com"), user("b@ok.com"), user("c@ok.com"));
+        let ids = (first.id, second.id, third.id);
+        let mut transaction = repository.begin().unwrap();
+
+        transaction.users().save(first).unwrap();
+        let outer = transaction.savepoint().unwrap();
+        transaction.users().save(second).unwrap();
+        let inner = transaction.savepoint().unwrap();
+        transaction.users().save(third).unwrap();
+        transaction.rollback_to(outer).unwrap();
+        assert!(transaction.users().find(ids.1).unwrap().is_none());
+        assert!(transaction.rollback_to(inner).is_err());
+        transaction.commit().unwrap();
+
+        assert!(repository.find(ids.0).unwrap().is_some());
+        assert!(repository.find(ids.1).unwrap().is_none());
+        assert!(repository.find(ids.2).unwrap().is_none());
+    }
+
+    #[test]
+    fn ok_rollback_discards_writes() {
+        let repository = InMemoryUserRepository::new();
+        let user = user("a@ok.com");
+        let id = user.id;
+        let transaction = repository.begin().unwrap();
+
+        transaction.users().save(user).unwrap();
+        assert!(transaction.users().find(id).unwrap().is_some());
+        transaction.rollback();
+
+        assert!(repository.find(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn ok_savepoint_rollback_restores_versions() {
+        let repository = InMemoryUserRepository::new();
+        let user = user("a@ok.com");
+        let id = user.id;
+        repository.save(user).unwrap();
+        let mut transaction = repository.begin().unwrap();
+
+        let mut renamed = transaction.users().find(id).unwrap().unwrap();
+        renamed.name = "Marco".to_string();
+        transaction.users().save(renamed).unwrap();
+        let savepoint = transaction.savepoint().unwrap();
+        let mut renamed = transaction.users().find(id).unwrap().unwrap();
+        renamed.name = "Paolo".to_string();
+        transaction.users().save(renamed).unwrap();
+        transaction.rollback_to(savepoint).unwrap();
+        let mut renamed = transaction.users().find(id).unwrap().unwrap();
+        assert_eq!((renamed.name.as_str(), renamed.version), ("Marco", 2));
+        renamed.surname = "Bianchi".to_string();
+        transaction.users().save(renamed).unwrap();
+        transaction.commit().unwrap();
+
+        let stored = repository.find(id).unwrap().unwrap();
+        assert_eq!(stored.full_name().to_string(), "Marco Bianchi");
+        assert_eq!(stored.version, 3);
+    }
+}
@@ -0,0 +1,234 @@
+//! Name suggestions for search-as-you-type fields, maintained from events.
+
+use crate::event_store::StoredEvent;
+use crate::events::{DomainEvent, EventId};
+use crate::format_fullname;
+use crate::projections::Projection;
+use crate::UserId;
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuggestUsers {
+    pub prefix: String,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub user_id: UserId,
+    pub full_name: String,
+}
+
+/// How a key matched the prefix; earlier variants rank first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Exactness {
+    /// The whole name, or username, is the prefix.
+    Exact,
+    /// The full name starts with the prefix.
+    FullName,
+    /// One name word or the username starts with the prefix.
+    Word,
+}
+
+/// Lowercased, with runs of whitespace collapsed to one space.
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[derive(Debug)]
+struct Entry {
+    full_name: String,
+    username: String,
+    /// Registration event, standing in for how recent the user is.
+    registered: EventId,
+}
+
+impl Entry {
+    /// Normalized full name, each name word after the first, and the
+    /// username, i.e. the local part of the email.
+    fn keys(&self) -> BTreeSet<String> {
+        let full_name = normalize(&self.full_name);
+        let mut keys: BTreeSet<String> = full_name.split(' ').skip(1).map(str::to_string).collect();
+        keys.insert(full_name);
+        keys.insert(self.username.clone());
+        keys
+    }
+
+    fn exactness(&self, prefix: &str) -> Exactness {
+        let full_name = normalize(&self.full_name);
+        if full_name == prefix || self.username == prefix {
+            Exactness::Exact
+        } else if full_name.starts_with(prefix) {
+            Exactness::FullName
+        } else {
+            Exactness::Word
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    entries: HashMap<UserId, Entry>,
+    /// Users by key, sorted so a prefix is a range scan.
+    keys: BTreeMap<String, BTreeSet<UserId>>,
+}
+
+impl Index {
+    fn insert(&mut self, user_id: UserId, entry: Entry) {
+        self.remove(user_id);
+        for key in entry.keys() {
+            self.keys.entry(key).or_default().insert(user_id);
+        }
+        self.entries.insert(user_id, entry);
+    }
+
+    fn remove(&mut self, user_id: UserId) -> Option<Entry> {
+        let entry = self.entries.remove(&user_id)?;
+        for key in entry.keys() {
+            if let Some(users) = self.keys.get_mut(&key) {
+                users.remove(&user_id);
+                if users.is_empty() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// Prefix index over names and usernames of live users.
+#[derive(Debug, Default)]
+pub struct Typeahead {
+    index: RwLock<Index>,
+}
+
+impl Typeahead {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers the `SuggestUsers` query: exact matches first, then full
+    /// names starting with the prefix, then other words, each group most
+    /// recently registered first.
+    pub fn suggest(&self, query: &SuggestUsers) -> Vec<Suggestion> {
+        let prefix = normalize(&query.prefix);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let index = self.index.read().unwrap();
+        let user_ids: BTreeSet<UserId> = index
+            .keys
+            .range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .flat_map(|(_, users)| users.iter().copied())
+            .collect();
+        let mut matches: Vec<(&UserId, &Entry)> = user_ids
+            .iter()
+            .map(|user_id| (user_id, &index.entries[user_id]))
+            .collect();
+        matches.sort_by(|(_, a), (_, b)| {
+            a.exactness(&prefix)
+                .cmp(&b.exactness(&prefix))
+                .then(b.registered.0.cmp(&a.registered.0))
+        });
+        matches
+            .into_iter()
+            .take(query.limit)
+            .map(|(user_id, entry)| Suggestion {
+                user_id: *user_id,
+                full_name: entry.full_name.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Projection for Typeahead {
+    fn name(&self) -> &str {
+        "typeahead"
+    }
+
+    fn apply(&self, stored: &StoredEvent) -> Result<()> {
+        let mut index = self.index.write().unwrap();
+        match &stored.event {
+            DomainEvent::UserRegistered(event) => {
+                let username = event.email.split('@').next().unwrap_or_default();
+                let entry = Entry {
+                    full_name: format_fullname(
+                        &event.name,
+                        event.middle_name.as_deref(),
+                        &event.surname,
+                    ),
+                    username: username.to_lowercase(),
+                    registered: stored.id,
+                };
+                index.insert(event.user_id, entry);
+            }
+            DomainEvent::NameChanged(event) => {
+                if let Some(mut entry) = index.remove(event.user_id) {
+                    entry.full_name =
+                        format_fullname(&event.name, event.middle_name.as_deref(), &event.surname);
+                    index.insert(event.user_id, entry);
+                }
+            }
+            DomainEvent::EmailVerified(_) | DomainEvent::VerificationThrottled(_) => {}
+            DomainEvent::UsersMerged(event) => {
+                index.remove(event.duplicate_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        *self.index.write().unwrap() = Index::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::UserRegistered;
+    use std::time::SystemTime;
+
+    #[test]
+    fn ok_suggest_by_exactness_then_recency() {
+        let typeahead = Typeahead::new();
+        let people = [
+            (1, "Marco", "Rossi", "mrossi"),
+            (2, "Anna", "Marconi", "anna"),
+            (3, "Marco", "Bianchi", "marco"),
+            (4, "Marcello", "Verdi", "mverdi"),
+        ];
+        for (id, name, surname, username) in people {
+            let event = DomainEvent::UserRegistered(UserRegistered {
+                user_id: UserId(id),
+                email: format!("{}@ok.com", username),
+                name: name.to_string(),
+                middle_name: None,
+                surname: surname.to_string(),
+                age: 22,
+            });
+            let stored = StoredEvent {
+                id: EventId(id),
+                recorded_at: SystemTime::UNIX_EPOCH,
+                event,
+            };
+            typeahead.apply(&stored).unwrap();
+        }
+
+        let suggestions = typeahead.suggest(&SuggestUsers {
+            prefix: " MARCO".to_string(),
+            limit: 3,
+        });
+
+        let ids: Vec<u64> = suggestions.iter().map(|s| s.user_id.0).collect();
+        assert_eq!(ids, vec![3, 1, 2]);
+        assert_eq!(suggestions[0].full_name, "Marco Bianchi");
+    }
+}
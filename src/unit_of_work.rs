@@ -0,0 +1,136 @@
+use crate::events::{DomainEvent, EventPublisher};
+use crate::repository::{StagingRepository, UserRepository};
+use anyhow::Result;
+
+/// Groups the repository writes and event publications of one command so
+/// they take effect together on [`commit`](UnitOfWork::commit) or not at all.
+pub trait UnitOfWork {
+    type Users: UserRepository;
+
+    fn users(&self) -> &Self::Users;
+    fn record(&mut self, event: DomainEvent);
+    fn commit(self) -> Result<()>;
+    fn rollback(self);
+}
+
+/// Stages writes in memory and applies them on commit in one
+/// [`save_all_with`](UserRepository::save_all_with), which publishes the
+/// recorded events as part of the write: if publishing fails, nothing is
+/// saved. Dropping it without committing is a rollback.
+///
+/// Events are published one by one, so a publisher failing on a later event
+/// leaves the earlier ones published.
+pub struct InMemoryUnitOfWork<'a, R: UserRepository, P: EventPublisher> {
+    users: StagingRepository<'a, R>,
+    repository: &'a R,
+    publisher: &'a P,
+    events: Vec<DomainEvent>,
+}
+
+impl<'a, R: UserRepository, P: EventPublisher> InMemoryUnitOfWork<'a, R, P> {
+    pub fn begin(repository: &'a R, publisher: &'a P) -> Self {
+        Self {
+            users: StagingRepository::new(repository),
+            repository,
+            publisher,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl<'a, R: UserRepository, P: EventPublisher> UnitOfWork for InMemoryUnitOfWork<'a, R, P> {
+    type Users = StagingRepository<'a, R>;
+
+    fn users(&self) -> &Self::Users {
+        &self.users
+    }
+
+    fn record(&mut self, event: DomainEvent) {
+        self.events.push(event);
+    }
+
+    fn commit(self) -> Result<()> {
+        let (publisher, events) = (self.publisher, self.events);
+        self.repository
+            .save_all_with(self.users.into_staged(), &mut || {
+                events.iter().try_for_each(|event| publisher.publish(event))
+            })
+    }
+
+    fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::{handle_create_user, CreateUser};
+    use crate::config::AgeLimits;
+    use crate::events::InMemoryEventPublisher;
+    use crate::repository::InMemoryUserRepository;
+    use crate::UserId;
+    use anyhow::Error;
+
+    struct FailingPublisher;
+
+    impl EventPublisher for FailingPublisher {
+        fn publish(&self, _event: &DomainEvent) -> Result<()> {
+            Err(Error::msg("Broker unavailable"))
+        }
+    }
+
+    fn create(unit_of_work: &mut impl UnitOfWork) -> UserId {
+        let command = CreateUser {
+            email: "foo@ok.com".to_string(),
+            age: 22,
+            name: "Luca".to_string(),
+            surname: "Rossi".to_string(),
+            middle_name: None,
+        };
+        let event =
+            handle_create_user(unit_of_work.users(), &AgeLimits::default(), command).unwrap();
+        let user_id = event.user_id;
+        unit_of_work.record(event.into());
+        user_id
+    }
+
+    #[test]
+    fn ok_commit_saves_and_publishes() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = InMemoryEventPublisher::new();
+        let mut unit_of_work = InMemoryUnitOfWork::begin(&repository, &publisher);
+
+        let user_id = create(&mut unit_of_work);
+        assert!(repository.find(user_id).unwrap().is_none());
+        assert!(publisher.published().is_empty());
+        unit_of_work.commit().unwrap();
+
+        assert!(repository.find(user_id).unwrap().is_some());
+        assert_eq!(publisher.published().len(), 1);
+    }
+
+    #[test]
+    fn err_failed_publish_saves_nothing() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = FailingPublisher;
+        let mut unit_of_work = InMemoryUnitOfWork::begin(&repository, &publisher);
+
+        let user_id = create(&mut unit_of_work);
+        let result = unit_of_work.commit();
+
+        assert_eq!(result.unwrap_err().to_string(), "Broker unavailable");
+        assert!(repository.find(user_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn ok_rollback_discards_everything() {
+        let repository = InMemoryUserRepository::new();
+        let publisher = InMemoryEventPublisher::new();
+        let mut unit_of_work = InMemoryUnitOfWork::begin(&repository, &publisher);
+
+        let user_id = create(&mut unit_of_work);
+        unit_of_work.rollback();
+
+        assert!(repository.find(user_id).unwrap().is_none());
+        assert!(publisher.published().is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::event_store::StoredEvent;
+use crate::events::DomainEvent;
+use crate::interner::Interner;
+use crate::projections::Projection;
+use crate::UserId;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DomainCount {
+    pub domain: String,
+    pub verified: usize,
+    pub unverified: usize,
+}
+
+/// Verified and unverified users per email domain. Merged duplicates are
+/// no longer counted.
+#[derive(Debug, Default)]
+pub struct UsersByDomain {
+    /// Domain and verification state of every counted user; keeping it per
+    /// user makes re-applied events harmless.
+    users: RwLock<HashMap<UserId, (Arc<str>, bool)>>,
+    domains: Interner,
+}
+
+impl UsersByDomain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn domain_of(&self, email: &str) -> Arc<str> {
+        let domain = email.rsplit_once('@').map_or(email, |(_, domain)| domain);
+        self.domains.intern(&domain.to_lowercase())
+    }
+
+    /// Every domain with at least one user, sorted by domain.
+    pub fn counts(&self) -> Vec<DomainCount> {
+        let mut counts: BTreeMap<&str, DomainCount> = BTreeMap::new();
+        let users = self.users.read().unwrap();
+        for (domain, verified) in users.values() {
+            let count = counts.entry(domain).or_insert_with(|| DomainCount {
+                domain: domain.to_string(),
+                ..DomainCount::default()
+            });
+            if *verified {
+                count.verified += 1;
+            } else {
+                count.unverified += 1;
+            }
+        }
+        counts.into_values().collect()
+    }
+
+    pub fn count_for(&self, domain: &str) -> DomainCount {
+        let domain = domain.to_lowercase();
+        self.counts()
+            .into_iter()
+            .find(|count| count.domain == domain)
+            .unwrap_or(DomainCount {
+                domain,
+                ..DomainCount::default()
+            })
+    }
+}
+
+impl Projection for UsersByDomain {
+    fn name(&self) -> &str {
+        "users_by_domain"
+    }
+
+    fn apply(&self, stored: &StoredEvent) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        match &stored.event {
+            DomainEvent::UserRegistered(event) => {
+                users
+                    .entry(event.user_id)
+                    .or_insert_with(|| (self.domain_of(&event.email), false));
+            }
+            DomainEvent::EmailVerified(event) => {
+                users.insert(event.user_id, (self.domain_of(&event.email), true));
+            }
+            DomainEvent::NameChanged(_) | DomainEvent::VerificationThrottled(_) => {}
+            DomainEvent::UsersMerged(event) => {
+                users.remove(&event.duplicate_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.users.write().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_store::{EventStore, InMemoryEventStore};
+    use crate::events::{EmailVerified, UserRegistered, UsersMerged};
+    use crate::projections::{InMemoryCheckpointStore, ProjectionRunner};
+
+    fn registered(user_id: u64, email: &str) -> DomainEvent {
+        DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(user_id),
+            email: email.to_string(),
+            name: "Luca".to_string(),
+            middle_name: None,
+            surname: "Rossi".to_string(),
+            age: 22,
+        })
+    }
+
+    #[test]
+    fn ok_count_users_by_domain() {
+        let store = InMemoryEventStore::new();
+        for event in [
+            registered(1, "a@ok.com"),
+            registered(2, "b@OK.com"),
+            registered(3, "c@example.ok"),
+            registered(4, "d@ok.com"),
+            DomainEvent::EmailVerified(EmailVerified {
+                user_id: UserId(1),
+                email: "a@ok.com".to_string(),
+            }),
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(4),
+                merged: None,
+            }),
+        ] {
+            store.append(event).unwrap();
+        }
+        let projection = UsersByDomain::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+
+        ProjectionRunner::new(&store, &checkpoints)
+            .register(&projection)
+            .run_once()
+            .unwrap();
+
+        assert_eq!(
+            projection.counts(),
+            vec![
+                DomainCount {
+                    domain: "example.ok".to_string(),
+                    verified: 0,
+                    unverified: 1,
+                },
+                DomainCount {
+                    domain: "ok.com".to_string(),
+                    verified: 1,
+                    unverified: 1,
+                },
+            ]
+        );
+        assert_eq!(projection.count_for("nowhere.com").verified, 0);
+    }
+}
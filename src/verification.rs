@@ -0,0 +1,17 @@
+use crate::{verify_email, UnverifiedEmail, VerifiedEmail};
+use anyhow::Result;
+
+/// Port to whatever proves a user owns an email address.
+pub trait EmailVerifier {
+    fn verify(&self, email: &UnverifiedEmail) -> Result<VerifiedEmail>;
+}
+
+/// Verifier applying the built-in [`verify_email`] rule.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RuleEmailVerifier;
+
+impl EmailVerifier for RuleEmailVerifier {
+    fn verify(&self, email: &UnverifiedEmail) -> Result<VerifiedEmail> {
+        Ok(verify_email(email)?)
+    }
+}
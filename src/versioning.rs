@@ -0,0 +1,254 @@
+use crate::events::DomainEvent;
+use crate::json::{parse_json, JsonValue};
+use anyhow::{Error, Result};
+
+/// Schema version written for new events of the given type. Bump it together
+/// with a new [`Upcaster`] whenever the payload shape changes.
+pub fn current_version(event_type: &str) -> u32 {
+    match event_type {
+        // v2 added `middle_name`.
+        "UserRegistered" => 2,
+        // v2 added `merged`.
+        "UsersMerged" => 2,
+        _ => 1,
+    }
+}
+
+/// Serialized event together with the schema version of its payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventEnvelope {
+    pub event_type: String,
+    pub version: u32,
+    pub payload: JsonValue,
+}
+
+impl EventEnvelope {
+    pub fn wrap(event: &DomainEvent) -> Self {
+        Self {
+            event_type: event.name().to_string(),
+            version: current_version(event.name()),
+            payload: event.to_json(),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::Object(vec![
+            (
+                "type".to_string(),
+                JsonValue::String(self.event_type.clone()),
+            ),
+            (
+                "version".to_string(),
+                JsonValue::Number(self.version as i64),
+            ),
+            ("payload".to_string(), self.payload.clone()),
+        ])
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let value = parse_json(text)?;
+        let invalid = || Error::msg("Invalid event envelope");
+        Ok(Self {
+            event_type: value
+                .get("type")
+                .and_then(JsonValue::as_str)
+                .ok_or_else(invalid)?
+                .to_string(),
+            version: value
+                .get("version")
+                .and_then(JsonValue::as_i64)
+                .ok_or_else(invalid)? as u32,
+            payload: value.get("payload").cloned().ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// Migrates one event type's payload from `source_version` to the next one.
+pub trait Upcaster {
+    fn event_type(&self) -> &str;
+    fn source_version(&self) -> u32;
+    fn upcast(&self, payload: JsonValue) -> Result<JsonValue>;
+}
+
+/// `UserRegistered.v1` predates middle names.
+pub struct UserRegisteredV1ToV2;
+
+impl Upcaster for UserRegisteredV1ToV2 {
+    fn event_type(&self) -> &str {
+        "UserRegistered"
+    }
+
+    fn source_version(&self) -> u32 {
+        1
+    }
+
+    fn upcast(&self, payload: JsonValue) -> Result<JsonValue> {
+        match payload {
+            JsonValue::Object(mut fields) => {
+                fields.push(("middle_name".to_string(), JsonValue::Null));
+                Ok(JsonValue::Object(fields))
+            }
+            _ => Err(Error::msg("Invalid event payload")),
+        }
+    }
+}
+
+/// `UsersMerged.v1` carries only the ids; the merged profile is unknown.
+pub struct UsersMergedV1ToV2;
+
+impl Upcaster for UsersMergedV1ToV2 {
+    fn event_type(&self) -> &str {
+        "UsersMerged"
+    }
+
+    fn source_version(&self) -> u32 {
+        1
+    }
+
+    fn upcast(&self, payload: JsonValue) -> Result<JsonValue> {
+        match payload {
+            JsonValue::Object(mut fields) => {
+                fields.push(("merged".to_string(), JsonValue::Null));
+                Ok(JsonValue::Object(fields))
+            }
+            _ => Err(Error::msg("Invalid event payload")),
+        }
+    }
+}
+
+/// Applies upcasters one version at a time until an envelope reaches the
+/// current schema, then decodes it.
+pub struct UpcasterChain {
+    upcasters: Vec<Box<dyn Upcaster>>,
+}
+
+impl UpcasterChain {
+    /// Chain with every upcaster this crate ships.
+    pub fn new() -> Self {
+        Self {
+            upcasters: vec![Box::new(UserRegisteredV1ToV2), Box::new(UsersMergedV1ToV2)],
+        }
+    }
+
+    pub fn register(mut self, upcaster: Box<dyn Upcaster>) -> Self {
+        self.upcasters.push(upcaster);
+        self
+    }
+
+    pub fn upcast(&self, envelope: EventEnvelope) -> Result<DomainEvent> {
+        let EventEnvelope {
+            event_type,
+            mut version,
+            mut payload,
+        } = envelope;
+        let target = current_version(&event_type);
+        if version > target {
+            return Err(Error::msg(format!(
+                "{}.v{} is newer than this application",
+                event_type, version
+            )));
+        }
+
+        while version < target {
+            let upcaster = self
+                .upcasters
+                .iter()
+                .find(|u| u.event_type() == event_type && u.source_version() == version)
+                .ok_or_else(|| {
+                    Error::msg(format!("No upcaster for {}.v{}", event_type, version))
+                })?;
+            payload = upcaster.upcast(payload)?;
+            version += 1;
+        }
+        DomainEvent::from_json(&payload)
+    }
+}
+
+impl Default for UpcasterChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{UserRegistered, UsersMerged};
+    use crate::UserId;
+
+    #[test]
+    fn ok_upcast_user_registered_v1() {
+        let envelope = EventEnvelope::parse(
+            r#"{"type":"UserRegistered","version":1,"payload":{"type":"UserRegistered","user_id":4,"email":"foo@ok.com","name":"Luca","surname":"Rossi","age":22}}"#,
+        )
+        .unwrap();
+
+        let event = UpcasterChain::new().upcast(envelope).unwrap();
+
+        assert_eq!(
+            event,
+            DomainEvent::UserRegistered(UserRegistered {
+                user_id: UserId(4),
+                email: "foo@ok.com".to_string(),
+                name: "Luca".to_string(),
+                middle_name: None,
+                surname: "Rossi".to_string(),
+                age: 22,
+            })
+        );
+    }
+
+    #[test]
+    fn ok_upcast_users_merged_v1() {
+        let envelope = EventEnvelope::parse(
+            r#"{"type":"UsersMerged","version":1,"payload":{"type":"UsersMerged","primary_id":4,"duplicate_id":5}}"#,
+        )
+        .unwrap();
+
+        let event = UpcasterChain::new().upcast(envelope).unwrap();
+
+        assert_eq!(
+            event,
+            DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(4),
+                duplicate_id: UserId(5),
+                merged: None,
+            })
+        );
+    }
+
+    #[test]
+    fn ok_current_version_roundtrip() {
+        let event = DomainEvent::UserRegistered(UserRegistered {
+            user_id: UserId(4),
+            email: "foo@ok.com".to_string(),
+            name: "Luca".to_string(),
+            middle_name: Some("Maria".to_string()),
+            surname: "Rossi".to_string(),
+            age: 22,
+        });
+
+        let text = EventEnvelope::wrap(&event).to_json().to_string();
+        let envelope = EventEnvelope::parse(&text).unwrap();
+
+        assert_eq!(envelope.version, 2);
+        assert_eq!(UpcasterChain::new().upcast(envelope).unwrap(), event);
+    }
+
+    #[test]
+    fn err_missing_upcaster() {
+        let envelope = EventEnvelope::parse(
+            r#"{"type":"UserRegistered","version":1,"payload":{"type":"UserRegistered"}}"#,
+        )
+        .unwrap();
+        let chain = UpcasterChain {
+            upcasters: Vec::new(),
+        };
+
+        let result = chain.upcast(envelope);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "No upcaster for UserRegistered.v1");
+    }
+}
@@ -0,0 +1,438 @@
+use crate::clock::Clock;
+use crate::config::WebhookConfig;
+use crate::events::{DomainEvent, EventHandler};
+use crate::hash::{hmac_sha256, to_hex};
+use crate::secrets::Secret;
+use crate::trace::{current_context, instrument, with_remote_parent, TraceContext};
+use anyhow::{Error, Result};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const SUPPORTED_EVENT_TYPES: [&str; 2] = ["UserRegistered", "EmailVerified"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookSubscription {
+    pub id: u64,
+    pub url: String,
+    /// Shared secret used to sign payloads; never sent over the wire, and
+    /// masked in debug output.
+    pub secret: Secret,
+    pub event_types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// Gave up after the maximum number of attempts.
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub subscription_id: u64,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub next_attempt_at: SystemTime,
+    pub status: DeliveryStatus,
+    pub last_error: Option<String>,
+    /// Trace of the event that caused the delivery, continued when sending.
+    pub traceparent: Option<String>,
+}
+
+/// Port to an HTTP client. Returns the response status code; an error means
+/// no response was received at all.
+pub trait WebhookTransport {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &str) -> Result<u16>;
+}
+
+pub struct WebhookDispatcher<T: WebhookTransport, C: Clock> {
+    transport: T,
+    clock: C,
+    max_attempts: u32,
+    base_backoff: Duration,
+    subscriptions: Mutex<Vec<WebhookSubscription>>,
+    deliveries: Mutex<Vec<WebhookDelivery>>,
+}
+
+pub fn sign_payload(secret: &str, payload: &str) -> String {
+    format!(
+        "sha256={}",
+        to_hex(&hmac_sha256(secret.as_bytes(), payload.as_bytes()))
+    )
+}
+
+impl<T: WebhookTransport, C: Clock> WebhookDispatcher<T, C> {
+    /// Retries failed deliveries as `config` says.
+    pub fn new(transport: T, clock: C, config: &WebhookConfig) -> Self {
+        Self {
+            transport,
+            clock,
+            max_attempts: config.max_attempts,
+            base_backoff: config.base_backoff,
+            subscriptions: Mutex::new(Vec::new()),
+            deliveries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn subscribe(&self, url: &str, secret: &str, event_types: &[&str]) -> Result<u64> {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(Error::msg("Webhook URL must be http or https"));
+        }
+        if secret.is_empty() {
+            return Err(Error::msg("Webhook secret cannot be empty"));
+        }
+        if event_types.is_empty() {
+            return Err(Error::msg("Webhook must select at least one event type"));
+        }
+        if let Some(unknown) = event_types
+            .iter()
+            .find(|event_type| !SUPPORTED_EVENT_TYPES.contains(event_type))
+        {
+            return Err(Error::msg(format!("Unsupported event type {}", unknown)));
+        }
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let id = subscriptions.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+        subscriptions.push(WebhookSubscription {
+            id,
+            url: url.to_string(),
+            secret: Secret::new(secret.to_string()),
+            event_types: event_types.iter().map(|t| t.to_string()).collect(),
+        });
+        Ok(id)
+    }
+
+    pub fn unsubscribe(&self, id: u64) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .retain(|subscription| subscription.id != id);
+    }
+
+    pub fn subscriptions(&self) -> Vec<WebhookSubscription> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+
+    pub fn deliveries(&self) -> Vec<WebhookDelivery> {
+        self.deliveries.lock().unwrap().clone()
+    }
+
+    /// Attempts every pending delivery whose retry time has come and returns
+    /// how many succeeded. Failures are retried with exponential backoff.
+    ///
+    /// Due deliveries are claimed under the lock, by counting the attempt and
+    /// scheduling the retry up front, and posted once it is released, so a
+    /// slow endpoint holds up neither other callers nor new deliveries.
+    pub fn deliver_due(&self) -> usize {
+        let now = self.clock.now();
+        let subscriptions = self.subscriptions();
+        let mut due = Vec::new();
+        for delivery in self
+            .deliveries
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|delivery| {
+                delivery.status == DeliveryStatus::Pending && delivery.next_attempt_at <= now
+            })
+        {
+            let Some(subscription) = subscriptions
+                .iter()
+                .find(|subscription| subscription.id == delivery.subscription_id)
+            else {
+                delivery.status = DeliveryStatus::Failed;
+                delivery.last_error = Some("Subscription was removed".to_string());
+                continue;
+            };
+            delivery.attempts += 1;
+            delivery.next_attempt_at = now + self.base_backoff * 2u32.pow(delivery.attempts - 1);
+            due.push((subscription, delivery.clone()));
+        }
+
+        let mut delivered = 0;
+        for (subscription, attempt) in due {
+            let result = self.send(subscription, &attempt);
+            let mut deliveries = self.deliveries.lock().unwrap();
+            let Some(delivery) = deliveries
+                .iter_mut()
+                .find(|delivery| delivery.id == attempt.id)
+            else {
+                continue;
+            };
+            match result {
+                Ok(()) => {
+                    delivery.status = DeliveryStatus::Delivered;
+                    delivery.last_error = None;
+                    delivered += 1;
+                }
+                Err(error) => {
+                    delivery.last_error = Some(error.to_string());
+                    if delivery.attempts >= self.max_attempts {
+                        delivery.status = DeliveryStatus::Failed;
+                    }
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Posts one attempt of `delivery` in a `webhook.deliver` span that
+    /// continues the trace of its event and is propagated to the endpoint.
+    fn send(&self, subscription: &WebhookSubscription, delivery: &WebhookDelivery) -> Result<()> {
+        let post = || {
+            let fields = vec![
+                ("event", delivery.event_type.clone()),
+                ("url", subscription.url.clone()),
+            ];
+            instrument("webhook.deliver", fields, |_| {
+                let mut headers = vec![
+                    ("Content-Type", "application/json".to_string()),
+                    ("X-Webhook-Event", delivery.event_type.clone()),
+                    (
+                        "X-Webhook-Signature",
+                        sign_payload(subscription.secret.expose(), &delivery.payload),
+                    ),
+                ];
+                if let Some(context) = current_context() {
+                    headers.push(("traceparent", context.traceparent()));
+                }
+                match self
+                    .transport
+                    .post(&subscription.url, &headers, &delivery.payload)?
+                {
+                    status if (200..300).contains(&status) => Ok(()),
+                    status => Err(Error::msg(format!("Endpoint answered {}", status))),
+                }
+            })
+        };
+        match delivery
+            .traceparent
+            .as_deref()
+            .and_then(|header| TraceContext::parse_traceparent(header).ok())
+        {
+            Some(parent) => with_remote_parent(parent, post),
+            None => post(),
+        }
+    }
+}
+
+/// Queues one delivery per matching subscription; sending happens in
+/// [`WebhookDispatcher::deliver_due`].
+impl<T: WebhookTransport, C: Clock> EventHandler for WebhookDispatcher<T, C> {
+    fn name(&self) -> &str {
+        "webhooks"
+    }
+
+    fn handle(&self, event: &DomainEvent) -> Result<()> {
+        let event_type = event.name();
+        let payload = event.to_json().to_string();
+        let now = self.clock.now();
+        let traceparent = current_context().map(|context| context.traceparent());
+        let mut deliveries = self.deliveries.lock().unwrap();
+
+        for subscription in self.subscriptions.lock().unwrap().iter() {
+            if !subscription.event_types.iter().any(|t| t == event_type) {
+                continue;
+            }
+            let id = deliveries.len() as u64 + 1;
+            deliveries.push(WebhookDelivery {
+                id,
+                subscription_id: subscription.id,
+                event_type: event_type.to_string(),
+                payload: payload.clone(),
+                attempts: 0,
+                next_attempt_at: now,
+                status: DeliveryStatus::Pending,
+                last_error: None,
+                traceparent: traceparent.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::events::{EmailVerified, UsersMerged};
+    use crate::test_support::assert_debug_masks;
+    use crate::UserId;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    /// (url, headers, body) of a request seen by the transport.
+    type SentRequest = (String, Vec<(String, String)>, String);
+
+    /// Answers with the scripted statuses in order, then 200.
+    #[derive(Default)]
+    struct ScriptedTransport {
+        statuses: Mutex<Vec<u16>>,
+        requests: Mutex<Vec<SentRequest>>,
+    }
+
+    impl WebhookTransport for &ScriptedTransport {
+        fn post(&self, url: &str, headers: &[(&str, String)], body: &str) -> Result<u16> {
+            self.requests.lock().unwrap().push((
+                url.to_string(),
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect(),
+                body.to_string(),
+            ));
+            let mut statuses = self.statuses.lock().unwrap();
+            Ok(if statuses.is_empty() {
+                200
+            } else {
+                statuses.remove(0)
+            })
+        }
+    }
+
+    fn email_verified() -> DomainEvent {
+        DomainEvent::EmailVerified(EmailVerified {
+            user_id: UserId(1),
+            email: "foo@ok.com".to_string(),
+        })
+    }
+
+    #[test]
+    fn ok_signed_delivery_after_backoff() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let transport = ScriptedTransport {
+            statuses: Mutex::new(vec![503]),
+            ..ScriptedTransport::default()
+        };
+        let dispatcher = WebhookDispatcher::new(&transport, &clock, &WebhookConfig::default());
+        dispatcher
+            .subscribe("https://example.com/hook", "s3cret", &["EmailVerified"])
+            .unwrap();
+
+        dispatcher.handle(&email_verified()).unwrap();
+        dispatcher
+            .handle(&DomainEvent::UsersMerged(UsersMerged {
+                primary_id: UserId(1),
+                duplicate_id: UserId(2),
+                merged: None,
+            }))
+            .unwrap();
+        assert_eq!(dispatcher.deliver_due(), 0);
+        assert_eq!(dispatcher.deliveries()[0].status, DeliveryStatus::Pending);
+        assert_eq!(dispatcher.deliver_due(), 0);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(dispatcher.deliver_due(), 1);
+
+        let deliveries = dispatcher.deliveries();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Delivered);
+        assert_eq!(deliveries[0].attempts, 2);
+        let requests = transport.requests.lock().unwrap();
+        let (url, headers, body) = &requests[1];
+        assert_eq!(url, "https://example.com/hook");
+        assert!(headers.contains(&(
+            "X-Webhook-Signature".to_string(),
+            sign_payload("s3cret", body)
+        )));
+    }
+
+    #[test]
+    fn err_delivery_fails_after_max_attempts() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let transport = ScriptedTransport {
+            statuses: Mutex::new(vec![500; 5]),
+            ..ScriptedTransport::default()
+        };
+        let dispatcher = WebhookDispatcher::new(&transport, &clock, &WebhookConfig::default());
+        dispatcher
+            .subscribe("https://example.com/hook", "s3cret", &["EmailVerified"])
+            .unwrap();
+        dispatcher.handle(&email_verified()).unwrap();
+
+        for _ in 0..5 {
+            dispatcher.deliver_due();
+            clock.advance(Duration::from_secs(3600));
+        }
+
+        let delivery = &dispatcher.deliveries()[0];
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 5);
+        assert_eq!(
+            delivery.last_error,
+            Some("Endpoint answered 500".to_string())
+        );
+    }
+
+    #[test]
+    fn err_subscribe_unsupported_event_type() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let transport = ScriptedTransport::default();
+        let dispatcher = WebhookDispatcher::new(&transport, &clock, &WebhookConfig::default());
+
+        let result = dispatcher.subscribe("https://example.com/hook", "s3cret", &["UsersMerged"]);
+
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported event type UsersMerged");
+    }
+
+    /// Signals when a post starts and holds it until released.
+    struct BlockingTransport {
+        entered: Mutex<Sender<()>>,
+        release: Mutex<Receiver<()>>,
+    }
+
+    impl WebhookTransport for &BlockingTransport {
+        fn post(&self, _url: &str, _headers: &[(&str, String)], _body: &str) -> Result<u16> {
+            self.entered.lock().unwrap().send(()).unwrap();
+            self.release.lock().unwrap().recv().unwrap();
+            Ok(200)
+        }
+    }
+
+    #[test]
+    fn ok_deliveries_readable_while_posting() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let (entered, posting) = channel();
+        let (release, released) = channel();
+        let transport = BlockingTransport {
+            entered: Mutex::new(entered),
+            release: Mutex::new(released),
+        };
+        let dispatcher = WebhookDispatcher::new(&transport, &clock, &WebhookConfig::default());
+        dispatcher
+            .subscribe("https://example.com/hook", "s3cret", &["EmailVerified"])
+            .unwrap();
+        dispatcher.handle(&email_verified()).unwrap();
+
+        thread::scope(|scope| {
+            let delivering = scope.spawn(|| dispatcher.deliver_due());
+            posting.recv().unwrap();
+
+            let in_flight = &dispatcher.deliveries()[0];
+            assert_eq!(in_flight.status, DeliveryStatus::Pending);
+            assert_eq!(in_flight.attempts, 1);
+            assert_eq!(dispatcher.deliver_due(), 0);
+
+            release.send(()).unwrap();
+            assert_eq!(delivering.join().unwrap(), 1);
+        });
+        assert_eq!(dispatcher.deliveries()[0].status, DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn ok_subscription_debug_masks_secret() {
+        let clock = TestClock::new(SystemTime::UNIX_EPOCH);
+        let transport = ScriptedTransport::default();
+        let dispatcher = WebhookDispatcher::new(&transport, &clock, &WebhookConfig::default());
+        dispatcher
+            .subscribe("https://example.com/hook", "s3cret", &["EmailVerified"])
+            .unwrap();
+
+        assert_debug_masks(&dispatcher.subscriptions(), &["s3cret"]);
+    }
+}